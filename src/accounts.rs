@@ -0,0 +1,288 @@
+use crate::table::{Style, Table, paint};
+use crate::{
+    Args, AssetTotals, CsvStyle, ImportFormat, Interner, ReportRow, STDIO_PATH, cad,
+    grouped_totals, load_inputs, q2, run_report, side_report_path, summary_color, summary_writer,
+    write_csv, write_report,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+// Whether accounts tagged with `--account` share pools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccountPools {
+    // Each account is a taxpayer of its own, with its own pools.
+    Isolated,
+    // One pool per asset across every account, as if the files were untagged.
+    Merged,
+}
+
+impl AccountPools {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "isolated" => Ok(AccountPools::Isolated),
+            "merged" => Ok(AccountPools::Merged),
+            other => Err(format!("unknown account pools: {:?}", other).into()),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            AccountPools::Isolated => "isolated",
+            AccountPools::Merged => "merged",
+        }
+    }
+}
+
+// Row of an overview of several runs: an account or a taxpayer.
+#[derive(Debug, Serialize)]
+struct OverviewRow {
+    name: String,
+    proceeds_cad: String,
+    acb_disposed_cad: String,
+    gain_cad: String,
+    income_cad: String,
+}
+
+// Input files by account, in the order the accounts are first tagged.
+type AccountInputs = Vec<(String, Vec<(ImportFormat, String)>)>;
+
+// Runs a ledger split across accounts by `--account name=path`. Isolated
+// pools run each account's files on their own, with the account's reports at
+// `<out>_<name>.csv`; merged pools run every file together into `<out>` and
+// split its rows by account. Either way the accounts end side by side.
+pub(crate) fn run(args: &Args) -> Result<u8, Box<dyn Error>> {
+    if args.output == STDIO_PATH {
+        return Err("--account needs a report file, not stdout".into());
+    }
+    if args.watch || args.db.is_some() || args.checkpoint.is_some() {
+        return Err("--account does not support --watch, --db or --checkpoint".into());
+    }
+    let accounts = account_inputs(args)?;
+    let color = summary_color(&args.output, args.no_color);
+    let mut out = summary_writer(&args.output);
+    let heading = |title: &str| paint(title, Style::Heading, color);
+
+    let mut code = 0;
+    // Report rows with the account they belong to, if any.
+    let mut rows: Vec<(Option<String>, ReportRow)> = Vec::new();
+    let mut written = Vec::new();
+    match args.account_pools {
+        AccountPools::Isolated => {
+            for (name, inputs) in &accounts {
+                writeln!(out, "{}", heading(&format!("=== ACCOUNT {} ===", name)))?;
+                let (_, account_code, report) = run_report(&account_args(args, name, inputs))?;
+                code = code.max(account_code);
+                rows.extend(report.into_iter().map(|r| (Some(name.clone()), r)));
+                writeln!(out)?;
+            }
+        }
+        AccountPools::Merged => {
+            let (_, merged_code, report) = run_report(args)?;
+            code = merged_code;
+            // A refid belongs to the account whose files hold it. Migration
+            // and deemed disposition rows belong to none.
+            let mut owner: HashMap<Arc<str>, &str> = HashMap::new();
+            for (name, inputs) in &accounts {
+                let account = account_args(args, name, inputs);
+                let (entries, _) = load_inputs(&account, &mut Interner::default())?;
+                for e in entries {
+                    owner.entry(e.refid).or_insert(name);
+                }
+            }
+            rows.extend(report.into_iter().map(|r| {
+                let name = owner.get(&r.refid).map(|n| n.to_string());
+                (name, r)
+            }));
+            for (name, _) in &accounts {
+                let path = side_report_path(&args.output, name);
+                let shown: Vec<&ReportRow> = rows
+                    .iter()
+                    .filter(|(a, r)| a.as_ref() == Some(name) && args.report_filter.keeps(r))
+                    .map(|(_, r)| r)
+                    .collect();
+                let style = CsvStyle {
+                    dialect: args.csv_dialect,
+                    ..CsvStyle::default()
+                };
+                write_report(&path, &shown, args.columns.as_deref(), style)?;
+                written.push((path, shown.len()));
+            }
+        }
+    }
+
+    let mut by_account = grouped_totals(
+        rows.iter()
+            .filter_map(|(a, r)| a.as_deref().map(|a| (a, r))),
+    )?;
+    let all = grouped_totals(rows.iter().map(|(_, r)| ((), r)))?
+        .remove(&())
+        .unwrap_or_default();
+    let mut overview: Vec<(String, AssetTotals)> = accounts
+        .iter()
+        .map(|(name, _)| {
+            let t = by_account.remove(name.as_str()).unwrap_or_default();
+            (name.clone(), t)
+        })
+        .collect();
+    overview.push(("All accounts".to_string(), all));
+
+    let overview_output = side_report_path(&args.output, "accounts");
+    let title = format!(
+        "=== ALL ACCOUNTS ({} pools) ===",
+        args.account_pools.as_str()
+    );
+    write_overview(
+        &mut out,
+        color,
+        &title,
+        "Account",
+        &overview,
+        &overview_output,
+    )?;
+    writeln!(out)?;
+    for (path, count) in &written {
+        writeln!(out, "Wrote account report ({} rows): {}", count, path)?;
+    }
+    writeln!(out, "Wrote accounts overview: {}", overview_output)?;
+    Ok(code)
+}
+
+// Prints the gain and income totals of several runs side by side, and writes
+// them to `path`. `label` heads the column naming each run.
+pub(crate) fn write_overview(
+    out: &mut dyn Write,
+    color: bool,
+    title: &str,
+    label: &str,
+    overview: &[(String, AssetTotals)],
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(out, "\n{}", paint(title, Style::Heading, color))?;
+    let mut table = Table::new(&[
+        label,
+        "Proceeds (CAD)",
+        "ACB disposed (CAD)",
+        "Gain/loss (CAD)",
+        "Income (CAD)",
+    ]);
+    for (name, t) in overview {
+        table.row(vec![
+            (name.clone(), Style::Plain),
+            (cad(t.proceeds_cad), Style::Plain),
+            (cad(t.acb_disposed_cad), Style::Plain),
+            (cad(t.gain_cad), Style::signed(t.gain_cad)),
+            (cad(t.income_cad), Style::Plain),
+        ]);
+    }
+    write!(out, "{}", table.render(color))?;
+    let rows: Vec<OverviewRow> = overview
+        .iter()
+        .map(|(name, t)| OverviewRow {
+            name: name.clone(),
+            proceeds_cad: q2(t.proceeds_cad).to_string(),
+            acb_disposed_cad: q2(t.acb_disposed_cad).to_string(),
+            gain_cad: q2(t.gain_cad).to_string(),
+            income_cad: q2(t.income_cad).to_string(),
+        })
+        .collect();
+    write_csv(path, &rows)
+}
+
+// Groups the input files by their `--account` tag. Every input needs one,
+// and names go into file names, so they are kept to letters, digits, `-` and
+// `_`.
+fn account_inputs(args: &Args) -> Result<AccountInputs, Box<dyn Error>> {
+    let inputs = std::iter::once((args.import_format, &args.input))
+        .chain(args.extra_inputs.iter().map(|(f, p)| (*f, p)));
+    let mut tags: BTreeMap<&str, &str> = BTreeMap::new();
+    for (name, path) in &args.accounts {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("--account {:?}: use letters, digits, - and _", name).into());
+        }
+        if tags.insert(path, name).is_some() {
+            return Err(format!("--account: {} is tagged twice", path).into());
+        }
+    }
+    let mut out: AccountInputs = Vec::new();
+    for (format, path) in inputs {
+        let name = tags
+            .remove(path.as_str())
+            .ok_or_else(|| format!("--account: input {} has no account", path))?;
+        match out.iter_mut().find(|(n, _)| n == name) {
+            Some((_, files)) => files.push((format, path.clone())),
+            None => out.push((name.to_string(), vec![(format, path.clone())])),
+        }
+    }
+    if let Some(path) = tags.keys().next() {
+        return Err(format!("--account: {} is not an input file", path).into());
+    }
+    Ok(out)
+}
+
+// The run of one account's files, writing next to `<out>_<name>.csv`.
+fn account_args(args: &Args, name: &str, inputs: &[(ImportFormat, String)]) -> Args {
+    let mut account = args.clone();
+    (account.import_format, account.input) = inputs[0].clone();
+    account.extra_inputs = inputs[1..].to_vec();
+    account.output = side_report_path(&args.output, name);
+    account.accounts = Vec::new();
+    account
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_args_from;
+
+    #[test]
+    fn inputs_are_grouped_by_account() {
+        let args = |raw: &[&str]| parse_args_from(raw.iter().map(|s| s.to_string()).collect());
+        let tagged = args(&[
+            "personal.csv",
+            "2025",
+            "out.csv",
+            "--extra-input",
+            "kraken:corp.csv",
+            "--manual",
+            "cold.csv",
+            "--account",
+            "personal=personal.csv",
+            "--account",
+            "corp=corp.csv",
+            "--account",
+            "personal=cold.csv",
+        ])
+        .unwrap();
+        let accounts = account_inputs(&tagged).unwrap();
+        let names: Vec<(&str, usize)> = accounts
+            .iter()
+            .map(|(n, files)| (n.as_str(), files.len()))
+            .collect();
+        assert_eq!(names, [("personal", 2), ("corp", 1)]);
+        let personal = account_args(&tagged, "personal", &accounts[0].1);
+        assert_eq!(personal.output, "out_personal.csv");
+        assert_eq!(personal.input, "personal.csv");
+        assert_eq!(
+            personal.extra_inputs[0],
+            (ImportFormat::Manual, "cold.csv".into())
+        );
+
+        let untagged = args(&[
+            "a.csv",
+            "--extra-input",
+            "kraken:b.csv",
+            "--account",
+            "x=a.csv",
+        ]);
+        assert!(account_inputs(&untagged.unwrap()).is_err());
+        let bad_name = args(&["a.csv", "--account", "my account=a.csv"]);
+        assert!(account_inputs(&bad_name.unwrap()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    STDIO_PATH, create_output, parse_decimal, positional_args, read_input_text, summary_writer,
+    take_flag,
+};
+use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+// Columns holding Kraken ids, replaced by scrambled ids.
+const ID_COLUMNS: [&str; 3] = ["txid", "refid", "ordertxid"];
+// Columns holding amounts, multiplied by `--scale`.
+const AMOUNT_COLUMNS: [&str; 3] = ["amount", "fee", "balance"];
+// Places scaled amounts are rounded to, as many as Kraken exports carry.
+const SCALED_DP: u32 = 10;
+const ID_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// What `anonymize` changes besides ids.
+#[derive(Debug, Clone, Default)]
+struct Options {
+    // Mixed into every scrambled id; the same salt gives the same ids.
+    salt: String,
+    scale: Option<Decimal>,
+}
+
+// An id in Kraken's `XXXXXX-XXXXX-XXXXXX` shape, from SHA-256 of the salt and
+// the original, so every occurrence of an id maps to the same new one.
+fn scramble(id: &str, salt: &str) -> String {
+    let hash = Sha256::digest(format!("{}\0{}", salt, id));
+    let chars: String = hash
+        .iter()
+        .take(17)
+        .map(|b| ID_ALPHABET[(b % 32) as usize] as char)
+        .collect();
+    format!("{}-{}-{}", &chars[..6], &chars[6..11], &chars[11..])
+}
+
+// `text` with its id columns scrambled and its amount columns scaled. Other
+// columns, the row order and the header are kept, so the file still groups
+// and sorts as the original did.
+fn anonymize_csv(text: &str, opts: &Options) -> Result<(String, usize), Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers()?.clone();
+    let column = |names: &[&str]| -> Vec<usize> {
+        headers
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| names.contains(&h.trim()))
+            .map(|(i, _)| i)
+            .collect()
+    };
+    let ids = column(&ID_COLUMNS);
+    let amounts = column(&AMOUNT_COLUMNS);
+    if ids.is_empty() {
+        return Err("no txid or refid column; is this a Kraken ledger export?".into());
+    }
+
+    let mut scrambled: HashMap<String, String> = HashMap::new();
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    wtr.write_record(&headers)?;
+    let mut rows = 0;
+    for (line, record) in rdr.records().enumerate() {
+        let record = record?;
+        let mut out: Vec<String> = record.iter().map(str::to_string).collect();
+        for &i in &ids {
+            let Some(id) = out.get_mut(i).filter(|v| !v.trim().is_empty()) else {
+                continue;
+            };
+            *id = scrambled
+                .entry(id.clone())
+                .or_insert_with(|| scramble(id.trim(), &opts.salt))
+                .clone();
+        }
+        if let Some(scale) = opts.scale {
+            for &i in &amounts {
+                let Some(value) = out.get_mut(i).filter(|v| !v.trim().is_empty()) else {
+                    continue;
+                };
+                let x = parse_decimal(value)
+                    .map_err(|e| format!("row {}: {}: {}", line + 2, headers[i].trim(), e))?;
+                *value = (x * scale).round_dp(SCALED_DP).normalize().to_string();
+            }
+        }
+        wtr.write_record(&out)?;
+        rows += 1;
+    }
+    Ok((String::from_utf8(wtr.into_inner()?)?, rows))
+}
+
+// `anonymize <ledger.csv> [--output file] [--scale <factor>] [--salt <text>]`:
+// writes a copy of a ledger that is safe to attach to a bug report.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut opts = Options::default();
+    let output = take_flag(&mut raw, "output")?;
+    if let Some(salt) = take_flag(&mut raw, "salt")? {
+        opts.salt = salt;
+    }
+    if let Some(value) = take_flag(&mut raw, "scale")? {
+        let scale = parse_decimal(&value)?;
+        if scale <= Decimal::ZERO {
+            return Err(format!("--scale must be positive, got {}", value).into());
+        }
+        opts.scale = Some(scale);
+    }
+    let input = match <[String; 1]>::try_from(positional_args("anonymize", raw)?) {
+        Ok([input]) => input,
+        Err(rest) if rest.is_empty() => return Err("anonymize requires an input file".into()),
+        Err(_) => return Err("anonymize takes one input file".into()),
+    };
+    let output = output.unwrap_or_else(|| {
+        if input == STDIO_PATH {
+            return STDIO_PATH.to_string();
+        }
+        let stem = input.strip_suffix(".csv").unwrap_or(&input);
+        format!("{}_anonymized.csv", stem)
+    });
+
+    let (text, rows) = anonymize_csv(&read_input_text(&input)?, &opts)?;
+    create_output(&output)?.write_all(text.as_bytes())?;
+
+    let mut out = summary_writer(&output);
+    writeln!(out, "Wrote {} anonymized rows: {}", rows, output)?;
+    if let Some(scale) = opts.scale {
+        writeln!(out, "Amounts scaled by {}", scale)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn ids_are_scrambled_consistently_and_amounts_scaled() {
+        let text = "\
+txid,refid,time,type,subtype,asset,amount,fee,balance
+L1,T1,2025-01-01 00:00:00,trade,tradespot,CAD,-100,0.5,
+L2,T1,2025-01-01 00:00:00,trade,tradespot,SOL,1,0,1
+L3,T2,2025-01-02 00:00:00,trade,tradespot,SOL,-1,0,0
+";
+        let opts = Options {
+            salt: "s".to_string(),
+            scale: Some(dec!(0.5)),
+        };
+        let (out, rows) = anonymize_csv(text, &opts).unwrap();
+        assert_eq!(rows, 3);
+        let lines: Vec<Vec<&str>> = out.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(lines[0].join(","), text.lines().next().unwrap());
+        // Both legs of T1 still share a refid, and no original id is left.
+        assert_eq!(lines[1][1], lines[2][1]);
+        assert_ne!(lines[1][1], lines[3][1]);
+        assert!(!out.contains("T1") && !out.contains("L1,"));
+        assert_eq!(lines[1][0].len(), 19);
+        assert_eq!(lines[1][6..], ["-50", "0.25", ""]);
+        // Deterministic for a salt, different across salts.
+        assert_eq!(anonymize_csv(text, &opts).unwrap().0, out);
+        let other = Options {
+            salt: "t".to_string(),
+            ..opts
+        };
+        assert_ne!(anonymize_csv(text, &other).unwrap().0, out);
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{Pool, remove_units_at_acb};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// Lot refids to take first, by disposal refid.
+pub type LotSelections = BTreeMap<Arc<str>, Vec<Arc<str>>>;
+
+// One acquisition, kept apart by the lot-based methods.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lot {
+    pub time: NaiveDateTime,
+    pub refid: Arc<str>,
+    pub units: Decimal,
+    pub cost_cad: Decimal,
+}
+
+// What a disposal took from a pool. `lots` holds the portion of each lot
+// taken, and is empty under average cost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Taken {
+    pub cost_cad: Decimal,
+    pub lots: Vec<Lot>,
+}
+
+// Which cost leaves a pool with the units disposed of. Implementations keep
+// the pool's `units` and `acb_cad` totals, and its lots if they use any; the
+// event loop only calls these two methods.
+pub trait CostBasisEngine: fmt::Debug + Send + Sync {
+    // The name `--cost-basis` selects it by.
+    fn name(&self) -> &'static str;
+
+    // Adds `units` that cost `cost_cad`, acquired at `time` under `refid`.
+    fn acquire(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        cost_cad: Decimal,
+        time: NaiveDateTime,
+        refid: &Arc<str>,
+    );
+
+    // Removes `units` disposed of under `refid` and returns their cost, with
+    // the lot portions it came from. `ctx` names the event in errors.
+    fn dispose(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        refid: &str,
+        ctx: &str,
+    ) -> Result<Taken, Box<dyn Error>>;
+}
+
+// Canadian adjusted cost base: every unit costs the pool's average.
+#[derive(Debug)]
+pub struct AverageCost;
+
+impl CostBasisEngine for AverageCost {
+    fn name(&self) -> &'static str {
+        "average"
+    }
+
+    fn acquire(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        cost_cad: Decimal,
+        _time: NaiveDateTime,
+        _refid: &Arc<str>,
+    ) {
+        pool.units += units;
+        pool.acb_cad += cost_cad;
+    }
+
+    fn dispose(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        _refid: &str,
+        ctx: &str,
+    ) -> Result<Taken, Box<dyn Error>> {
+        Ok(Taken {
+            cost_cad: remove_units_at_acb(pool, units, ctx)?,
+            lots: Vec::new(),
+        })
+    }
+}
+
+// First in, first out.
+#[derive(Debug)]
+pub struct Fifo;
+
+impl CostBasisEngine for Fifo {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn acquire(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        cost_cad: Decimal,
+        time: NaiveDateTime,
+        refid: &Arc<str>,
+    ) {
+        push_lot(pool, units, cost_cad, time, refid);
+    }
+
+    fn dispose(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        _refid: &str,
+        ctx: &str,
+    ) -> Result<Taken, Box<dyn Error>> {
+        let order = (0..pool.lots.len()).collect();
+        take_lots(pool, units, order, ctx)
+    }
+}
+
+// Highest cost per unit first; equal costs go oldest first.
+#[derive(Debug)]
+pub struct Hifo;
+
+impl CostBasisEngine for Hifo {
+    fn name(&self) -> &'static str {
+        "hifo"
+    }
+
+    fn acquire(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        cost_cad: Decimal,
+        time: NaiveDateTime,
+        refid: &Arc<str>,
+    ) {
+        push_lot(pool, units, cost_cad, time, refid);
+    }
+
+    fn dispose(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        _refid: &str,
+        ctx: &str,
+    ) -> Result<Taken, Box<dyn Error>> {
+        let mut order: Vec<usize> = (0..pool.lots.len()).collect();
+        let unit_cost = |i: &usize| pool.lots[*i].cost_cad / pool.lots[*i].units;
+        order.sort_by(|a, b| unit_cost(b).cmp(&unit_cost(a)).then(a.cmp(b)));
+        take_lots(pool, units, order, ctx)
+    }
+}
+
+// Specific identification: a disposal takes the lots its refid is mapped to,
+// in the order listed, then the oldest remaining lots.
+#[derive(Debug, Default)]
+pub struct SpecificId {
+    pub selections: LotSelections,
+}
+
+impl CostBasisEngine for SpecificId {
+    fn name(&self) -> &'static str {
+        "spec-id"
+    }
+
+    fn acquire(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        cost_cad: Decimal,
+        time: NaiveDateTime,
+        refid: &Arc<str>,
+    ) {
+        push_lot(pool, units, cost_cad, time, refid);
+    }
+
+    fn dispose(
+        &self,
+        pool: &mut Pool,
+        units: Decimal,
+        refid: &str,
+        ctx: &str,
+    ) -> Result<Taken, Box<dyn Error>> {
+        let mut order = Vec::new();
+        for lot_refid in self.selections.get(refid).into_iter().flatten() {
+            let before = order.len();
+            order.extend((0..pool.lots.len()).filter(|i| pool.lots[*i].refid == *lot_refid));
+            if order.len() == before {
+                return Err(format!("{}: lot {} is not in the pool", ctx, lot_refid).into());
+            }
+        }
+        let rest: Vec<usize> = (0..pool.lots.len())
+            .filter(|i| !order.contains(i))
+            .collect();
+        order.extend(rest);
+        take_lots(pool, units, order, ctx)
+    }
+}
+
+// The names `--cost-basis` accepts.
+pub(crate) const METHODS: [&str; 4] = ["average", "fifo", "hifo", "spec-id"];
+
+// The method `name` selects; `acb` is another name for `average`.
+pub(crate) fn method_name(name: &str) -> Option<&'static str> {
+    match name.trim().to_lowercase().as_str() {
+        "acb" => Some("average"),
+        other => METHODS.iter().find(|m| **m == other).copied(),
+    }
+}
+
+// The engine `name` selects; `spec-id` takes `selections`.
+pub(crate) fn by_name(
+    name: &str,
+    selections: Option<LotSelections>,
+) -> Result<Arc<dyn CostBasisEngine>, Box<dyn Error>> {
+    match (name.trim().to_lowercase().as_str(), selections) {
+        ("average" | "acb", _) => Ok(Arc::new(AverageCost)),
+        ("fifo", _) => Ok(Arc::new(Fifo)),
+        ("hifo", _) => Ok(Arc::new(Hifo)),
+        ("spec-id", Some(selections)) => Ok(Arc::new(SpecificId { selections })),
+        ("spec-id", None) => Err("--cost-basis spec-id needs --lot-selection <file>".into()),
+        (other, _) => Err(format!("unknown cost basis method: {}", other).into()),
+    }
+}
+
+// Lot selection file, one disposal per line: `refid = lot_refid, lot_refid`;
+// `#` starts a comment.
+pub(crate) fn parse_selections(text: &str) -> Result<LotSelections, Box<dyn Error>> {
+    let mut out = LotSelections::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (refid, lots) = line.split_once('=').ok_or_else(|| {
+            format!(
+                "lot selection line {}: expected refid = lot_refid, ...",
+                i + 1
+            )
+        })?;
+        let lots: Vec<Arc<str>> = lots
+            .split(',')
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(Arc::from)
+            .collect();
+        if lots.is_empty() {
+            return Err(format!("lot selection line {}: no lots listed", i + 1).into());
+        }
+        if out.insert(Arc::from(refid.trim()), lots).is_some() {
+            return Err(format!(
+                "lot selection line {}: {} is listed twice",
+                i + 1,
+                refid.trim()
+            )
+            .into());
+        }
+    }
+    Ok(out)
+}
+
+fn push_lot(
+    pool: &mut Pool,
+    units: Decimal,
+    cost_cad: Decimal,
+    time: NaiveDateTime,
+    refid: &Arc<str>,
+) {
+    pool.units += units;
+    pool.acb_cad += cost_cad;
+    pool.lots.push(Lot {
+        time,
+        refid: refid.clone(),
+        units,
+        cost_cad,
+    });
+}
+
+// Takes `units` from the lots at the `order` indexes, each at its own cost
+// per unit, and returns the cost taken.
+fn take_lots(
+    pool: &mut Pool,
+    units: Decimal,
+    order: Vec<usize>,
+    ctx: &str,
+) -> Result<Taken, Box<dyn Error>> {
+    if units < Decimal::ZERO {
+        return Err(format!("negative removal units in {}", ctx).into());
+    }
+    if units > pool.units {
+        return Err(format!(
+            "insufficient units in {}: remove={}, pool={}",
+            ctx, units, pool.units
+        )
+        .into());
+    }
+    let mut left = units;
+    let mut cost = Decimal::ZERO;
+    let mut taken = Vec::new();
+    for i in order {
+        if left.is_zero() {
+            break;
+        }
+        let lot = &mut pool.lots[i];
+        let take = left.min(lot.units);
+        let take_cost = if take == lot.units {
+            lot.cost_cad
+        } else {
+            lot.cost_cad * take / lot.units
+        };
+        lot.units -= take;
+        lot.cost_cad -= take_cost;
+        left -= take;
+        cost += take_cost;
+        taken.push(Lot {
+            units: take,
+            cost_cad: take_cost,
+            ..lot.clone()
+        });
+    }
+    if !left.is_zero() {
+        return Err(format!("{}: the pool's lots hold fewer units than it does", ctx).into());
+    }
+    pool.lots.retain(|l| !l.units.is_zero());
+    pool.units -= units;
+    pool.acb_cad -= cost;
+    if pool.units.is_zero() {
+        pool.acb_cad = Decimal::ZERO;
+        pool.lots.clear();
+    }
+    Ok(Taken {
+        cost_cad: cost,
+        lots: taken,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn lot_methods_pick_their_own_lots() {
+        let time = |s: &str| crate::parse_time(s).unwrap();
+        let buy = |engine: &dyn CostBasisEngine| {
+            let mut pool = Pool::default();
+            engine.acquire(
+                &mut pool,
+                dec!(1),
+                dec!(100),
+                time("2025-01-01 00:00:00"),
+                &"B1".into(),
+            );
+            engine.acquire(
+                &mut pool,
+                dec!(1),
+                dec!(300),
+                time("2025-02-01 00:00:00"),
+                &"B2".into(),
+            );
+            engine.acquire(
+                &mut pool,
+                dec!(2),
+                dec!(400),
+                time("2025-03-01 00:00:00"),
+                &"B3".into(),
+            );
+            pool
+        };
+        let cost = |engine: &dyn CostBasisEngine, refid: &str| {
+            let mut pool = buy(engine);
+            let taken = engine.dispose(&mut pool, dec!(1.5), refid, "test").unwrap();
+            (taken.cost_cad, pool)
+        };
+
+        assert_eq!(cost(&AverageCost, "S1").0, dec!(300));
+        let (fifo, pool) = cost(&Fifo, "S1");
+        assert_eq!(fifo, dec!(250));
+        assert_eq!((pool.units, pool.acb_cad), (dec!(2.5), dec!(550)));
+        assert_eq!(pool.lots[0].refid.as_ref(), "B2");
+        assert_eq!(cost(&Hifo, "S1").0, dec!(400));
+
+        let spec = SpecificId {
+            selections: parse_selections("S1 = B3 # the March buy\n").unwrap(),
+        };
+        assert_eq!(cost(&spec, "S1").0, dec!(300));
+        assert_eq!(cost(&spec, "S2").0, dec!(250));
+        let wrong = SpecificId {
+            selections: parse_selections("S1 = B9\n").unwrap(),
+        };
+        assert!(
+            wrong
+                .dispose(&mut buy(&wrong), dec!(1), "S1", "test")
+                .is_err()
+        );
+        assert!(
+            Fifo.dispose(&mut buy(&Fifo), dec!(5), "S1", "test")
+                .is_err()
+        );
+        assert!(by_name("spec-id", None).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{
+    CsvLocale, CsvStyle, DEFAULT_FALLBACK_FX, Interner, ProcessOptions, build_events,
+    build_trade_groups, load_entries, positional_args, process, read_input_text, take_flag,
+    write_report,
+};
+use chrono::{Datelike, Duration, NaiveDate};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::Instant;
+
+const DEFAULT_RUNS: usize = 3;
+const SYNTHETIC_ASSETS: [&str; 4] = ["BTC", "ETH", "SOL", "DOT"];
+
+// A stage's name and its best time.
+type Stage = (&'static str, std::time::Duration);
+
+// A ledger of `rows` rows (rounded down to whole trades) over 2025: each
+// asset is bought twice and sold once in turn, against CAD, at prices that
+// drift so gains and losses both occur.
+fn synthetic_ledger(rows: usize) -> String {
+    let trades = rows / 2;
+    let start = NaiveDate::from_ymd_opt(2025, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .expect("valid start");
+    let step = (365 * 86_400 / trades.max(1) as i64).max(1);
+    let mut out = String::from("txid,refid,time,type,subtype,asset,amount,fee\n");
+    for i in 0..trades {
+        let asset = SYNTHETIC_ASSETS[i % SYNTHETIC_ASSETS.len()];
+        let time = (start + Duration::seconds(i as i64 * step)).format("%Y-%m-%d %H:%M:%S");
+        let price = 100 + (i * 7) % 50;
+        let sign = if (i / SYNTHETIC_ASSETS.len()) % 3 == 2 {
+            -1
+        } else {
+            1
+        };
+        let _ = writeln!(out, "L{i}A,T{i},{time},trade,tradespot,{asset},{},0", sign);
+        let _ = writeln!(
+            out,
+            "L{i}B,T{i},{time},trade,tradespot,CAD,{},0.1",
+            -sign * price as i64
+        );
+    }
+    out
+}
+
+// Best time of `runs` calls of `f`, and its last result.
+fn best<T>(
+    runs: usize,
+    mut f: impl FnMut() -> Result<T, Box<dyn Error>>,
+) -> Result<(std::time::Duration, T), Box<dyn Error>> {
+    let mut best = None;
+    let mut out = None;
+    for _ in 0..runs {
+        let started = Instant::now();
+        out = Some(f()?);
+        let took = started.elapsed();
+        best = Some(best.map_or(took, |b: std::time::Duration| b.min(took)));
+    }
+    Ok((best.expect("runs > 0"), out.expect("runs > 0")))
+}
+
+// Parses, groups, processes and writes `text` `runs` times each, and returns
+// the best time of each stage with the ledger and report row counts.
+fn measure(text: &str, runs: usize) -> Result<(Vec<Stage>, usize, usize), Box<dyn Error>> {
+    let (parse, entries) = best(runs, || {
+        load_entries(text, CsvLocale::default(), &mut Interner::default())
+    })?;
+    let tax_year = entries
+        .iter()
+        .map(|e| e.time.year())
+        .max()
+        .ok_or("the ledger has no rows")?;
+    let opts = ProcessOptions::new(tax_year, Decimal::from_str(DEFAULT_FALLBACK_FX)?);
+    let (group, _) = best(runs, || {
+        let groups = build_trade_groups(&entries, &*opts.jurisdiction, tax_year)?;
+        Ok(build_events(&entries, &groups, &[], &opts).len())
+    })?;
+    let (processing, output) = best(runs, || process(entries.clone(), &opts))?;
+    let path = std::env::temp_dir().join(format!("kraken_acb_bench_{}.csv", std::process::id()));
+    let path = path.to_str().ok_or("temporary path is not UTF-8")?;
+    let written = best(runs, || {
+        write_report(path, &output.report, None, CsvStyle::default())
+    });
+    let _ = std::fs::remove_file(path);
+    let (write, ()) = written?;
+    let stages = vec![
+        ("parse", parse),
+        ("group", group),
+        ("process", processing),
+        ("write", write),
+    ];
+    Ok((stages, entries.len(), output.report.len()))
+}
+
+// `bench [<ledger.csv> | --synthetic <rows>] [--runs N]`: times each stage of
+// a run and prints its throughput in ledger rows per second.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let count = |name: &str, value: String| {
+        value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("--{} expects a positive count, got {:?}", name, value))
+    };
+    let synthetic = match take_flag(&mut raw, "synthetic")? {
+        Some(value) => Some(count("synthetic", value)?),
+        None => None,
+    };
+    let runs = match take_flag(&mut raw, "runs")? {
+        Some(value) => count("runs", value)?,
+        None => DEFAULT_RUNS,
+    };
+    let mut inputs = positional_args("bench", raw)?;
+    if inputs.len() > 1 {
+        return Err("bench takes one input file".into());
+    }
+    let (text, source) = match (inputs.pop(), synthetic) {
+        (Some(path), None) => (read_input_text(&path)?, path),
+        (None, Some(rows)) => (synthetic_ledger(rows), "synthetic".to_string()),
+        _ => return Err("bench takes a ledger file or --synthetic <rows>".into()),
+    };
+
+    let (stages, rows, report_rows) = measure(&text, runs)?;
+    println!(
+        "Benchmark: {} ledger rows ({}), {} report rows, best of {} runs",
+        rows, source, report_rows, runs
+    );
+    let total = stages.iter().map(|(_, took)| *took).sum();
+    let per_second = |took: std::time::Duration| rows as f64 / took.as_secs_f64().max(1e-9);
+    for (name, took) in stages.into_iter().chain([("total", total)]) {
+        println!(
+            "  {:<8} {:>10.1} ms {:>14.0} rows/s",
+            name,
+            took.as_secs_f64() * 1000.0,
+            per_second(took)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_ledgers_run_through_every_stage() {
+        let text = synthetic_ledger(240);
+        assert_eq!(text.lines().count(), 241);
+        let (stages, rows, report_rows) = measure(&text, 1).unwrap();
+        let names: Vec<&str> = stages.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["parse", "group", "process", "write"]);
+        assert_eq!((rows, report_rows), (240, 120));
+    }
+}
@@ -0,0 +1,775 @@
+use crate::credentials::{api_key, redact};
+use crate::http;
+use crate::{LedgerEntry, create_output, positional_args, sort_entries, summary_writer, take_flag};
+use bitcoin::bip32::{ChildNumber, Xpub};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::{Address, CompressedPublicKey, Network, NetworkKind, base58};
+use chrono::DateTime;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::io::Write;
+
+const ETHERSCAN_API_URL: &str = "https://api.etherscan.io/v2/api";
+const ESPLORA_API_URL: &str = "https://blockstream.info/api";
+const DEFAULT_GAP_LIMIT: u32 = 20;
+// Esplora returns confirmed history in pages of this many transactions.
+const ESPLORA_PAGE_SIZE: usize = 25;
+
+// Kraken ledger columns, so chain imports feed back in through `--extra-input kraken:<file>`.
+#[derive(Debug, Serialize)]
+struct LedgerCsvRow<'a> {
+    txid: &'a str,
+    refid: &'a str,
+    time: String,
+    #[serde(rename = "type")]
+    row_type: &'a str,
+    subtype: &'a str,
+    asset: &'a str,
+    amount: String,
+    fee: String,
+}
+
+pub(crate) fn write_ledger_csv(path: &str, entries: &[LedgerEntry]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(create_output(path)?);
+    for e in entries {
+        wtr.serialize(LedgerCsvRow {
+            txid: &e.txid,
+            refid: &e.refid,
+            time: e.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            row_type: &e.row_type,
+            subtype: &e.subtype,
+            asset: &e.asset,
+            amount: e.amount.normalize().to_string(),
+            fee: e.fee.normalize().to_string(),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Places the decimal point in an integer base-unit string (wei, satoshi, token units).
+pub(crate) fn scale_units(raw: &str, decimals: u32) -> Result<Decimal, Box<dyn Error>> {
+    let digits = raw.trim().trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(dec!(0));
+    }
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("bad integer amount: {}", raw).into());
+    }
+    let decimals = decimals as usize;
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (int, frac) = padded.split_at(padded.len() - decimals);
+    Ok(Decimal::from_str(&format!("{}.{}", int, frac))
+        .or_else(|_| Decimal::from_str(int))?
+        .normalize())
+}
+
+#[derive(Debug, Deserialize)]
+struct EtherscanResponse {
+    status: String,
+    message: String,
+    result: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EthTx {
+    time_stamp: String,
+    hash: String,
+    from: String,
+    to: String,
+    value: String,
+    #[serde(default)]
+    gas_price: String,
+    #[serde(default)]
+    gas_used: String,
+    #[serde(default)]
+    is_error: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenTx {
+    time_stamp: String,
+    hash: String,
+    from: String,
+    to: String,
+    value: String,
+    token_symbol: String,
+    token_decimal: String,
+}
+
+fn fetch_etherscan<T: for<'de> Deserialize<'de>>(
+    api_url: &str,
+    chain_id: &str,
+    action: &str,
+    address: &str,
+    api_key: &str,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let query = [
+        ("chainid", chain_id),
+        ("module", "account"),
+        ("action", action),
+        ("address", address),
+        ("startblock", "0"),
+        ("endblock", "99999999"),
+        ("sort", "asc"),
+        ("apikey", api_key),
+    ];
+    let resp: EtherscanResponse = http::get(api_url, &query)
+        // ureq's errors quote the request URL, key included.
+        .map_err(|e| redact(&e.to_string(), api_key))?
+        .into_json()?;
+    if resp.status != "1" {
+        if resp.message.starts_with("No transactions found") {
+            return Ok(Vec::new());
+        }
+        let msg = format!(
+            "etherscan {} failed: {} {}",
+            action, resp.message, resp.result
+        );
+        return Err(redact(&msg, api_key).into());
+    }
+    Ok(serde_json::from_value(resp.result)?)
+}
+
+fn chain_entry(
+    hash: &str,
+    time: chrono::NaiveDateTime,
+    row_type: &str,
+    subtype: &str,
+    asset: &str,
+    amount: Decimal,
+    fee: Decimal,
+) -> LedgerEntry {
+    LedgerEntry {
+        txid: format!("{}-{}", hash, asset).into(),
+        refid: hash.into(),
+        time,
+        row_type: row_type.to_string(),
+        subtype: subtype.to_string(),
+        asset: asset.into(),
+        amount,
+        fee,
+        net_delta: amount - fee,
+        balance: None,
+        wallet: None,
+        utc_offset: None,
+    }
+}
+
+// Nets each transaction hash to per-asset deltas: one asset in and one out is
+// a swap (`trade`), a single asset is a deposit/withdrawal. Gas paid by the
+// address rides on an ETH leg when there is one, else it is a `spend/gas` row.
+fn ethereum_entries(
+    address: &str,
+    txs: &[EthTx],
+    token_txs: &[TokenTx],
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let address = address.to_lowercase();
+    let mut by_hash: BTreeMap<String, (i64, BTreeMap<String, Decimal>, Decimal)> = BTreeMap::new();
+
+    for tx in txs {
+        let ts: i64 = tx.time_stamp.parse()?;
+        let slot = by_hash
+            .entry(tx.hash.clone())
+            .or_insert((ts, BTreeMap::new(), dec!(0)));
+        let from_me = tx.from.to_lowercase() == address;
+        let to_me = tx.to.to_lowercase() == address;
+        if from_me && !tx.gas_used.is_empty() {
+            let gas: u128 = tx.gas_used.parse::<u128>()? * tx.gas_price.parse::<u128>()?;
+            slot.2 += scale_units(&gas.to_string(), 18)?;
+        }
+        if tx.is_error == "1" {
+            continue;
+        }
+        let value = scale_units(&tx.value, 18)?;
+        let delta = match (from_me, to_me) {
+            (true, false) => -value,
+            (false, true) => value,
+            _ => dec!(0),
+        };
+        *slot.1.entry("ETH".to_string()).or_default() += delta;
+    }
+    for tx in token_txs {
+        let ts: i64 = tx.time_stamp.parse()?;
+        let slot = by_hash
+            .entry(tx.hash.clone())
+            .or_insert((ts, BTreeMap::new(), dec!(0)));
+        let value = scale_units(&tx.value, tx.token_decimal.parse()?)?;
+        let delta = match (
+            tx.from.to_lowercase() == address,
+            tx.to.to_lowercase() == address,
+        ) {
+            (true, false) => -value,
+            (false, true) => value,
+            _ => dec!(0),
+        };
+        *slot
+            .1
+            .entry(tx.token_symbol.trim().to_uppercase())
+            .or_default() += delta;
+    }
+
+    let mut out = Vec::new();
+    for (hash, (ts, deltas, mut gas)) in by_hash {
+        let time = DateTime::from_timestamp(ts, 0)
+            .ok_or_else(|| format!("bad timestamp {} for {}", ts, hash))?
+            .naive_utc();
+        let legs: Vec<_> = deltas.into_iter().filter(|(_, d)| !d.is_zero()).collect();
+        let is_swap = legs.len() == 2 && (legs[0].1 > dec!(0)) != (legs[1].1 > dec!(0));
+        for (asset, delta) in &legs {
+            let row_type = if is_swap {
+                "trade"
+            } else if *delta > dec!(0) {
+                "deposit"
+            } else {
+                "withdrawal"
+            };
+            // Gas is only attached to an outgoing or traded ETH leg.
+            let fee = if asset == "ETH" && (is_swap || *delta < dec!(0)) {
+                std::mem::take(&mut gas)
+            } else {
+                dec!(0)
+            };
+            out.push(chain_entry(&hash, time, row_type, "", asset, *delta, fee));
+        }
+        if gas > dec!(0) {
+            let mut e = chain_entry(&hash, time, "spend", "gas", "ETH", -gas, dec!(0));
+            e.txid = format!("{}-GAS", hash).into();
+            out.push(e);
+        }
+    }
+
+    sort_entries(&mut out);
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScriptKind {
+    Pkh,
+    ShWpkh,
+    Wpkh,
+    Tr,
+}
+
+// A single-key wallet: the account xpub plus the unhardened paths (usually
+// receive `0` and change `1`) whose children are the addresses.
+#[derive(Debug)]
+struct BtcWallet {
+    kind: ScriptKind,
+    xpub: Xpub,
+    branches: Vec<Vec<ChildNumber>>,
+}
+
+// SLIP-132 version bytes mapped to the standard xpub/tpub prefix and the
+// script type they imply.
+const SLIP132_VERSIONS: &[([u8; 4], [u8; 4], ScriptKind)] = &[
+    (
+        [0x04, 0x88, 0xb2, 0x1e],
+        [0x04, 0x88, 0xb2, 0x1e],
+        ScriptKind::Pkh,
+    ),
+    (
+        [0x04, 0x9d, 0x7c, 0xb2],
+        [0x04, 0x88, 0xb2, 0x1e],
+        ScriptKind::ShWpkh,
+    ),
+    (
+        [0x04, 0xb2, 0x47, 0x46],
+        [0x04, 0x88, 0xb2, 0x1e],
+        ScriptKind::Wpkh,
+    ),
+    (
+        [0x04, 0x35, 0x87, 0xcf],
+        [0x04, 0x35, 0x87, 0xcf],
+        ScriptKind::Pkh,
+    ),
+    (
+        [0x04, 0x4a, 0x52, 0x62],
+        [0x04, 0x35, 0x87, 0xcf],
+        ScriptKind::ShWpkh,
+    ),
+    (
+        [0x04, 0x5f, 0x1c, 0xf6],
+        [0x04, 0x35, 0x87, 0xcf],
+        ScriptKind::Wpkh,
+    ),
+];
+
+fn parse_extended_key(s: &str) -> Result<(Xpub, ScriptKind), Box<dyn Error>> {
+    let mut data = base58::decode_check(s.trim())
+        .map_err(|e| format!("bad extended public key {}: {}", s, e))?;
+    if data.len() < 4 {
+        return Err(format!("bad extended public key {}", s).into());
+    }
+    let (standard, kind) = SLIP132_VERSIONS
+        .iter()
+        .find(|(v, _, _)| data[..4] == v[..])
+        .map(|(_, std, kind)| (*std, *kind))
+        .ok_or_else(|| format!("unsupported extended key version: {}", s))?;
+    data[..4].copy_from_slice(&standard);
+    Ok((Xpub::decode(&data)?, kind))
+}
+
+fn parse_branch_path(path: &str) -> Result<Vec<Vec<ChildNumber>>, Box<dyn Error>> {
+    let mut branches = vec![Vec::new()];
+    let steps: Vec<_> = path.split('/').filter(|p| !p.is_empty()).collect();
+    match steps.last() {
+        Some(&"*") => {}
+        _ => return Err(format!("descriptor path must end in /*: {}", path).into()),
+    }
+    for step in &steps[..steps.len() - 1] {
+        let values: Vec<u32> = match step.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            Some(multi) => multi.split(';').map(str::parse).collect::<Result<_, _>>()?,
+            None => vec![
+                step.parse()
+                    .map_err(|_| format!("unsupported descriptor path step: {}", step))?,
+            ],
+        };
+        branches = branches
+            .iter()
+            .flat_map(|b| {
+                values.iter().map(move |v| {
+                    let mut b = b.clone();
+                    b.push(ChildNumber::Normal { index: *v });
+                    b
+                })
+            })
+            .collect();
+    }
+    Ok(branches)
+}
+
+// Accepts a bare xpub/ypub/zpub (or testnet tpub/upub/vpub), or a single-key
+// descriptor: `pkh(...)`, `sh(wpkh(...))`, `wpkh(...)` or `tr(...)` around
+// `[origin]xpub/<path>/*`.
+fn parse_btc_wallet(s: &str) -> Result<BtcWallet, Box<dyn Error>> {
+    let s = s.split('#').next().unwrap_or("").trim();
+    let wrappers = [
+        ("sh(wpkh(", "))", ScriptKind::ShWpkh),
+        ("wpkh(", ")", ScriptKind::Wpkh),
+        ("pkh(", ")", ScriptKind::Pkh),
+        ("tr(", ")", ScriptKind::Tr),
+    ];
+    let descriptor = wrappers.iter().find_map(|(open, close, kind)| {
+        s.strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+            .map(|inner| (inner, *kind))
+    });
+    let Some((inner, kind)) = descriptor else {
+        let (xpub, kind) = parse_extended_key(s)?;
+        return Ok(BtcWallet {
+            kind,
+            xpub,
+            branches: vec![
+                vec![ChildNumber::Normal { index: 0 }],
+                vec![ChildNumber::Normal { index: 1 }],
+            ],
+        });
+    };
+    let key = match inner.strip_prefix('[') {
+        Some(rest) => {
+            rest.split_once(']')
+                .ok_or_else(|| format!("unterminated key origin in {}", s))?
+                .1
+        }
+        None => inner,
+    };
+    let (key, path) = key.split_once('/').unwrap_or((key, ""));
+    let branches = if path.is_empty() {
+        vec![
+            vec![ChildNumber::Normal { index: 0 }],
+            vec![ChildNumber::Normal { index: 1 }],
+        ]
+    } else {
+        parse_branch_path(path)?
+    };
+    Ok(BtcWallet {
+        kind,
+        xpub: parse_extended_key(key)?.0,
+        branches,
+    })
+}
+
+fn derive_btc_address<C: Verification>(
+    secp: &Secp256k1<C>,
+    wallet: &BtcWallet,
+    branch: &[ChildNumber],
+    index: u32,
+) -> Result<String, Box<dyn Error>> {
+    let mut path = branch.to_vec();
+    path.push(ChildNumber::Normal { index });
+    let child = wallet.xpub.derive_pub(secp, &path)?;
+    let network = match wallet.xpub.network {
+        NetworkKind::Main => Network::Bitcoin,
+        NetworkKind::Test => Network::Testnet,
+    };
+    let pk = CompressedPublicKey(child.public_key);
+    let address = match wallet.kind {
+        ScriptKind::Pkh => Address::p2pkh(pk, network),
+        ScriptKind::ShWpkh => Address::p2shwpkh(&pk, network),
+        ScriptKind::Wpkh => Address::p2wpkh(&pk, network),
+        ScriptKind::Tr => Address::p2tr(secp, child.to_x_only_pub(), None, network),
+    };
+    Ok(address.to_string())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraStatus {
+    confirmed: bool,
+    #[serde(default)]
+    block_time: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraOutput {
+    #[serde(default)]
+    scriptpubkey_address: Option<String>,
+    value: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraInput {
+    #[serde(default)]
+    prevout: Option<EsploraOutput>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EsploraTx {
+    txid: String,
+    status: EsploraStatus,
+    #[serde(default)]
+    fee: u64,
+    vin: Vec<EsploraInput>,
+    vout: Vec<EsploraOutput>,
+}
+
+fn fetch_esplora_txs(api_url: &str, address: &str) -> Result<Vec<EsploraTx>, Box<dyn Error>> {
+    let mut out: Vec<EsploraTx> = Vec::new();
+    loop {
+        let url = match out.last() {
+            Some(last) => format!("{}/address/{}/txs/chain/{}", api_url, address, last.txid),
+            None => format!("{}/address/{}/txs/chain", api_url, address),
+        };
+        let page: Vec<EsploraTx> = http::get(&url, &[])?.into_json()?;
+        let done = page.len() < ESPLORA_PAGE_SIZE;
+        out.extend(page);
+        if done {
+            return Ok(out);
+        }
+    }
+}
+
+// Walks each branch until `gap_limit` consecutive addresses have no history.
+fn scan_btc_wallet(
+    wallet: &BtcWallet,
+    api_url: &str,
+    gap_limit: u32,
+) -> Result<(HashSet<String>, Vec<EsploraTx>), Box<dyn Error>> {
+    let secp = Secp256k1::verification_only();
+    let mut addresses = HashSet::new();
+    let mut txs: HashMap<String, EsploraTx> = HashMap::new();
+    for branch in &wallet.branches {
+        let mut unused = 0;
+        let mut index = 0;
+        while unused < gap_limit {
+            let address = derive_btc_address(&secp, wallet, branch, index)?;
+            let history = fetch_esplora_txs(api_url, &address)?;
+            if history.is_empty() {
+                unused += 1;
+            } else {
+                unused = 0;
+                addresses.insert(address.clone());
+            }
+            for tx in history {
+                txs.entry(tx.txid.clone()).or_insert(tx);
+            }
+            index += 1;
+        }
+    }
+    Ok((addresses, txs.into_values().collect()))
+}
+
+// Nets each confirmed transaction over the wallet's addresses. Receipts are
+// deposits; payments out are withdrawals with the miner fee as the fee, and
+// a payment to ourselves only (consolidation) is a `spend/fee` row.
+fn bitcoin_entries(
+    ours: &HashSet<String>,
+    txs: &[EsploraTx],
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let is_ours = |o: &EsploraOutput| {
+        o.scriptpubkey_address
+            .as_ref()
+            .is_some_and(|a| ours.contains(a))
+    };
+    let mut out = Vec::new();
+    for tx in txs {
+        if !tx.status.confirmed {
+            continue;
+        }
+        let ts = tx
+            .status
+            .block_time
+            .ok_or_else(|| format!("confirmed tx {} has no block time", tx.txid))?;
+        let time = DateTime::from_timestamp(ts, 0)
+            .ok_or_else(|| format!("bad timestamp {} for {}", ts, tx.txid))?
+            .naive_utc();
+        let spent: u64 = tx
+            .vin
+            .iter()
+            .filter_map(|i| i.prevout.as_ref())
+            .filter(|o| is_ours(o))
+            .map(|o| o.value)
+            .sum();
+        let received: u64 = tx.vout.iter().filter(|o| is_ours(o)).map(|o| o.value).sum();
+        let sats = |v: u64| Decimal::new(v as i64, 8);
+
+        if spent == 0 {
+            if received > 0 {
+                out.push(chain_entry(
+                    &tx.txid,
+                    time,
+                    "deposit",
+                    "",
+                    "BTC",
+                    sats(received),
+                    dec!(0),
+                ));
+            }
+            continue;
+        }
+        // Inputs are ours, so the miner fee is ours too.
+        let sent_away = spent.saturating_sub(received).saturating_sub(tx.fee);
+        if sent_away > 0 {
+            out.push(chain_entry(
+                &tx.txid,
+                time,
+                "withdrawal",
+                "",
+                "BTC",
+                -sats(sent_away),
+                sats(tx.fee),
+            ));
+        } else if tx.fee > 0 {
+            out.push(chain_entry(
+                &tx.txid,
+                time,
+                "spend",
+                "fee",
+                "BTC",
+                -sats(tx.fee),
+                dec!(0),
+            ));
+        }
+    }
+    sort_entries(&mut out);
+    Ok(out)
+}
+
+// `import-chain --address <0x...> [--api-url URL] [--chain-id N] [--output file]`, with the
+// Etherscan key from `ETHERSCAN_API_KEY` or the OS keyring
+// or `import-chain --xpub <xpub|descriptor> [--esplora-url URL] [--gap-limit N] [--output file]`
+pub(crate) fn import_chain(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    if take_flag(&mut raw, "api-key")?.is_some() {
+        return Err(
+            "import-chain: --api-key is not accepted, so the key stays out of \
+            shell history; set ETHERSCAN_API_KEY or store it in the OS keyring"
+                .into(),
+        );
+    }
+    let address = take_flag(&mut raw, "address")?;
+    let api_url = take_flag(&mut raw, "api-url")?.unwrap_or_else(|| ETHERSCAN_API_URL.to_string());
+    let chain_id = take_flag(&mut raw, "chain-id")?.unwrap_or_else(|| "1".to_string());
+    let descriptor = take_flag(&mut raw, "descriptor")?;
+    let xpub = take_flag(&mut raw, "xpub")?.or(descriptor);
+    let esplora_url = take_flag(&mut raw, "esplora-url")?.map_or_else(
+        || ESPLORA_API_URL.to_string(),
+        |url| url.trim_end_matches('/').to_string(),
+    );
+    let gap_limit = match take_flag(&mut raw, "gap-limit")? {
+        Some(value) => value.parse()?,
+        None => DEFAULT_GAP_LIMIT,
+    };
+    let output = take_flag(&mut raw, "output")?;
+    if let Some(arg) = positional_args("import-chain", raw)?.first() {
+        return Err(format!("import-chain: unexpected argument {}", arg).into());
+    }
+
+    if let Some(xpub) = xpub {
+        let wallet = parse_btc_wallet(&xpub)?;
+        let output = output.unwrap_or_else(|| "chain_btc.csv".to_string());
+        let (addresses, txs) = scan_btc_wallet(&wallet, &esplora_url, gap_limit)?;
+        let entries = bitcoin_entries(&addresses, &txs)?;
+        write_ledger_csv(&output, &entries)?;
+
+        let mut out = summary_writer(&output);
+        writeln!(
+            out,
+            "Found {} used addresses and {} transactions",
+            addresses.len(),
+            txs.len()
+        )?;
+        writeln!(out, "Wrote {} ledger rows: {}", entries.len(), output)?;
+        return Ok(());
+    }
+
+    let address = address.ok_or("import-chain requires --address or --xpub")?;
+    let api_key = api_key("etherscan", "ETHERSCAN_API_KEY")
+        .ok_or("import-chain requires ETHERSCAN_API_KEY or an `etherscan` key in the OS keyring")?;
+    let output = output.unwrap_or_else(|| format!("chain_{}.csv", address.to_lowercase()));
+
+    let txs: Vec<EthTx> = fetch_etherscan(&api_url, &chain_id, "txlist", &address, &api_key)?;
+    let token_txs: Vec<TokenTx> =
+        fetch_etherscan(&api_url, &chain_id, "tokentx", &address, &api_key)?;
+    let entries = ethereum_entries(&address, &txs, &token_txs)?;
+    write_ledger_csv(&output, &entries)?;
+
+    let mut out = summary_writer(&output);
+    writeln!(
+        out,
+        "Fetched {} transactions and {} token transfers for {}",
+        txs.len(),
+        token_txs.len(),
+        address
+    )?;
+    writeln!(out, "Wrote {} ledger rows: {}", entries.len(), output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ME: &str = "0xAbC0000000000000000000000000000000000001";
+    const OTHER: &str = "0x0000000000000000000000000000000000000002";
+
+    // BIP84 test vector (mnemonic "abandon ... about", account 0).
+    const ZPUB: &str = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+
+    #[test]
+    fn zpub_and_descriptor_derive_bip84_addresses() {
+        let secp = Secp256k1::verification_only();
+        let wallet = parse_btc_wallet(ZPUB).unwrap();
+        assert_eq!(wallet.kind, ScriptKind::Wpkh);
+        assert_eq!(
+            derive_btc_address(&secp, &wallet, &wallet.branches[0], 0).unwrap(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+
+        let (xpub, _) = parse_extended_key(ZPUB).unwrap();
+        let descriptor = format!("wpkh([73c5da0a/84h/0h/0h]{}/<0;1>/*)#abcd", xpub);
+        let wallet = parse_btc_wallet(&descriptor).unwrap();
+        assert_eq!(wallet.branches.len(), 2);
+        assert_eq!(
+            derive_btc_address(&secp, &wallet, &wallet.branches[0], 0).unwrap(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+    }
+
+    #[test]
+    fn bitcoin_receipts_and_payments_become_transfers() {
+        let ours: HashSet<String> = ["bc1qours".to_string(), "bc1qchange".to_string()].into();
+        let out = |addr: &str, value| EsploraOutput {
+            scriptpubkey_address: Some(addr.to_string()),
+            value,
+        };
+        let status = |t| EsploraStatus {
+            confirmed: true,
+            block_time: Some(t),
+        };
+        let txs = vec![
+            EsploraTx {
+                txid: "a1".into(),
+                status: status(1714550400),
+                fee: 500,
+                vin: vec![EsploraInput {
+                    prevout: Some(out("bc1qkraken", 10_000_500)),
+                }],
+                vout: vec![out("bc1qours", 10_000_000)],
+            },
+            EsploraTx {
+                txid: "b2".into(),
+                status: status(1714636800),
+                fee: 1_000,
+                vin: vec![EsploraInput {
+                    prevout: Some(out("bc1qours", 10_000_000)),
+                }],
+                vout: vec![out("bc1qelse", 4_000_000), out("bc1qchange", 5_999_000)],
+            },
+        ];
+
+        let entries = bitcoin_entries(&ours, &txs).unwrap();
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|e| (e.row_type.as_str(), e.amount, e.fee))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("deposit", dec!(0.1), dec!(0)),
+                ("withdrawal", dec!(-0.04), dec!(0.00001)),
+            ]
+        );
+    }
+
+    #[test]
+    fn scale_units_places_decimal_point() {
+        assert_eq!(scale_units("1500000000000000000", 18).unwrap(), dec!(1.5));
+        assert_eq!(scale_units("42", 6).unwrap(), dec!(0.000042));
+        assert_eq!(scale_units("0", 18).unwrap(), dec!(0));
+    }
+
+    #[test]
+    fn ethereum_transfers_and_swaps_become_ledger_rows() {
+        let txs = vec![
+            EthTx {
+                time_stamp: "1714550400".into(),
+                hash: "0xin".into(),
+                from: OTHER.into(),
+                to: ME.to_lowercase(),
+                value: "2000000000000000000".into(),
+                gas_price: "10".into(),
+                gas_used: "21000".into(),
+                is_error: "0".into(),
+            },
+            EthTx {
+                time_stamp: "1714636800".into(),
+                hash: "0xswap".into(),
+                from: ME.to_lowercase(),
+                to: OTHER.into(),
+                value: "1000000000000000000".into(),
+                gas_price: "1000000000".into(),
+                gas_used: "100000".into(),
+                is_error: "0".into(),
+            },
+        ];
+        let token_txs = vec![TokenTx {
+            time_stamp: "1714636800".into(),
+            hash: "0xswap".into(),
+            from: OTHER.into(),
+            to: ME.to_lowercase(),
+            value: "3000000000".into(),
+            token_symbol: "usdc".into(),
+            token_decimal: "6".into(),
+        }];
+
+        let entries = ethereum_entries(ME, &txs, &token_txs).unwrap();
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|e| (e.row_type.as_str(), &*e.asset, e.amount, e.fee))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("deposit", "ETH", dec!(2), dec!(0)),
+                ("trade", "ETH", dec!(-1), dec!(0.0001)),
+                ("trade", "USDC", dec!(3000), dec!(0)),
+            ]
+        );
+        assert_eq!(entries[0].time.to_string(), "2024-05-01 08:00:00");
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{LedgerEntry, Pool, PriceState, ProcessOptions};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+// Pool and price state after every event up to the end of `year`, keyed by a
+// hash of the ledger rows and options that produced it.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) year: i32,
+    entry_count: usize,
+    prefix_hash: String,
+    pub(crate) pools: HashMap<Arc<str>, Pool>,
+    pub(crate) prices: PriceState,
+}
+
+// Hashes the rows processed through `year` together with the options that
+// change pool state, so a checkpoint is only reused for identical history.
+fn prefix_hash(entries: &[LedgerEntry], opts: &ProcessOptions, year: i32) -> (usize, String) {
+    let mut hasher = Sha256::new();
+    hasher.update(
+        format!(
+            "{:?}|{:?}|{:?}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}\n",
+            opts.fee_mode,
+            opts.rebate_mode,
+            opts.adjustment_mode,
+            opts.donation_proceeds,
+            opts.fallback_fx,
+            opts.migrations,
+            opts.wraps,
+            opts.deemed_disposition,
+            opts.timezone,
+            opts.income_valuation,
+            opts.price_chain,
+            opts.cost_basis,
+            opts.jurisdiction,
+            opts.pooling,
+            opts.tie_break,
+        )
+        .as_bytes(),
+    );
+    let mut count = 0;
+    for e in entries
+        .iter()
+        .filter(|e| opts.jurisdiction.tax_year(e.time) <= year)
+    {
+        hasher.update(
+            format!(
+                "{}|{}|{}|{}|{}|{}|{}|{}\n",
+                e.txid, e.refid, e.time, e.row_type, e.subtype, e.asset, e.amount, e.fee
+            )
+            .as_bytes(),
+        );
+        // Fills and closes change prices; later ones do not touch the prefix.
+        if let Some(fill) = opts.trade_fills.get(&e.refid) {
+            hasher.update(format!("{:?}\n", fill).as_bytes());
+        }
+        if let Some(close) = opts.daily_closes.get(&(e.asset.clone(), e.time.date())) {
+            hasher.update(format!("{:?}\n", close).as_bytes());
+        }
+        count += 1;
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    (count, hash)
+}
+
+pub(crate) fn load(path: &str) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(serde_json::from_str(&text)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn save(
+    path: &str,
+    entries: &[LedgerEntry],
+    opts: &ProcessOptions,
+    pools: &HashMap<Arc<str>, Pool>,
+    prices: &PriceState,
+) -> Result<(), Box<dyn Error>> {
+    let (entry_count, prefix_hash) = prefix_hash(entries, opts, opts.tax_year);
+    let cp = Checkpoint {
+        year: opts.tax_year,
+        entry_count,
+        prefix_hash,
+        pools: pools.clone(),
+        prices: prices.clone(),
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&cp)?)?;
+    Ok(())
+}
+
+// Number of leading (time-sorted) entries the checkpoint already covers, if
+// it applies to this ledger and these options.
+pub(crate) fn covered_prefix(
+    cp: &Checkpoint,
+    entries: &[LedgerEntry],
+    opts: &ProcessOptions,
+) -> Option<usize> {
+    if cp.year >= opts.tax_year {
+        return None;
+    }
+    let (count, hash) = prefix_hash(entries, opts, cp.year);
+    (count == cp.entry_count && hash == cp.prefix_hash).then_some(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::process;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn resumed_run_matches_full_replay() {
+        let entries = vec![
+            leg("R1", "2023-05-01 00:00:00", "CAD", dec!(-100)),
+            leg("R1", "2023-05-01 00:00:00", "SOL", dec!(1)),
+            leg("R2", "2025-02-01 00:00:00", "SOL", dec!(-0.5)),
+            leg("R2", "2025-02-01 00:00:00", "CAD", dec!(80)),
+        ];
+        let path = std::env::temp_dir().join(format!("kraken_acb_cp_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let opts_2024 = ProcessOptions::new(2024, dec!(1.4));
+        let out = process(entries.clone(), &opts_2024).unwrap();
+        save(path, &entries, &opts_2024, &out.pools, &out.prices).unwrap();
+
+        let cp = load(path).unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+        let opts_2025 = ProcessOptions::new(2025, dec!(1.4));
+        let skip = covered_prefix(&cp, &entries, &opts_2025).unwrap();
+        assert_eq!(skip, 2);
+        let resumed = process(
+            entries[skip..].to_vec(),
+            &ProcessOptions {
+                start_pools: cp.pools,
+                start_prices: cp.prices,
+                ..opts_2025.clone()
+            },
+        )
+        .unwrap();
+        let full = process(entries, &opts_2025).unwrap();
+        assert_eq!(resumed.totals.capital_gain_cad, dec!(30));
+        assert_eq!(
+            resumed.totals.capital_gain_cad,
+            full.totals.capital_gain_cad
+        );
+        assert_eq!(resumed.pools["SOL"].acb_cad, full.pools["SOL"].acb_cad);
+    }
+}
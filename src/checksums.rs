@@ -0,0 +1,113 @@
+use crate::meta::InputFile;
+use crate::{STDIO_PATH, create_output, read_input_text};
+use std::error::Error;
+use std::io::Write;
+
+// `--checksums`: writes the SHA-256 of every file a run read and wrote to
+// `path`, one `<hash>  <path>` line each, the format `sha256sum -c` checks.
+// Standard input and output, which cannot be read back, are left out.
+pub(crate) fn write(path: &str, inputs: &[&str], outputs: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut out = create_output(path)?;
+    for file in inputs.iter().chain(outputs).filter(|p| **p != STDIO_PATH) {
+        let hash = InputFile::read(file)?
+            .sha256
+            .ok_or("standard input has no hash")?;
+        writeln!(out, "{}  {}", hash, file)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Changed,
+    Missing,
+}
+
+// Each file listed in `text`, a checksum file, and whether it still hashes
+// the same. Paths are read relative to the current directory.
+fn check(text: &str) -> Result<Vec<(String, Status)>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for (i, line) in text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        // `sha256sum --binary` marks each path with `*`.
+        let (hash, path) = line
+            .split_once("  ")
+            .or_else(|| line.split_once(" *"))
+            .ok_or_else(|| format!("line {}: expected `<sha256>  <path>`", i + 1))?;
+        let status = match std::fs::exists(path)? {
+            false => Status::Missing,
+            true if InputFile::read(path)?.sha256.as_deref() == Some(hash.trim()) => Status::Ok,
+            true => Status::Changed,
+        };
+        files.push((path.to_string(), status));
+    }
+    Ok(files)
+}
+
+// `verify-checksums <report.sha256>`: checks that every file a `--checksums`
+// run listed is unchanged.
+pub(crate) fn run(raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let [path] = raw.as_slice() else {
+        return Err("usage: kraken_acb verify-checksums <report.sha256>".into());
+    };
+    let files = check(&read_input_text(path)?)?;
+    for (file, status) in &files {
+        let status = match status {
+            Status::Ok => "OK",
+            Status::Changed => "CHANGED",
+            Status::Missing => "MISSING",
+        };
+        println!("{}: {}", file, status);
+    }
+    let bad = files.iter().filter(|(_, s)| *s != Status::Ok).count();
+    if bad > 0 {
+        return Err(format!("{} of {} files do not match {}", bad, files.len(), path).into());
+    }
+    println!("All {} files match {}", files.len(), path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_and_missing_files_are_caught() {
+        let dir = std::env::temp_dir().join(format!("kraken_acb_checksums_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(path("ledger.csv"), "abc").unwrap();
+        for name in ["report.csv", "report_meta.json"] {
+            std::fs::write(path(name), name).unwrap();
+        }
+        let sums = path("report.sha256");
+        write(
+            &sums,
+            &[&path("ledger.csv"), STDIO_PATH],
+            &[&path("report.csv"), &path("report_meta.json")],
+        )
+        .unwrap();
+        let text = std::fs::read_to_string(&sums).unwrap();
+        // `printf abc | sha256sum`, and standard input left out.
+        assert_eq!(
+            text.lines().next().unwrap(),
+            format!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {}",
+                path("ledger.csv")
+            )
+        );
+        assert_eq!(text.lines().count(), 3);
+
+        std::fs::write(path("report.csv"), "edited").unwrap();
+        std::fs::remove_file(path("report_meta.json")).unwrap();
+        let files = check(&text);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let statuses: Vec<Status> = files.unwrap().into_iter().map(|(_, s)| s).collect();
+        assert_eq!(statuses, [Status::Ok, Status::Changed, Status::Missing]);
+        assert!(check("not a checksum line").is_err());
+    }
+}
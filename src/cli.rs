@@ -0,0 +1,832 @@
+use crate::accounts::AccountPools;
+use crate::export::ExportFormat;
+use crate::import::ImportFormat;
+use crate::locale::Locale;
+use crate::split::Attribution;
+use crate::table::{Style, Table, paint};
+use crate::tax_rates::Province;
+#[cfg(feature = "tui")]
+use crate::tui;
+use crate::{
+    AdjustmentMode, Args, AverageCost, Canada, CostBasisEngine, CsvDialect, CsvLocale,
+    DEFAULT_FALLBACK_FX, DEFAULT_FILL_WINDOW_SECS, DEFAULT_SPLIT_TEMPLATE, DEFAULT_TAX_YEAR,
+    DonationProceeds, Exemptions, FeeMode, IncomeValuation, Jurisdiction, Pooling, RebateMode,
+    ReportFilter, ReportFormat, RewardPeriod, Rounding, STDIO_PATH, SplitBy,
+    StablecoinDispositions, StalePrices, TieBreak, Totals, accounts, anonymize, basis, bench, cad,
+    checksums, compare, config, diff, fiat_stablecoins, input_paths, instalments, jurisdiction,
+    parse_date_or_time, parse_decimal, parse_delimiter, parse_report_columns, parse_timezone,
+    prices, round_trip, run_report, split, summary_color, summary_writer, timeline, verify,
+    watch_inputs,
+};
+#[cfg(feature = "cli")]
+use crate::{chain, daemon, http, serve, sync};
+use rust_decimal::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+fn flag_value(
+    name: &str,
+    inline: Option<String>,
+    rest: &mut impl Iterator<Item = String>,
+) -> Result<String, Box<dyn Error>> {
+    inline
+        .or_else(|| rest.next())
+        .ok_or_else(|| format!("--{} requires a value", name).into())
+}
+
+// Removes `--name value` / `--name=value` from `raw` and returns the value,
+// for subcommand flags the main argument parser does not know.
+pub(crate) fn take_flag(
+    raw: &mut Vec<String>,
+    name: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let Some(i) = raw
+        .iter()
+        .position(|a| *a == flag || a.starts_with(&prefix))
+    else {
+        return Ok(None);
+    };
+    let arg = raw.remove(i);
+    if let Some(value) = arg.strip_prefix(&prefix) {
+        return Ok(Some(value.to_string()));
+    }
+    if i < raw.len() {
+        Ok(Some(raw.remove(i)))
+    } else {
+        Err(format!("missing value for {}", flag).into())
+    }
+}
+
+// Removes a `--name` switch from `raw` and returns whether it was given.
+pub(crate) fn take_switch(raw: &mut Vec<String>, name: &str) -> bool {
+    let flag = format!("--{}", name);
+    let before = raw.len();
+    raw.retain(|a| *a != flag);
+    raw.len() < before
+}
+
+// What a subcommand's arguments leave once its flags are taken: the
+// positional ones. Any option left over is one it does not know, or one
+// given twice.
+pub(crate) fn positional_args(
+    command: &str,
+    raw: Vec<String>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(flag) = raw.iter().find(|a| a.starts_with("--")) {
+        return Err(format!("{}: unknown or repeated option {}", command, flag).into());
+    }
+    Ok(raw)
+}
+
+pub(crate) fn parse_args_from(raw: Vec<String>) -> Result<Args, Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut fee_mode = FeeMode::Embedded;
+    let mut rebate_mode = RebateMode::Cost;
+    let mut adjustment_mode = AdjustmentMode::Acquisition;
+    let mut donation_proceeds = DonationProceeds::Fmv;
+    let mut income_valuation = IncomeValuation::LastTrade;
+    let mut daily_closes = None;
+    let mut max_price_age = None;
+    let mut stale_prices = None;
+    let mut round_trip_window = round_trip::DEFAULT_WINDOW_MINUTES as u32;
+    let mut stablecoins_as_fiat = BTreeSet::new();
+    let mut stablecoin_dispositions = StablecoinDispositions::Report;
+    let mut pooling = Pooling::Universal;
+    let mut rounding = Rounding::default();
+    let mut tie_break = TieBreak::Id;
+    let mut check_invariants = false;
+    let mut price_chain = prices::DEFAULT_PRICE_CHAIN.to_string();
+    let mut price_overrides = None;
+    let mut price_cache = None;
+    let mut offline = false;
+    let mut cost_basis = AverageCost.name().to_string();
+    let mut lot_selection = None;
+    let mut jurisdiction: Arc<dyn Jurisdiction> = Arc::new(Canada);
+    let mut gifts = None;
+    let mut exclude_refids = None;
+    let mut overrides = None;
+    let mut deemed_disposition = None;
+    let mut timezone = None;
+    let mut loss_carryover_from = None;
+    let mut exemptions = Exemptions::default();
+    let mut province = None;
+    let mut other_income = None;
+    let mut db = None;
+    let mut checkpoint = None;
+    let mut migrations = None;
+    let mut wraps = None;
+    let mut trades = None;
+    let mut classify = None;
+    let mut import_format = ImportFormat::Kraken;
+    let mut mapping = None;
+    let mut extra_inputs = Vec::new();
+    let mut accounts = Vec::new();
+    let mut account_pools = AccountPools::Isolated;
+    let mut split = None;
+    let mut split_names = vec!["taxpayer1".to_string(), "taxpayer2".to_string()];
+    let mut csv_locale = CsvLocale::default();
+    let mut columns = None;
+    let mut json = false;
+    let mut formats = Vec::new();
+    let mut csv_dialect = CsvDialect::Standard;
+    let mut checksums = false;
+    let mut pool_mutations = false;
+    let mut split_by = None;
+    let mut split_template = DEFAULT_SPLIT_TEMPLATE.to_string();
+    let mut exports = Vec::new();
+    let mut chart_of_accounts = None;
+    let mut match_transfers = false;
+    let mut no_color = false;
+    let mut locale = Locale::En;
+    let mut watch = false;
+    let mut report_filter = ReportFilter::default();
+    let mut repair_trades = false;
+    let mut aggregate_fills = None;
+    let mut aggregate_rewards = None;
+    let mut tax_year = None;
+    let mut fallback_usd_cad_fx = None;
+    let mut asset_aliases = BTreeMap::new();
+    let mut config = None;
+    let mut profile = None;
+
+    let mut raw = raw.into_iter();
+    while let Some(arg) = raw.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            positional.push(arg);
+            continue;
+        };
+        let (name, inline) = match flag.split_once('=') {
+            Some((n, v)) => (n.to_string(), Some(v.to_string())),
+            None => (flag.to_string(), None),
+        };
+        match name.as_str() {
+            "fee-mode" => fee_mode = FeeMode::parse(&flag_value(&name, inline, &mut raw)?)?,
+            "rebate-mode" => {
+                rebate_mode = RebateMode::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "adjustment-mode" => {
+                adjustment_mode = AdjustmentMode::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "donation-proceeds" => {
+                donation_proceeds = DonationProceeds::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "income-valuation" => {
+                income_valuation = IncomeValuation::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "daily-closes" => daily_closes = Some(flag_value(&name, inline, &mut raw)?),
+            "max-price-age" => {
+                let v = flag_value(&name, inline, &mut raw)?;
+                max_price_age = Some(v.trim().parse::<u32>().map_err(|_| {
+                    format!("--max-price-age expects a number of days, got {:?}", v)
+                })?)
+            }
+            "stale-prices" => {
+                stale_prices = Some(StalePrices::parse(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "round-trip-window" => {
+                let v = flag_value(&name, inline, &mut raw)?;
+                round_trip_window = v.trim().parse::<u32>().map_err(|_| {
+                    format!(
+                        "--round-trip-window expects a number of minutes, got {:?}",
+                        v
+                    )
+                })?
+            }
+            "treat-stablecoins-as-fiat" => {
+                stablecoins_as_fiat =
+                    fiat_stablecoins(flag_value(&name, inline, &mut raw)?.split(','))?
+            }
+            "stablecoin-dispositions" => {
+                stablecoin_dispositions =
+                    StablecoinDispositions::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "pooling" => pooling = Pooling::parse(&flag_value(&name, inline, &mut raw)?)?,
+            "rounding-strategy" => {
+                rounding.strategy = Rounding::parse_strategy(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "decimal-places" => rounding.set_places(&flag_value(&name, inline, &mut raw)?)?,
+            "no-rounding" => rounding.full_precision = true,
+            "tie-break" => tie_break = TieBreak::parse(&flag_value(&name, inline, &mut raw)?)?,
+            "check-invariants" => check_invariants = true,
+            "price-chain" => price_chain = flag_value(&name, inline, &mut raw)?,
+            "price-overrides" => price_overrides = Some(flag_value(&name, inline, &mut raw)?),
+            "price-cache" => price_cache = Some(flag_value(&name, inline, &mut raw)?),
+            "offline" => offline = true,
+            "cost-basis" => cost_basis = flag_value(&name, inline, &mut raw)?.trim().to_lowercase(),
+            "lot-selection" => lot_selection = Some(flag_value(&name, inline, &mut raw)?),
+            "jurisdiction" => {
+                jurisdiction = jurisdiction::by_name(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "deemed-disposition-date" => {
+                deemed_disposition =
+                    Some(parse_date_or_time(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "timezone" => timezone = Some(parse_timezone(&flag_value(&name, inline, &mut raw)?)?),
+            "loss-carryover-from" => {
+                loss_carryover_from = Some(flag_value(&name, inline, &mut raw)?.parse()?)
+            }
+            "exempt-fx-under" => {
+                exemptions.fx_per_disposition = Some(Exemptions::parse_limit(
+                    &name,
+                    &flag_value(&name, inline, &mut raw)?,
+                )?)
+            }
+            "exempt-spends-under" => {
+                exemptions.spends_under = Some(Exemptions::parse_limit(
+                    &name,
+                    &flag_value(&name, inline, &mut raw)?,
+                )?)
+            }
+            "province" => province = Some(Province::parse(&flag_value(&name, inline, &mut raw)?)?),
+            "other-income" => {
+                other_income = Some(parse_decimal(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "checkpoint" => checkpoint = Some(flag_value(&name, inline, &mut raw)?),
+            "db" => db = Some(flag_value(&name, inline, &mut raw)?),
+            "gifts" => gifts = Some(flag_value(&name, inline, &mut raw)?),
+            "exclude-refids" => exclude_refids = Some(flag_value(&name, inline, &mut raw)?),
+            "overrides" => overrides = Some(flag_value(&name, inline, &mut raw)?),
+            "migrations" => migrations = Some(flag_value(&name, inline, &mut raw)?),
+            "wraps" => wraps = Some(flag_value(&name, inline, &mut raw)?),
+            "trades" => trades = Some(flag_value(&name, inline, &mut raw)?),
+            "classify" => classify = Some(flag_value(&name, inline, &mut raw)?),
+            "import-format" => {
+                import_format = ImportFormat::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "mapping" => mapping = Some(flag_value(&name, inline, &mut raw)?),
+            "extra-input" => {
+                let value = flag_value(&name, inline, &mut raw)?;
+                let (format, path) = value
+                    .split_once(':')
+                    .ok_or("--extra-input expects <format>:<path>")?;
+                extra_inputs.push((ImportFormat::parse(format)?, path.to_string()));
+            }
+            "manual" => {
+                extra_inputs.push((ImportFormat::Manual, flag_value(&name, inline, &mut raw)?))
+            }
+            "account" => {
+                let value = flag_value(&name, inline, &mut raw)?;
+                let (account, path) = value
+                    .split_once('=')
+                    .ok_or("--account expects <name>=<path>")?;
+                accounts.push((account.trim().to_string(), path.to_string()));
+            }
+            "account-pools" => {
+                account_pools = AccountPools::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "split" => split = Some(Attribution::parse(&flag_value(&name, inline, &mut raw)?)?),
+            "split-names" => {
+                let value = flag_value(&name, inline, &mut raw)?;
+                split_names = value.split(',').map(|n| n.trim().to_string()).collect();
+                if split_names.len() != 2
+                    || split_names[0] == split_names[1]
+                    || split_names.iter().any(|n| {
+                        n.is_empty()
+                            || !n
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    })
+                {
+                    return Err(format!(
+                        "--split-names expects two names of letters, digits, - and _, got {:?}",
+                        value
+                    )
+                    .into());
+                }
+            }
+            "match-transfers" => match_transfers = true,
+            "repair-trades" => repair_trades = true,
+            "aggregate-rewards" => {
+                aggregate_rewards =
+                    Some(RewardPeriod::parse(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "aggregate-fills" => {
+                let secs = match &inline {
+                    Some(v) => v.trim().parse::<u32>().map_err(|_| {
+                        format!("--aggregate-fills expects a window in seconds, got {:?}", v)
+                    })?,
+                    None => DEFAULT_FILL_WINDOW_SECS,
+                };
+                aggregate_fills = Some(chrono::TimeDelta::seconds(secs.into()));
+            }
+            "no-color" => no_color = true,
+            "locale" => locale = Locale::parse(&flag_value(&name, inline, &mut raw)?)?,
+            "watch" => watch = true,
+            "delimiter" => {
+                csv_locale.delimiter = Some(parse_delimiter(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "columns" => {
+                columns = Some(parse_report_columns(
+                    flag_value(&name, inline, &mut raw)?.split(','),
+                )?)
+            }
+            "columns-file" => {
+                let text = std::fs::read_to_string(flag_value(&name, inline, &mut raw)?)?;
+                columns = Some(parse_report_columns(text.lines())?)
+            }
+            "json" => json = true,
+            "format" => {
+                let value = flag_value(&name, inline, &mut raw)?;
+                // The beancount books are an export, asked for here as well.
+                if value.trim().eq_ignore_ascii_case("beancount") {
+                    exports.push(ExportFormat::Beancount);
+                } else {
+                    formats.push(ReportFormat::parse(&value)?);
+                }
+            }
+            "csv-dialect" => {
+                csv_dialect = CsvDialect::parse(&flag_value(&name, inline, &mut raw)?)?
+            }
+            "checksums" => checksums = true,
+            "pool-mutations" => pool_mutations = true,
+            "split-by" => split_by = Some(SplitBy::parse(&flag_value(&name, inline, &mut raw)?)?),
+            "split-template" => split_template = flag_value(&name, inline, &mut raw)?,
+            "decimal-comma" => csv_locale.decimal_comma = Some(true),
+            "decimal-point" => csv_locale.decimal_comma = Some(false),
+            "export" => exports.push(ExportFormat::parse(&flag_value(&name, inline, &mut raw)?)?),
+            "chart-of-accounts" => chart_of_accounts = Some(flag_value(&name, inline, &mut raw)?),
+            "from" => {
+                report_filter.from = Some(ReportFilter::parse_date(
+                    &name,
+                    &flag_value(&name, inline, &mut raw)?,
+                )?)
+            }
+            "to" => {
+                report_filter.to = Some(ReportFilter::parse_date(
+                    &name,
+                    &flag_value(&name, inline, &mut raw)?,
+                )?)
+            }
+            "assets" => {
+                report_filter.assets = Some(ReportFilter::parse_assets(&flag_value(
+                    &name, inline, &mut raw,
+                )?))
+            }
+            "tax-year" => {
+                let v = flag_value(&name, inline, &mut raw)?;
+                tax_year = Some(
+                    v.trim()
+                        .parse::<i32>()
+                        .map_err(|_| format!("--tax-year expects a year, got {:?}", v))?,
+                )
+            }
+            "fallback-usd-cad-fx" => {
+                fallback_usd_cad_fx = Some(parse_decimal(&flag_value(&name, inline, &mut raw)?)?)
+            }
+            "asset-alias" => {
+                let value = flag_value(&name, inline, &mut raw)?;
+                let (from, to) = value
+                    .split_once('=')
+                    .filter(|(f, t)| !f.trim().is_empty() && !t.trim().is_empty())
+                    .ok_or("--asset-alias expects <from>=<to>")?;
+                asset_aliases.insert(
+                    from.trim().to_uppercase().into(),
+                    to.trim().to_uppercase().into(),
+                );
+            }
+            "config" => config = Some(flag_value(&name, inline, &mut raw)?),
+            "profile" => profile = Some(flag_value(&name, inline, &mut raw)?),
+            _ => return Err(format!("unknown option --{}", name).into()),
+        }
+    }
+
+    // Each export is written once, however many times it was asked for.
+    let mut asked = Vec::new();
+    exports.retain(|f| {
+        let first = !asked.contains(f);
+        if first {
+            asked.push(*f);
+        }
+        first
+    });
+    if let (Some(from), Some(to)) = (report_filter.from, report_filter.to)
+        && from > to
+    {
+        return Err(format!("--from {} is after --to {}", from, to).into());
+    }
+
+    let mut args = positional.into_iter();
+    let input = args
+        .next()
+        .unwrap_or_else(|| "kraken_2024_2025_ledgers.csv".to_string());
+    let tax_year: i32 = match args.next() {
+        Some(year) => year
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid tax year: {}", year))?,
+        None => tax_year.unwrap_or(DEFAULT_TAX_YEAR),
+    };
+    let output = args
+        .next()
+        .unwrap_or_else(|| format!("kraken_tax_report_{}.csv", tax_year));
+    let fallback_usd_cad_fx = match args.next() {
+        Some(fx) => Decimal::from_str(&fx)?,
+        None => fallback_usd_cad_fx.unwrap_or(Decimal::from_str(DEFAULT_FALLBACK_FX)?),
+    };
+
+    if output == STDIO_PATH {
+        let files = [
+            ("--export", !exports.is_empty()),
+            ("--json", json),
+            ("--format", formats.iter().any(|f| *f != ReportFormat::Csv)),
+            ("--split-by", split_by.is_some()),
+            ("--pool-mutations", pool_mutations),
+            ("--checksums", checksums),
+        ];
+        if let Some((flag, _)) = files.into_iter().find(|(_, asked)| *asked) {
+            return Err(format!("{} needs a report file, not stdout", flag).into());
+        }
+    }
+    if stale_prices.is_some() && max_price_age.is_none() {
+        return Err("--stale-prices needs --max-price-age <days>".into());
+    }
+    if other_income.is_some() && province.is_none() {
+        return Err("--other-income needs --province <code>".into());
+    }
+    let rounded = Rounding {
+        full_precision: false,
+        ..rounding
+    };
+    if rounding.full_precision && rounded != Rounding::default() {
+        return Err(
+            "--no-rounding cannot be combined with --rounding-strategy or --decimal-places".into(),
+        );
+    }
+    let cost_basis = basis::method_name(&cost_basis)
+        .ok_or_else(|| format!("unknown cost basis method: {}", cost_basis))?
+        .to_string();
+    if lot_selection.is_some() != (cost_basis == "spec-id") {
+        return Err("--lot-selection <file> goes with --cost-basis spec-id".into());
+    }
+
+    Ok(Args {
+        input,
+        tax_year,
+        output,
+        fallback_usd_cad_fx,
+        fee_mode,
+        rebate_mode,
+        adjustment_mode,
+        donation_proceeds,
+        income_valuation,
+        daily_closes,
+        max_price_age,
+        stale_prices: stale_prices.unwrap_or(StalePrices::Warn),
+        round_trip_window,
+        stablecoins_as_fiat,
+        stablecoin_dispositions,
+        pooling,
+        rounding,
+        tie_break,
+        check_invariants,
+        price_chain,
+        price_overrides,
+        price_cache,
+        offline,
+        cost_basis,
+        lot_selection,
+        jurisdiction,
+        migrations,
+        wraps,
+        trades,
+        gifts,
+        exclude_refids,
+        overrides,
+        deemed_disposition,
+        timezone,
+        loss_carryover_from,
+        exemptions,
+        province,
+        other_income,
+        db,
+        checkpoint,
+        classify,
+        import_format,
+        mapping,
+        extra_inputs,
+        accounts,
+        account_pools,
+        split,
+        split_names,
+        split_side: None,
+        csv_locale,
+        columns,
+        json,
+        formats,
+        csv_dialect,
+        checksums,
+        pool_mutations,
+        split_by,
+        split_template,
+        exports,
+        chart_of_accounts,
+        match_transfers,
+        no_color,
+        locale,
+        watch,
+        report_filter,
+        repair_trades,
+        aggregate_fills,
+        aggregate_rewards,
+        asset_aliases,
+        config,
+        profile,
+        quiet: false,
+    })
+}
+
+// Whether `first`, the first argument, names a subcommand rather than a
+// ledger; subcommands take no config file.
+pub(crate) fn is_subcommand(first: Option<&str>) -> bool {
+    matches!(
+        first,
+        Some(
+            "import-chain"
+                | "sync"
+                | "export-ledger"
+                | "anonymize"
+                | "bench"
+                | "verify"
+                | "verify-checksums"
+                | "serve"
+                | "compare"
+                | "pools"
+                | "diff"
+                | "instalments"
+                | "tui"
+        )
+    )
+}
+
+// Command-line entry point: the tax report run and its subcommands.
+pub fn run() -> Result<ExitCode, Box<dyn Error>> {
+    let mut raw: Vec<String> = std::env::args().skip(1).collect();
+    // `report` names the default run, as the subcommands are named.
+    if raw.first().map(String::as_str) == Some("report") {
+        raw.remove(0);
+    }
+    let raw = if is_subcommand(raw.first().map(String::as_str)) {
+        raw
+    } else {
+        config::expand(raw)?
+    };
+    #[cfg(feature = "cli")]
+    let raw = http::configure(raw)?;
+    if raw.first().map(String::as_str) == Some("import-chain") {
+        #[cfg(feature = "cli")]
+        return chain::import_chain(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+        #[cfg(not(feature = "cli"))]
+        return Err("import-chain requires a build with the `cli` feature".into());
+    }
+    if raw.first().map(String::as_str) == Some("sync") {
+        #[cfg(feature = "cli")]
+        return sync::sync(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+        #[cfg(not(feature = "cli"))]
+        return Err("sync requires a build with the `cli` feature".into());
+    }
+    if raw.first().map(String::as_str) == Some("export-ledger") {
+        #[cfg(feature = "cli")]
+        return sync::export_ledger(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+        #[cfg(not(feature = "cli"))]
+        return Err("export-ledger requires a build with the `cli` feature".into());
+    }
+    if raw.first().map(String::as_str) == Some("serve") {
+        #[cfg(feature = "cli")]
+        return serve::serve(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+        #[cfg(not(feature = "cli"))]
+        return Err("serve requires a build with the `cli` feature".into());
+    }
+    if raw.first().map(String::as_str) == Some("compare") {
+        return compare::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("pools") {
+        return timeline::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("anonymize") {
+        return anonymize::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("bench") {
+        return bench::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("verify") {
+        return verify::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("verify-checksums") {
+        return checksums::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("diff") {
+        return diff::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("instalments") {
+        return instalments::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+    }
+    if raw.first().map(String::as_str) == Some("tui") {
+        #[cfg(feature = "tui")]
+        return tui::run(raw[1..].to_vec()).map(|()| ExitCode::SUCCESS);
+        #[cfg(not(feature = "tui"))]
+        return Err("tui requires a build with `--features tui`".into());
+    }
+    if let Some(i) = raw.iter().position(|a| a == "--daemon") {
+        #[cfg(feature = "cli")]
+        {
+            let mut raw = raw;
+            raw.remove(i);
+            return daemon::daemon(raw).map(|()| ExitCode::SUCCESS);
+        }
+        #[cfg(not(feature = "cli"))]
+        {
+            let _ = i;
+            return Err("--daemon requires a build with the `cli` feature".into());
+        }
+    }
+    let args = parse_args_from(raw)?;
+    if !args.accounts.is_empty() {
+        if args.split.is_some() {
+            return Err("--account and --split cannot be used together".into());
+        }
+        return accounts::run(&args).map(ExitCode::from);
+    }
+    if args.split.is_some() {
+        return split::run(&args).map(ExitCode::from);
+    }
+    if args.watch {
+        return watch(&args).map(|()| ExitCode::SUCCESS);
+    }
+    let (_, code, _) = run_report(&args)?;
+    Ok(ExitCode::from(code))
+}
+
+// `--watch`: runs the report, then reruns it whenever an input file changes
+// and prints how the totals moved. A failed run is reported and watching goes
+// on, since the file may be mid-save.
+fn watch(args: &Args) -> Result<(), Box<dyn Error>> {
+    if input_paths(args).contains(&STDIO_PATH) {
+        return Err("--watch: inputs must be files, not stdin".into());
+    }
+    let mut previous = match run_report(args) {
+        Ok((totals, _, _)) => Some(totals),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            None
+        }
+    };
+    let color = summary_color(&args.output, args.no_color);
+    eprintln!("\nWatching the inputs for changes (Ctrl-C to stop)");
+    watch_inputs(args, || {
+        let totals = match run_report(args) {
+            Ok((totals, _, _)) => totals,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        };
+        let mut out = summary_writer(&args.output);
+        let _ = writeln!(
+            out,
+            "\n{}",
+            paint(
+                "=== TOTALS CHANGED SINCE LAST RUN ===",
+                Style::Heading,
+                color
+            )
+        );
+        let diff = previous
+            .as_ref()
+            .map(|before| totals_diff(before, &totals))
+            .filter(|t| !t.is_empty());
+        let _ = match diff {
+            Some(table) => write!(out, "{}", table.render(color)),
+            None if previous.is_some() => writeln!(out, "No totals changed"),
+            None => writeln!(out, "No earlier successful run to compare with"),
+        };
+        previous = Some(totals);
+    });
+    Ok(())
+}
+
+// The totals that differ between two runs, with the change.
+fn totals_diff(before: &Totals, after: &Totals) -> Table {
+    let mut table = Table::new(&["Total", "Before (CAD)", "After (CAD)", "Change (CAD)"]);
+    for ((label, old, _), (_, new, _)) in before.rows().into_iter().zip(after.rows()) {
+        if old != new {
+            table.row(vec![
+                (label.to_string(), Style::Plain),
+                (cad(old), Style::Plain),
+                (cad(new), Style::Plain),
+                (cad(new - old), Style::signed(new - old)),
+            ]);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ReportRow;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn parses_flags_between_positionals() {
+        let args = parse_args_from(
+            ["in.csv", "--fee-mode", "proceeds", "2024", "--fee-mode=acb"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(args.tax_year, 2024);
+        assert_eq!(args.fee_mode, FeeMode::Acb);
+        assert!(parse_args_from(vec!["--bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn subcommand_flags_leave_positionals_and_reject_leftovers() {
+        let args = |flags: &[&str]| flags.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut raw = args(&["--no-color", "report.csv", "--against=in.csv"]);
+        assert!(take_switch(&mut raw, "no-color"));
+        assert_eq!(
+            take_flag(&mut raw, "against").unwrap().as_deref(),
+            Some("in.csv")
+        );
+        assert_eq!(positional_args("verify", raw).unwrap(), ["report.csv"]);
+
+        let mut raw = args(&["--meta", "a.json", "--meta", "b.json", "--bogus"]);
+        take_flag(&mut raw, "meta").unwrap();
+        let err = positional_args("verify", raw).unwrap_err().to_string();
+        assert_eq!(err, "verify: unknown or repeated option --meta");
+    }
+
+    #[test]
+    fn format_beancount_is_the_beancount_export() {
+        let parse = |flags: &[&str]| parse_args_from(flags.iter().map(|s| s.to_string()).collect());
+        let args = parse(&["in.csv", "--format", "Beancount", "--export=beancount"]).unwrap();
+        assert_eq!(args.exports, [ExportFormat::Beancount]);
+        assert!(args.formats.is_empty());
+        assert!(parse(&["in.csv", "--format", "ledger"]).is_err());
+    }
+
+    #[test]
+    fn report_filter_limits_rows_by_date_and_asset() {
+        let args = parse_args_from(
+            [
+                "in.csv",
+                "--from",
+                "2025-02-01",
+                "--to=2025-03-31",
+                "--assets",
+                "btc, ETH",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        )
+        .unwrap();
+        let row = |time: &str, asset: &str| ReportRow {
+            time: time.to_string(),
+            asset: asset.into(),
+            ..ReportRow::default()
+        };
+        let filter = &args.report_filter;
+        assert!(filter.keeps(&row("2025-02-01T00:00:00-05:00", "BTC")));
+        assert!(filter.keeps(&row("2025-03-31T23:59:59+00:00", "ETH")));
+        assert!(!filter.keeps(&row("2025-01-31T23:59:59+00:00", "BTC")));
+        assert!(!filter.keeps(&row("2025-04-01T00:00:00+00:00", "BTC")));
+        assert!(!filter.keeps(&row("2025-02-15T00:00:00+00:00", "SOL")));
+        assert!(!ReportFilter::default().is_active());
+        let reversed = ["in.csv", "--from=2025-04-01", "--to=2025-03-01"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_args_from(reversed).is_err());
+    }
+
+    #[test]
+    fn watch_diff_lists_only_changed_totals() {
+        let before = Totals {
+            proceeds_cad: dec!(80),
+            capital_gain_cad: dec!(30),
+            reward_income_cad: dec!(5),
+            ..Totals::default()
+        };
+        let after = Totals {
+            proceeds_cad: dec!(80),
+            capital_gain_cad: dec!(-12.5),
+            reward_income_cad: dec!(7.25),
+            ..Totals::default()
+        };
+        assert_eq!(
+            totals_diff(&before, &after).render(false),
+            "Total                  Before (CAD)  After (CAD)  Change (CAD)\n\
+             ---------------------  ------------  -----------  ------------\n\
+             Net capital gain/loss         30.00       -12.50        -42.50\n\
+             Reward income                  5.00         7.25          2.25\n"
+        );
+        assert!(totals_diff(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{ReportRow, create_output, parse_decimal};
+use arrow_array::{ArrayRef, Decimal128Array, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::DateTime;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::error::Error;
+use std::sync::Arc;
+
+// Amounts are stored as DECIMAL(38, 18): exact, and wide enough for any
+// unit count or CAD value the report holds.
+const PRECISION: u8 = 38;
+const SCALE: u32 = 18;
+
+// `amount` as a DECIMAL(38, 18) value, or null when blank.
+fn scaled(amount: &str) -> Result<Option<i128>, Box<dyn Error>> {
+    if amount.is_empty() {
+        return Ok(None);
+    }
+    let d = parse_decimal(amount)?.round_dp(SCALE);
+    10i128
+        .checked_pow(SCALE - d.scale())
+        .and_then(|f| d.mantissa().checked_mul(f))
+        .map(Some)
+        .ok_or_else(|| format!("{} does not fit the Parquet amount column", amount).into())
+}
+
+type AmountColumn = (&'static str, fn(&ReportRow) -> &str);
+
+const AMOUNTS: [AmountColumn; 10] = [
+    ("units_in", |r| &r.units_in),
+    ("units_out", |r| &r.units_out),
+    ("proceeds_cad", |r| &r.proceeds_cad),
+    ("acb_disposed_cad", |r| &r.acb_disposed_cad),
+    ("gain_cad", |r| &r.gain_cad),
+    ("income_cad", |r| &r.income_cad),
+    ("acb_added_cad", |r| &r.acb_added_cad),
+    ("fee_cad", |r| &r.fee_cad),
+    ("pool_units_after", |r| &r.pool_units_after),
+    ("pool_acb_cad_after", |r| &r.pool_acb_cad_after),
+];
+
+// The report rows as one record batch, with the CSV's columns in its order:
+// the time as a UTC timestamp, amounts as decimals (null where the CSV is
+// blank), the rest as strings.
+fn record_batch(rows: &[&ReportRow]) -> Result<RecordBatch, Box<dyn Error>> {
+    let strings = |f: fn(&ReportRow) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|r| f(r))))
+    };
+    let times = rows
+        .iter()
+        .map(|r| Ok(DateTime::parse_from_rfc3339(&r.time)?.timestamp_micros()))
+        .collect::<Result<Vec<i64>, Box<dyn Error>>>()?;
+    let mut fields = vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("refid", DataType::Utf8, false),
+        Field::new("txid", DataType::Utf8, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new("asset", DataType::Utf8, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampMicrosecondArray::from(times).with_timezone("UTC")),
+        strings(|r| &r.refid),
+        strings(|r| &r.txid),
+        strings(|r| &r.event_type),
+        strings(|r| &r.asset),
+    ];
+    for (name, amount) in AMOUNTS {
+        let values = rows
+            .iter()
+            .map(|r| scaled(amount(r)))
+            .collect::<Result<Vec<_>, _>>()?;
+        fields.push(Field::new(
+            name,
+            DataType::Decimal128(PRECISION, SCALE as i8),
+            true,
+        ));
+        columns.push(Arc::new(
+            Decimal128Array::from(values).with_precision_and_scale(PRECISION, SCALE as i8)?,
+        ));
+    }
+    for (name, text) in [
+        (
+            "price_source",
+            (|r| &r.price_source) as fn(&ReportRow) -> &str,
+        ),
+        ("fx_source", |r| &r.fx_source),
+        ("notes", |r| &r.notes),
+    ] {
+        fields.push(Field::new(name, DataType::Utf8, false));
+        columns.push(strings(text));
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+// `--format parquet`: writes the report rows to `path` as a Snappy-compressed
+// Parquet file.
+pub(crate) fn write(path: &str, rows: &[&ReportRow]) -> Result<(), Box<dyn Error>> {
+    let batch = record_batch(rows)?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(create_output(path)?, batch.schema(), Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_row, parse_time, stamp};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn report_rows_keep_their_types() {
+        let refid: Arc<str> = Arc::from("S1");
+        let time = stamp(parse_time("2025-04-01 12:00:00").unwrap(), None);
+        let mut sale = make_row(time, &refid, &refid, "trade_disposition", &"BTC".into());
+        sale.units_out = "0.00012345".to_string();
+        sale.proceeds_cad = "-12.5".to_string();
+        let path = std::env::temp_dir().join(format!("kraken_acb_{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        write(path, &[&sale]).unwrap();
+        let file = std::fs::File::open(path).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let schema = batch.schema();
+        assert_eq!(schema.fields().len(), 18);
+        assert_eq!(schema.field(0).name(), "time");
+        let times = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(times.value_as_datetime(0), Some(time.naive_utc()));
+        let amount = |name: &str| {
+            let column = batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Decimal128Array>()
+                .unwrap()
+                .clone();
+            (!column.is_null(0)).then(|| Decimal::from_i128_with_scale(column.value(0), SCALE))
+        };
+        assert_eq!(amount("units_out"), Some(Decimal::new(12345, 8)));
+        assert_eq!(amount("proceeds_cad"), Some(Decimal::new(-125, 1)));
+        assert_eq!(amount("gain_cad"), None);
+        assert!(scaled("1000000000000000000000").is_err());
+    }
+}
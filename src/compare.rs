@@ -0,0 +1,158 @@
+use crate::table::{Style, Table, paint};
+use crate::{
+    CostBasisEngine, Interner, LedgerEntry, ProcessOptions, Totals, basis, cad, explain_failure,
+    load_inputs, load_lot_selection, parse_args_from, prepare_entries, process, process_options,
+    take_flag, use_color,
+};
+use rayon::prelude::*;
+use std::error::Error;
+use std::sync::Arc;
+
+// `compare --methods average,fifo <ledger.csv> [tax_year] [options]`: runs the
+// ledger under each cost-basis method and prints their capital gains totals
+// side by side, for every year from the first in the ledger to the tax year.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let methods = take_flag(&mut raw, "methods")?
+        .ok_or("compare: --methods <list> is required, e.g. --methods average,fifo")?;
+    if take_flag(&mut raw, "cost-basis")?.is_some() {
+        return Err("compare: use --methods instead of --cost-basis".into());
+    }
+    let lot_selection = take_flag(&mut raw, "lot-selection")?;
+    let args = parse_args_from(raw)?;
+
+    let (mut entries, data_quality) = load_inputs(&args, &mut Interner::default())?;
+    prepare_entries(&args, &mut entries)?;
+    let selections = match &lot_selection {
+        Some(path) => Some(load_lot_selection(path, &entries)?),
+        None => None,
+    };
+    let mut engines: Vec<Arc<dyn CostBasisEngine>> = Vec::new();
+    for name in methods.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        let name = basis::method_name(name)
+            .ok_or_else(|| format!("unknown cost basis method: {}", name))?;
+        let engine = basis::by_name(name, selections.clone())?;
+        if engines.iter().any(|e| e.name() == name) {
+            return Err(format!("compare: {} is listed twice", name).into());
+        }
+        engines.push(engine);
+    }
+    if engines.len() < 2 {
+        return Err("compare: --methods needs at least two methods".into());
+    }
+    let opts = process_options(&args, &entries)?;
+    let first_year = entries
+        .first()
+        .map_or(args.tax_year, |e| args.jurisdiction.tax_year(e.time));
+    let years: Vec<i32> = (first_year.min(args.tax_year)..=args.tax_year).collect();
+    let table = compare_totals(&entries, &opts, &engines, &years)
+        .map_err(|e| explain_failure(e, &data_quality))?;
+
+    let color = use_color(std::io::stdout(), args.no_color);
+    let headers: Vec<String> = std::iter::once("Total".to_string())
+        .chain(engines.iter().map(|e| format!("{} (CAD)", e.name())))
+        .collect();
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+    for (year, totals) in years.iter().zip(&table) {
+        println!(
+            "{}",
+            paint(
+                &format!("=== {} BY COST BASIS METHOD ===", year),
+                Style::Heading,
+                color
+            )
+        );
+        let mut t = Table::new(&headers);
+        // Only the capital rows depend on the method.
+        for row in 0..CAPITAL_ROWS {
+            let label = totals[0].rows()[row].0;
+            let mut cells = vec![(label.to_string(), Style::Plain)];
+            for method_totals in totals {
+                let (_, amount, signed) = method_totals.rows()[row];
+                let style = if signed {
+                    Style::signed(amount)
+                } else {
+                    Style::Plain
+                };
+                cells.push((cad(amount), style));
+            }
+            t.row(cells);
+        }
+        println!("{}", t.render(color));
+    }
+    let accepted = args.jurisdiction.cost_basis_methods();
+    for engine in engines.iter().filter(|e| !accepted.contains(&e.name())) {
+        let line = format!(
+            "{} is not accepted on a {} return",
+            engine.name(),
+            args.jurisdiction.name()
+        );
+        println!("{}", paint(&line, Style::Warning, color));
+    }
+    Ok(())
+}
+
+// Proceeds, ACB disposed, net and taxable capital gain: the leading rows of
+// `Totals::rows`.
+const CAPITAL_ROWS: usize = 4;
+
+// Totals for each year, one per engine in `engines` order. Every run replays
+// the whole ledger, so they go side by side.
+fn compare_totals(
+    entries: &[LedgerEntry],
+    opts: &ProcessOptions,
+    engines: &[Arc<dyn CostBasisEngine>],
+    years: &[i32],
+) -> Result<Vec<Vec<Totals>>, Box<dyn Error>> {
+    let runs: Vec<(i32, &Arc<dyn CostBasisEngine>)> = years
+        .iter()
+        .flat_map(|year| engines.iter().map(move |e| (*year, e)))
+        .collect();
+    let totals = runs
+        .into_par_iter()
+        .map(|(year, engine)| {
+            let run_opts = ProcessOptions {
+                tax_year: year,
+                cost_basis: engine.clone(),
+                ..opts.clone()
+            };
+            process(entries.to_vec(), &run_opts)
+                .map(|out| out.totals)
+                .map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut totals = totals.into_iter();
+    Ok(years
+        .iter()
+        .map(|_| totals.by_ref().take(engines.len()).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::{AverageCost, Fifo};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn methods_are_compared_year_by_year() {
+        let entries = vec![
+            leg("B1", "2024-01-10 00:00:00", "CAD", dec!(-100)),
+            leg("B1", "2024-01-10 00:00:00", "SOL", dec!(1)),
+            leg("B2", "2024-06-10 00:00:00", "CAD", dec!(-300)),
+            leg("B2", "2024-06-10 00:00:00", "SOL", dec!(1)),
+            leg("S1", "2025-03-01 00:00:00", "SOL", dec!(-1)),
+            leg("S1", "2025-03-01 00:00:00", "CAD", dec!(250)),
+        ];
+        let engines: Vec<Arc<dyn CostBasisEngine>> = vec![Arc::new(AverageCost), Arc::new(Fifo)];
+        let opts = ProcessOptions::new(2025, dec!(1.4));
+        let table = compare_totals(&entries, &opts, &engines, &[2024, 2025]).unwrap();
+        let gains: Vec<Vec<Decimal>> = table
+            .iter()
+            .map(|year| year.iter().map(|t| t.capital_gain_cad).collect())
+            .collect();
+        assert_eq!(gains, [vec![dec!(0), dec!(0)], vec![dec!(50), dec!(150)]]);
+        assert_eq!(basis::method_name("ACB"), Some("average"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+// `config.toml`: default flags for every run, and named profiles of flags
+// chosen with `--profile`. Keys are flag names without the dashes:
+//
+//     tax-year = 2025
+//     fee-mode = "expense"
+//     treat-stablecoins-as-fiat = "USDC,USDT"
+//     export = ["koinly", "cointracking"]
+//
+//     [aliases]
+//     XBT = "BTC"
+//
+//     [profiles.corporate]
+//     jurisdiction = "canada"
+//     offline = true
+
+const CONFIG_DIR: &str = "kraken-tax-reporting";
+
+// `$XDG_CONFIG_HOME/kraken-tax-reporting/config.toml`, else the same under
+// `~/.config`, if it exists.
+fn default_path() -> Option<PathBuf> {
+    let non_empty = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    let dir = non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join(CONFIG_DIR).join("config.toml")).filter(|p| p.exists())
+}
+
+// Puts the config file's flags, then the profile's, ahead of the command
+// line's, so flags given on the command line win. `--config <file>` picks
+// the file and `--no-config` skips it; the file and profile used are passed
+// on as `--config=` and `--profile=` for the summary.
+pub(crate) fn expand(raw: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut path = None;
+    let mut profile = None;
+    let mut skip = false;
+    let mut rest = Vec::new();
+    let mut args = raw.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((n, v)) => (n.to_string(), Some(v.to_string())),
+            None => (arg.clone(), None),
+        };
+        let slot = match name.as_str() {
+            "--config" => &mut path,
+            "--profile" => &mut profile,
+            "--no-config" => {
+                skip = true;
+                continue;
+            }
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        let value = inline
+            .or_else(|| args.next())
+            .ok_or_else(|| format!("{} requires a value", name))?;
+        *slot = Some(value);
+    }
+    let path = if skip {
+        None
+    } else {
+        path.map(PathBuf::from).or_else(default_path)
+    };
+    let Some(path) = path else {
+        if let Some(profile) = profile {
+            return Err(format!("--profile {}: no config file found", profile).into());
+        }
+        return Ok(rest);
+    };
+    let text =
+        std::fs::read_to_string(&path).map_err(|e| format!("config {}: {}", path.display(), e))?;
+    let mut out = vec![format!("--config={}", path.display())];
+    out.extend(
+        config_flags(&text, profile.as_deref())
+            .map_err(|e| format!("config {}: {}", path.display(), e))?,
+    );
+    if let Some(profile) = profile {
+        out.push(format!("--profile={}", profile));
+    }
+    out.extend(rest);
+    Ok(out)
+}
+
+// The flags a config file's text sets: its top-level keys, then `profile`'s.
+fn config_flags(text: &str, profile: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut table: toml::Table = toml::from_str(text)?;
+    let profiles = match table.remove("profiles") {
+        Some(toml::Value::Table(t)) => t,
+        Some(_) => return Err("[profiles] must be a table of tables".into()),
+        None => toml::Table::new(),
+    };
+    let mut flags = Vec::new();
+    table_flags(&table, &mut flags)?;
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(toml::Value::Table(t)) if !t.contains_key("profiles") => {
+                table_flags(t, &mut flags)?
+            }
+            Some(_) => return Err(format!("profile {} must be a table of flags", name).into()),
+            None => {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                return Err(format!(
+                    "no profile {} (profiles: {})",
+                    name,
+                    if names.is_empty() {
+                        "none".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                )
+                .into());
+            }
+        }
+    }
+    Ok(flags)
+}
+
+fn table_flags(table: &toml::Table, flags: &mut Vec<String>) -> Result<(), Box<dyn Error>> {
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            // Reports are in CAD; the key is checked so a config meant for
+            // another currency fails instead of mislabelling amounts.
+            ("base-currency", toml::Value::String(c)) if c.eq_ignore_ascii_case("CAD") => {}
+            ("base-currency", _) => return Err("base-currency must be \"CAD\"".into()),
+            ("aliases", toml::Value::Table(aliases)) => {
+                for (from, to) in aliases {
+                    let to = to
+                        .as_str()
+                        .ok_or_else(|| format!("alias {} must name an asset", from))?;
+                    flags.push(format!("--asset-alias={}={}", from, to));
+                }
+            }
+            _ => push_value(key, value, flags)?,
+        }
+    }
+    Ok(())
+}
+
+fn push_value(
+    key: &str,
+    value: &toml::Value,
+    flags: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    match value {
+        toml::Value::String(s) => flags.push(format!("--{}={}", key, s)),
+        toml::Value::Integer(n) => flags.push(format!("--{}={}", key, n)),
+        toml::Value::Float(x) => flags.push(format!("--{}={}", key, x)),
+        toml::Value::Datetime(d) => flags.push(format!("--{}={}", key, d)),
+        toml::Value::Boolean(true) => flags.push(format!("--{}", key)),
+        toml::Value::Boolean(false) => {}
+        toml::Value::Array(items) => {
+            for item in items {
+                if matches!(item, toml::Value::Array(_) | toml::Value::Table(_)) {
+                    return Err(format!("{}: list items must be plain values", key).into());
+                }
+                push_value(key, item, flags)?;
+            }
+        }
+        toml::Value::Table(_) => return Err(format!("{} must be a value, not a table", key).into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_flags_follow_the_defaults() {
+        let text = r#"
+            tax-year = 2024
+            fee-mode = "expense"
+            offline = true
+            match-transfers = false
+            export = ["koinly", "cointracking"]
+            deemed-disposition-date = 2024-06-30
+
+            [aliases]
+            XBT = "BTC"
+
+            [profiles.corporate]
+            fee-mode = "embedded"
+        "#;
+        assert_eq!(
+            config_flags(text, Some("corporate")).unwrap(),
+            [
+                "--asset-alias=XBT=BTC",
+                "--deemed-disposition-date=2024-06-30",
+                "--export=koinly",
+                "--export=cointracking",
+                "--fee-mode=expense",
+                "--offline",
+                "--tax-year=2024",
+                "--fee-mode=embedded",
+            ]
+        );
+        assert!(config_flags(text, Some("personal")).is_err());
+        assert!(config_flags("base-currency = \"USD\"", None).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use std::process::Command;
+
+// Service name API keys are stored under in the OS keyring.
+const KEYRING_SERVICE: &str = "kraken-tax-reporting";
+
+// An API key for `account` (e.g. `etherscan`): the `env` variable when set,
+// else the OS keyring entry for `kraken-tax-reporting`/`account`. Keys are
+// never taken from flags, which end up in shell history and process lists.
+pub(crate) fn api_key(account: &str, env: &str) -> Option<String> {
+    std::env::var(env)
+        .ok()
+        .filter(|k| !k.trim().is_empty())
+        .or_else(|| keyring(account))
+        .map(|k| k.trim().to_string())
+}
+
+// The macOS keychain through `security`, or the Secret Service (GNOME
+// Keyring, KWallet) through libsecret's `secret-tool`. `None` when the tool
+// is missing or has no entry.
+fn keyring(account: &str) -> Option<String> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("security");
+        cmd.args(["find-generic-password", "-s", KEYRING_SERVICE])
+            .args(["-a", account, "-w"]);
+        cmd
+    } else if cfg!(unix) {
+        let mut cmd = Command::new("secret-tool");
+        cmd.args(["lookup", "service", KEYRING_SERVICE, "account", account]);
+        cmd
+    } else {
+        return None;
+    };
+    let out = cmd.stderr(std::process::Stdio::null()).output().ok()?;
+    let key = String::from_utf8(out.stdout).ok()?;
+    (out.status.success() && !key.trim().is_empty()).then_some(key)
+}
+
+// `text` with every occurrence of `secret` masked, for errors that may quote
+// a request URL carrying the key.
+pub(crate) fn redact(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return text.to_string();
+    }
+    text.replace(secret, "[redacted]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_masked_in_error_text() {
+        let err = "https://api.etherscan.io/v2/api?module=account&apikey=ABC123: status 403";
+        assert_eq!(
+            redact(err, "ABC123"),
+            "https://api.etherscan.io/v2/api?module=account&apikey=[redacted]: status 403"
+        );
+        assert_eq!(redact("no key here", ""), "no key here");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::engine::process_args;
+use crate::serve::content_type_header;
+use crate::{
+    EngineOptions, Pool, ReportBundle, STDIO_PATH, input_paths, parse_args_from, process_csv, q8,
+    remove_units_at_acb, take_flag, watch_inputs,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Method, Response, Server};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8081";
+
+// The loaded ledger's latest run, or why it failed.
+type Loaded = Result<Arc<ReportBundle>, String>;
+
+// `POST /report` body: an export to process instead of the loaded ledger.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReportRequest {
+    csv: String,
+    #[serde(default)]
+    options: EngineOptions,
+}
+
+// `POST /simulate` body: a hypothetical disposal from one pool.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SimulateRequest {
+    asset: String,
+    units: Decimal,
+    proceeds_cad: Decimal,
+    #[serde(default)]
+    fee_cad: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct Simulation {
+    asset: String,
+    units: Decimal,
+    proceeds_cad: Decimal,
+    fee_cad: Decimal,
+    acb_disposed_cad: Decimal,
+    gain_cad: Decimal,
+    pool_units_after: Decimal,
+    pool_acb_cad_after: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+// `--daemon [--listen ADDR] <ledger.csv> [tax_year] ... [options]`: serves the
+// engine as a JSON API. The ledger on the command line is kept loaded (and
+// reloaded when it changes) for `GET /report`, `GET /pools` and
+// `POST /simulate`; `POST /report` processes an export sent in the request.
+pub(crate) fn daemon(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let listen = take_flag(&mut raw, "listen")?.unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+    let args = parse_args_from(raw)?;
+    if input_paths(&args).contains(&STDIO_PATH) {
+        return Err("daemon: inputs must be files, not stdin".into());
+    }
+    if args.db.is_some() {
+        return Err("daemon: --db is not supported".into());
+    }
+
+    let load = || process_args(&args).map(Arc::new).map_err(|e| e.to_string());
+    let loaded = Mutex::new(load());
+    if let Ok(Err(e)) = loaded.lock().as_deref() {
+        eprintln!("daemon: {}", e);
+    }
+    let server = Server::http(&listen).map_err(|e| format!("daemon: {}: {}", listen, e))?;
+    eprintln!(
+        "Serving the API for tax year {} at http://{}/ (Ctrl-C to stop)",
+        args.tax_year, listen
+    );
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            watch_inputs(&args, || {
+                let result = load();
+                if let Err(e) = &result {
+                    eprintln!("daemon: {}", e);
+                }
+                if let Ok(mut l) = loaded.lock() {
+                    *l = result;
+                }
+                eprintln!("Inputs changed; reloaded the ledger");
+            })
+        });
+        for mut request in server.incoming_requests() {
+            let mut body = String::new();
+            let (status, json) = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => {
+                    // A poisoned lock only means a reload panicked; the last
+                    // result is still usable.
+                    let current = loaded.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    handle(request.method(), request.url(), &body, &current)
+                }
+                Err(e) => error(400, &format!("reading request body: {}", e)),
+            };
+            let response = Response::from_string(json)
+                .with_status_code(status)
+                .with_header(content_type_header("application/json"));
+            if let Err(e) = request.respond(response) {
+                eprintln!("daemon: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Routes one request; returns the status code and JSON body.
+fn handle(method: &Method, url: &str, body: &str, loaded: &Loaded) -> (u16, String) {
+    let path = url.split('?').next().unwrap_or(url);
+    match (method, path) {
+        (Method::Post, "/report") => match serde_json::from_str::<ReportRequest>(body) {
+            Ok(req) => match process_csv(req.csv.as_bytes(), &req.options) {
+                Ok(bundle) => json(200, &bundle),
+                Err(e) => error(422, &e.to_string()),
+            },
+            Err(e) => error(400, &e.to_string()),
+        },
+        (Method::Get, "/report") => match loaded {
+            Ok(bundle) => json(200, bundle.as_ref()),
+            Err(e) => error(500, e),
+        },
+        (Method::Get, "/pools") => match loaded {
+            Ok(bundle) => json(200, &bundle.pools),
+            Err(e) => error(500, e),
+        },
+        (Method::Post, "/simulate") => match (loaded, serde_json::from_str(body)) {
+            (Err(e), _) => error(500, e),
+            (_, Err(e)) => error(400, &e.to_string()),
+            (Ok(bundle), Ok(req)) => match simulate(&bundle.pools, req) {
+                Ok(sim) => json(200, &sim),
+                Err(e) => error(422, &e.to_string()),
+            },
+        },
+        (_, "/report" | "/pools" | "/simulate") => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+// Disposes of `req.units` from a copy of the ending pool at its average cost,
+// as a sale for CAD would, without changing the loaded ledger.
+fn simulate(
+    pools: &std::collections::BTreeMap<Arc<str>, Pool>,
+    req: SimulateRequest,
+) -> Result<Simulation, Box<dyn Error>> {
+    let mut pool = pools
+        .get(req.asset.as_str())
+        .cloned()
+        .ok_or_else(|| format!("no pool for {}", req.asset))?;
+    let acb = remove_units_at_acb(&mut pool, req.units, &format!("simulate {}", req.asset))?;
+    Ok(Simulation {
+        gain_cad: q8(req.proceeds_cad - req.fee_cad - acb),
+        acb_disposed_cad: q8(acb),
+        pool_units_after: q8(pool.units),
+        pool_acb_cad_after: q8(pool.acb_cad),
+        asset: req.asset,
+        units: req.units,
+        proceeds_cad: req.proceeds_cad,
+        fee_cad: req.fee_cad,
+    })
+}
+
+fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> (u16, String) {
+    match serde_json::to_string(value) {
+        Ok(body) => (status, body),
+        Err(e) => error(500, &e.to_string()),
+    }
+}
+
+fn error(status: u16, message: &str) -> (u16, String) {
+    let body =
+        serde_json::to_string(&ErrorBody { error: message }).unwrap_or_else(|_| "{}".to_string());
+    (status, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "txid,refid,time,type,subtype,asset,amount,fee\n\
+                       T1,R1,2025-01-01 00:00:00,trade,tradespot,CAD,-100,0\n\
+                       T2,R1,2025-01-01 00:00:00,trade,tradespot,SOL,1,0\n";
+
+    #[test]
+    fn report_pools_and_simulate_over_json() {
+        let loaded: Loaded = Ok(Arc::new(
+            process_csv(CSV.as_bytes(), &EngineOptions::default()).unwrap(),
+        ));
+
+        let body = serde_json::json!({"csv": CSV, "options": {"tax_year": 2025}}).to_string();
+        let (status, json) = handle(&Method::Post, "/report", &body, &loaded);
+        assert_eq!(status, 200);
+        let bundle: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(bundle["pools"]["SOL"]["acb_cad"], "100");
+
+        let (status, json) = handle(&Method::Get, "/pools", "", &loaded);
+        assert_eq!(status, 200);
+        assert!(json.contains("\"SOL\":{\"units\":\"1\""));
+
+        let body = r#"{"asset": "SOL", "units": "0.25", "proceeds_cad": "40", "fee_cad": "1"}"#;
+        let (status, json) = handle(&Method::Post, "/simulate", body, &loaded);
+        assert_eq!(status, 200);
+        let sim: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(sim["acb_disposed_cad"], "25.00");
+        assert_eq!(sim["gain_cad"], "14.00");
+        assert_eq!(sim["pool_units_after"], "0.75");
+
+        let body = r#"{"asset": "SOL", "units": "2", "proceeds_cad": "40"}"#;
+        assert_eq!(handle(&Method::Post, "/simulate", body, &loaded).0, 422);
+        assert_eq!(handle(&Method::Get, "/simulate", "", &loaded).0, 405);
+        assert_eq!(handle(&Method::Get, "/nope", "", &loaded).0, 404);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{
+    Event, Interner, LedgerEntry, Pool, ProcessOptions, ReportRow, build_events,
+    build_trade_groups, parse_decimal, parse_time, sort_entries, split_conversions,
+};
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS ledger_rows (
+    txid TEXT NOT NULL,
+    refid TEXT NOT NULL,
+    time TEXT NOT NULL,
+    type TEXT NOT NULL,
+    subtype TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    PRIMARY KEY (txid, asset)
+);
+CREATE TABLE IF NOT EXISTS events (
+    tax_year INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    time TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    refid TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    PRIMARY KEY (tax_year, seq)
+);
+CREATE TABLE IF NOT EXISTS report_rows (
+    tax_year INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    time TEXT, refid TEXT, txid TEXT, event_type TEXT, asset TEXT,
+    units_in TEXT, units_out TEXT, proceeds_cad TEXT, acb_disposed_cad TEXT,
+    gain_cad TEXT, income_cad TEXT, acb_added_cad TEXT, fee_cad TEXT,
+    pool_units_after TEXT, pool_acb_cad_after TEXT,
+    price_source TEXT, fx_source TEXT, notes TEXT,
+    PRIMARY KEY (tax_year, seq)
+);
+CREATE TABLE IF NOT EXISTS pool_mutations (
+    tax_year INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    time TEXT NOT NULL,
+    refid TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    units_after TEXT NOT NULL,
+    acb_cad_after TEXT NOT NULL,
+    PRIMARY KEY (tax_year, seq)
+);
+CREATE TABLE IF NOT EXISTS ending_pools (
+    tax_year INTEGER NOT NULL,
+    asset TEXT NOT NULL,
+    units TEXT NOT NULL,
+    acb_cad TEXT NOT NULL,
+    PRIMARY KEY (tax_year, asset)
+);
+";
+
+// `--db` store: ledger rows accumulate across runs; everything derived is
+// replaced per tax year.
+pub(crate) struct Db {
+    conn: Connection,
+}
+
+impl Db {
+    pub(crate) fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Db { conn })
+    }
+
+    // Adds rows not seen before and returns the full stored history, so a run
+    // with only this year's export still sees every earlier year.
+    pub(crate) fn merge_ledger(
+        &mut self,
+        entries: &[LedgerEntry],
+    ) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO ledger_rows
+                 (txid, refid, time, type, subtype, asset, amount, fee)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for e in entries {
+                insert.execute(params![
+                    e.txid,
+                    e.refid,
+                    e.time.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+                    e.row_type,
+                    e.subtype,
+                    e.asset,
+                    e.amount.to_string(),
+                    e.fee.to_string(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT txid, refid, time, type, subtype, asset, amount, fee FROM ledger_rows",
+        )?;
+        let rows = stmt.query_map([], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, String>(2)?,
+                r.get::<_, String>(3)?,
+                r.get::<_, String>(4)?,
+                r.get::<_, String>(5)?,
+                r.get::<_, String>(6)?,
+                r.get::<_, String>(7)?,
+            ))
+        })?;
+        let mut interner = Interner::default();
+        let mut out = Vec::new();
+        for row in rows {
+            let (txid, refid, time, row_type, subtype, asset, amount, fee) = row?;
+            let amount = parse_decimal(&amount)?;
+            let fee = parse_decimal(&fee)?;
+            out.push(LedgerEntry {
+                txid: txid.into(),
+                refid: interner.intern(&refid),
+                time: parse_time(&time)?,
+                row_type,
+                subtype,
+                asset: interner.intern(&asset),
+                amount,
+                fee,
+                net_delta: amount - fee,
+                balance: None,
+                wallet: None,
+                utc_offset: None,
+            });
+        }
+        sort_entries(&mut out);
+        Ok(out)
+    }
+
+    pub(crate) fn store_events(
+        &mut self,
+        entries: &[LedgerEntry],
+        opts: &ProcessOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut entries = entries.to_vec();
+        let conversions = split_conversions(&mut entries, &opts.wraps);
+        let groups = build_trade_groups(&entries, &*opts.jurisdiction, opts.tax_year)?;
+        let events = build_events(&entries, &groups, &conversions, opts);
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM events WHERE tax_year = ?1", [opts.tax_year])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO events (tax_year, seq, time, kind, refid, detail)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for (seq, ev) in events.iter().enumerate() {
+                let (time, kind, refid, detail) = match ev {
+                    Event::Trade(g) | Event::Conversion(g) => (
+                        g.time,
+                        if matches!(ev, Event::Trade(_)) {
+                            "trade"
+                        } else {
+                            "conversion"
+                        },
+                        g.refid.clone(),
+                        g.entries
+                            .iter()
+                            .map(|e| format!("{} {}", e.net_delta, e.asset))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    ),
+                    Event::Entry(e) => (
+                        e.time,
+                        "entry",
+                        e.refid.clone(),
+                        format!("{}/{} {} {}", e.row_type, e.subtype, e.net_delta, e.asset),
+                    ),
+                    Event::Migration(m) => (
+                        m.time,
+                        "migration",
+                        "".into(),
+                        format!("{} -> {} x{}", m.from, m.to, m.factor),
+                    ),
+                    Event::DeemedDisposition(t) => {
+                        (*t, "deemed_disposition", "".into(), String::new())
+                    }
+                };
+                insert.execute(params![
+                    opts.tax_year,
+                    seq as i64,
+                    time.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+                    kind,
+                    refid,
+                    detail,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn store_results(
+        &mut self,
+        tax_year: i32,
+        report: &[ReportRow],
+        pools: &HashMap<Arc<str>, Pool>,
+    ) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        for table in ["report_rows", "pool_mutations", "ending_pools"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE tax_year = ?1", table),
+                [tax_year],
+            )?;
+        }
+        {
+            let mut insert_row = tx.prepare(
+                "INSERT INTO report_rows VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+            )?;
+            let mut insert_mutation = tx.prepare(
+                "INSERT INTO pool_mutations
+                 (tax_year, seq, time, refid, event_type, asset, units_after, acb_cad_after)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for (seq, r) in report.iter().enumerate() {
+                insert_row.execute(params![
+                    tax_year,
+                    seq as i64,
+                    r.time,
+                    r.refid,
+                    r.txid,
+                    r.event_type,
+                    r.asset,
+                    r.units_in,
+                    r.units_out,
+                    r.proceeds_cad,
+                    r.acb_disposed_cad,
+                    r.gain_cad,
+                    r.income_cad,
+                    r.acb_added_cad,
+                    r.fee_cad,
+                    r.pool_units_after,
+                    r.pool_acb_cad_after,
+                    r.price_source,
+                    r.fx_source,
+                    r.notes,
+                ])?;
+                if !r.pool_units_after.is_empty() {
+                    insert_mutation.execute(params![
+                        tax_year,
+                        seq as i64,
+                        r.time,
+                        r.refid,
+                        r.event_type,
+                        r.asset,
+                        r.pool_units_after,
+                        r.pool_acb_cad_after,
+                    ])?;
+                }
+            }
+            let mut insert_pool = tx.prepare(
+                "INSERT INTO ending_pools (tax_year, asset, units, acb_cad) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (asset, pool) in pools {
+                insert_pool.execute(params![
+                    tax_year,
+                    asset,
+                    pool.units.to_string(),
+                    pool.acb_cad.to_string(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::entry;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn ledger_rows_accumulate_across_runs() {
+        let mut db = Db::open(":memory:").unwrap();
+        db.merge_ledger(&[entry(
+            "2023-01-01 00:00:00",
+            "T1",
+            "RT1",
+            "deposit",
+            "",
+            "BTC",
+            "1.5",
+            "0",
+        )])
+        .unwrap();
+        let all = db
+            .merge_ledger(&[
+                entry(
+                    "2023-01-01 00:00:00",
+                    "T1",
+                    "RT1",
+                    "deposit",
+                    "",
+                    "BTC",
+                    "1.5",
+                    "0",
+                ),
+                entry(
+                    "2025-01-01 00:00:00",
+                    "T2",
+                    "RT2",
+                    "deposit",
+                    "",
+                    "ETH",
+                    "1.5",
+                    "0",
+                ),
+            ])
+            .unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(&*all[0].txid, "T1");
+        assert_eq!(all[1].amount, dec!(1.5));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::table::{Style, Table, paint};
+use crate::{AssetTotals, ReportRow, asset_totals, cad, parse_decimal, read_report, use_color};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::Arc;
+
+// A row is matched across reports by the ledger row it came from and what it
+// records.
+type RowKey = (Arc<str>, Arc<str>, String, Arc<str>);
+
+#[derive(Debug, PartialEq)]
+struct FieldChange {
+    refid: Arc<str>,
+    event_type: String,
+    asset: Arc<str>,
+    field: &'static str,
+    old: String,
+    new: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ReportDiff {
+    old_rows: usize,
+    new_rows: usize,
+    removed_refids: Vec<Arc<str>>,
+    added_refids: Vec<Arc<str>>,
+    // Rows of refids found in both reports that only one of them has.
+    removed_rows: usize,
+    added_rows: usize,
+    changes: Vec<FieldChange>,
+    old_totals: AssetTotals,
+    new_totals: AssetTotals,
+}
+
+// `diff [--no-color] <old_report.csv> <new_report.csv>`: compares two reports
+// written by the main command, e.g. before and after Kraken re-issues an
+// export.
+pub(crate) fn run(raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut no_color = false;
+    let mut paths = Vec::new();
+    for arg in raw {
+        match arg.as_str() {
+            "--no-color" => no_color = true,
+            _ if arg.starts_with("--") => return Err(format!("diff: unknown flag {}", arg).into()),
+            _ => paths.push(arg),
+        }
+    }
+    let [old_path, new_path] = paths.as_slice() else {
+        return Err("usage: kraken_acb diff [--no-color] <old_report.csv> <new_report.csv>".into());
+    };
+    let diff = diff_reports(&read_report(old_path)?, &read_report(new_path)?)?;
+    let color = use_color(std::io::stdout(), no_color);
+    print!("{}", render(&diff, old_path, new_path, color)?);
+    Ok(())
+}
+
+// Amount and time columns compared between matched rows.
+fn compared_fields(r: &ReportRow) -> [(&'static str, &str); 11] {
+    [
+        ("time", &r.time),
+        ("units_in", &r.units_in),
+        ("units_out", &r.units_out),
+        ("proceeds_cad", &r.proceeds_cad),
+        ("acb_disposed_cad", &r.acb_disposed_cad),
+        ("gain_cad", &r.gain_cad),
+        ("income_cad", &r.income_cad),
+        ("acb_added_cad", &r.acb_added_cad),
+        ("fee_cad", &r.fee_cad),
+        ("pool_units_after", &r.pool_units_after),
+        ("pool_acb_cad_after", &r.pool_acb_cad_after),
+    ]
+}
+
+// Amounts are compared as numbers, so `30.0` and `30.00` are the same.
+fn same_value(old: &str, new: &str) -> bool {
+    if old == new {
+        return true;
+    }
+    match (parse_decimal(old), parse_decimal(new)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+impl ReportDiff {
+    // Whether the reports hold the same rows with the same values.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.removed_refids.is_empty()
+            && self.added_refids.is_empty()
+            && self.removed_rows + self.added_rows == 0
+            && self.changes.is_empty()
+    }
+}
+
+fn totals(report: &[ReportRow]) -> Result<AssetTotals, Box<dyn Error>> {
+    let mut sum = AssetTotals::default();
+    for t in asset_totals(report)?.values() {
+        sum.proceeds_cad += t.proceeds_cad;
+        sum.acb_disposed_cad += t.acb_disposed_cad;
+        sum.gain_cad += t.gain_cad;
+        sum.income_cad += t.income_cad;
+    }
+    Ok(sum)
+}
+
+pub(crate) fn diff_reports(
+    old: &[ReportRow],
+    new: &[ReportRow],
+) -> Result<ReportDiff, Box<dyn Error>> {
+    let key = |r: &ReportRow| -> RowKey {
+        (
+            r.refid.clone(),
+            r.txid.clone(),
+            r.event_type.clone(),
+            r.asset.clone(),
+        )
+    };
+    let old_refids: BTreeSet<Arc<str>> = old.iter().map(|r| r.refid.clone()).collect();
+    let new_refids: BTreeSet<Arc<str>> = new.iter().map(|r| r.refid.clone()).collect();
+    let mut diff = ReportDiff {
+        old_rows: old.len(),
+        new_rows: new.len(),
+        removed_refids: old_refids.difference(&new_refids).cloned().collect(),
+        added_refids: new_refids.difference(&old_refids).cloned().collect(),
+        old_totals: totals(old)?,
+        new_totals: totals(new)?,
+        ..ReportDiff::default()
+    };
+
+    // Rows with the same key (e.g. repeated dust sweeps) pair up in order.
+    let mut unmatched: BTreeMap<RowKey, VecDeque<&ReportRow>> = BTreeMap::new();
+    for r in old {
+        unmatched.entry(key(r)).or_default().push_back(r);
+    }
+    for r in new {
+        let Some(o) = unmatched.get_mut(&key(r)).and_then(|q| q.pop_front()) else {
+            if old_refids.contains(&r.refid) {
+                diff.added_rows += 1;
+            }
+            continue;
+        };
+        for ((field, old_value), (_, new_value)) in
+            compared_fields(o).into_iter().zip(compared_fields(r))
+        {
+            if !same_value(old_value, new_value) {
+                diff.changes.push(FieldChange {
+                    refid: r.refid.clone(),
+                    event_type: r.event_type.clone(),
+                    asset: r.asset.clone(),
+                    field,
+                    old: old_value.to_string(),
+                    new: new_value.to_string(),
+                });
+            }
+        }
+    }
+    diff.removed_rows = unmatched
+        .values()
+        .flatten()
+        .filter(|r| new_refids.contains(&r.refid))
+        .count();
+    Ok(diff)
+}
+
+pub(crate) fn render(
+    diff: &ReportDiff,
+    old_path: &str,
+    new_path: &str,
+    color: bool,
+) -> Result<String, Box<dyn Error>> {
+    let heading = |title: &str| paint(title, Style::Heading, color);
+    let mut out = String::new();
+    writeln!(out, "{}", heading("=== REPORT DIFF ==="))?;
+    writeln!(out, "Old: {} ({} rows)", old_path, diff.old_rows)?;
+    writeln!(out, "New: {} ({} rows)", new_path, diff.new_rows)?;
+
+    let mut totals_table = Table::new(&["Total", "Old (CAD)", "New (CAD)", "Change (CAD)"]);
+    let (o, n) = (&diff.old_totals, &diff.new_totals);
+    for (label, old, new, signed) in [
+        ("Proceeds", o.proceeds_cad, n.proceeds_cad, false),
+        (
+            "ACB disposed",
+            o.acb_disposed_cad,
+            n.acb_disposed_cad,
+            false,
+        ),
+        ("Net capital gain/loss", o.gain_cad, n.gain_cad, true),
+        ("Income", o.income_cad, n.income_cad, false),
+    ] {
+        let change = new - old;
+        let style = |x: Decimal| {
+            if signed {
+                Style::signed(x)
+            } else {
+                Style::Plain
+            }
+        };
+        totals_table.row(vec![
+            (label.to_string(), Style::Plain),
+            (cad(old), style(old)),
+            (cad(new), style(new)),
+            (cad(change), Style::signed(change)),
+        ]);
+    }
+    write!(out, "\n{}", totals_table.render(color))?;
+
+    for (label, refids) in [
+        ("Refids only in the old report", &diff.removed_refids),
+        ("Refids only in the new report", &diff.added_refids),
+    ] {
+        writeln!(out, "\n{}: {}", label, refids.len())?;
+        for refid in refids {
+            writeln!(out, "  {}", refid)?;
+        }
+    }
+    if diff.removed_rows + diff.added_rows > 0 {
+        writeln!(
+            out,
+            "\nRows of shared refids only in the old report: {}, only in the new report: {}",
+            diff.removed_rows, diff.added_rows
+        )?;
+    }
+
+    writeln!(out, "\nChanged values: {}", diff.changes.len())?;
+    if !diff.changes.is_empty() {
+        // The identifying columns share the left-aligned first column.
+        let mut changes_table = Table::new(&["Refid / event / asset / field", "Old", "New"]);
+        for c in &diff.changes {
+            changes_table.row(vec![
+                (
+                    format!("{} {} {} {}", c.refid, c.event_type, c.asset, c.field),
+                    Style::Plain,
+                ),
+                (c.old.clone(), Style::Plain),
+                (c.new.clone(), Style::Warning),
+            ]);
+        }
+        write!(out, "{}", changes_table.render(color))?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn row(refid: &str, event_type: &str, proceeds: &str, gain: &str) -> ReportRow {
+        ReportRow {
+            refid: refid.into(),
+            txid: format!("T{}", refid).into(),
+            event_type: event_type.to_string(),
+            asset: "BTC".into(),
+            proceeds_cad: proceeds.to_string(),
+            gain_cad: gain.to_string(),
+            ..ReportRow::default()
+        }
+    }
+
+    #[test]
+    fn matches_rows_and_reports_changes_refids_and_totals() {
+        let old = vec![
+            row("R1", "trade_disposition", "100.0", "30.0"),
+            row("R2", "trade_disposition", "50", "-5"),
+            row("R3", "withdrawal", "", ""),
+        ];
+        let new = vec![
+            row("R1", "trade_disposition", "100.00", "30.00"),
+            row("R2", "trade_disposition", "55", "0"),
+            row("R2", "warning_transfer_in_zero_acb", "", ""),
+            row("R4", "trade_disposition", "10", "10"),
+        ];
+        let diff = diff_reports(&old, &new).unwrap();
+        assert_eq!(diff.removed_refids, vec![Arc::<str>::from("R3")]);
+        assert_eq!(diff.added_refids, vec![Arc::<str>::from("R4")]);
+        assert_eq!((diff.removed_rows, diff.added_rows), (0, 1));
+        let fields: Vec<_> = diff
+            .changes
+            .iter()
+            .map(|c| (c.refid.as_ref(), c.field, c.new.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![("R2", "proceeds_cad", "55"), ("R2", "gain_cad", "0")]
+        );
+        assert_eq!(diff.old_totals.gain_cad, dec!(25));
+        assert_eq!(diff.new_totals.gain_cad, dec!(40));
+
+        let text = render(&diff, "old.csv", "new.csv", false).unwrap();
+        assert!(text.contains("Net capital gain/loss      25.00      40.00         15.00\n"));
+        assert!(text.contains("Refids only in the old report: 1\n  R3\n"));
+        assert!(text.contains("R2 trade_disposition BTC proceeds_cad   50   55\n"));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::basis::{self, CostBasisEngine};
+use crate::import::{self, ImportFormat};
+use crate::jurisdiction;
+use crate::prices::{self, DailyCloses, PriceChain};
+use crate::round_trip::{self, RoundTrip};
+use crate::{
+    AdjustmentMode, BalanceMismatch, CsvLocale, DEFAULT_FALLBACK_FX, DEFAULT_TAX_YEAR, DataIssue,
+    DonationProceeds, Exemptions, FeeMode, FeeRow, FxAuditRow, IgnoredRowSummary, Interner,
+    LineageRow, Pool, ProcessOptions, ProcessOutput, RebateMode, ReportRow, Rounding,
+    StablecoinDispositions, StalePrices, TieBreak, Totals, decode_text, exit_code, explain_failure,
+    fiat_stablecoins, ledger_gaps, localize_entries, match_transfers, merge_inputs,
+    parse_date_or_time, parse_delimiter, parse_timezone, process, reconcile_balances,
+    repair_trade_groups, sort_entries,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Options for an in-memory run; the JSON counterpart of the command-line
+// flags that do not name files. Mode names are those the flags accept.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineOptions {
+    pub tax_year: i32,
+    pub fallback_usd_cad_fx: Decimal,
+    pub import_format: String,
+    // TOML column mapping, for `import_format = "generic"`.
+    pub mapping: Option<String>,
+    pub fee_mode: String,
+    pub rebate_mode: String,
+    pub adjustment_mode: String,
+    pub donation_proceeds: String,
+    pub deemed_disposition_date: Option<String>,
+    pub timezone: Option<String>,
+    pub delimiter: Option<String>,
+    pub decimal_comma: Option<bool>,
+    pub match_transfers: bool,
+    pub repair_trades: bool,
+    pub max_price_age: Option<u32>,
+    pub stale_prices: String,
+    // Minutes; 0 turns round-trip detection off.
+    pub round_trip_window: u32,
+    // `--treat-stablecoins-as-fiat`.
+    pub treat_stablecoins_as_fiat: Vec<String>,
+    pub stablecoin_dispositions: String,
+    // `--rounding-strategy`, `--decimal-places` and `--no-rounding`.
+    pub rounding_strategy: String,
+    pub decimal_places: Option<String>,
+    pub no_rounding: bool,
+    // `--tie-break`.
+    pub tie_break: String,
+    pub check_invariants: bool,
+    // `--exempt-fx-under` and `--exempt-spends-under`, in CAD.
+    pub exempt_fx_under: Option<Decimal>,
+    pub exempt_spends_under: Option<Decimal>,
+    // `--price-chain`; the network links need the `cli` feature.
+    pub price_chain: Option<String>,
+    pub offline: bool,
+    pub cost_basis: String,
+    // Lot selection file text, for `cost_basis = "spec-id"`.
+    pub lot_selection: Option<String>,
+    pub jurisdiction: String,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            tax_year: DEFAULT_TAX_YEAR,
+            fallback_usd_cad_fx: Decimal::from_str(DEFAULT_FALLBACK_FX).expect("default FX parses"),
+            import_format: "kraken".to_string(),
+            mapping: None,
+            fee_mode: FeeMode::Embedded.as_str().to_string(),
+            rebate_mode: RebateMode::Cost.as_str().to_string(),
+            adjustment_mode: AdjustmentMode::Acquisition.as_str().to_string(),
+            donation_proceeds: DonationProceeds::Fmv.as_str().to_string(),
+            deemed_disposition_date: None,
+            timezone: None,
+            delimiter: None,
+            decimal_comma: None,
+            match_transfers: false,
+            repair_trades: false,
+            max_price_age: None,
+            stale_prices: StalePrices::Warn.as_str().to_string(),
+            round_trip_window: round_trip::DEFAULT_WINDOW_MINUTES as u32,
+            treat_stablecoins_as_fiat: Vec::new(),
+            stablecoin_dispositions: StablecoinDispositions::Report.as_str().to_string(),
+            rounding_strategy: "half-up".to_string(),
+            decimal_places: None,
+            no_rounding: false,
+            tie_break: TieBreak::Id.as_str().to_string(),
+            check_invariants: false,
+            exempt_fx_under: None,
+            exempt_spends_under: None,
+            price_chain: None,
+            offline: false,
+            cost_basis: "average".to_string(),
+            lot_selection: None,
+            jurisdiction: "canada".to_string(),
+        }
+    }
+}
+
+// Everything a run produces, for callers that render it themselves.
+#[derive(Debug, Serialize)]
+pub struct ReportBundle {
+    tax_year: i32,
+    totals: Totals,
+    report: Vec<ReportRow>,
+    fx_audit: Vec<FxAuditRow>,
+    audit: Vec<LineageRow>,
+    fees: Vec<FeeRow>,
+    round_trips: Vec<RoundTrip>,
+    ignored: Vec<IgnoredRowSummary>,
+    balance_mismatches: Vec<BalanceMismatch>,
+    data_quality: Vec<DataIssue>,
+    pub(crate) pools: BTreeMap<Arc<str>, Pool>,
+    matched_transfers: usize,
+    // The status the command line would exit with.
+    exit_code: u8,
+}
+
+// Processes one ledger export held in memory. Nothing is read from or written
+// to disk, so this is the entry point for the WebAssembly build.
+pub fn process_csv(bytes: &[u8], options: &EngineOptions) -> Result<ReportBundle, Box<dyn Error>> {
+    let selections = match &options.lot_selection {
+        Some(text) => Some(basis::parse_selections(text)?),
+        None => None,
+    };
+    let cost_basis = basis::by_name(&options.cost_basis, selections)?;
+    process_csv_with_cost_basis(bytes, options, cost_basis)
+}
+
+// As `process_csv`, but with a caller's own cost-basis engine in place of
+// the one `options.cost_basis` names.
+pub fn process_csv_with_cost_basis(
+    bytes: &[u8],
+    options: &EngineOptions,
+    cost_basis: Arc<dyn CostBasisEngine>,
+) -> Result<ReportBundle, Box<dyn Error>> {
+    let text = decode_text(bytes)?;
+    let format = ImportFormat::parse(&options.import_format)?;
+    let mapping = match &options.mapping {
+        Some(text) => Some(import::parse_generic_mapping(text)?),
+        None => None,
+    };
+    let locale = CsvLocale {
+        delimiter: match &options.delimiter {
+            Some(d) => Some(parse_delimiter(d)?),
+            None => None,
+        },
+        decimal_comma: options.decimal_comma,
+    };
+    let timezone = match &options.timezone {
+        Some(name) => Some(parse_timezone(name)?),
+        None => None,
+    };
+    let deemed_disposition = match &options.deemed_disposition_date {
+        Some(date) => Some(parse_date_or_time(date)?),
+        None => None,
+    };
+
+    let rows = import::parse_input(
+        format,
+        &text,
+        mapping.as_ref(),
+        "",
+        locale,
+        &mut Interner::default(),
+    )?;
+    let (mut entries, mut data_quality) = merge_inputs(vec![("input", format, rows)]);
+    sort_entries(&mut entries);
+    let balance_mismatches = reconcile_balances(&entries);
+    if let Some(tz) = timezone {
+        localize_entries(&mut entries, tz);
+    }
+    if options.repair_trades {
+        repair_trade_groups(&mut entries);
+    }
+    let matched_transfers = if options.match_transfers {
+        match_transfers(&mut entries)
+    } else {
+        0
+    };
+    let mut rounding = Rounding {
+        strategy: Rounding::parse_strategy(&options.rounding_strategy)?,
+        full_precision: options.no_rounding,
+        ..Rounding::default()
+    };
+    if let Some(places) = &options.decimal_places {
+        rounding.set_places(places)?;
+    }
+    let opts = ProcessOptions {
+        fee_mode: FeeMode::parse(&options.fee_mode)?,
+        rebate_mode: RebateMode::parse(&options.rebate_mode)?,
+        adjustment_mode: AdjustmentMode::parse(&options.adjustment_mode)?,
+        donation_proceeds: DonationProceeds::parse(&options.donation_proceeds)?,
+        deemed_disposition,
+        timezone,
+        max_price_age: options.max_price_age,
+        stale_prices: StalePrices::parse(&options.stale_prices)?,
+        round_trip_window: (options.round_trip_window > 0)
+            .then(|| chrono::Duration::minutes(i64::from(options.round_trip_window))),
+        price_chain: PriceChain::build(
+            options
+                .price_chain
+                .as_deref()
+                .unwrap_or(prices::DEFAULT_PRICE_CHAIN),
+            options.fallback_usd_cad_fx,
+            DailyCloses::new(),
+            DailyCloses::new(),
+            options.offline,
+        )?
+        .with_usd_par(fiat_stablecoins(
+            options.treat_stablecoins_as_fiat.iter().map(String::as_str),
+        )?),
+        stablecoin_dispositions: StablecoinDispositions::parse(&options.stablecoin_dispositions)?,
+        rounding,
+        tie_break: TieBreak::parse(&options.tie_break)?,
+        check_invariants: options.check_invariants,
+        exemptions: Exemptions {
+            fx_per_disposition: options.exempt_fx_under,
+            spends_under: options.exempt_spends_under,
+        },
+        cost_basis,
+        jurisdiction: jurisdiction::by_name(&options.jurisdiction)?,
+        ..ProcessOptions::new(options.tax_year, options.fallback_usd_cad_fx)
+    };
+    data_quality.extend(ledger_gaps(&entries));
+    let out = process(entries, &opts).map_err(|e| explain_failure(e, &data_quality))?;
+    Ok(ReportBundle::new(
+        options.tax_year,
+        out,
+        balance_mismatches,
+        data_quality,
+        matched_transfers,
+    ))
+}
+
+// Runs the inputs named on the command line, as the main command would, but
+// keeps the results in memory.
+#[cfg(feature = "cli")]
+pub(crate) fn process_args(args: &crate::Args) -> Result<ReportBundle, Box<dyn Error>> {
+    let (mut entries, mut data_quality) = crate::load_inputs(args, &mut Interner::default())?;
+    let balance_mismatches = reconcile_balances(&entries);
+    let prepared = crate::prepare_entries(args, &mut entries)?;
+    data_quality.extend(prepared.gaps);
+    let opts = crate::process_options(args, &entries)?;
+    let out = process(entries, &opts).map_err(|e| explain_failure(e, &data_quality))?;
+    Ok(ReportBundle::new(
+        args.tax_year,
+        out,
+        balance_mismatches,
+        data_quality,
+        prepared.matched_transfers,
+    ))
+}
+
+impl ReportBundle {
+    fn new(
+        tax_year: i32,
+        out: ProcessOutput,
+        balance_mismatches: Vec<BalanceMismatch>,
+        data_quality: Vec<DataIssue>,
+        matched_transfers: usize,
+    ) -> Self {
+        let ignored_count = out.ignored.iter().map(|s| s.count).sum();
+        ReportBundle {
+            tax_year,
+            exit_code: exit_code(
+                out.totals.warning_count + out.totals.stale_price_count,
+                ignored_count,
+                balance_mismatches.len(),
+                data_quality.iter().filter(|d| !d.is_note()).count(),
+            ),
+            totals: out.totals,
+            report: out.report,
+            fx_audit: out.fx_audit,
+            audit: out.lineage,
+            fees: out.fees,
+            round_trips: out.round_trips,
+            ignored: out.ignored,
+            balance_mismatches,
+            data_quality,
+            pools: out.pools.into_iter().collect(),
+            matched_transfers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_in_json_bundle_out() {
+        let csv = "txid,refid,time,type,subtype,asset,amount,fee\n\
+                   T1,R1,2025-01-01 00:00:00,trade,tradespot,CAD,-100,0\n\
+                   T2,R1,2025-01-01 00:00:00,trade,tradespot,SOL,1,0\n\
+                   T3,R2,2025-02-01 00:00:00,trade,tradespot,SOL,-0.5,0\n\
+                   T4,R2,2025-02-01 00:00:00,trade,tradespot,CAD,80,0\n";
+        let options: EngineOptions =
+            serde_json::from_str(r#"{"tax_year": 2025, "timezone": "America/Toronto"}"#).unwrap();
+        assert_eq!(options.fee_mode, "embedded");
+        let bundle = process_csv(csv.as_bytes(), &options).unwrap();
+        let json: serde_json::Value = serde_json::to_value(&bundle).unwrap();
+        assert_eq!(json["totals"]["capital_gain_cad"], "30.0");
+        assert_eq!(json["report"][0]["event_type"], "trade_disposition");
+        assert_eq!(json["report"][0]["time"], "2025-01-31T19:00:00-05:00");
+        assert_eq!(json["pools"]["SOL"]["units"], "0.5");
+        assert_eq!(json["exit_code"], 0);
+        assert!(serde_json::from_str::<EngineOptions>(r#"{"fee_mod": "acb"}"#).is_err());
+    }
+}
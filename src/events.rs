@@ -0,0 +1,1316 @@
+use crate::mutation::{Mutation, PoolBook, PoolMutation};
+use crate::prices::Lookup;
+use crate::{
+    AdjustmentMode, DonationProceeds, Event, FeeRow, FxAuditRow, IgnoredRowSummary,
+    IncomeValuation, LedgerEntry, LineageRow, Migration, Pooling, PriceState, ProcessOptions,
+    RebateMode, ReportRow, StablecoinDispositions, Totals, TradeGroup, WrapPolicy, add_note,
+    asset_value_cad, book_income, book_zero_proceeds_disposition, check_price_age, close_value_cad,
+    event_sort_keys, fee_row, flag_superficial_loss, fx_audit_row, income_value_cad,
+    is_earn_internal, is_nft_row, is_reward_row, is_yield_asset, make_row, q2, q8, record_ignored,
+    record_lineage, set_trade_fee, set_valuation_sources, split_trade_legs, take_rebates,
+    trade_amounts, update_prices_from_trade, wall_clock,
+};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+// The books one `process` run keeps while it applies the events in order.
+pub(crate) struct Pass<'a> {
+    pub(crate) opts: &'a ProcessOptions,
+    pub(crate) acquisitions: &'a HashMap<Arc<str>, Vec<(NaiveDateTime, Arc<str>)>>,
+    pub(crate) merged_txids: &'a HashSet<Arc<str>>,
+    pub(crate) merged_from: &'a HashMap<Arc<str>, Arc<str>>,
+    pub(crate) wallet_moves: &'a HashMap<Arc<str>, Arc<str>>,
+    pub(crate) book: PoolBook,
+    pub(crate) state: PriceState,
+    pub(crate) report: Vec<ReportRow>,
+    pub(crate) fx_audit: Vec<FxAuditRow>,
+    pub(crate) lineage: Vec<LineageRow>,
+    pub(crate) fees: Vec<FeeRow>,
+    pub(crate) ignored: Vec<IgnoredRowSummary>,
+    pub(crate) totals: Totals,
+    pub(crate) traded: HashSet<Arc<str>>,
+    // refid/txid for rows not tied to a ledger row (migrations, deemed dispositions).
+    pub(crate) no_id: Arc<str>,
+}
+
+impl Pass<'_> {
+    pub(crate) fn event(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+        let prices = self.opts.price_chain.at(event_sort_keys(&ev).0);
+        if self.opts.pooling == Pooling::PerWallet {
+            self.book.set_wallet(match &ev {
+                Event::Trade(g) | Event::Conversion(g) => g.entries[0].wallet.clone(),
+                Event::Entry(e) => e.wallet.clone(),
+                Event::Migration(_) | Event::DeemedDisposition(_) => None,
+            });
+        }
+        match ev {
+            Event::Trade(g) => self.trade(g, prices),
+            Event::Conversion(g) => self.conversion(g, prices),
+            Event::Migration(m) => self.migration(m),
+            Event::DeemedDisposition(t) => self.deemed_disposition(t, prices),
+            Event::Entry(e) => self.entry(e, prices),
+        }
+    }
+
+    fn entry(&mut self, e: LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        match (e.row_type.as_str(), e.subtype.as_str()) {
+            ("dividend", _) | ("earn", "dividend") => self.dividend(&e, prices),
+            ("earn", sub) if is_yield_asset(&e.asset) && !is_earn_internal(sub) => {
+                self.yield_interest(&e, prices)
+            }
+            _ if is_reward_row(&e) => self.reward(&e, prices),
+            ("credit", _) | ("bonus", _) | ("referral", _) | ("earn", "referral" | "bonus") => {
+                self.credit(&e, prices)
+            }
+            (t, _) if is_nft_row(&e) && t != "nfttrade" => self.nft_charge(&e, prices),
+            ("adjustment", _) => self.adjustment(&e, prices),
+            ("internal", _) => {
+                // Classified as internal by a user rule.
+                Ok(())
+            }
+            ("earn", "autoallocation") | ("earn", "allocation") | ("earn", "deallocation") => {
+                // Internal wallet movements; pooled holdings are unchanged.
+                Ok(())
+            }
+            ("deposit", "") => self.deposit(&e),
+            ("withdrawal", "") | ("transfer", "out" | "in") => self.withdrawal(&e, prices),
+            ("spend", _) | ("withdrawal", "gift" | "donation") => self.spend(&e, prices),
+            _ => self.unrecognized(&e, prices),
+        }
+    }
+
+    fn trade(&mut self, g: TradeGroup, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let acquisitions = self.acquisitions;
+        let jurisdiction = &*opts.jurisdiction;
+        let (mut out, mut inn) = split_trade_legs(&g)?;
+        if out.asset == inn.asset {
+            return self.merged_wrap(&g, &out, &inn);
+        }
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            lineage,
+            fees,
+            totals,
+            traded,
+            ..
+        } = self;
+        let wrap_note = opts
+            .wraps
+            .iter()
+            .find(|w| {
+                w.policy == WrapPolicy::Taxable
+                    && ((w.wrapped == out.asset && w.underlying[0] == inn.asset)
+                        || (w.wrapped == inn.asset && w.underlying[0] == out.asset))
+            })
+            .map(|_| {
+                format!(
+                    "Converted {} to {}; a disposition under the taxable wrap policy",
+                    out.asset, inn.asset
+                )
+            });
+        let rebates = if opts.rebate_mode == RebateMode::Income {
+            take_rebates([&mut out, &mut inn])
+        } else {
+            Vec::new()
+        };
+        let out_units = -out.net_delta;
+        let in_units = inn.net_delta;
+        let fill = opts.trade_fills.get(&g.refid);
+        let fill_units = fill.and_then(|f| f.leg_units(&out, &inn));
+        let fill_note = fill.map(|f| match fill_units {
+            Some(_) => f.note(),
+            None => "trades.csv lists this refid with other amounts; not used".to_string(),
+        });
+        let quote_is_in = fill.filter(|_| fill_units.is_some()).map(|f| !f.buy);
+
+        let amounts = trade_amounts(
+            &out,
+            &inn,
+            state,
+            prices,
+            opts.fee_mode,
+            quote_is_in,
+            &g.refid,
+        )?;
+        let kind = if g.entries.iter().any(is_nft_row) {
+            "nft"
+        } else {
+            "trade"
+        };
+        let out_cad = amounts.acb_added_cad;
+        let in_cad = amounts.proceeds_cad;
+        if opts.in_year(g.time) {
+            totals.trade_fee_expense_cad += amounts.expensed_fee_cad;
+            totals.fee_rebate_cad += amounts.rebate_cad;
+            totals.activity.trades += 1;
+            totals.activity.volume_cad += in_cad;
+            for (leg, fee_cad) in [&out, &inn].into_iter().zip(amounts.leg_fees_cad) {
+                if leg.fee > dec!(0) {
+                    fees.push(fee_row(
+                        g.stamp(),
+                        &g.refid,
+                        "trading",
+                        &leg.asset,
+                        leg.fee,
+                        Some(fee_cad),
+                        &mut totals.fees,
+                    ));
+                }
+            }
+            traded.extend(
+                [&out.asset, &inn.asset]
+                    .into_iter()
+                    .filter(|a| a.as_ref() != "CAD")
+                    .cloned(),
+            );
+        }
+
+        if &*out.asset != "CAD" {
+            let pool = book.apply(
+                PoolMutation::new(
+                    g.time,
+                    &g.refid,
+                    &out.asset,
+                    Mutation::Remove { units: out_units },
+                ),
+                &format!("trade disposition {} {}", g.refid, out.asset),
+            )?;
+            let acb_disposed = pool.cost_cad;
+            let gain = in_cad - acb_disposed;
+
+            if opts.in_year(g.time) {
+                let mut rr = make_row(
+                    g.stamp(),
+                    &g.refid,
+                    &g.txid,
+                    &format!("{}_disposition", kind),
+                    &out.asset,
+                );
+                rr.units_out = out_units.to_string();
+                rr.set_disposal(in_cad, acb_disposed, gain);
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                set_trade_fee(
+                    &mut rr,
+                    amounts.disposition_fee_cad + amounts.expensed_fee_cad,
+                    !amounts.expensed_fee_cad.is_zero(),
+                );
+                set_valuation_sources(&mut rr, &amounts.disposition_val);
+                if let Some(note) = &fill_note {
+                    add_note(&mut rr, note);
+                }
+                if let Some(note) = &wrap_note {
+                    add_note(&mut rr, note);
+                }
+                let skipped = opts.stablecoin_dispositions == StablecoinDispositions::Skip
+                    && prices.at_par(&out.asset);
+                if !skipped {
+                    flag_superficial_loss(&mut rr, gain, g.time, acquisitions, jurisdiction);
+                }
+                totals.stale_price_count += usize::from(check_price_age(
+                    &mut rr,
+                    &amounts.disposition_val,
+                    g.time,
+                    opts,
+                )?);
+                fx_audit.extend(fx_audit_row(&rr, &amounts.disposition_val));
+                if skipped {
+                    rr.gain_cad.clear();
+                    rr.booked = None;
+                    add_note(
+                        &mut rr,
+                        "Stablecoin treated as fiat; not reported as a disposition",
+                    );
+                    report.push(rr);
+                } else {
+                    record_lineage(lineage, totals, &rr, g.time, in_cad, &pool, opts.timezone)?;
+                    report.push(rr);
+
+                    totals.proceeds_cad += in_cad;
+                    totals.acb_disposed_cad += acb_disposed;
+                    totals.capital_gain_cad += gain;
+                }
+            }
+        }
+
+        if &*inn.asset != "CAD" {
+            let pool = book.apply(
+                PoolMutation::new(
+                    g.time,
+                    &g.refid,
+                    &inn.asset,
+                    Mutation::Add {
+                        units: in_units,
+                        cost_cad: out_cad,
+                    },
+                ),
+                "",
+            )?;
+
+            if opts.in_year(g.time) {
+                let mut rr = make_row(
+                    g.stamp(),
+                    &g.refid,
+                    &g.txid,
+                    &format!("{}_acquisition", kind),
+                    &inn.asset,
+                );
+                rr.units_in = in_units.to_string();
+                rr.acb_added_cad = out_cad.to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                let expensed = if &*out.asset == "CAD" {
+                    amounts.expensed_fee_cad
+                } else {
+                    dec!(0)
+                };
+                set_trade_fee(
+                    &mut rr,
+                    amounts.acquisition_fee_cad + expensed,
+                    !expensed.is_zero(),
+                );
+                set_valuation_sources(&mut rr, &amounts.acquisition_val);
+                if let Some(note) = &fill_note {
+                    add_note(&mut rr, note);
+                }
+                if let Some(note) = &wrap_note {
+                    add_note(&mut rr, note);
+                }
+                totals.stale_price_count += usize::from(check_price_age(
+                    &mut rr,
+                    &amounts.acquisition_val,
+                    g.time,
+                    opts,
+                )?);
+                fx_audit.extend(fx_audit_row(&rr, &amounts.acquisition_val));
+                report.push(rr);
+            }
+        }
+
+        update_prices_from_trade(&out, &inn, fill_units, state, prices)?;
+
+        for (asset, units) in rebates {
+            let rebate_val = asset_value_cad(
+                &asset,
+                units,
+                state,
+                prices,
+                &format!("trade {} fee rebate", g.refid),
+            )?;
+            let rebate_cad = rebate_val.cad;
+            let mut rr = make_row(
+                g.stamp(),
+                &g.refid,
+                &g.txid,
+                "trade_fee_rebate_income",
+                &asset,
+            );
+            rr.units_in = units.to_string();
+            rr.income_cad = rebate_cad.to_string();
+            if &*asset != "CAD" {
+                let pool = book.apply(
+                    PoolMutation::new(
+                        g.time,
+                        &g.refid,
+                        &asset,
+                        Mutation::Income {
+                            units,
+                            value_cad: rebate_cad,
+                        },
+                    ),
+                    "",
+                )?;
+                rr.acb_added_cad = rebate_cad.to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+            }
+
+            if opts.in_year(g.time) {
+                set_valuation_sources(&mut rr, &rebate_val);
+                totals.stale_price_count +=
+                    usize::from(check_price_age(&mut rr, &rebate_val, g.time, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &rebate_val));
+                report.push(rr);
+                totals.fee_rebate_cad += rebate_cad;
+                totals.rebate_income_cad += rebate_cad;
+            }
+        }
+        Ok(())
+    }
+
+    // A wrap or unwrap under the merge policy: one pool, so only units the
+    // conversion cost leave it.
+    fn merged_wrap(
+        &mut self,
+        g: &TradeGroup,
+        out: &LedgerEntry,
+        inn: &LedgerEntry,
+    ) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let merged_txids = self.merged_txids;
+        let merged_from = self.merged_from;
+        let Pass {
+            book,
+            report,
+            lineage,
+            totals,
+            ..
+        } = self;
+        let kind = match (
+            merged_txids.contains(&out.txid),
+            merged_txids.contains(&inn.txid),
+        ) {
+            (false, true) => "wrap",
+            (true, false) => "unwrap",
+            _ => {
+                return Err(format!("trade refid {} has two {} legs", g.refid, out.asset).into());
+            }
+        };
+        let wrapped = merged_from[&g.refid].clone();
+        let out_units = -out.net_delta;
+        let in_units = inn.net_delta;
+        let lost = out_units - in_units;
+        let fee = if lost > dec!(0) {
+            Some(book.apply(
+                PoolMutation::new(
+                    g.time,
+                    &g.refid,
+                    &out.asset,
+                    Mutation::Remove { units: lost },
+                ),
+                &format!("{} fee {} {}", kind, g.refid, out.asset),
+            )?)
+        } else {
+            if lost < dec!(0) {
+                book.apply(
+                    PoolMutation::new(
+                        g.time,
+                        &g.refid,
+                        &out.asset,
+                        Mutation::Add {
+                            units: -lost,
+                            cost_cad: dec!(0),
+                        },
+                    ),
+                    "",
+                )?;
+            }
+            None
+        };
+
+        if opts.in_year(g.time) {
+            let pool = book.pool(&out.asset).cloned().unwrap_or_default();
+            let mut rr = make_row(g.stamp(), &g.refid, &g.txid, kind, &out.asset);
+            rr.units_out = out_units.to_string();
+            rr.units_in = in_units.to_string();
+            rr.pool_units_after = pool.units.to_string();
+            rr.pool_acb_cad_after = pool.acb_cad.to_string();
+            let (from, to) = if kind == "wrap" {
+                (&out.asset, &wrapped)
+            } else {
+                (&wrapped, &out.asset)
+            };
+            rr.notes = format!(
+                "Converted {} to {}; both are held in the {} pool under the merge wrap policy, so this is not a disposition",
+                from, to, out.asset
+            );
+            report.push(rr);
+
+            if let Some(effect) = fee {
+                let mut rr = make_row(
+                    g.stamp(),
+                    &g.refid,
+                    &g.txid,
+                    &format!("{}_fee_disposition", kind),
+                    &out.asset,
+                );
+                rr.units_out = lost.to_string();
+                rr.set_disposal(dec!(0), effect.cost_cad, -effect.cost_cad);
+                rr.pool_units_after = effect.units_after.to_string();
+                rr.pool_acb_cad_after = effect.acb_after.to_string();
+                record_lineage(
+                    lineage,
+                    totals,
+                    &rr,
+                    g.time,
+                    dec!(0),
+                    &effect,
+                    opts.timezone,
+                )?;
+                report.push(rr);
+
+                totals.acb_disposed_cad += effect.cost_cad;
+                totals.capital_gain_cad -= effect.cost_cad;
+            }
+        }
+        Ok(())
+    }
+
+    fn conversion(&mut self, g: TradeGroup, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            ..
+        } = self;
+        // The cost of the legs given up moves to the legs received;
+        // several received share it by market value.
+        let ctx = format!("conversion {}", g.refid);
+        let (given, received): (Vec<&LedgerEntry>, Vec<&LedgerEntry>) =
+            g.entries.iter().partition(|e| e.net_delta < dec!(0));
+        let mut rows = Vec::new();
+        let mut carried = dec!(0);
+        for e in &given {
+            let effect = book.apply(
+                PoolMutation::new(
+                    g.time,
+                    &g.refid,
+                    &e.asset,
+                    Mutation::Remove {
+                        units: -e.net_delta,
+                    },
+                ),
+                &format!("{} {}", ctx, e.asset),
+            )?;
+            carried += effect.cost_cad;
+            let mut rr = make_row(g.stamp(), &g.refid, &e.txid, "", &e.asset);
+            rr.units_out = (-e.net_delta).to_string();
+            rr.acb_disposed_cad = effect.cost_cad.to_string();
+            rr.pool_units_after = effect.units_after.to_string();
+            rr.pool_acb_cad_after = effect.acb_after.to_string();
+            rows.push(rr);
+        }
+        let weights = if received.len() == 1 {
+            vec![dec!(1)]
+        } else {
+            received
+                .iter()
+                .map(|e| asset_value_cad(&e.asset, e.net_delta, state, prices, &ctx).map(|v| v.cad))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let weight_sum: Decimal = weights.iter().sum();
+        if weight_sum <= dec!(0) {
+            return Err(format!("{}: the assets received have no value", ctx).into());
+        }
+        let mut left = carried;
+        for (i, (e, w)) in received.iter().zip(&weights).enumerate() {
+            let cost = if i + 1 == received.len() {
+                left
+            } else {
+                carried * w / weight_sum
+            };
+            left -= cost;
+            let effect = book.apply(
+                PoolMutation::new(
+                    g.time,
+                    &g.refid,
+                    &e.asset,
+                    Mutation::Add {
+                        units: e.net_delta,
+                        cost_cad: cost,
+                    },
+                ),
+                "",
+            )?;
+            let mut rr = make_row(g.stamp(), &g.refid, &e.txid, "", &e.asset);
+            rr.units_in = e.net_delta.to_string();
+            rr.acb_added_cad = cost.to_string();
+            rr.pool_units_after = effect.units_after.to_string();
+            rr.pool_acb_cad_after = effect.acb_after.to_string();
+            rows.push(rr);
+        }
+
+        if opts.in_year(g.time) {
+            let names = |legs: &[&LedgerEntry]| {
+                legs.iter()
+                    .map(|e| e.asset.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" + ")
+            };
+            let receipt_in = opts
+                .wraps
+                .iter()
+                .filter(|w| w.policy == WrapPolicy::Separate)
+                .any(|w| received.iter().any(|e| e.asset == w.wrapped));
+            let kind = if receipt_in {
+                "receipt_mint"
+            } else {
+                "receipt_redeem"
+            };
+            let note = format!(
+                "Converted {} to {}; the cost carries over under the separate wrap policy, so this is not a disposition",
+                names(&given),
+                names(&received)
+            );
+            for mut rr in rows {
+                rr.event_type = kind.to_string();
+                rr.notes = note.clone();
+                report.push(rr);
+            }
+        }
+        Ok(())
+    }
+
+    fn migration(&mut self, m: Migration) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            no_id,
+            ..
+        } = self;
+        let Some(old_units) = book.pools().get(&m.from).map(|p| p.units) else {
+            return Ok(());
+        };
+        let new_units = old_units * m.factor;
+        let pool = book.apply(
+            PoolMutation::new(
+                m.time,
+                no_id,
+                &m.to,
+                Mutation::Migrate {
+                    from: m.from.clone(),
+                    factor: m.factor,
+                },
+            ),
+            "",
+        )?;
+        let old_acb = pool.cost_cad;
+
+        for prices in [&mut state.asset_price_usd, &mut state.asset_price_cad] {
+            if let Some(p) = prices.get(&m.from).copied() {
+                prices.entry(m.to.clone()).or_insert(p / m.factor);
+            }
+        }
+        if let Some(fx) = state.asset_price_cad_fx.get(&m.from).copied() {
+            state.asset_price_cad_fx.entry(m.to.clone()).or_insert(fx);
+        }
+        if let Some(t) = state.observed_at.get(&m.from).copied() {
+            state.observed_at.entry(m.to.clone()).or_insert(t);
+        }
+
+        if opts.in_year(m.time) {
+            let mut rr = make_row(
+                wall_clock(m.time, opts.timezone),
+                no_id,
+                no_id,
+                "asset_migration",
+                &m.to,
+            );
+            rr.units_out = old_units.to_string();
+            rr.units_in = new_units.to_string();
+            rr.acb_added_cad = old_acb.to_string();
+            rr.pool_units_after = pool.units_after.to_string();
+            rr.pool_acb_cad_after = pool.acb_after.to_string();
+            rr.notes = format!(
+                "Migrated {} {} into {} at factor {}; ACB carried over",
+                q8(old_units),
+                m.from,
+                m.to,
+                m.factor
+            );
+            report.push(rr);
+        }
+        Ok(())
+    }
+
+    fn deemed_disposition(
+        &mut self,
+        t: NaiveDateTime,
+        prices: Lookup,
+    ) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            lineage,
+            totals,
+            no_id,
+            ..
+        } = self;
+        let mut assets: Vec<_> = book
+            .pools()
+            .iter()
+            .filter(|(a, p)| a.as_ref() != "CAD" && p.units > dec!(0))
+            .map(|(a, _)| a.clone())
+            .collect();
+        assets.sort();
+        for asset in assets {
+            let units = book.pools()[&asset].units;
+            let fmv = asset_value_cad(&asset, units, state, prices, "deemed disposition")?;
+            // Re-acquired at the same FMV: units unchanged, ACB stepped to FMV.
+            let pool = book.apply(
+                PoolMutation::new(t, no_id, &asset, Mutation::Restate { cost_cad: fmv.cad }),
+                "",
+            )?;
+            let acb = pool.cost_cad;
+            let gain = fmv.cad - acb;
+
+            if opts.in_year(t) {
+                let mut rr = make_row(
+                    wall_clock(t, opts.timezone),
+                    no_id,
+                    no_id,
+                    "deemed_disposition",
+                    &asset,
+                );
+                rr.units_out = units.to_string();
+                rr.set_disposal(fmv.cad, acb, gain);
+                set_valuation_sources(&mut rr, &fmv);
+                totals.stale_price_count += usize::from(check_price_age(&mut rr, &fmv, t, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &fmv));
+                record_lineage(lineage, totals, &rr, t, fmv.cad, &pool, opts.timezone)?;
+                report.push(rr);
+
+                let mut rr = make_row(
+                    wall_clock(t, opts.timezone),
+                    no_id,
+                    no_id,
+                    "deemed_reacquisition",
+                    &asset,
+                );
+                rr.units_in = units.to_string();
+                rr.acb_added_cad = fmv.cad.to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                rr.notes = "Deemed re-acquisition at FMV on emigration".to_string();
+                report.push(rr);
+
+                totals.proceeds_cad += fmv.cad;
+                totals.acb_disposed_cad += acb;
+                totals.capital_gain_cad += gain;
+                totals.deemed_proceeds_cad += fmv.cad;
+                totals.deemed_gain_cad += gain;
+            }
+        }
+        Ok(())
+    }
+
+    fn dividend(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            totals,
+            ..
+        } = self;
+        // Tokenized-equity dividends; the equity tokens themselves pool
+        // through the regular trade path.
+        if e.net_delta <= dec!(0) {
+            return Err(format!("dividend must be positive net for refid {}", e.refid).into());
+        }
+        let income_val = income_value_cad(
+            &e.asset,
+            e.net_delta,
+            state,
+            prices,
+            &format!("dividend {}", e.refid),
+        )?;
+        let mut rr = book_income(e, "dividend_income", &income_val, book)?;
+
+        if opts.in_year(e.time) {
+            totals.stale_price_count +=
+                usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+            fx_audit.extend(fx_audit_row(&rr, &income_val));
+            report.push(rr);
+            totals.dividend_income_cad += income_val.cad;
+        }
+        Ok(())
+    }
+
+    fn yield_interest(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            totals,
+            ..
+        } = self;
+        // Fiat and stablecoin yield is interest income, whatever the subtype.
+        if e.net_delta <= dec!(0) {
+            return Err(format!("earn interest must be positive net for refid {}", e.refid).into());
+        }
+        let income_val = income_value_cad(
+            &e.asset,
+            e.net_delta,
+            state,
+            prices,
+            &format!("earn interest {}", e.refid),
+        )?;
+        let mut rr = book_income(e, "earn_interest_income", &income_val, book)?;
+
+        if opts.in_year(e.time) {
+            totals.stale_price_count +=
+                usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+            fx_audit.extend(fx_audit_row(&rr, &income_val));
+            report.push(rr);
+            totals.interest_income_cad += income_val.cad;
+        }
+        Ok(())
+    }
+
+    fn reward(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            totals,
+            ..
+        } = self;
+        if e.net_delta <= dec!(0) {
+            return Err(format!("earn reward must be positive net for refid {}", e.refid).into());
+        }
+        let close_val = close_value_cad(
+            &e.asset,
+            e.net_delta,
+            e.time.date(),
+            &opts.daily_closes,
+            state,
+            prices,
+        )?;
+        let income_val = match close_val {
+            Some(v) => v,
+            None => asset_value_cad(
+                &e.asset,
+                e.net_delta,
+                state,
+                prices,
+                &format!("earn reward {}", e.refid),
+            )?,
+        };
+        let income_cad = income_val.cad;
+
+        if &*e.asset != "CAD" {
+            let pool = book.apply(
+                PoolMutation::new(
+                    e.time,
+                    &e.refid,
+                    &e.asset,
+                    Mutation::Income {
+                        units: e.net_delta,
+                        value_cad: income_cad,
+                    },
+                ),
+                "",
+            )?;
+
+            if opts.in_year(e.time) {
+                let mut rr = make_row(e.stamp(), &e.refid, &e.txid, "earn_reward_income", &e.asset);
+                rr.units_in = e.net_delta.to_string();
+                rr.income_cad = income_cad.to_string();
+                rr.acb_added_cad = income_cad.to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                set_valuation_sources(&mut rr, &income_val);
+                if close_val.is_none() && opts.income_valuation != IncomeValuation::LastTrade {
+                    add_note(
+                        &mut rr,
+                        &format!(
+                            "No daily close for {} on {}; valued at the last trade price",
+                            e.asset,
+                            e.time.date()
+                        ),
+                    );
+                }
+                totals.stale_price_count +=
+                    usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &income_val));
+                report.push(rr);
+                totals.reward_income_cad += income_cad;
+            }
+        }
+        Ok(())
+    }
+
+    fn credit(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            totals,
+            ..
+        } = self;
+        // Promotional credits and referral bonuses are income at FMV.
+        if e.net_delta <= dec!(0) {
+            return Err(format!("credit/bonus must be positive net for refid {}", e.refid).into());
+        }
+        let income_val = asset_value_cad(
+            &e.asset,
+            e.net_delta,
+            state,
+            prices,
+            &format!("credit {}", e.refid),
+        )?;
+        let mut rr = book_income(e, "credit_income", &income_val, book)?;
+
+        if opts.in_year(e.time) {
+            totals.stale_price_count +=
+                usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+            fx_audit.extend(fx_audit_row(&rr, &income_val));
+            report.push(rr);
+            totals.credit_income_cad += income_val.cad;
+        }
+        Ok(())
+    }
+
+    fn nft_charge(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            lineage,
+            totals,
+            ..
+        } = self;
+        // Creator fees and other NFT charges paid out are zero-proceed
+        // dispositions; amounts received (e.g. royalties) are income.
+        if e.net_delta > dec!(0) {
+            let income_val = asset_value_cad(
+                &e.asset,
+                e.net_delta,
+                state,
+                prices,
+                &format!("nft income {}", e.refid),
+            )?;
+            let mut rr = book_income(e, "nft_royalty_income", &income_val, book)?;
+
+            if opts.in_year(e.time) {
+                totals.stale_price_count +=
+                    usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &income_val));
+                report.push(rr);
+                totals.other_income_cad += income_val.cad;
+            }
+        } else if e.net_delta < dec!(0) && &*e.asset != "CAD" {
+            let (rr, pool) = book_zero_proceeds_disposition(
+                e,
+                "nft_fee_disposition",
+                &format!("nft fee {} {}", e.refid, e.asset),
+                book,
+            )?;
+            let acb_fee = pool.cost_cad;
+
+            if opts.in_year(e.time) {
+                record_lineage(lineage, totals, &rr, e.time, dec!(0), &pool, opts.timezone)?;
+                report.push(rr);
+                totals.acb_disposed_cad += acb_fee;
+                totals.capital_gain_cad -= acb_fee;
+            }
+        }
+        Ok(())
+    }
+
+    fn adjustment(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            lineage,
+            totals,
+            ..
+        } = self;
+        // Exchange-side balance corrections.
+        if e.net_delta > dec!(0) && opts.adjustment_mode == AdjustmentMode::Income {
+            let income_val = asset_value_cad(
+                &e.asset,
+                e.net_delta,
+                state,
+                prices,
+                &format!("adjustment {}", e.refid),
+            )?;
+            let mut rr = book_income(e, "adjustment_income", &income_val, book)?;
+
+            if opts.in_year(e.time) {
+                totals.stale_price_count +=
+                    usize::from(check_price_age(&mut rr, &income_val, e.time, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &income_val));
+                report.push(rr);
+                totals.other_income_cad += income_val.cad;
+            }
+        } else if e.net_delta > dec!(0) {
+            let mut rr = make_row(
+                e.stamp(),
+                &e.refid,
+                &e.txid,
+                "adjustment_acquisition",
+                &e.asset,
+            );
+            rr.units_in = e.net_delta.to_string();
+            if &*e.asset != "CAD" {
+                let pool = book.apply(
+                    PoolMutation::new(
+                        e.time,
+                        &e.refid,
+                        &e.asset,
+                        Mutation::Add {
+                            units: e.net_delta,
+                            cost_cad: dec!(0),
+                        },
+                    ),
+                    "",
+                )?;
+                rr.acb_added_cad = "0".to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                rr.notes = "Adjustment added units at zero cost".to_string();
+            }
+
+            if opts.in_year(e.time) {
+                report.push(rr);
+            }
+        } else if e.net_delta < dec!(0) {
+            let (rr, pool) = if &*e.asset != "CAD" {
+                let (rr, pool) = book_zero_proceeds_disposition(
+                    e,
+                    "adjustment_disposition",
+                    &format!("adjustment {} {}", e.refid, e.asset),
+                    book,
+                )?;
+                (rr, Some(pool))
+            } else {
+                let mut rr = make_row(
+                    e.stamp(),
+                    &e.refid,
+                    &e.txid,
+                    "adjustment_disposition",
+                    &e.asset,
+                );
+                rr.units_out = (-e.net_delta).to_string();
+                (rr, None)
+            };
+            let acb = pool.as_ref().map_or(dec!(0), |p| p.cost_cad);
+
+            if opts.in_year(e.time) {
+                if let Some(pool) = &pool {
+                    record_lineage(lineage, totals, &rr, e.time, dec!(0), pool, opts.timezone)?;
+                }
+                report.push(rr);
+                totals.acb_disposed_cad += acb;
+                totals.capital_gain_cad -= acb;
+            }
+        }
+        Ok(())
+    }
+
+    fn deposit(&mut self, e: &LedgerEntry) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            book,
+            report,
+            totals,
+            ..
+        } = self;
+        if e.net_delta <= dec!(0) {
+            return Err(format!("deposit with non-positive net delta at refid {}", e.refid).into());
+        }
+        if &*e.asset != "CAD" {
+            let pool = book.apply(
+                PoolMutation::new(
+                    e.time,
+                    &e.refid,
+                    &e.asset,
+                    Mutation::Add {
+                        units: e.net_delta,
+                        cost_cad: dec!(0),
+                    },
+                ),
+                "",
+            )?;
+
+            if opts.in_year(e.time) {
+                let mut rr = make_row(
+                    e.stamp(),
+                    &e.refid,
+                    &e.txid,
+                    "warning_unpriced_transfer_in",
+                    &e.asset,
+                );
+                rr.units_in = e.net_delta.to_string();
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                rr.notes = "Deposit treated as transfer-in with unknown ACB; assumed 0 CAD basis"
+                    .to_string();
+                report.push(rr);
+                totals.warning_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn withdrawal(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let wallet_moves = self.wallet_moves;
+        let Pass {
+            book,
+            state,
+            report,
+            lineage,
+            fees,
+            totals,
+            ..
+        } = self;
+        let transfer = e.row_type == "transfer";
+        if !transfer && e.amount >= dec!(0) {
+            return Err(format!("withdrawal amount must be negative at refid {}", e.refid).into());
+        }
+        let fee_units = e.fee;
+        if fee_units > dec!(0) && opts.in_year(e.time) {
+            let value = asset_value_cad(&e.asset, fee_units, state, prices, "")
+                .ok()
+                .map(|v| v.cad);
+            fees.push(fee_row(
+                e.stamp(),
+                &e.refid,
+                "withdrawal",
+                &e.asset,
+                fee_units,
+                value,
+                &mut totals.fees,
+            ));
+        }
+
+        if &*e.asset != "CAD" {
+            // A matched transfer between the user's own accounts keeps
+            // its units and ACB in the pool; only the network fee leaves.
+            // Between two wallets' pools, the units move with their
+            // share of the ACB when they leave.
+            if let Some(to) = wallet_moves.get(&e.txid) {
+                let units = -e.amount;
+                let from = e.wallet.clone().unwrap_or_default();
+                let moved = book.apply(
+                    PoolMutation::new(e.time, &e.refid, &e.asset, Mutation::Remove { units }),
+                    &format!("wallet transfer {} {}", e.refid, e.asset),
+                )?;
+                book.set_wallet(Some(to.clone()));
+                let arrived = book.apply(
+                    PoolMutation::new(
+                        e.time,
+                        &e.refid,
+                        &e.asset,
+                        Mutation::Add {
+                            units,
+                            cost_cad: moved.cost_cad,
+                        },
+                    ),
+                    "",
+                )?;
+                book.set_wallet(e.wallet.clone());
+                if opts.in_year(e.time) {
+                    let notes = format!(
+                        "Moved {} {} and {} CAD of ACB from wallet {} to wallet {}",
+                        q8(units),
+                        e.asset,
+                        q2(moved.cost_cad),
+                        from,
+                        to
+                    );
+                    for (kind, effect) in [
+                        ("wallet_transfer_out", &moved),
+                        ("wallet_transfer_in", &arrived),
+                    ] {
+                        let mut rr = make_row(e.stamp(), &e.refid, &e.txid, kind, &e.asset);
+                        if kind == "wallet_transfer_out" {
+                            rr.units_out = units.to_string();
+                        } else {
+                            rr.units_in = units.to_string();
+                            rr.acb_added_cad = moved.cost_cad.to_string();
+                        }
+                        rr.pool_units_after = effect.units_after.to_string();
+                        rr.pool_acb_cad_after = effect.acb_after.to_string();
+                        rr.notes = notes.clone();
+                        report.push(rr);
+                    }
+                }
+            }
+            if !transfer {
+                book.apply(
+                    PoolMutation::new(
+                        e.time,
+                        &e.refid,
+                        &e.asset,
+                        Mutation::Remove { units: -e.amount },
+                    ),
+                    &format!("withdrawal principal {} {}", e.refid, e.asset),
+                )?;
+            }
+
+            if fee_units > dec!(0) {
+                let pool = book.apply(
+                    PoolMutation::new(
+                        e.time,
+                        &e.refid,
+                        &e.asset,
+                        Mutation::Remove { units: fee_units },
+                    ),
+                    &format!("withdrawal fee {} {}", e.refid, e.asset),
+                )?;
+                let acb_fee = pool.cost_cad;
+                let gain = -acb_fee;
+
+                if opts.in_year(e.time) {
+                    let mut rr = make_row(
+                        e.stamp(),
+                        &e.refid,
+                        &e.txid,
+                        if transfer {
+                            "transfer_fee_disposition"
+                        } else {
+                            "withdrawal_fee_disposition"
+                        },
+                        &e.asset,
+                    );
+                    rr.units_out = fee_units.to_string();
+                    rr.set_disposal(dec!(0), acb_fee, gain);
+                    rr.pool_units_after = pool.units_after.to_string();
+                    rr.pool_acb_cad_after = pool.acb_after.to_string();
+                    record_lineage(lineage, totals, &rr, e.time, dec!(0), &pool, opts.timezone)?;
+                    report.push(rr);
+
+                    totals.proceeds_cad += dec!(0);
+                    totals.acb_disposed_cad += acb_fee;
+                    totals.capital_gain_cad += gain;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn spend(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let acquisitions = self.acquisitions;
+        let jurisdiction = &*opts.jurisdiction;
+        let Pass {
+            book,
+            state,
+            report,
+            fx_audit,
+            lineage,
+            totals,
+            ..
+        } = self;
+        // Paying with crypto (Kraken Pay / card) disposes of the units at FMV;
+        // so does giving them away.
+        if e.amount >= dec!(0) {
+            return Err(format!(
+                "{} amount must be negative at refid {}",
+                e.row_type, e.refid
+            )
+            .into());
+        }
+        if &*e.asset != "CAD" {
+            let spent_units = -e.amount;
+            let units_out = -e.net_delta;
+            let ctx = format!("spend {}", e.refid);
+            let proceeds_val = asset_value_cad(&e.asset, spent_units, state, prices, &ctx)?;
+            let fee_val = asset_value_cad(&e.asset, e.fee, state, prices, &ctx)?;
+            let pool = book.apply(
+                PoolMutation::new(
+                    e.time,
+                    &e.refid,
+                    &e.asset,
+                    Mutation::Remove { units: units_out },
+                ),
+                &format!("spend disposition {} {}", e.refid, e.asset),
+            )?;
+            let acb_disposed = pool.cost_cad;
+            let (event_type, proceeds) = match e.subtype.as_str() {
+                "gift" if e.row_type == "withdrawal" => ("gift_disposition", proceeds_val.cad),
+                "donation" if e.row_type == "withdrawal" => (
+                    "donation_disposition",
+                    match opts.donation_proceeds {
+                        DonationProceeds::Fmv => proceeds_val.cad,
+                        DonationProceeds::Acb => proceeds_val.cad.min(acb_disposed),
+                    },
+                ),
+                _ => ("spend_disposition", proceeds_val.cad),
+            };
+            let gain = proceeds - acb_disposed;
+
+            if opts.in_year(e.time) {
+                let mut rr = make_row(e.stamp(), &e.refid, &e.txid, event_type, &e.asset);
+                rr.units_out = units_out.to_string();
+                rr.set_disposal(proceeds, acb_disposed, gain);
+                if !fee_val.cad.is_zero() {
+                    rr.fee_cad = fee_val.cad.to_string();
+                }
+                rr.pool_units_after = pool.units_after.to_string();
+                rr.pool_acb_cad_after = pool.acb_after.to_string();
+                set_valuation_sources(&mut rr, &proceeds_val);
+                flag_superficial_loss(&mut rr, gain, e.time, acquisitions, jurisdiction);
+                totals.stale_price_count +=
+                    usize::from(check_price_age(&mut rr, &proceeds_val, e.time, opts)?);
+                fx_audit.extend(fx_audit_row(&rr, &proceeds_val));
+                record_lineage(lineage, totals, &rr, e.time, proceeds, &pool, opts.timezone)?;
+                report.push(rr);
+
+                totals.proceeds_cad += proceeds;
+                totals.acb_disposed_cad += acb_disposed;
+                totals.capital_gain_cad += gain;
+                match event_type {
+                    "gift_disposition" => totals.gift_fmv_cad += proceeds,
+                    "donation_disposition" => totals.donation_amount_cad += proceeds,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn unrecognized(&mut self, e: &LedgerEntry, prices: Lookup) -> Result<(), Box<dyn Error>> {
+        let opts = self.opts;
+        let Pass {
+            state,
+            fees,
+            ignored,
+            totals,
+            ..
+        } = self;
+        // Unknown/non-tax-relevant ledger types are ignored by default,
+        // but tallied so new Kraken row types don't go unnoticed.
+        record_ignored(ignored, e);
+        // Margin positions aren't tracked, but their fees are still
+        // an expense.
+        if matches!(e.row_type.as_str(), "margin" | "rollover")
+            && e.fee > dec!(0)
+            && opts.in_year(e.time)
+        {
+            let value = asset_value_cad(&e.asset, e.fee, state, prices, "")
+                .ok()
+                .map(|v| v.cad);
+            fees.push(fee_row(
+                e.stamp(),
+                &e.refid,
+                "margin",
+                &e.asset,
+                e.fee,
+                value,
+                &mut totals.fees,
+            ));
+        }
+        Ok(())
+    }
+}
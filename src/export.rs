@@ -0,0 +1,1250 @@
+use crate::journal;
+use crate::locale::Locale;
+use crate::{
+    EndingPoolRow, Jurisdiction, LedgerEntry, LineageRow, ReportRow, build_trade_groups,
+    create_output, is_earn_internal, is_nft_row, is_spend_receive_row, is_trade_row,
+    is_yield_asset, parse_decimal, q2, q8, side_report_path, split_trade_legs, write_csv,
+};
+use chrono::NaiveDateTime;
+use csv::WriterBuilder;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    Koinly,
+    CoinTracking,
+    // Quebec's Schedule G, from the report rather than the ledger.
+    ScheduleG,
+    // The federal Schedule 3's list, with acquisition years.
+    Schedule3,
+    // Double-entry books for beancount, also from the report.
+    Beancount,
+    // The same books as journal entries for accounting software.
+    Journal,
+    // adjustedcostbase.ca's transaction import, from the report.
+    AdjustedCostBase,
+    // Capital-gains imports of Canadian filing software, from the report:
+    // Wealthsimple Tax's lists one line per asset, TurboTax's one per
+    // disposition.
+    WealthsimpleTax,
+    TurboTax,
+}
+
+impl ExportFormat {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "koinly" => Ok(ExportFormat::Koinly),
+            "cointracking" => Ok(ExportFormat::CoinTracking),
+            "schedule-g" => Ok(ExportFormat::ScheduleG),
+            "schedule-3" => Ok(ExportFormat::Schedule3),
+            "beancount" => Ok(ExportFormat::Beancount),
+            "journal" => Ok(ExportFormat::Journal),
+            "adjustedcostbase" => Ok(ExportFormat::AdjustedCostBase),
+            "wealthsimple-tax" => Ok(ExportFormat::WealthsimpleTax),
+            "turbotax" => Ok(ExportFormat::TurboTax),
+            other => Err(format!("unknown export format: {}", other).into()),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Koinly => "koinly",
+            ExportFormat::CoinTracking => "cointracking",
+            ExportFormat::ScheduleG => "schedule_g",
+            ExportFormat::Schedule3 => "schedule_3",
+            ExportFormat::Beancount => "beancount",
+            ExportFormat::Journal => "journal",
+            ExportFormat::AdjustedCostBase => "adjustedcostbase",
+            ExportFormat::WealthsimpleTax => "wealthsimple_tax",
+            ExportFormat::TurboTax => "turbotax",
+        }
+    }
+
+    // `<out>_<format>.csv`, or `<out>.beancount`.
+    pub(crate) fn path(&self, side_stem: &str) -> String {
+        match self {
+            ExportFormat::Beancount => format!(
+                "{}.beancount",
+                side_stem.strip_suffix(".csv").unwrap_or(side_stem)
+            ),
+            _ => side_report_path(side_stem, self.as_str()),
+        }
+    }
+
+    // Whether the export is of the ledger history, written before processing.
+    pub(crate) fn is_ledger_history(&self) -> bool {
+        matches!(self, ExportFormat::Koinly | ExportFormat::CoinTracking)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Trade,
+    Deposit,
+    Withdrawal,
+    Reward,
+    Interest,
+    Income,
+    Dividend,
+    Spend,
+    Gift,
+    Donation,
+}
+
+impl Kind {
+    fn koinly_label(&self) -> &'static str {
+        match self {
+            Kind::Trade | Kind::Deposit | Kind::Withdrawal => "",
+            Kind::Reward => "reward",
+            Kind::Interest => "lending interest",
+            Kind::Income => "other income",
+            Kind::Dividend => "income",
+            Kind::Spend => "cost",
+            Kind::Gift => "gift",
+            Kind::Donation => "donation",
+        }
+    }
+
+    fn cointracking_type(&self) -> &'static str {
+        match self {
+            Kind::Trade => "Trade",
+            Kind::Deposit => "Deposit",
+            Kind::Withdrawal => "Withdrawal",
+            Kind::Reward => "Staking",
+            Kind::Interest => "Interest Income",
+            Kind::Income => "Income",
+            Kind::Dividend => "Dividends Income",
+            Kind::Spend => "Spend",
+            Kind::Gift => "Gift",
+            Kind::Donation => "Donation",
+        }
+    }
+}
+
+// One exported transaction: what left, what arrived, and any fee paid on top.
+#[derive(Debug)]
+struct UniversalRow {
+    time: NaiveDateTime,
+    kind: Kind,
+    sent: Option<(Arc<str>, Decimal)>,
+    received: Option<(Arc<str>, Decimal)>,
+    fee: Option<(Arc<str>, Decimal)>,
+    refid: Arc<str>,
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+struct KoinlyRow {
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Sent Amount")]
+    sent_amount: String,
+    #[serde(rename = "Sent Currency")]
+    sent_currency: String,
+    #[serde(rename = "Received Amount")]
+    received_amount: String,
+    #[serde(rename = "Received Currency")]
+    received_currency: String,
+    #[serde(rename = "Fee Amount")]
+    fee_amount: String,
+    #[serde(rename = "Fee Currency")]
+    fee_currency: String,
+    #[serde(rename = "Net Worth Amount")]
+    net_worth_amount: String,
+    #[serde(rename = "Net Worth Currency")]
+    net_worth_currency: String,
+    #[serde(rename = "Label")]
+    label: String,
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "TxHash")]
+    tx_hash: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AdjustedCostBaseRow {
+    #[serde(rename = "Security")]
+    security: String,
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Transaction Type")]
+    transaction_type: &'static str,
+    #[serde(rename = "Amount")]
+    amount: String,
+    #[serde(rename = "Shares")]
+    shares: String,
+    #[serde(rename = "Commission")]
+    commission: String,
+    #[serde(rename = "Memo")]
+    memo: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WealthsimpleTaxRow {
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "Year of acquisition")]
+    year_acquired: String,
+    #[serde(rename = "Proceeds of disposition")]
+    proceeds: String,
+    #[serde(rename = "Adjusted cost base")]
+    acb: String,
+    #[serde(rename = "Outlays and expenses")]
+    outlays: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TurboTaxRow {
+    #[serde(rename = "Description")]
+    description: String,
+    #[serde(rename = "Date acquired")]
+    date_acquired: String,
+    #[serde(rename = "Date sold")]
+    date_sold: String,
+    #[serde(rename = "Proceeds of disposition")]
+    proceeds: String,
+    #[serde(rename = "Adjusted cost base")]
+    acb: String,
+    #[serde(rename = "Outlays and expenses")]
+    outlays: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CoinTrackingRow {
+    #[serde(rename = "Type")]
+    row_type: String,
+    #[serde(rename = "Buy Amount")]
+    buy_amount: String,
+    #[serde(rename = "Buy Currency")]
+    buy_currency: String,
+    #[serde(rename = "Sell Amount")]
+    sell_amount: String,
+    #[serde(rename = "Sell Currency")]
+    sell_currency: String,
+    #[serde(rename = "Fee")]
+    fee: String,
+    #[serde(rename = "Fee Currency")]
+    fee_currency: String,
+    #[serde(rename = "Exchange")]
+    exchange: String,
+    #[serde(rename = "Trade-Group")]
+    trade_group: String,
+    #[serde(rename = "Comment")]
+    comment: String,
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Tx-ID")]
+    tx_id: String,
+}
+
+fn split_leg(leg: &Option<(Arc<str>, Decimal)>) -> (String, String) {
+    match leg {
+        Some((asset, amount)) => (amount.normalize().to_string(), asset.to_string()),
+        None => (String::new(), String::new()),
+    }
+}
+
+// Mirrors the entry dispatch in `process`; rows it would skip or ignore are
+// not exported.
+fn entry_kind(e: &LedgerEntry) -> Option<Kind> {
+    match (e.row_type.as_str(), e.subtype.as_str()) {
+        ("dividend", _) | ("earn", "dividend") => Some(Kind::Dividend),
+        ("earn", sub) if is_earn_internal(sub) => None,
+        ("earn", _) if is_yield_asset(&e.asset) => Some(Kind::Interest),
+        ("earn", "reward") | ("staking", _) => Some(Kind::Reward),
+        ("credit", _) | ("bonus", _) | ("referral", _) | ("earn", "referral" | "bonus") => {
+            Some(Kind::Income)
+        }
+        (t, _) if is_nft_row(e) && t != "nfttrade" => Some(if e.net_delta > dec!(0) {
+            Kind::Income
+        } else {
+            Kind::Spend
+        }),
+        ("adjustment", _) => Some(if e.net_delta > dec!(0) {
+            Kind::Deposit
+        } else {
+            Kind::Withdrawal
+        }),
+        ("deposit", "") => Some(Kind::Deposit),
+        ("withdrawal", "") => Some(Kind::Withdrawal),
+        ("withdrawal", "gift") => Some(Kind::Gift),
+        ("withdrawal", "donation") => Some(Kind::Donation),
+        ("spend", _) => Some(Kind::Spend),
+        _ => None,
+    }
+}
+
+fn universal_rows(
+    entries: &[LedgerEntry],
+    jurisdiction: &dyn Jurisdiction,
+    tax_year: i32,
+) -> Result<Vec<UniversalRow>, Box<dyn Error>> {
+    let trade_groups = build_trade_groups(entries, jurisdiction, tax_year)?;
+    let mut emitted_trade = HashSet::new();
+    let mut out = Vec::new();
+
+    for e in entries {
+        if jurisdiction.tax_year(e.time) > tax_year {
+            continue;
+        }
+        if (is_trade_row(e) || is_spend_receive_row(e)) && trade_groups.contains_key(&e.refid) {
+            if !emitted_trade.insert(e.refid.clone()) {
+                continue;
+            }
+            let g = &trade_groups[&e.refid];
+            let (sell, buy) = split_trade_legs(g)?;
+            // Only one fee column: keep a positive fee on the sold leg (else the
+            // bought leg) and fold anything else, like maker rebates, into the
+            // leg amounts.
+            let (sent, received, fee) = if sell.fee > dec!(0) {
+                (
+                    -sell.amount,
+                    buy.net_delta,
+                    Some((sell.asset.clone(), sell.fee)),
+                )
+            } else if buy.fee > dec!(0) {
+                (
+                    -sell.net_delta,
+                    buy.amount,
+                    Some((buy.asset.clone(), buy.fee)),
+                )
+            } else {
+                (-sell.net_delta, buy.net_delta, None)
+            };
+            out.push(UniversalRow {
+                time: g.time,
+                kind: Kind::Trade,
+                sent: Some((sell.asset.clone(), sent)),
+                received: Some((buy.asset.clone(), received)),
+                fee,
+                refid: g.refid.clone(),
+                description: format!("trade {}", g.refid),
+            });
+            continue;
+        }
+
+        let Some(kind) = entry_kind(e) else {
+            continue;
+        };
+        let (amount, fee) = if e.fee > dec!(0) && !e.amount.is_zero() {
+            (e.amount, Some((e.asset.clone(), e.fee)))
+        } else {
+            (e.net_delta, None)
+        };
+        if amount.is_zero() {
+            continue;
+        }
+        let leg = Some((e.asset.clone(), amount.abs()));
+        let (sent, received) = if amount < dec!(0) {
+            (leg, None)
+        } else {
+            (None, leg)
+        };
+        let subtype = if e.subtype.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", e.subtype)
+        };
+        out.push(UniversalRow {
+            time: e.time,
+            kind,
+            sent,
+            received,
+            fee,
+            refid: e.refid.clone(),
+            description: format!("{}{} {}", e.row_type, subtype, e.refid),
+        });
+    }
+
+    Ok(out)
+}
+
+fn koinly_row(r: &UniversalRow) -> KoinlyRow {
+    let (sent_amount, sent_currency) = split_leg(&r.sent);
+    let (received_amount, received_currency) = split_leg(&r.received);
+    let (fee_amount, fee_currency) = split_leg(&r.fee);
+    KoinlyRow {
+        date: r.time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        sent_amount,
+        sent_currency,
+        received_amount,
+        received_currency,
+        fee_amount,
+        fee_currency,
+        net_worth_amount: String::new(),
+        net_worth_currency: String::new(),
+        label: r.kind.koinly_label().to_string(),
+        description: r.description.clone(),
+        tx_hash: String::new(),
+    }
+}
+
+fn cointracking_row(r: &UniversalRow) -> CoinTrackingRow {
+    let (sell_amount, sell_currency) = split_leg(&r.sent);
+    let (buy_amount, buy_currency) = split_leg(&r.received);
+    let (fee, fee_currency) = split_leg(&r.fee);
+    CoinTrackingRow {
+        row_type: r.kind.cointracking_type().to_string(),
+        buy_amount,
+        buy_currency,
+        sell_amount,
+        sell_currency,
+        fee,
+        fee_currency,
+        exchange: String::new(),
+        trade_group: String::new(),
+        comment: r.description.clone(),
+        date: r.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        tx_id: r.refid.to_string(),
+    }
+}
+
+// Writes the ledger history (through `tax_year`) in the given tool's import
+// format and returns the number of rows written.
+pub(crate) fn write_export(
+    format: ExportFormat,
+    path: &str,
+    entries: &[LedgerEntry],
+    jurisdiction: &dyn Jurisdiction,
+    tax_year: i32,
+) -> Result<usize, Box<dyn Error>> {
+    let rows = universal_rows(entries, jurisdiction, tax_year)?;
+    match format {
+        ExportFormat::Koinly => write_csv(path, &rows.iter().map(koinly_row).collect::<Vec<_>>())?,
+        ExportFormat::CoinTracking => {
+            write_csv(path, &rows.iter().map(cointracking_row).collect::<Vec<_>>())?
+        }
+        ExportFormat::ScheduleG
+        | ExportFormat::Schedule3
+        | ExportFormat::Beancount
+        | ExportFormat::Journal
+        | ExportFormat::AdjustedCostBase
+        | ExportFormat::WealthsimpleTax
+        | ExportFormat::TurboTax => {
+            return Err(format!("{} is written from the report", format.as_str()).into());
+        }
+    }
+    Ok(rows.len())
+}
+
+// A report amount, zero when blank.
+pub(crate) fn amount(s: &str) -> Result<Decimal, Box<dyn Error>> {
+    if s.is_empty() {
+        Ok(Decimal::ZERO)
+    } else {
+        parse_decimal(s)
+    }
+}
+
+// One line of Schedule G's list of dispositions: every disposition of one
+// asset in the tax year, summed.
+#[derive(Debug, Default, PartialEq)]
+struct ScheduleGLine {
+    description: String,
+    units: Decimal,
+    proceeds_cad: Decimal,
+    acb_cad: Decimal,
+    gain_cad: Decimal,
+}
+
+// The report's taxable dispositions with their ACB and gain: a row with a
+// gain or loss is a disposition, one an exemption covers is left out, and
+// the exempt part of a partly exempt gain moves into its ACB, so the
+// schedules add up to the summary's net capital gain.
+fn taxable_dispositions(
+    report: &[ReportRow],
+) -> impl Iterator<Item = Result<(&ReportRow, Decimal, Decimal), Box<dyn Error>>> {
+    report
+        .iter()
+        .filter(|r| !r.gain_cad.is_empty() && !r.is_exempt())
+        .map(|r| {
+            let exempt = r.exempt_gain_cad();
+            Ok((
+                r,
+                amount(&r.acb_disposed_cad)? + exempt,
+                amount(&r.gain_cad)? - exempt,
+            ))
+        })
+}
+
+// The report's taxable dispositions by asset, then their total.
+fn schedule_g_lines(report: &[ReportRow]) -> Result<Vec<ScheduleGLine>, Box<dyn Error>> {
+    let mut by_asset: BTreeMap<&str, ScheduleGLine> = BTreeMap::new();
+    for disposition in taxable_dispositions(report) {
+        let (r, acb, gain) = disposition?;
+        let line = by_asset.entry(&r.asset).or_default();
+        line.units += amount(&r.units_out)?;
+        line.proceeds_cad += amount(&r.proceeds_cad)?;
+        line.acb_cad += acb;
+        line.gain_cad += gain;
+    }
+    let mut total = ScheduleGLine {
+        description: "Total".to_string(),
+        ..ScheduleGLine::default()
+    };
+    let mut lines = Vec::new();
+    for (asset, mut line) in by_asset {
+        total.proceeds_cad += line.proceeds_cad;
+        total.acb_cad += line.acb_cad;
+        total.gain_cad += line.gain_cad;
+        line.description = asset.to_string();
+        lines.push(line);
+    }
+    lines.push(total);
+    Ok(lines)
+}
+
+// Writes the report's taxable dispositions in the columns of Quebec's
+// Schedule G, headed in `locale`, and returns the number of assets listed.
+// The outlays column is zero: trading fees are in the proceeds and ACB, or
+// under `--fee-mode expense` are claimed once as the summary's expensed fees
+// rather than against each disposition.
+pub(crate) fn write_schedule_g(
+    path: &str,
+    report: &[ReportRow],
+    locale: Locale,
+) -> Result<usize, Box<dyn Error>> {
+    let lines = schedule_g_lines(report)?;
+    let mut wtr = WriterBuilder::new().from_writer(create_output(path)?);
+    wtr.write_record(
+        [
+            "Number",
+            "Description of property",
+            "Proceeds of disposition",
+            "Adjusted cost base",
+            "Outlays and expenses",
+            "Gain (or loss)",
+        ]
+        .map(|h| locale.text(h)),
+    )?;
+    for (i, line) in lines.iter().enumerate() {
+        let description = if i + 1 == lines.len() {
+            locale.text("Total")
+        } else {
+            &line.description
+        };
+        let units = if line.units.is_zero() {
+            String::new()
+        } else {
+            q8(line.units).to_string()
+        };
+        wtr.write_record([
+            units,
+            description.to_string(),
+            q2(line.proceeds_cad).to_string(),
+            q2(line.acb_cad).to_string(),
+            "0".to_string(),
+            q2(line.gain_cad).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(lines.len() - 1)
+}
+
+// Writes the report's dispositions as the list on the federal Schedule 3,
+// one line per asset with the years its units were acquired in and a total,
+// headed in `locale`, and returns the number of assets listed. Outlays are
+// zero, as on Schedule G.
+pub(crate) fn write_schedule_3(
+    path: &str,
+    report: &[ReportRow],
+    lineage: &[LineageRow],
+    locale: Locale,
+) -> Result<usize, Box<dyn Error>> {
+    let lines = schedule_g_lines(report)?;
+    let years = acquisition_years(report, lineage);
+    let mut wtr = WriterBuilder::new().from_writer(create_output(path)?);
+    wtr.write_record(
+        [
+            "Number",
+            "Description of property",
+            "Year of acquisition",
+            "Proceeds of disposition",
+            "Adjusted cost base",
+            "Outlays and expenses",
+            "Gain (or loss)",
+        ]
+        .map(|h| locale.text(h)),
+    )?;
+    for (i, line) in lines.iter().enumerate() {
+        let total = i + 1 == lines.len();
+        let (units, description, acquired) = if total {
+            (String::new(), locale.text("Total"), String::new())
+        } else {
+            (
+                q8(line.units).normalize().to_string(),
+                line.description.as_str(),
+                year_range(years.get(line.description.as_str())),
+            )
+        };
+        wtr.write_record([
+            units,
+            description.to_string(),
+            acquired,
+            q2(line.proceeds_cad).to_string(),
+            q2(line.acb_cad).to_string(),
+            "0".to_string(),
+            q2(line.gain_cad).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(lines.len() - 1)
+}
+
+// The report as adjustedcostbase.ca transactions: a Buy per asset for the
+// pool held at the start of the year, then a Buy for every row adding units,
+// at the ACB it added, and a Sell for every row removing them, at its
+// proceeds. Fees are already in both, so commissions are zero.
+fn adjustedcostbase_rows(
+    report: &[ReportRow],
+    ending_pools: &[EndingPoolRow],
+    tax_year: i32,
+) -> Result<Vec<AdjustedCostBaseRow>, Box<dyn Error>> {
+    let start = journal::start_date(report, tax_year);
+    let mut rows: Vec<AdjustedCostBaseRow> = journal::opening_pools(report, ending_pools)?
+        .into_iter()
+        .filter(|(asset, (units, _))| *asset != "CAD" && *units > Decimal::ZERO)
+        .map(|(asset, (units, acb))| AdjustedCostBaseRow {
+            security: asset.to_string(),
+            date: start.clone(),
+            transaction_type: "Buy",
+            amount: acb.to_string(),
+            shares: units.normalize().to_string(),
+            commission: "0".to_string(),
+            memo: format!("Pool at the start of {}", tax_year),
+        })
+        .collect();
+    for r in report.iter().filter(|r| &*r.asset != "CAD") {
+        let legs = [
+            ("Buy", &r.units_in, &r.acb_added_cad),
+            ("Sell", &r.units_out, &r.proceeds_cad),
+        ];
+        for (transaction_type, units, value) in legs {
+            let units = amount(units)?;
+            if units.is_zero() {
+                continue;
+            }
+            rows.push(AdjustedCostBaseRow {
+                security: r.asset.to_string(),
+                date: r.time.get(..10).unwrap_or(&r.time).to_string(),
+                transaction_type,
+                amount: amount(value)?.to_string(),
+                shares: units.normalize().to_string(),
+                commission: "0".to_string(),
+                memo: format!("{} {}", r.event_type, r.refid).trim().to_string(),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+// Writes the report in adjustedcostbase.ca's import format and returns the
+// number of transactions written.
+pub(crate) fn write_adjustedcostbase(
+    path: &str,
+    report: &[ReportRow],
+    ending_pools: &[EndingPoolRow],
+    tax_year: i32,
+) -> Result<usize, Box<dyn Error>> {
+    let rows = adjustedcostbase_rows(report, ending_pools, tax_year)?;
+    write_csv(path, &rows)?;
+    Ok(rows.len())
+}
+
+// When the units behind a disposition, or an asset's dispositions, were
+// acquired: the dates (or years), with "" for unknown, and whether any came
+// from an average-cost pool of more than one acquisition, whose units have
+// no date of their own.
+#[derive(Debug, Default)]
+struct Acquired {
+    dates: BTreeSet<String>,
+    pooled: bool,
+}
+
+// The acquisition dates of each disposition's lots, by refid and asset,
+// from the lot audit. Under average cost they are the first and last
+// acquisition of the pool's units, not the mean time the audit shows.
+fn acquisition_dates(lineage: &[LineageRow]) -> HashMap<(&str, &str), Acquired> {
+    let mut dates: HashMap<(&str, &str), Acquired> = HashMap::new();
+    for l in lineage {
+        let held = dates.entry((&l.disposition_refid, &l.asset)).or_default();
+        match (l.basis, l.acquisitions) {
+            ("pool_average", Some(span)) => {
+                held.dates.insert(span.first.date().to_string());
+                held.dates.insert(span.last.date().to_string());
+                held.pooled |= span.count > 1;
+            }
+            ("pool_average", None) => {
+                held.dates.insert(String::new());
+            }
+            _ => {
+                let day = l.acquisition_time.get(..10).unwrap_or_default();
+                held.dates.insert(day.to_string());
+            }
+        }
+    }
+    dates
+}
+
+// A single known date as it is, anything else as "Various", as the filing
+// software's own entry forms have it.
+fn acquired(held: Option<&Acquired>) -> String {
+    match held.filter(|h| !h.pooled && h.dates.len() == 1) {
+        Some(h) if !h.dates.contains("") => h.dates.iter().map(String::as_str).collect(),
+        _ => "Various".to_string(),
+    }
+}
+
+// The years each asset's disposed units were acquired in, with "" for
+// dispositions the lot audit has no acquisition date for.
+fn acquisition_years<'a>(
+    report: &'a [ReportRow],
+    lineage: &[LineageRow],
+) -> BTreeMap<&'a str, Acquired> {
+    let dates = acquisition_dates(lineage);
+    let mut years: BTreeMap<&str, Acquired> = BTreeMap::new();
+    for r in report.iter().filter(|r| !r.gain_cad.is_empty()) {
+        let held = years.entry(&r.asset).or_default();
+        match dates.get(&(&*r.refid, &*r.asset)) {
+            Some(d) => {
+                let days = d.dates.iter();
+                held.dates
+                    .extend(days.map(|day| day.get(..4).unwrap_or_default().to_string()));
+                held.pooled |= d.pooled;
+            }
+            None => {
+                held.dates.insert(String::new());
+            }
+        }
+    }
+    years
+}
+
+// `2023` or `2021-2024`: the span of the known acquisition years, blank when
+// none is known.
+fn year_range(years: Option<&Acquired>) -> String {
+    let mut known = years
+        .into_iter()
+        .flat_map(|y| &y.dates)
+        .filter(|y| !y.is_empty());
+    match (known.next(), known.next_back()) {
+        (Some(first), Some(last)) => format!("{}-{}", first, last),
+        (Some(first), None) => first.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn units_description(units: Decimal, asset: &str) -> String {
+    format!("{} {}", q8(units).normalize(), asset)
+}
+
+// Wealthsimple Tax's capital-gains import: the year's dispositions summed by
+// asset, like Schedule 3's list, with zero outlays.
+fn wealthsimple_tax_rows(
+    report: &[ReportRow],
+    lineage: &[LineageRow],
+) -> Result<Vec<WealthsimpleTaxRow>, Box<dyn Error>> {
+    let years = acquisition_years(report, lineage);
+    let mut lines = schedule_g_lines(report)?;
+    lines.pop();
+    Ok(lines
+        .into_iter()
+        .map(|line| WealthsimpleTaxRow {
+            year_acquired: acquired(years.get(line.description.as_str())),
+            description: units_description(line.units, &line.description),
+            proceeds: q2(line.proceeds_cad).to_string(),
+            acb: q2(line.acb_cad).to_string(),
+            outlays: "0".to_string(),
+        })
+        .collect())
+}
+
+// TurboTax's capital-gains import: one row per taxable disposition, deemed
+// ones included.
+fn turbotax_rows(
+    report: &[ReportRow],
+    lineage: &[LineageRow],
+) -> Result<Vec<TurboTaxRow>, Box<dyn Error>> {
+    let dates = acquisition_dates(lineage);
+    taxable_dispositions(report)
+        .map(|disposition| {
+            let (r, acb, _) = disposition?;
+            Ok(TurboTaxRow {
+                description: units_description(amount(&r.units_out)?, &r.asset),
+                date_acquired: acquired(dates.get(&(&*r.refid, &*r.asset))),
+                date_sold: r.time.get(..10).unwrap_or(&r.time).to_string(),
+                proceeds: q2(amount(&r.proceeds_cad)?).to_string(),
+                acb: q2(acb).to_string(),
+                outlays: "0".to_string(),
+            })
+        })
+        .collect()
+}
+
+// Writes the report's dispositions as `format`'s capital-gains import and
+// returns the number of rows written.
+pub(crate) fn write_capital_gains(
+    format: ExportFormat,
+    path: &str,
+    report: &[ReportRow],
+    lineage: &[LineageRow],
+) -> Result<usize, Box<dyn Error>> {
+    match format {
+        ExportFormat::WealthsimpleTax => {
+            let rows = wealthsimple_tax_rows(report, lineage)?;
+            write_csv(path, &rows)?;
+            Ok(rows.len())
+        }
+        ExportFormat::TurboTax => {
+            let rows = turbotax_rows(report, lineage)?;
+            write_csv(path, &rows)?;
+            Ok(rows.len())
+        }
+        _ => Err(format!("{} is not a capital-gains import", format.as_str()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{entry, leg};
+    use crate::{parse_time, stamp};
+
+    #[test]
+    fn trades_and_rewards_export_as_koinly_rows() {
+        let entries = vec![
+            entry(
+                "2025-03-01 12:00:00",
+                "TR1CAD",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-1000",
+                "2.6",
+            ),
+            leg("R1", "2025-03-01 12:00:00", "BTC", dec!(0.01)),
+            entry(
+                "2025-03-01 12:00:00",
+                "TR2DOT",
+                "R2",
+                "earn",
+                "reward",
+                "DOT",
+                "0.5",
+                "0",
+            ),
+            entry(
+                "2025-03-01 12:00:00",
+                "TR3DOT",
+                "R3",
+                "earn",
+                "allocation",
+                "DOT",
+                "-0.5",
+                "0",
+            ),
+        ];
+
+        let rows: Vec<_> = universal_rows(&entries, &crate::Canada, 2025)
+            .unwrap()
+            .iter()
+            .map(koinly_row)
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].date, "2025-03-01 12:00:00 UTC");
+        assert_eq!(
+            (rows[0].sent_amount.as_str(), rows[0].sent_currency.as_str()),
+            ("1000", "CAD")
+        );
+        assert_eq!(rows[0].received_amount, "0.01");
+        assert_eq!(rows[0].fee_amount, "2.6");
+        assert_eq!(rows[0].label, "");
+        assert_eq!(rows[1].received_currency, "DOT");
+        assert_eq!(rows[1].label, "reward");
+    }
+
+    #[test]
+    fn schedule_g_sums_dispositions_by_asset() {
+        let no_id: Arc<str> = Arc::from("");
+        let row = |event_type: &str, asset: &str, units: &str, amounts: [&str; 3]| {
+            let mut r = crate::make_row(
+                stamp(NaiveDateTime::MIN, None),
+                &no_id,
+                &no_id,
+                event_type,
+                &asset.into(),
+            );
+            r.units_out = units.to_string();
+            r.proceeds_cad = amounts[0].to_string();
+            r.acb_disposed_cad = amounts[1].to_string();
+            r.gain_cad = amounts[2].to_string();
+            r
+        };
+        let mut reward = row("staking_reward", "DOT", "", ["", "", ""]);
+        reward.income_cad = "10".to_string();
+        let report = vec![
+            row("trade_disposition", "BTC", "0.5", ["300", "200", "100"]),
+            row("trade_disposition", "BTC", "0.25", ["100", "120", "-20"]),
+            row("trade_disposition", "ETH", "1", ["50", "80", "-30"]),
+            reward,
+        ];
+        let lines = schedule_g_lines(&report).unwrap();
+        let summary: Vec<_> = lines
+            .iter()
+            .map(|l| (l.description.as_str(), l.units, l.proceeds_cad, l.gain_cad))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("BTC", dec!(0.75), dec!(400), dec!(80)),
+                ("ETH", dec!(1), dec!(50), dec!(-30)),
+                ("Total", dec!(0), dec!(450), dec!(50)),
+            ]
+        );
+    }
+
+    #[test]
+    fn capital_gains_imports_date_each_disposition() {
+        let sale = |refid: &str, asset: &str, units: &str, amounts: [&str; 3]| {
+            let mut r = crate::make_row(
+                stamp(parse_time("2025-05-02 09:30:00").unwrap(), None),
+                &refid.into(),
+                &refid.into(),
+                "trade_disposition",
+                &asset.into(),
+            );
+            r.units_out = units.to_string();
+            r.proceeds_cad = amounts[0].to_string();
+            r.acb_disposed_cad = amounts[1].to_string();
+            r.gain_cad = amounts[2].to_string();
+            r
+        };
+        let lot = |refid: &str, acquired: &str| LineageRow {
+            disposition_time: String::new(),
+            disposition_refid: refid.into(),
+            event_type: "trade_disposition".to_string(),
+            asset: "BTC".into(),
+            basis: "lot",
+            acquisition_time: acquired.to_string(),
+            acquisition_refid: "".into(),
+            acquisitions: None,
+            units: String::new(),
+            acb_cad: String::new(),
+            proceeds_cad: String::new(),
+            gain_cad: String::new(),
+            holding_days: String::new(),
+            holding_period: "",
+        };
+        let report = vec![
+            sale("S1", "BTC", "0.5", ["300.004", "200", "100.004"]),
+            sale("S2", "BTC", "0.25", ["100", "120", "-20"]),
+            sale("S3", "ETH", "1", ["50", "80", "-30"]),
+        ];
+        let lineage = vec![
+            lot("S1", "2023-01-05T00:00:00+00:00"),
+            lot("S2", "2023-06-01T00:00:00+00:00"),
+            lot("S2", "2024-02-01T00:00:00+00:00"),
+        ];
+
+        let turbotax: Vec<_> = turbotax_rows(&report, &lineage)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.description, r.date_acquired, r.date_sold, r.proceeds))
+            .collect();
+        let row = |d: &str, acquired: &str, proceeds: &str| {
+            (
+                d.to_string(),
+                acquired.to_string(),
+                "2025-05-02".to_string(),
+                proceeds.to_string(),
+            )
+        };
+        assert_eq!(
+            turbotax,
+            [
+                row("0.5 BTC", "2023-01-05", "300.00"),
+                row("0.25 BTC", "Various", "100"),
+                // Not in the lot audit, so its acquisition is unknown.
+                row("1 ETH", "Various", "50"),
+            ]
+        );
+
+        let wealthsimple: Vec<_> = wealthsimple_tax_rows(&report, &lineage[..1])
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.description, r.year_acquired, r.acb))
+            .collect();
+        assert_eq!(
+            wealthsimple,
+            [
+                (
+                    "0.75 BTC".to_string(),
+                    "Various".to_string(),
+                    "320".to_string()
+                ),
+                ("1 ETH".to_string(), "Various".to_string(), "80".to_string()),
+            ]
+        );
+        let one_lot = wealthsimple_tax_rows(&report[..1], &lineage).unwrap();
+        assert_eq!(one_lot[0].year_acquired, "2023");
+    }
+
+    #[test]
+    fn schedule_3_lists_assets_with_acquisition_year_ranges() {
+        let sale = |refid: &str, asset: &str, units: &str, amounts: [&str; 3]| {
+            let mut r = crate::make_row(
+                stamp(parse_time("2025-05-02 09:30:00").unwrap(), None),
+                &refid.into(),
+                &refid.into(),
+                "trade_disposition",
+                &asset.into(),
+            );
+            r.units_out = units.to_string();
+            r.proceeds_cad = amounts[0].to_string();
+            r.acb_disposed_cad = amounts[1].to_string();
+            r.gain_cad = amounts[2].to_string();
+            r
+        };
+        let lot = |refid: &str, acquired: &str| LineageRow {
+            disposition_time: String::new(),
+            disposition_refid: refid.into(),
+            event_type: "trade_disposition".to_string(),
+            asset: "BTC".into(),
+            basis: "lot",
+            acquisition_time: acquired.to_string(),
+            acquisition_refid: "".into(),
+            acquisitions: None,
+            units: String::new(),
+            acb_cad: String::new(),
+            proceeds_cad: String::new(),
+            gain_cad: String::new(),
+            holding_days: String::new(),
+            holding_period: "",
+        };
+        let report = vec![
+            sale("S1", "BTC", "0.5", ["300", "200", "100"]),
+            sale("S2", "BTC", "0.25", ["100", "120", "-20"]),
+            sale("S3", "BTC", "0.25", ["100", "80", "20"]),
+            sale("S4", "ETH", "1", ["50", "80", "-30"]),
+        ];
+        let lineage = vec![
+            lot("S1", "2023-01-05T00:00:00+00:00"),
+            lot("S2", "2021-06-01T00:00:00+00:00"),
+            // A transfer in with no known acquisition date.
+            lot("S3", ""),
+        ];
+        let path = std::env::temp_dir().join(format!("kraken_acb_s3_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let assets = write_schedule_3(path, &report, &lineage, Locale::En).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(assets, 2);
+        assert_eq!(
+            written,
+            "Number,Description of property,Year of acquisition,Proceeds of disposition,\
+             Adjusted cost base,Outlays and expenses,Gain (or loss)\n\
+             1,BTC,2021-2023,500,400,0,100\n\
+             1,ETH,,50,80,0,-30\n\
+             ,Total,,550,480,0,70\n"
+        );
+    }
+
+    #[test]
+    fn pool_average_dispositions_span_their_acquisition_years() {
+        let entries = vec![
+            leg("B1", "2021-01-01 00:00:00", "CAD", dec!(-30000)),
+            leg("B1", "2021-01-01 00:00:00", "BTC", dec!(1)),
+            leg("B2", "2024-12-31 00:00:00", "CAD", dec!(-90000)),
+            leg("B2", "2024-12-31 00:00:00", "BTC", dec!(1)),
+            leg("S1", "2025-02-01 00:00:00", "BTC", dec!(-2)),
+            leg("S1", "2025-02-01 00:00:00", "CAD", dec!(200000)),
+        ];
+        let out = crate::process(entries, &crate::ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        // The audit's mean acquisition time falls in 2023, a year no unit
+        // was bought in.
+        assert!(out.lineage[0].acquisition_time.starts_with("2023-"));
+        let path = std::env::temp_dir().join(format!("kraken_acb_span_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_schedule_3(path, &out.report, &out.lineage, Locale::En).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(
+            written.contains("\n2,BTC,2021-2024,200000,120000,0,80000\n"),
+            "{}",
+            written
+        );
+    }
+
+    // A USD round trip at `usd_gain` CAD and a 100 CAD gain on BTC.
+    fn fx_round_trip(usd_gain: Decimal) -> crate::ProcessOutput {
+        let entries = vec![
+            leg("B1", "2025-01-10 00:00:00", "CAD", dec!(-1400)),
+            leg("B1", "2025-01-10 00:00:00", "USD", dec!(1000)),
+            leg("B2", "2025-02-01 00:00:00", "CAD", dec!(-1000)),
+            leg("B2", "2025-02-01 00:00:00", "BTC", dec!(0.01)),
+            leg("S1", "2025-03-01 12:00:00", "USD", dec!(-1000)),
+            leg("S1", "2025-03-01 12:00:00", "CAD", dec!(1400) + usd_gain),
+            leg("S2", "2025-03-01 12:00:00", "BTC", dec!(-0.01)),
+            leg("S2", "2025-03-01 12:00:00", "CAD", dec!(1100)),
+        ];
+        crate::process(entries, &crate::ProcessOptions::new(2025, dec!(1.4))).unwrap()
+    }
+
+    #[test]
+    fn schedule_3_leaves_out_exempt_foreign_exchange_gains() {
+        let path = std::env::temp_dir().join(format!("kraken_acb_fx_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        // The schedule, and its total gain against the summary's.
+        let schedule = |out: &crate::ProcessOutput| {
+            write_schedule_3(path, &out.report, &out.lineage, Locale::En).unwrap();
+            let written = std::fs::read_to_string(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            let total = written.lines().last().unwrap().rsplit(',').next().unwrap();
+            assert_eq!(parse_decimal(total).unwrap(), out.totals.capital_gain_cad);
+            written
+        };
+
+        // A 150 CAD gain is within the year's 200 CAD exemption.
+        let out = fx_round_trip(dec!(150));
+        assert_eq!(out.totals.fx_exempt_cad, dec!(150));
+        assert_eq!(out.totals.capital_gain_cad, dec!(100));
+        let written = schedule(&out);
+        assert!(!written.contains("USD"), "{}", written);
+
+        // Only what a 350 CAD gain exceeds it by is taxable.
+        let out = fx_round_trip(dec!(350));
+        assert_eq!(out.totals.capital_gain_cad, dec!(250));
+        let written = schedule(&out);
+        assert!(
+            written.contains("\n1000,USD,2025,1750,1600.00,0,150.00\n"),
+            "{}",
+            written
+        );
+    }
+
+    #[test]
+    fn schedule_g_leaves_out_exempt_foreign_exchange_gains() {
+        let out = fx_round_trip(dec!(150));
+        let path = std::env::temp_dir().join(format!("kraken_acb_g_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(write_schedule_g(path, &out.report, Locale::En).unwrap(), 1);
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!written.contains("USD"), "{}", written);
+        let total = written.lines().last().unwrap().rsplit(',').next().unwrap();
+        assert_eq!(parse_decimal(total).unwrap(), out.totals.capital_gain_cad);
+    }
+
+    #[test]
+    fn filing_imports_leave_out_exempt_foreign_exchange_gains() {
+        // Proceeds less ACB over an import's rows, against the summary.
+        let gain = |rows: Vec<(String, String, String)>, out: &crate::ProcessOutput| {
+            let descriptions: Vec<String> = rows.iter().map(|r| r.0.clone()).collect();
+            let total: Decimal = rows
+                .iter()
+                .map(|r| parse_decimal(&r.1).unwrap() - parse_decimal(&r.2).unwrap())
+                .sum();
+            assert_eq!(total, out.totals.capital_gain_cad);
+            descriptions
+        };
+        for (usd_gain, listed) in [
+            (dec!(150), vec!["0.01 BTC"]),
+            (dec!(350), vec!["0.01 BTC", "1000 USD"]),
+        ] {
+            let out = fx_round_trip(usd_gain);
+            let wealthsimple = wealthsimple_tax_rows(&out.report, &out.lineage)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.description, r.proceeds, r.acb))
+                .collect();
+            assert_eq!(gain(wealthsimple, &out), listed);
+            let mut turbotax: Vec<_> = turbotax_rows(&out.report, &out.lineage)
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.description, r.proceeds, r.acb))
+                .collect();
+            turbotax.sort();
+            assert_eq!(gain(turbotax, &out), listed);
+        }
+    }
+
+    #[test]
+    fn pool_average_dispositions_of_several_acquisitions_read_various() {
+        let entries = vec![
+            // Two ETH purchases on one day, one of SOL.
+            leg("B1", "2024-03-01 09:00:00", "CAD", dec!(-3000)),
+            leg("B1", "2024-03-01 09:00:00", "ETH", dec!(1)),
+            leg("B2", "2024-03-01 17:00:00", "CAD", dec!(-3200)),
+            leg("B2", "2024-03-01 17:00:00", "ETH", dec!(1)),
+            leg("B3", "2024-05-01 00:00:00", "CAD", dec!(-200)),
+            leg("B3", "2024-05-01 00:00:00", "SOL", dec!(1)),
+            leg("S1", "2025-03-01 12:00:00", "ETH", dec!(-1)),
+            leg("S1", "2025-03-01 12:00:00", "CAD", dec!(4000)),
+            leg("S2", "2025-03-01 12:00:00", "SOL", dec!(-0.5)),
+            leg("S2", "2025-03-01 12:00:00", "CAD", dec!(150)),
+        ];
+        let out = crate::process(entries, &crate::ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let turbotax: Vec<_> = turbotax_rows(&out.report, &out.lineage)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.description, r.date_acquired))
+            .collect();
+        assert_eq!(
+            turbotax,
+            [
+                ("1 ETH".to_string(), "Various".to_string()),
+                ("0.5 SOL".to_string(), "2024-05-01".to_string()),
+            ]
+        );
+        let years: Vec<_> = wealthsimple_tax_rows(&out.report, &out.lineage)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.year_acquired)
+            .collect();
+        assert_eq!(years, ["Various", "2024"]);
+    }
+
+    #[test]
+    fn adjustedcostbase_rows_open_with_the_starting_pools() {
+        let refid: Arc<str> = Arc::from("S1");
+        let mut sale = crate::make_row(
+            stamp(parse_time("2025-04-01 12:00:00").unwrap(), None),
+            &refid,
+            &refid,
+            "trade_disposition",
+            &"SOL".into(),
+        );
+        sale.time = "2025-04-01T12:00:00+00:00".to_string();
+        sale.units_out = "1".to_string();
+        sale.proceeds_cad = "180".to_string();
+        sale.acb_disposed_cad = "100".to_string();
+        sale.pool_units_after = "2".to_string();
+        sale.pool_acb_cad_after = "200".to_string();
+        let rows = adjustedcostbase_rows(&[sale], &[], 2025).unwrap();
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|r| {
+                (
+                    r.security.as_str(),
+                    r.date.as_str(),
+                    r.transaction_type,
+                    r.amount.as_str(),
+                    r.shares.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("SOL", "2025-01-01", "Buy", "300", "3"),
+                ("SOL", "2025-04-01", "Sell", "180", "1"),
+            ]
+        );
+    }
+}
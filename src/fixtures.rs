@@ -0,0 +1,54 @@
+// Ledger rows for the unit tests, so a new `LedgerEntry` field is set in one
+// place.
+use crate::{LedgerEntry, parse_time};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+// A ledger row as an export would have it, amount and fee as text.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn entry(
+    time: &str,
+    txid: &str,
+    refid: &str,
+    row_type: &str,
+    subtype: &str,
+    asset: &str,
+    amount: &str,
+    fee: &str,
+) -> LedgerEntry {
+    let amount = Decimal::from_str(amount).unwrap();
+    let fee = Decimal::from_str(fee).unwrap();
+    LedgerEntry {
+        txid: txid.into(),
+        refid: refid.into(),
+        time: parse_time(time).unwrap(),
+        row_type: row_type.to_string(),
+        subtype: subtype.to_string(),
+        asset: asset.into(),
+        amount,
+        fee,
+        net_delta: amount - fee,
+        balance: None,
+        wallet: None,
+        utc_offset: None,
+    }
+}
+
+// A fee-free `row_type` row of `refid`, with txid `L<refid><asset>`.
+pub(crate) fn row(
+    refid: &str,
+    time: &str,
+    row_type: &str,
+    subtype: &str,
+    asset: &str,
+    amount: Decimal,
+) -> LedgerEntry {
+    let txid = format!("L{}{}", refid, asset);
+    let amount = amount.to_string();
+    entry(time, &txid, refid, row_type, subtype, asset, &amount, "0")
+}
+
+// One leg of spot trade `refid`.
+pub(crate) fn leg(refid: &str, time: &str, asset: &str, amount: Decimal) -> LedgerEntry {
+    row(refid, time, "trade", "tradespot", asset, amount)
+}
@@ -0,0 +1,203 @@
+use crate::take_flag;
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// Longest `Retry-After` honoured; a server asking for more fails the request.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// The one agent every network lookup (price providers, Bank of Canada FX,
+// chain imports) goes through, set from the `--http-*` flags.
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+#[derive(Debug)]
+struct Client {
+    agent: ureq::Agent,
+    retries: u32,
+    backoff: Duration,
+}
+
+#[derive(Debug)]
+struct Settings {
+    retries: u32,
+    backoff: Duration,
+    timeout: Duration,
+    // `None` takes `ALL_PROXY`, `HTTPS_PROXY` or `HTTP_PROXY` from the
+    // environment, when set.
+    proxy: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            timeout: DEFAULT_TIMEOUT,
+            proxy: None,
+        }
+    }
+}
+
+impl Settings {
+    fn client(&self) -> Result<Client, Box<dyn Error>> {
+        let mut builder = ureq::AgentBuilder::new()
+            .timeout(self.timeout)
+            .try_proxy_from_env(true);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(
+                ureq::Proxy::new(proxy).map_err(|_| "--http-proxy expects [http://]host[:port]")?,
+            );
+        }
+        Ok(Client {
+            agent: builder.build(),
+            retries: self.retries,
+            backoff: self.backoff,
+        })
+    }
+}
+
+fn seconds(name: &str, value: &str) -> Result<Duration, Box<dyn Error>> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| format!("--{} expects a number of seconds, got {:?}", name, value).into())
+}
+
+// Takes `--http-retries`, `--http-backoff`, `--http-timeout` and
+// `--http-proxy` out of `raw`, wherever they are, and sets up the client with
+// them.
+pub(crate) fn configure(mut raw: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let settings = take_settings(&mut raw)?;
+    let _ = CLIENT.set(settings.client()?);
+    Ok(raw)
+}
+
+// The last of a repeated flag wins.
+fn take_settings(raw: &mut Vec<String>) -> Result<Settings, Box<dyn Error>> {
+    let mut settings = Settings::default();
+    while let Some(v) = take_flag(raw, "http-retries")? {
+        settings.retries = v
+            .trim()
+            .parse()
+            .map_err(|_| format!("--http-retries expects a count, got {:?}", v))?;
+    }
+    while let Some(v) = take_flag(raw, "http-backoff")? {
+        settings.backoff = seconds("http-backoff", &v)?;
+    }
+    while let Some(v) = take_flag(raw, "http-timeout")? {
+        settings.timeout = seconds("http-timeout", &v)?;
+    }
+    while let Some(v) = take_flag(raw, "http-proxy")? {
+        settings.proxy = Some(v);
+    }
+    Ok(settings)
+}
+
+fn client() -> &'static Client {
+    CLIENT.get_or_init(|| {
+        Settings::default()
+            .client()
+            .expect("default client settings are valid")
+    })
+}
+
+// A GET of `url` with `query`, retried as `send` retries.
+pub(crate) fn get(url: &str, query: &[(&str, &str)]) -> Result<ureq::Response, Box<ureq::Error>> {
+    send(|agent| {
+        let request = query.iter().fold(agent.get(url), |r, (k, v)| r.query(k, v));
+        (request, None)
+    })
+}
+
+// Sends the request `request` builds, with its body if any, building it again
+// for each attempt (so a signed request gets a fresh nonce). Connection failures,
+// timeouts, 429 and 5xx responses are retried up to `--http-retries` times,
+// waiting `--http-backoff` and doubling each time, or as long as a 429's
+// `Retry-After` asks.
+pub(crate) fn send(
+    request: impl Fn(&ureq::Agent) -> (ureq::Request, Option<String>),
+) -> Result<ureq::Response, Box<ureq::Error>> {
+    let client = client();
+    let mut attempt = 0;
+    loop {
+        let result = match request(&client.agent) {
+            (request, Some(body)) => request.send_string(&body),
+            (request, None) => request.call(),
+        };
+        match result {
+            Err(e) if attempt < client.retries => {
+                let backoff = client.backoff.saturating_mul(2u32.saturating_pow(attempt));
+                let Some(delay) = retry_delay(&e, backoff) else {
+                    return Err(Box::new(e));
+                };
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result.map_err(Box::new),
+        }
+    }
+}
+
+// How long to wait before retrying after `e`, or `None` when it is not worth
+// retrying.
+fn retry_delay(e: &ureq::Error, backoff: Duration) -> Option<Duration> {
+    match e {
+        ureq::Error::Transport(_) => Some(backoff),
+        ureq::Error::Status(429, resp) => match resp.header("Retry-After") {
+            Some(after) => {
+                let after = Duration::from_secs(after.trim().parse().ok()?);
+                (after <= MAX_RETRY_AFTER).then_some(after.max(backoff))
+            }
+            None => Some(backoff),
+        },
+        ureq::Error::Status(500..=599, _) => Some(backoff),
+        ureq::Error::Status(..) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_flags_are_taken_out_of_the_arguments() {
+        let raw = [
+            "ledger.csv",
+            "--http-retries",
+            "5",
+            "2025",
+            "--http-timeout=2.5",
+        ];
+        let mut raw: Vec<String> = raw.iter().map(|s| s.to_string()).collect();
+        let settings = take_settings(&mut raw).unwrap();
+        assert_eq!(raw, ["ledger.csv", "2025"]);
+        assert_eq!(
+            (settings.retries, settings.timeout, settings.backoff),
+            (5, Duration::from_millis(2500), DEFAULT_BACKOFF)
+        );
+        let mut bad = vec!["--http-backoff=-1".to_string()];
+        assert!(take_settings(&mut bad).is_err());
+
+        let status = |code, after: Option<&str>| {
+            let mut text = format!("HTTP/1.1 {} X\r\n", code);
+            if let Some(after) = after {
+                text.push_str(&format!("Retry-After: {}\r\n", after));
+            }
+            let resp: ureq::Response = format!("{}\r\n", text).parse().unwrap();
+            ureq::Error::Status(code, resp)
+        };
+        let backoff = Duration::from_secs(2);
+        assert_eq!(retry_delay(&status(503, None), backoff), Some(backoff));
+        assert_eq!(
+            retry_delay(&status(429, Some("7")), backoff),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(retry_delay(&status(429, Some("3600")), backoff), None);
+        assert_eq!(retry_delay(&status(404, None), backoff), None);
+    }
+}
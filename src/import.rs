@@ -0,0 +1,756 @@
+use crate::{
+    CsvLocale, Interner, LedgerEntry, load_entries, parse_amount, parse_date_or_time, parse_time,
+    read_input_text, sort_entries,
+};
+use chrono::{DateTime, NaiveDateTime};
+use csv::{ReaderBuilder, StringRecord};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ImportFormat {
+    Kraken,
+    Generic,
+    Shakepay,
+    Newton,
+    Koinly,
+    CoinTracking,
+    Manual,
+    AdjustedCostBase,
+}
+
+impl ImportFormat {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "kraken" => Ok(ImportFormat::Kraken),
+            "generic" => Ok(ImportFormat::Generic),
+            "shakepay" => Ok(ImportFormat::Shakepay),
+            "newton" => Ok(ImportFormat::Newton),
+            "koinly" => Ok(ImportFormat::Koinly),
+            "cointracking" => Ok(ImportFormat::CoinTracking),
+            "manual" => Ok(ImportFormat::Manual),
+            "adjustedcostbase" => Ok(ImportFormat::AdjustedCostBase),
+            other => Err(format!("unknown import format: {}", other).into()),
+        }
+    }
+
+    fn id_prefix(&self) -> &'static str {
+        match self {
+            ImportFormat::Kraken => "KRAKEN",
+            ImportFormat::Generic => "GEN",
+            ImportFormat::Shakepay => "SHAKEPAY",
+            ImportFormat::Newton => "NEWTON",
+            ImportFormat::Koinly => "KOINLY",
+            ImportFormat::CoinTracking => "COINTRACKING",
+            ImportFormat::Manual => "MANUAL",
+            ImportFormat::AdjustedCostBase => "ACB",
+        }
+    }
+}
+
+// Shakepay "transactions summary" export.
+const SHAKEPAY_MAPPING: &str = r#"
+time = "Date"
+time_format = "%Y-%m-%dT%H:%M:%S%#z"
+type = "Transaction Type"
+buy_asset = "Credit Currency"
+buy_amount = "Amount Credited"
+sell_asset = "Debit Currency"
+sell_amount = "Amount Debited"
+
+[types]
+"purchase/sale" = "trade"
+"fiat funding" = "deposit"
+"fiat cashout" = "withdrawal"
+"crypto funding" = "deposit"
+"crypto cashout" = "withdrawal"
+"shakingsats" = "earn/reward"
+"secret sats" = "earn/reward"
+"card cashbacks" = "earn/reward"
+"card cashback" = "earn/reward"
+"referral reward" = "credit"
+"referral" = "credit"
+"#;
+
+// Newton transaction history export.
+const NEWTON_MAPPING: &str = r#"
+time = "Date"
+type = "Type"
+buy_asset = "Received Currency"
+buy_amount = "Received Quantity"
+sell_asset = "Sent Currency"
+sell_amount = "Sent Quantity"
+fee_asset = "Fee Currency"
+fee_amount = "Fee Amount"
+
+[types]
+"TRADE" = "trade"
+"DEPOSIT" = "deposit"
+"WITHDRAWN" = "withdrawal"
+"WITHDRAWAL" = "withdrawal"
+"REFERRAL" = "credit"
+"#;
+
+// Koinly universal CSV. Unlabelled rows are inferred from which legs are set.
+const KOINLY_MAPPING: &str = r#"
+time = "Date"
+time_format = "%Y-%m-%d %H:%M:%S UTC"
+type = "Label"
+buy_asset = "Received Currency"
+buy_amount = "Received Amount"
+sell_asset = "Sent Currency"
+sell_amount = "Sent Amount"
+fee_asset = "Fee Currency"
+fee_amount = "Fee Amount"
+
+[types]
+"swap" = "trade"
+"reward" = "earn/reward"
+"staking" = "earn/reward"
+"mining" = "earn/reward"
+"lending interest" = "earn/reward"
+"loan interest" = "earn/reward"
+"airdrop" = "credit"
+"income" = "credit"
+"other income" = "credit"
+"cost" = "spend"
+"donation" = "withdrawal/donation"
+"#;
+
+// CoinTracking CSV import template.
+const COINTRACKING_MAPPING: &str = r#"
+time = "Date"
+type = "Type"
+buy_asset = "Buy Currency"
+buy_amount = "Buy Amount"
+sell_asset = "Sell Currency"
+sell_amount = "Sell Amount"
+fee_asset = "Fee Currency"
+fee_amount = "Fee"
+
+[types]
+"Trade" = "trade"
+"Deposit" = "deposit"
+"Withdrawal" = "withdrawal"
+"Staking" = "earn/reward"
+"Mining" = "earn/reward"
+"Interest Income" = "earn/reward"
+"Income" = "credit"
+"Reward / Bonus" = "credit"
+"Airdrop" = "credit"
+"Dividends Income" = "dividend"
+"Spend" = "spend"
+"Other Fee" = "spend"
+"#;
+
+// Hand-maintained file for off-exchange activity (P2P buys, DEX swaps, ...).
+const MANUAL_MAPPING: &str = r#"
+time = "time"
+type = "type"
+refid = "refid"
+buy_asset = "buy_asset"
+buy_amount = "buy_amount"
+sell_asset = "sell_asset"
+sell_amount = "sell_amount"
+fee_asset = "fee_asset"
+fee_amount = "fee_amount"
+
+[types]
+"buy" = "trade"
+"sell" = "trade"
+"swap" = "trade"
+"reward" = "earn/reward"
+"staking" = "earn/reward"
+"income" = "credit"
+"airdrop" = "credit"
+"gift" = "withdrawal/gift"
+"donation" = "withdrawal/donation"
+"#;
+
+// Loads one input file; generated ids are prefixed with `tag` so rows from
+// different files never share a refid.
+pub(crate) fn load_input(
+    format: ImportFormat,
+    path: &str,
+    mapping: Option<&GenericMapping>,
+    tag: &str,
+    locale: CsvLocale,
+    interner: &mut Interner,
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let text = read_input_text(path)?;
+    parse_input(format, &text, mapping, tag, locale, interner)
+}
+
+pub(crate) fn parse_input(
+    format: ImportFormat,
+    text: &str,
+    mapping: Option<&GenericMapping>,
+    tag: &str,
+    locale: CsvLocale,
+    interner: &mut Interner,
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let prefix = format!("{}{}-", format.id_prefix(), tag);
+    match format {
+        ImportFormat::Kraken => load_entries(text, locale, interner),
+        ImportFormat::Generic => {
+            let mapping = mapping.ok_or("--import-format generic requires --mapping")?;
+            parse_generic(text, mapping, &prefix, locale, interner)
+        }
+        ImportFormat::Shakepay => parse_generic(
+            text,
+            &toml::from_str(SHAKEPAY_MAPPING)?,
+            &prefix,
+            locale,
+            interner,
+        ),
+        ImportFormat::Newton => parse_generic(
+            text,
+            &toml::from_str(NEWTON_MAPPING)?,
+            &prefix,
+            locale,
+            interner,
+        ),
+        ImportFormat::Koinly => parse_generic(
+            text,
+            &toml::from_str(KOINLY_MAPPING)?,
+            &prefix,
+            locale,
+            interner,
+        ),
+        ImportFormat::CoinTracking => parse_generic(
+            text,
+            &toml::from_str(COINTRACKING_MAPPING)?,
+            &prefix,
+            locale,
+            interner,
+        ),
+        ImportFormat::Manual => parse_generic(
+            text,
+            &toml::from_str(MANUAL_MAPPING)?,
+            &prefix,
+            locale,
+            interner,
+        ),
+        ImportFormat::AdjustedCostBase => parse_adjustedcostbase(text, &prefix, locale, interner),
+    }
+}
+
+// Column mapping for `--import-format generic`. Each field names a column in
+// the source CSV; rows either carry buy/sell legs or a single asset/amount.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct GenericMapping {
+    time: String,
+    #[serde(default)]
+    time_format: Option<String>,
+    #[serde(default)]
+    txid: Option<String>,
+    #[serde(default)]
+    refid: Option<String>,
+    #[serde(default, rename = "type")]
+    row_type: Option<String>,
+    #[serde(default)]
+    buy_asset: Option<String>,
+    #[serde(default)]
+    buy_amount: Option<String>,
+    #[serde(default)]
+    sell_asset: Option<String>,
+    #[serde(default)]
+    sell_amount: Option<String>,
+    #[serde(default)]
+    fee_asset: Option<String>,
+    #[serde(default)]
+    fee_amount: Option<String>,
+    #[serde(default)]
+    asset: Option<String>,
+    #[serde(default)]
+    amount: Option<String>,
+    #[serde(default)]
+    delimiter: Option<char>,
+    // Source type value -> ledger `type` or `type/subtype`.
+    #[serde(default)]
+    types: HashMap<String, String>,
+}
+
+pub(crate) fn load_generic_mapping(path: &str) -> Result<GenericMapping, Box<dyn Error>> {
+    parse_generic_mapping(&std::fs::read_to_string(path)?)
+}
+
+pub(crate) fn parse_generic_mapping(text: &str) -> Result<GenericMapping, Box<dyn Error>> {
+    Ok(toml::from_str(text)?)
+}
+
+struct RowBase {
+    txid: String,
+    refid: Arc<str>,
+    time: NaiveDateTime,
+    row_type: String,
+    subtype: String,
+}
+
+impl RowBase {
+    fn leg(
+        &self,
+        interner: &mut Interner,
+        suffix: &str,
+        asset: &str,
+        amount: Decimal,
+        fee: Decimal,
+    ) -> LedgerEntry {
+        LedgerEntry {
+            txid: format!("{}{}", self.txid, suffix).into(),
+            refid: self.refid.clone(),
+            time: self.time,
+            row_type: self.row_type.clone(),
+            subtype: self.subtype.clone(),
+            asset: interner.intern(&asset.trim().to_uppercase()),
+            amount,
+            fee,
+            net_delta: amount - fee,
+            balance: None,
+            wallet: None,
+            utc_offset: None,
+        }
+    }
+}
+
+fn column<'a>(
+    headers: &StringRecord,
+    record: &'a StringRecord,
+    name: &Option<String>,
+) -> Option<&'a str> {
+    let name = name.as_ref()?;
+    let idx = headers.iter().position(|h| h.trim() == name)?;
+    record.get(idx).map(str::trim).filter(|v| !v.is_empty())
+}
+
+fn amount_column(
+    headers: &StringRecord,
+    record: &StringRecord,
+    name: &Option<String>,
+    decimal_comma: bool,
+) -> Result<Option<Decimal>, Box<dyn Error>> {
+    match column(headers, record, name) {
+        Some(v) => Ok(Some(parse_amount(v, decimal_comma)?.abs())),
+        None => Ok(None),
+    }
+}
+
+fn parse_time_with_format(raw: &str, fmt: &str) -> Result<NaiveDateTime, chrono::ParseError> {
+    // Formats carrying an offset are normalized to UTC.
+    if fmt.contains('z') {
+        return DateTime::parse_from_str(raw, fmt).map(|t| t.naive_utc());
+    }
+    NaiveDateTime::parse_from_str(raw, fmt)
+}
+
+fn parse_generic(
+    text: &str,
+    mapping: &GenericMapping,
+    id_prefix: &str,
+    locale: CsvLocale,
+    interner: &mut Interner,
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    // A delimiter named in the mapping wins over `--delimiter` and detection.
+    let locale = CsvLocale {
+        delimiter: mapping.delimiter.map(|c| c as u8).or(locale.delimiter),
+        ..locale
+    };
+    let (delimiter, decimal_comma) = locale.resolve(text);
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers()?.clone();
+    let mut out = Vec::new();
+
+    for (i, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = i + 2;
+        let col = |name: &Option<String>| column(&headers, &record, name);
+
+        let raw_time = col(&Some(mapping.time.clone()))
+            .ok_or_else(|| format!("line {}: missing time column {}", line, mapping.time))?;
+        let time = match &mapping.time_format {
+            Some(fmt) => parse_time_with_format(raw_time, fmt)
+                .map_err(|e| format!("line {}: bad time {}: {}", line, raw_time, e))?,
+            None => parse_time(raw_time)?,
+        };
+        let id = format!("{}{}", id_prefix, line);
+        let txid = col(&mapping.txid).unwrap_or(&id).to_string();
+        let refid = col(&mapping.refid).unwrap_or(&txid).to_string();
+
+        let buy = match (
+            col(&mapping.buy_asset),
+            amount_column(&headers, &record, &mapping.buy_amount, decimal_comma)?,
+        ) {
+            (Some(a), Some(n)) => Some((a.to_string(), n)),
+            _ => None,
+        };
+        let sell = match (
+            col(&mapping.sell_asset),
+            amount_column(&headers, &record, &mapping.sell_amount, decimal_comma)?,
+        ) {
+            (Some(a), Some(n)) => Some((a.to_string(), n)),
+            _ => None,
+        };
+        let fee = match (
+            col(&mapping.fee_asset),
+            amount_column(&headers, &record, &mapping.fee_amount, decimal_comma)?,
+        ) {
+            (Some(a), Some(n)) if !n.is_zero() => Some((a.trim().to_uppercase(), n)),
+            _ => None,
+        };
+
+        let mapped = match col(&mapping.row_type) {
+            Some(t) => mapping
+                .types
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(t))
+                .map(|(_, v)| v.clone())
+                .unwrap_or_else(|| t.to_lowercase()),
+            None => match (&buy, &sell) {
+                (Some(_), Some(_)) => "trade".to_string(),
+                (Some(_), None) => "deposit".to_string(),
+                _ => "withdrawal".to_string(),
+            },
+        };
+        let (row_type, subtype) = mapped.split_once('/').unwrap_or((&mapped, ""));
+        let base = RowBase {
+            refid: interner.intern(&refid),
+            txid,
+            time,
+            row_type: row_type.trim().to_lowercase(),
+            subtype: subtype.trim().to_lowercase(),
+        };
+
+        let fee_for = |asset: &str| match &fee {
+            Some((a, n)) if a == &asset.trim().to_uppercase() => *n,
+            _ => dec!(0),
+        };
+
+        match (&buy, &sell) {
+            (Some((buy_asset, buy_amount)), Some((sell_asset, sell_amount))) => {
+                if let Some((a, _)) = &fee
+                    && *a != buy_asset.trim().to_uppercase()
+                    && *a != sell_asset.trim().to_uppercase()
+                {
+                    return Err(format!(
+                        "line {}: fee asset {} matches neither trade leg",
+                        line, a
+                    )
+                    .into());
+                }
+                out.push(base.leg(
+                    interner,
+                    "-S",
+                    sell_asset,
+                    -*sell_amount,
+                    fee_for(sell_asset),
+                ));
+                out.push(base.leg(interner, "-B", buy_asset, *buy_amount, fee_for(buy_asset)));
+            }
+            (Some((asset, amount)), None) => {
+                out.push(base.leg(interner, "", asset, *amount, fee_for(asset)));
+            }
+            (None, Some((asset, amount))) => {
+                out.push(base.leg(interner, "", asset, -*amount, fee_for(asset)));
+            }
+            (None, None) => {
+                let asset = col(&mapping.asset)
+                    .ok_or_else(|| format!("line {}: no buy/sell or asset column", line))?;
+                let amount =
+                    col(&mapping.amount).ok_or_else(|| format!("line {}: missing amount", line))?;
+                out.push(base.leg(
+                    interner,
+                    "",
+                    asset,
+                    parse_amount(amount, decimal_comma)?,
+                    fee_for(asset),
+                ));
+            }
+        }
+    }
+
+    sort_entries(&mut out);
+    Ok(out)
+}
+
+// adjustedcostbase.ca's transaction export: one row per transaction in a
+// security, with the total amount in CAD (or in `Currency` at `Exchange
+// Rate`). Each Buy or Sell becomes a trade against CAD; its commission is a
+// CAD fee. Securities are taken as asset codes.
+fn parse_adjustedcostbase(
+    text: &str,
+    id_prefix: &str,
+    locale: CsvLocale,
+    interner: &mut Interner,
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let (delimiter, decimal_comma) = locale.resolve(text);
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers()?.clone();
+    let find = |names: &[&str]| {
+        headers
+            .iter()
+            .position(|h| names.iter().any(|n| h.trim().eq_ignore_ascii_case(n)))
+    };
+    let required = |names: &[&str]| {
+        find(names).ok_or_else(|| format!("adjustedcostbase.ca export has no {} column", names[0]))
+    };
+    let security = required(&["Security"])?;
+    let date = required(&["Date"])?;
+    let kind = required(&["Transaction Type", "Type"])?;
+    let total = required(&["Amount", "Total Amount"])?;
+    let shares = required(&["Shares", "Units"])?;
+    let commission = find(&["Commission"]);
+    let currency = find(&["Currency", "Amount Currency"]);
+    let rate = find(&["Exchange Rate"]);
+
+    let mut out = Vec::new();
+    for (i, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = i + 2;
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let number = |idx: Option<usize>| -> Result<Decimal, Box<dyn Error>> {
+            match field(idx) {
+                Some(v) => Ok(parse_amount(&v.replace('$', ""), decimal_comma)?.abs()),
+                None => Ok(Decimal::ZERO),
+            }
+        };
+        let Some(asset) = field(Some(security)) else {
+            continue;
+        };
+        let raw_time = field(Some(date)).ok_or_else(|| format!("line {}: missing date", line))?;
+        let time = parse_date_or_time(raw_time)
+            .map_err(|e| format!("line {}: bad date {}: {}", line, raw_time, e))?;
+        let fx = match field(currency) {
+            Some(c) if !c.eq_ignore_ascii_case("CAD") => {
+                let fx = number(rate)?;
+                if fx.is_zero() {
+                    return Err(
+                        format!("line {}: {} amount without an exchange rate", line, c).into(),
+                    );
+                }
+                fx
+            }
+            _ => Decimal::ONE,
+        };
+        let cad = number(Some(total))? * fx;
+        let fee = number(commission)? * fx;
+        let units = number(Some(shares))?;
+        let id = format!("{}{}", id_prefix, line);
+        let base = RowBase {
+            txid: id.clone(),
+            refid: interner.intern(&id),
+            time,
+            row_type: "trade".to_string(),
+            subtype: String::new(),
+        };
+        let raw_kind = field(Some(kind)).unwrap_or("");
+        match raw_kind.to_lowercase().as_str() {
+            "buy" => {
+                out.push(base.leg(interner, "-S", "CAD", -cad, fee));
+                out.push(base.leg(interner, "-B", asset, units, dec!(0)));
+            }
+            // Units given away for nothing, like a fee paid in the asset.
+            "sell" if cad.is_zero() => {
+                let base = RowBase {
+                    row_type: "transfer".to_string(),
+                    subtype: "out".to_string(),
+                    ..base
+                };
+                out.push(base.leg(interner, "", asset, dec!(0), units));
+            }
+            "sell" => {
+                out.push(base.leg(interner, "-S", asset, -units, dec!(0)));
+                out.push(base.leg(interner, "-B", "CAD", cad, fee));
+            }
+            _ => {
+                return Err(format!(
+                    "line {}: {:?} transactions cannot be imported; only Buy and Sell can",
+                    line, raw_kind
+                )
+                .into());
+            }
+        }
+    }
+
+    sort_entries(&mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generic_buy_sell_rows_become_trade_legs() {
+        let mapping: GenericMapping = toml::from_str(
+            r#"
+            time = "Date"
+            type = "Kind"
+            buy_asset = "Buy Cur"
+            buy_amount = "Buy"
+            sell_asset = "Sell Cur"
+            sell_amount = "Sell"
+            fee_asset = "Fee Cur"
+            fee_amount = "Fee"
+
+            [types]
+            Staking = "earn/reward"
+            "#,
+        )
+        .unwrap();
+        let csv = "Date,Kind,Buy Cur,Buy,Sell Cur,Sell,Fee Cur,Fee\n\
+                   2025-01-02 10:00:00,Trade,BTC,0.01,CAD,1000,CAD,5\n\
+                   2025-01-01 10:00:00,Staking,ETH,0.1,,,,\n";
+
+        let entries = parse_generic(
+            csv,
+            &mapping,
+            "GEN-",
+            CsvLocale::default(),
+            &mut Interner::default(),
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].row_type, "earn");
+        assert_eq!(entries[0].subtype, "reward");
+        let cad = entries.iter().find(|e| &*e.asset == "CAD").unwrap();
+        assert_eq!(cad.row_type, "trade");
+        assert_eq!(cad.net_delta, dec!(-1005));
+        assert_eq!(&*cad.refid, "GEN-2");
+    }
+
+    #[test]
+    fn shakepay_export_maps_to_ledger_rows() {
+        let csv = "Transaction Type,Date,Amount Debited,Debit Currency,Amount Credited,Credit Currency,Buy / Sell Rate,Direction,Spot Rate,Source / Destination,Blockchain Transaction ID\n\
+                   fiat funding,2021-03-01T18:59:50+00,,,500,CAD,,credit,,,\n\
+                   purchase/sale,2021-03-02T12:00:00+00,500,CAD,0.01,BTC,50000,purchase,,,\n\
+                   shakingsats,2021-03-03T04:00:00-05,,,0.00000123,BTC,,credit,,,\n";
+
+        let mapping: GenericMapping = toml::from_str(SHAKEPAY_MAPPING).unwrap();
+        let entries = parse_generic(
+            csv,
+            &mapping,
+            "SHAKEPAY-",
+            CsvLocale::default(),
+            &mut Interner::default(),
+        )
+        .unwrap();
+        let kinds: Vec<_> = entries
+            .iter()
+            .map(|e| (e.row_type.as_str(), &*e.asset))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("deposit", "CAD"),
+                ("trade", "BTC"),
+                ("trade", "CAD"),
+                ("earn", "BTC"),
+            ]
+        );
+        assert_eq!(entries[3].time.to_string(), "2021-03-03 09:00:00");
+    }
+
+    #[test]
+    fn manual_rows_use_documented_schema() {
+        let csv = "time,type,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,refid,notes\n\
+                   2023-06-01 15:00:00,buy,BTC,0.05,CAD,2000,,,p2p-1,cash meetup\n\
+                   2023-07-01 10:00:00,swap,ETH,1,USDC,1900,ETH,0.002,,uniswap\n";
+
+        let mapping: GenericMapping = toml::from_str(MANUAL_MAPPING).unwrap();
+        let entries = parse_generic(
+            csv,
+            &mapping,
+            "MANUAL-",
+            CsvLocale::default(),
+            &mut Interner::default(),
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries.iter().all(|e| e.row_type == "trade"));
+        assert_eq!(&*entries[0].refid, "p2p-1");
+        let eth = entries.iter().find(|e| &*e.asset == "ETH").unwrap();
+        assert_eq!(eth.net_delta, dec!(0.998));
+        assert_eq!(&*eth.refid, "MANUAL-3");
+    }
+
+    #[test]
+    fn koinly_universal_rows_infer_type_from_legs() {
+        let csv = "Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Net Worth Amount,Net Worth Currency,Label,Description,TxHash\n\
+                   2024-05-01 09:30:00 UTC,1000,CAD,0.02,BTC,,,,,,,\n\
+                   2024-05-02 00:00:00 UTC,,,0.5,DOT,,,,,staking,,\n\
+                   2024-05-03 12:00:00 UTC,0.01,BTC,,,0.0001,BTC,,,,,\n";
+
+        let mapping: GenericMapping = toml::from_str(KOINLY_MAPPING).unwrap();
+        let entries = parse_generic(
+            csv,
+            &mapping,
+            "KOINLY-",
+            CsvLocale::default(),
+            &mut Interner::default(),
+        )
+        .unwrap();
+        let kinds: Vec<_> = entries
+            .iter()
+            .map(|e| (e.row_type.as_str(), &*e.asset, e.net_delta))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("trade", "BTC", dec!(0.02)),
+                ("trade", "CAD", dec!(-1000)),
+                ("earn", "DOT", dec!(0.5)),
+                ("withdrawal", "BTC", dec!(-0.0101)),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjustedcostbase_buys_and_sells_trade_against_cad() {
+        let csv = "Security,Date,Transaction Type,Amount,Shares,Commission,Currency,Exchange Rate,Memo\n\
+                   BTC,2024-03-01,Buy,\"$1,000.00\",0.01,2.50,CAD,,\n\
+                   ETH,2024-04-01,Buy,300,0.1,,USD,1.35,\n\
+                   BTC,2024-05-01,Sell,600,0.005,1,,,\n\
+                   BTC,2024-06-01,Sell,0,0.0001,,,,\n";
+        let entries =
+            parse_adjustedcostbase(csv, "ACB-", CsvLocale::default(), &mut Interner::default())
+                .unwrap();
+        let legs: Vec<_> = entries
+            .iter()
+            .map(|e| (&*e.refid, &*e.asset, e.amount, e.net_delta))
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                ("ACB-2", "BTC", dec!(0.01), dec!(0.01)),
+                ("ACB-2", "CAD", dec!(-1000.00), dec!(-1002.50)),
+                ("ACB-3", "ETH", dec!(0.1), dec!(0.1)),
+                ("ACB-3", "CAD", dec!(-405.00), dec!(-405.00)),
+                ("ACB-4", "CAD", dec!(600), dec!(599)),
+                ("ACB-4", "BTC", dec!(-0.005), dec!(-0.005)),
+                ("ACB-5", "BTC", dec!(0), dec!(-0.0001)),
+            ]
+        );
+        let err = parse_adjustedcostbase(
+            "Security,Date,Transaction Type,Amount,Shares\nBTC,2024-03-01,Return of Capital,5,\n",
+            "ACB-",
+            CsvLocale::default(),
+            &mut Interner::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("\"Return of Capital\""), "{}", err);
+    }
+}
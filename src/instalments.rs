@@ -0,0 +1,224 @@
+use crate::table::{Style, Table};
+use crate::{
+    ReportRow, cad, grouped_totals, jurisdiction, parse_decimal, positional_args, read_report,
+    take_flag, take_switch, use_color,
+};
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt::Write;
+
+// Individuals' instalment due dates (month, day), one per quarter.
+const DUE_DATES: [(u32, u32); 4] = [(3, 15), (6, 15), (9, 15), (12, 15)];
+
+// One calendar quarter of the report: what was realized in it and the tax
+// it adds at the marginal rate.
+#[derive(Debug, Default, PartialEq)]
+struct Quarter {
+    gain_cad: Decimal,
+    taxable_gain_cad: Decimal,
+    income_cad: Decimal,
+    tax_cad: Decimal,
+}
+
+// The report's gains and income by calendar quarter, from the row times. Tax
+// is on the year to date, with net capital losses only offsetting gains, so
+// a quarter's tax is what it adds to the year's (negative when a loss undoes
+// earlier gains).
+fn quarters(
+    report: &[ReportRow],
+    inclusion_rate: Decimal,
+    marginal_rate: Decimal,
+) -> Result<[Quarter; 4], Box<dyn Error>> {
+    let by_quarter = grouped_totals(report.iter().map(|r| {
+        let month = r.time.get(5..7).and_then(|m| m.parse::<usize>().ok());
+        (month.map_or(0, |m| (m - 1) / 3), r)
+    }))?;
+    let mut out: [Quarter; 4] = Default::default();
+    let (mut gain, mut income, mut tax) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+    for (i, q) in out.iter_mut().enumerate() {
+        if let Some(t) = by_quarter.get(&i) {
+            q.gain_cad = t.gain_cad;
+            q.taxable_gain_cad = t.gain_cad * inclusion_rate;
+            q.income_cad = t.income_cad;
+        }
+        gain += q.taxable_gain_cad;
+        income += q.income_cad;
+        let to_date = (gain.max(Decimal::ZERO) + income) * marginal_rate;
+        q.tax_cad = to_date - tax;
+        tax = to_date;
+    }
+    Ok(out)
+}
+
+// Per due date: the current-year option's even quarter of the year's tax,
+// and the tax realized in the quarters before it, each paid by the next due
+// date. What the second leaves is due with the return.
+fn schedule(quarters: &[Quarter; 4]) -> ([Decimal; 4], [Decimal; 4], Decimal) {
+    let total: Decimal = quarters.iter().map(|q| q.tax_cad).sum();
+    let even = (total / Decimal::from(4)).max(Decimal::ZERO);
+    let mut realized = [Decimal::ZERO; 4];
+    let (mut owed, mut paid) = (Decimal::ZERO, Decimal::ZERO);
+    for i in 1..4 {
+        owed += quarters[i - 1].tax_cad;
+        realized[i] = (owed - paid).max(Decimal::ZERO);
+        paid += realized[i];
+    }
+    ([even; 4], realized, (total - paid).max(Decimal::ZERO))
+}
+
+// `0.43` or `43%`, as a fraction.
+fn parse_rate(s: &str) -> Result<Decimal, Box<dyn Error>> {
+    let rate = match s.trim().strip_suffix('%') {
+        Some(percent) => parse_decimal(percent)? / Decimal::from(100),
+        None => parse_decimal(s)?,
+    };
+    if rate < Decimal::ZERO || rate >= Decimal::ONE {
+        return Err(format!(
+            "--marginal-rate must be between 0 and 1 (or 0% and 100%): {}",
+            s
+        )
+        .into());
+    }
+    Ok(rate)
+}
+
+// `instalments <report.csv> --marginal-rate <rate> [--jurisdiction <name>]
+// [--no-color]`: estimates quarterly tax instalments on a report's
+// year-to-date gains and income.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let no_color = take_switch(&mut raw, "no-color");
+    let marginal_rate = take_flag(&mut raw, "marginal-rate")?
+        .ok_or("instalments requires --marginal-rate <rate>")?;
+    let jurisdiction_name =
+        take_flag(&mut raw, "jurisdiction")?.unwrap_or_else(|| "canada".to_string());
+    let report = match <[String; 1]>::try_from(positional_args("instalments", raw)?) {
+        Ok([report]) => report,
+        Err(rest) if rest.is_empty() => return Err("instalments requires a report file".into()),
+        Err(_) => return Err("instalments takes one report".into()),
+    };
+    let marginal_rate = parse_rate(&marginal_rate)?;
+    let rows = read_report(&report)?;
+    let year: i32 = rows
+        .first()
+        .and_then(|r| r.time.get(..4))
+        .ok_or("the report has no rows")?
+        .parse()?;
+    let inclusion_rate = jurisdiction::by_name(&jurisdiction_name)?.inclusion_rate(year);
+    let quarters = quarters(&rows, inclusion_rate, marginal_rate)?;
+    let color = use_color(std::io::stdout(), no_color);
+    let rates = (marginal_rate, inclusion_rate);
+    print!("{}", render(&report, year, rates, &quarters, color)?);
+    Ok(())
+}
+
+fn render(
+    report: &str,
+    year: i32,
+    (marginal_rate, inclusion_rate): (Decimal, Decimal),
+    quarters: &[Quarter; 4],
+    color: bool,
+) -> Result<String, Box<dyn Error>> {
+    let plain = |text: String| (text, Style::Plain);
+    let mut out = String::new();
+    writeln!(out, "Instalment estimate for {}: {}", year, report)?;
+    writeln!(
+        out,
+        "Marginal rate: {}%, inclusion rate: {}%",
+        (marginal_rate * Decimal::from(100)).normalize(),
+        (inclusion_rate * Decimal::from(100)).normalize()
+    )?;
+
+    let mut table = Table::new(&[
+        "Quarter",
+        "Gain/loss (CAD)",
+        "Taxable gain (CAD)",
+        "Income (CAD)",
+        "Tax (CAD)",
+    ]);
+    for (i, q) in quarters.iter().enumerate() {
+        table.row(vec![
+            plain(format!("Q{}", i + 1)),
+            (cad(q.gain_cad), Style::signed(q.gain_cad)),
+            plain(cad(q.taxable_gain_cad)),
+            plain(cad(q.income_cad)),
+            plain(cad(q.tax_cad)),
+        ]);
+    }
+    let total: Decimal = quarters.iter().map(|q| q.tax_cad).sum();
+    table.row(vec![
+        plain("Year to date".to_string()),
+        plain(String::new()),
+        plain(String::new()),
+        plain(String::new()),
+        plain(cad(total)),
+    ]);
+    write!(out, "\n{}", table.render(color))?;
+
+    let (even, realized, balance) = schedule(quarters);
+    let mut table = Table::new(&["Due", "Current-year option (CAD)", "As realized (CAD)"]);
+    for (i, (month, day)) in DUE_DATES.iter().enumerate() {
+        table.row(vec![
+            plain(format!("{}-{:02}-{:02}", year, month, day)),
+            plain(cad(even[i])),
+            plain(cad(realized[i])),
+        ]);
+    }
+    table.row(vec![
+        plain(format!("{}-04-30 (balance)", year + 1)),
+        plain(cad(Decimal::ZERO)),
+        plain(cad(balance)),
+    ]);
+    write!(out, "\n{}", table.render(color))?;
+    writeln!(
+        out,
+        "\nAn estimate only: tax at one marginal rate on this report's gains and income, \
+         without other income, credits or the instalment threshold."
+    )?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_row, parse_time, stamp};
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+
+    #[test]
+    fn quarters_split_the_year_and_losses_only_offset_gains() {
+        let id: Arc<str> = Arc::from("R1");
+        let row = |time: &str, event: &str, gain: &str, income: &str| {
+            let mut r = make_row(
+                stamp(parse_time(time).unwrap(), None),
+                &id,
+                &id,
+                event,
+                &"BTC".into(),
+            );
+            r.gain_cad = gain.to_string();
+            r.income_cad = income.to_string();
+            r
+        };
+        let report = [
+            row("2025-02-01 00:00:00", "trade_disposition", "1000", ""),
+            row("2025-05-01 00:00:00", "earn_reward_income", "", "200"),
+            row("2025-08-01 00:00:00", "trade_disposition", "-1500", ""),
+            row("2025-11-01 00:00:00", "trade_disposition", "800", ""),
+        ];
+        let q = quarters(&report, dec!(0.5), dec!(0.4)).unwrap();
+        let tax: Vec<Decimal> = q.iter().map(|q| q.tax_cad).collect();
+        // Q3's loss undoes Q1's gain but not Q2's income; Q4's gain first
+        // makes up the rest of the loss.
+        assert_eq!(tax, [dec!(200), dec!(80), dec!(-200), dec!(60)]);
+        assert_eq!(q[2].taxable_gain_cad, dec!(-750));
+
+        let (even, realized, balance) = schedule(&q);
+        assert_eq!(even, [dec!(35); 4]);
+        assert_eq!(realized, [dec!(0), dec!(200), dec!(80), dec!(0)]);
+        // Already paid 280 against 140 owed: nothing is left for the return.
+        assert_eq!(balance, dec!(0));
+
+        assert_eq!(parse_rate("43.41%").unwrap(), dec!(0.4341));
+        assert!(parse_rate("43").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::mutation::{Applied, Mutation, PoolBook};
+use crate::{Event, Pool, ReportRow, Totals, event_sort_keys, parse_decimal};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+// Checks, under `--check-invariants`, what each event did to the pools,
+// report and totals, and stops the run at the first event that breaks:
+//
+// - no pool holds negative units;
+// - a pool with no units has no ACB;
+// - each pool's units are its starting units plus those its mutations added,
+//   less those they removed, and its lots add up to them;
+// - the gains of the report's rows add up to the capital gain total.
+//
+// Each check only looks at what changed since the last one.
+pub(crate) struct Checker {
+    // Units each pool should hold from the mutations applied so far.
+    expected: HashMap<Arc<str>, Decimal>,
+    mutations_seen: usize,
+    rows_seen: usize,
+    gains: Decimal,
+    // The event under way, as shown in errors.
+    event: Option<String>,
+}
+
+impl Checker {
+    pub(crate) fn new(start_pools: &HashMap<Arc<str>, Pool>) -> Self {
+        Checker {
+            expected: start_pools
+                .iter()
+                .map(|(k, p)| (k.clone(), p.units))
+                .collect(),
+            mutations_seen: 0,
+            rows_seen: 0,
+            gains: Decimal::ZERO,
+            event: None,
+        }
+    }
+
+    // Checks what the previous event did, then notes `ev` as under way.
+    pub(crate) fn event(
+        &mut self,
+        ev: &Event,
+        book: &PoolBook,
+        report: &[ReportRow],
+        totals: &Totals,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check(book, report, totals)?;
+        let (time, _, key) = event_sort_keys(ev);
+        let kind = match ev {
+            Event::Trade(_) => "trade",
+            Event::Conversion(_) => "conversion",
+            Event::Entry(e) => &e.row_type,
+            Event::Migration(_) => "migration",
+            Event::DeemedDisposition(_) => "deemed disposition",
+        };
+        self.event = Some(format!("{} {} at {}", kind, key, time).trim().to_string());
+        Ok(())
+    }
+
+    // Checks what the last event did.
+    pub(crate) fn finish(
+        mut self,
+        book: &PoolBook,
+        report: &[ReportRow],
+        totals: &Totals,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check(book, report, totals)
+    }
+
+    fn check(
+        &mut self,
+        book: &PoolBook,
+        report: &[ReportRow],
+        totals: &Totals,
+    ) -> Result<(), Box<dyn Error>> {
+        let applied = &book.log()[self.mutations_seen..];
+        let rows = &report[self.rows_seen..];
+        let fail = |what: String| -> Box<dyn Error> {
+            violation(self.event.as_deref(), &what, applied, rows).into()
+        };
+
+        let mut touched = BTreeSet::new();
+        for a in applied {
+            let key = &a.mutation.asset;
+            match &a.mutation.change {
+                Mutation::Add { units, .. } | Mutation::Income { units, .. } => {
+                    *self.expected.entry(key.clone()).or_default() += units;
+                }
+                Mutation::Remove { units } => {
+                    *self.expected.entry(key.clone()).or_default() -= units;
+                }
+                Mutation::Migrate { from, factor } => {
+                    let moved = self.expected.remove(from).unwrap_or_default() * factor;
+                    *self.expected.entry(key.clone()).or_default() += moved;
+                    touched.insert(from.clone());
+                }
+                Mutation::Restate { .. } => {}
+            }
+            touched.insert(key.clone());
+        }
+        for key in &touched {
+            let pool = book.pools().get(key).cloned().unwrap_or_default();
+            let expected = self.expected.get(key).copied().unwrap_or_default();
+            if pool.units < Decimal::ZERO {
+                return Err(fail(format!("pool {} holds {} units", key, pool.units)));
+            }
+            if pool.units.is_zero() && !pool.acb_cad.is_zero() {
+                return Err(fail(format!(
+                    "pool {} holds no units but has an ACB of {} CAD",
+                    key, pool.acb_cad
+                )));
+            }
+            if pool.units != expected {
+                return Err(fail(format!(
+                    "pool {} holds {} units, but its mutations leave {}",
+                    key, pool.units, expected
+                )));
+            }
+            let lot_units: Decimal = pool.lots.iter().map(|l| l.units).sum();
+            if !pool.lots.is_empty() && lot_units != pool.units {
+                return Err(fail(format!(
+                    "pool {} holds {} units, but its lots add up to {}",
+                    key, pool.units, lot_units
+                )));
+            }
+        }
+
+        for rr in rows.iter().filter(|r| !r.gain_cad.is_empty()) {
+            self.gains += parse_decimal(&rr.gain_cad)?;
+        }
+        if self.gains != totals.capital_gain_cad {
+            return Err(fail(format!(
+                "the report's gains add up to {} CAD, but the capital gain total is {} CAD",
+                self.gains, totals.capital_gain_cad
+            )));
+        }
+
+        self.mutations_seen += applied.len();
+        self.rows_seen = report.len();
+        Ok(())
+    }
+}
+
+// The error for a broken invariant, with the event's pool mutations and
+// report rows.
+fn violation(event: Option<&str>, what: &str, applied: &[Applied], rows: &[ReportRow]) -> String {
+    let mut out = format!(
+        "invariant violated after {}: {}",
+        event.unwrap_or("the starting pools"),
+        what
+    );
+    for a in applied {
+        let e = &a.effect;
+        let _ = write!(
+            out,
+            "\n  mutation {} {:?}: cost {} CAD, pool now {} units, ACB {} CAD",
+            a.mutation.asset, a.mutation.change, e.cost_cad, e.units_after, e.acb_after
+        );
+    }
+    for rr in rows {
+        let _ = write!(
+            out,
+            "\n  report row {} {}: in {:?}, out {:?}, proceeds {:?}, ACB {:?}, gain {:?}",
+            rr.event_type,
+            rr.asset,
+            rr.units_in,
+            rr.units_out,
+            rr.proceeds_cad,
+            rr.acb_disposed_cad,
+            rr.gain_cad
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AverageCost, PoolMutation};
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn a_pool_left_with_cost_but_no_units_is_caught() {
+        let time = NaiveDate::from_ymd_opt(2025, 3, 1)
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .unwrap();
+        let (refid, btc): (Arc<str>, Arc<str>) = ("T1".into(), "BTC".into());
+        let mut book = PoolBook::new(HashMap::new(), Arc::new(AverageCost));
+        let mut checker = Checker::new(&HashMap::new());
+        let add = Mutation::Add {
+            units: dec!(2),
+            cost_cad: dec!(100),
+        };
+        book.apply(PoolMutation::new(time, &refid, &btc, add), "")
+            .unwrap();
+        let totals = Totals::default();
+        checker.check(&book, &[], &totals).unwrap();
+
+        // A restatement that leaves cost in a pool later emptied.
+        let remove = Mutation::Remove { units: dec!(2) };
+        book.apply(PoolMutation::new(time, &refid, &btc, remove), "")
+            .unwrap();
+        let restate = Mutation::Restate { cost_cad: dec!(5) };
+        book.apply(PoolMutation::new(time, &refid, &btc, restate), "")
+            .unwrap();
+        let err = checker.check(&book, &[], &totals).unwrap_err().to_string();
+        assert!(
+            err.contains("pool BTC holds no units but has an ACB of 5 CAD"),
+            "{}",
+            err
+        );
+        assert!(err.contains("mutation BTC Restate"), "{}", err);
+
+        // Gains must match the total.
+        let mut checker = Checker::new(&HashMap::new());
+        let row = ReportRow {
+            gain_cad: "12.5".to_string(),
+            ..ReportRow::default()
+        };
+        let err = checker.check(
+            &PoolBook::new(HashMap::new(), Arc::new(AverageCost)),
+            &[row],
+            &totals,
+        );
+        assert!(
+            err.unwrap_err()
+                .to_string()
+                .contains("gains add up to 12.5 CAD")
+        );
+    }
+}
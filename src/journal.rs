@@ -0,0 +1,655 @@
+use crate::export::amount;
+use crate::{EndingPoolRow, ReportRow, create_output, q2, write_csv};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+// The books the `beancount` and `journal` exports write: the tax year's
+// report as double-entry transactions in CAD.
+
+// One leg of a transaction. Assets other than CAD carry their CAD value, so
+// each transaction balances in CAD.
+#[derive(Debug)]
+struct Posting {
+    account: String,
+    units: Decimal,
+    commodity: String,
+    value_cad: Option<Decimal>,
+}
+
+impl Posting {
+    fn cad(account: &str, units: Decimal) -> Self {
+        Posting {
+            account: account.to_string(),
+            units,
+            commodity: "CAD".to_string(),
+            value_cad: None,
+        }
+    }
+
+    fn asset(asset: &str, units: Decimal, value_cad: Decimal) -> Self {
+        let commodity = commodity(asset);
+        Posting {
+            account: asset_account(asset),
+            value_cad: (commodity != "CAD").then_some(value_cad),
+            units: if commodity == "CAD" { value_cad } else { units },
+            commodity,
+        }
+    }
+
+    // What the posting counts for in the transaction's CAD balance: a debit
+    // when positive, a credit when negative.
+    fn weight(&self) -> Decimal {
+        match self.value_cad {
+            Some(value) if self.units < Decimal::ZERO => -value,
+            Some(value) => value,
+            None => self.units,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Transaction {
+    date: String,
+    narration: String,
+    refid: String,
+    postings: Vec<Posting>,
+}
+
+impl Transaction {
+    // A transaction with a last posting to `balance_to` for whatever is left
+    // over, or `None` when there are no postings.
+    fn new(
+        date: &str,
+        narration: &str,
+        refid: &str,
+        mut postings: Vec<Posting>,
+        balance_to: &str,
+    ) -> Option<Self> {
+        if postings.is_empty() {
+            return None;
+        }
+        let left: Decimal = postings.iter().map(Posting::weight).sum();
+        if !left.is_zero() {
+            postings.push(Posting::cad(balance_to, -left));
+        }
+        Some(Transaction {
+            date: date.to_string(),
+            narration: narration.to_string(),
+            refid: refid.to_string(),
+            postings,
+        })
+    }
+}
+
+// Beancount commodities are upper case, start with a letter and end with a
+// letter or digit; Kraken's codes mostly are already (`1INCH` is not).
+// Per-wallet pools (`BTC@cold`) hold their base asset.
+fn commodity(asset: &str) -> String {
+    let base = asset.split('@').next().unwrap_or(asset).to_uppercase();
+    let mut name: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "'._-".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'X');
+    }
+    if !name.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+        name.push('X');
+    }
+    name
+}
+
+fn asset_account(asset: &str) -> String {
+    let name: String = asset
+        .to_uppercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("Assets:Kraken:{}", name)
+}
+
+fn income_account(event_type: &str) -> &'static str {
+    match event_type {
+        "earn_reward_income" => "Income:Kraken:Staking",
+        "earn_interest_income" => "Income:Kraken:Interest",
+        "dividend_income" => "Income:Kraken:Dividends",
+        "trade_fee_rebate_income" => "Income:Kraken:Rebates",
+        _ => "Income:Kraken:Other",
+    }
+}
+
+// Where the CAD value left over in a transaction went. The report has no
+// rows for CAD, so a trade against CAD balances against `Assets:Kraken:CAD`;
+// between two other assets, only fees are left over.
+fn counterpart(rows: &[ReportRow]) -> &'static str {
+    let has = |t: &str| rows.iter().any(|r| r.event_type == t);
+    if has("trade_disposition") && has("trade_acquisition") {
+        return "Expenses:Kraken:Fees";
+    }
+    match rows[0].event_type.as_str() {
+        "spend_disposition" => "Expenses:Kraken:Spending",
+        "gift_disposition" => "Expenses:Kraken:Gifts",
+        "donation_disposition" => "Expenses:Kraken:Donations",
+        t if t.ends_with("fee_disposition") => "Expenses:Kraken:Fees",
+        t if t.starts_with("trade_") || t.starts_with("nft_") => "Assets:Kraken:CAD",
+        _ => "Equity:Kraken:Other",
+    }
+}
+
+// The postings of one report row: units in at the ACB added, units out at the
+// ACB disposed, and the gain and income it recorded.
+fn row_postings(r: &ReportRow) -> Result<Vec<Posting>, Box<dyn Error>> {
+    let mut postings = Vec::new();
+    let units_in = amount(&r.units_in)?;
+    if !units_in.is_zero() {
+        postings.push(Posting::asset(
+            &r.asset,
+            units_in,
+            amount(&r.acb_added_cad)?,
+        ));
+    }
+    let units_out = amount(&r.units_out)?;
+    if !units_out.is_zero() {
+        postings.push(Posting::asset(
+            &r.asset,
+            -units_out,
+            amount(&r.acb_disposed_cad)?,
+        ));
+    }
+    let gain = amount(&r.gain_cad)?;
+    if !gain.is_zero() {
+        postings.push(Posting::cad("Income:Kraken:CapitalGains", -gain));
+    }
+    let income = amount(&r.income_cad)?;
+    if !income.is_zero() {
+        postings.push(Posting::cad(income_account(&r.event_type), -income));
+    }
+    Ok(postings)
+}
+
+// A pool's units and ACB.
+type Holding = (Decimal, Decimal);
+
+// A row's pool before it, or `None` if it shows no pool.
+fn pool_before(r: &ReportRow) -> Result<Option<Holding>, Box<dyn Error>> {
+    if r.pool_units_after.is_empty() {
+        return Ok(None);
+    }
+    let units = amount(&r.pool_units_after)? - amount(&r.units_in)? + amount(&r.units_out)?;
+    let acb =
+        amount(&r.pool_acb_cad_after)? - amount(&r.acb_added_cad)? + amount(&r.acb_disposed_cad)?;
+    Ok(Some((units, acb)))
+}
+
+// Postings bringing the books' units of `asset` from `held` to `pool`, for
+// changes the report has no rows for, like transfers and migrations.
+fn pool_gap(asset: &str, held: Holding, pool: Holding) -> Option<Posting> {
+    let units = pool.0 - held.0;
+    (!units.is_zero()).then(|| Posting::asset(asset, units, (pool.1 - held.1).abs()))
+}
+
+// Each asset's units and ACB at the start of the report: its pool before its
+// first row, or all year for assets with no rows.
+pub(crate) fn opening_pools<'a>(
+    report: &'a [ReportRow],
+    ending_pools: &'a [EndingPoolRow],
+) -> Result<BTreeMap<&'a str, Holding>, Box<dyn Error>> {
+    let mut pools = BTreeMap::new();
+    for r in report {
+        if !pools.contains_key(&*r.asset)
+            && let Some(pool) = pool_before(r)?
+        {
+            pools.insert(&*r.asset, pool);
+        }
+    }
+    for p in ending_pools {
+        pools.entry(&*p.asset).or_insert((p.units, p.acb_cad));
+    }
+    Ok(pools)
+}
+
+// The date opening pools are stated at: the start of the tax year, or the
+// first row's date if a time zone puts it earlier.
+pub(crate) fn start_date(report: &[ReportRow], tax_year: i32) -> String {
+    let year_start = format!("{}-01-01", tax_year);
+    report
+        .first()
+        .map(|r| r.time.get(..10).unwrap_or(&r.time))
+        .filter(|d| **d < *year_start)
+        .map_or(year_start.clone(), str::to_string)
+}
+
+const POOL_GAP_NARRATION: &str = "Pool changes without report rows (transfers, migrations)";
+
+// The tax year's transactions: one putting the pools held at the start of
+// the year at their ACB, then one per event. Pool changes between rows that
+// the report does not show get transactions of their own, so each asset
+// account ends the year holding its ending pool. Returns them with the
+// number of event transactions.
+fn transactions(
+    report: &[ReportRow],
+    ending_pools: &[EndingPoolRow],
+    tax_year: i32,
+) -> Result<(Vec<Transaction>, usize), Box<dyn Error>> {
+    let mut held = opening_pools(report, ending_pools)?;
+    let start = start_date(report, tax_year);
+    let opening = held
+        .iter()
+        .filter(|(_, (units, acb))| !units.is_zero() || !acb.is_zero())
+        .map(|(asset, (units, acb))| Posting::asset(asset, *units, *acb))
+        .collect();
+    let mut out: Vec<Transaction> = Transaction::new(
+        &start,
+        "Pools at the start of the tax year, at ACB",
+        "",
+        opening,
+        "Equity:Opening-Balances",
+    )
+    .into_iter()
+    .collect();
+
+    let mut events = 0;
+    let mut rest = report;
+    while let Some(first) = rest.first() {
+        // A refid's rows are adjacent; rows without one stand alone.
+        let len = match &*first.refid {
+            "" => 1,
+            refid => rest.iter().take_while(|r| &*r.refid == refid).count(),
+        };
+        let rows = &rest[..len];
+        rest = &rest[len..];
+        let date = first.time.get(..10).unwrap_or(&first.time);
+        let mut gaps = Vec::new();
+        let mut postings = Vec::new();
+        for r in rows {
+            if let Some(before) = pool_before(r)? {
+                let books = held.entry(&r.asset).or_default();
+                gaps.extend(pool_gap(&r.asset, *books, before));
+                *books = (amount(&r.pool_units_after)?, amount(&r.pool_acb_cad_after)?);
+            }
+            postings.extend(row_postings(r)?);
+        }
+        out.extend(Transaction::new(
+            date,
+            POOL_GAP_NARRATION,
+            "",
+            gaps,
+            "Equity:Kraken:Transfers",
+        ));
+        let narration: Vec<String> = rows
+            .iter()
+            .map(|r| format!("{} {}", r.event_type, r.asset))
+            .collect();
+        if let Some(t) = Transaction::new(
+            date,
+            &narration.join(", "),
+            &first.refid,
+            postings,
+            counterpart(rows),
+        ) {
+            out.push(t);
+            events += 1;
+        }
+    }
+    let gaps = held
+        .iter()
+        .filter_map(|(asset, books)| {
+            let pool = ending_pools
+                .iter()
+                .find(|p| &*p.asset == *asset)
+                .map_or((Decimal::ZERO, Decimal::ZERO), |p| (p.units, p.acb_cad));
+            pool_gap(asset, *books, pool)
+        })
+        .collect();
+    out.extend(Transaction::new(
+        &format!("{}-12-31", tax_year),
+        POOL_GAP_NARRATION,
+        "",
+        gaps,
+        "Equity:Kraken:Transfers",
+    ));
+    Ok((out, events))
+}
+
+// The transactions as a beancount file, with every account opened on the
+// first transaction's date.
+fn beancount(transactions: &[Transaction], tax_year: i32) -> String {
+    let mut out = format!(
+        "option \"title\" \"Kraken {}\"\noption \"operating_currency\" \"CAD\"\n\n",
+        tax_year
+    );
+    let accounts: BTreeSet<&str> = transactions
+        .iter()
+        .flat_map(|t| &t.postings)
+        .map(|p| p.account.as_str())
+        .collect();
+    let start = transactions.first().map_or("", |t| &t.date);
+    for account in accounts {
+        let _ = writeln!(out, "{} open {}", start, account);
+    }
+    for t in transactions {
+        let _ = writeln!(out, "\n{} * \"{}\"", t.date, t.narration.replace('"', "'"));
+        if !t.refid.is_empty() {
+            let _ = writeln!(out, "  refid: \"{}\"", t.refid);
+        }
+        for p in &t.postings {
+            let _ = write!(
+                out,
+                "  {}  {} {}",
+                p.account,
+                p.units.normalize(),
+                p.commodity
+            );
+            if let Some(value) = p.value_cad {
+                let _ = write!(out, " @@ {} CAD", value.normalize());
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Writes the report as beancount transactions and returns the number of
+// events written.
+pub(crate) fn write_beancount(
+    path: &str,
+    report: &[ReportRow],
+    ending_pools: &[EndingPoolRow],
+    tax_year: i32,
+) -> Result<usize, Box<dyn Error>> {
+    let (transactions, events) = transactions(report, ending_pools, tax_year)?;
+    create_output(path)?.write_all(beancount(&transactions, tax_year).as_bytes())?;
+    Ok(events)
+}
+
+// `--chart-of-accounts`: the user's account for each of the books' accounts,
+// one `Assets:Kraken:BTC = 1510 Bitcoin` per line (`#` comments allowed). A
+// name ending in `*` covers every account it prefixes; the longest match
+// wins.
+#[derive(Debug, Default)]
+pub(crate) struct ChartOfAccounts {
+    exact: BTreeMap<String, String>,
+    prefixes: Vec<(String, String)>,
+}
+
+impl ChartOfAccounts {
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut chart = ChartOfAccounts::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (from, to) = line.split_once('=').ok_or_else(|| {
+                format!("chart of accounts line {}: expected account = name", i + 1)
+            })?;
+            let (from, to) = (from.trim(), to.trim().to_string());
+            if to.is_empty() {
+                return Err(
+                    format!("chart of accounts line {}: no name for {}", i + 1, from).into(),
+                );
+            }
+            match from.strip_suffix('*') {
+                Some(prefix) => chart.prefixes.push((prefix.to_string(), to)),
+                None => {
+                    chart.exact.insert(from.to_string(), to);
+                }
+            }
+        }
+        chart
+            .prefixes
+            .sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
+        Ok(chart)
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn map(&self, account: &str) -> Option<&str> {
+        self.exact.get(account).map(String::as_str).or_else(|| {
+            self.prefixes
+                .iter()
+                .find(|(prefix, _)| account.starts_with(prefix.as_str()))
+                .map(|(_, to)| to.as_str())
+        })
+    }
+}
+
+// Row of the journal export: one line of a journal entry.
+#[derive(Debug, Serialize)]
+struct JournalRow {
+    entry: usize,
+    date: String,
+    account: String,
+    debit: String,
+    credit: String,
+    memo: String,
+}
+
+// The transactions as journal entry lines in cents, with accounts named by
+// `chart`. Rounding each line to cents can leave an entry a cent or two off
+// balance; the difference goes on its last line. Lines that round to zero
+// are left out.
+fn journal_rows(
+    transactions: &[Transaction],
+    chart: Option<&ChartOfAccounts>,
+) -> Result<Vec<JournalRow>, Box<dyn Error>> {
+    let mut unmapped = BTreeSet::new();
+    let mut rows = Vec::new();
+    for (i, t) in transactions.iter().enumerate() {
+        let mut amounts: Vec<Decimal> = t.postings.iter().map(|p| q2(p.weight())).collect();
+        let off: Decimal = amounts.iter().sum();
+        if let Some(last) = amounts.last_mut() {
+            *last -= off;
+        }
+        let memo = match t.refid.as_str() {
+            "" => t.narration.clone(),
+            refid => format!("{} ({})", t.narration, refid),
+        };
+        for (p, value) in t.postings.iter().zip(amounts) {
+            if value.is_zero() {
+                continue;
+            }
+            let account = match chart {
+                None => p.account.as_str(),
+                Some(chart) => chart.map(&p.account).unwrap_or_else(|| {
+                    unmapped.insert(p.account.as_str());
+                    ""
+                }),
+            };
+            let (debit, credit) = if value > Decimal::ZERO {
+                (format!("{:.2}", value), String::new())
+            } else {
+                (String::new(), format!("{:.2}", -value))
+            };
+            rows.push(JournalRow {
+                entry: i + 1,
+                date: t.date.clone(),
+                account: account.to_string(),
+                debit,
+                credit,
+                memo: memo.clone(),
+            });
+        }
+    }
+    if !unmapped.is_empty() {
+        let names: Vec<&str> = unmapped.into_iter().collect();
+        return Err(format!(
+            "the chart of accounts has no account for {}",
+            names.join(", ")
+        )
+        .into());
+    }
+    Ok(rows)
+}
+
+// Writes the report as journal entries and returns the number of events
+// written.
+pub(crate) fn write_journal(
+    path: &str,
+    report: &[ReportRow],
+    ending_pools: &[EndingPoolRow],
+    tax_year: i32,
+    chart: Option<&ChartOfAccounts>,
+) -> Result<usize, Box<dyn Error>> {
+    let (transactions, events) = transactions(report, ending_pools, tax_year)?;
+    write_csv(path, &journal_rows(&transactions, chart)?)?;
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_time, stamp};
+    use std::sync::Arc;
+
+    fn report() -> Vec<ReportRow> {
+        let row = |refid: &str, event_type: &str, asset: &str, fields: [&str; 8]| {
+            let refid: Arc<str> = refid.into();
+            let mut r = crate::make_row(
+                stamp(parse_time("2025-03-01 12:00:00").unwrap(), None),
+                &refid,
+                &refid,
+                event_type,
+                &asset.into(),
+            );
+            r.time = "2025-03-01T12:00:00+00:00".to_string();
+            [
+                r.units_in,
+                r.units_out,
+                r.acb_added_cad,
+                r.acb_disposed_cad,
+                r.gain_cad,
+                r.income_cad,
+                r.pool_units_after,
+                r.pool_acb_cad_after,
+            ] = fields.map(str::to_string);
+            r
+        };
+        vec![
+            // 1 SOL held from 2024 at 100, then 1 more bought for CAD.
+            row(
+                "B1",
+                "trade_acquisition",
+                "SOL",
+                ["1", "", "150", "", "", "", "2", "250"],
+            ),
+            row(
+                "X1",
+                "trade_disposition",
+                "SOL",
+                ["", "1", "", "125", "75", "", "1", "125"],
+            ),
+            row(
+                "X1",
+                "trade_acquisition",
+                "ETH",
+                ["0.05", "", "199", "", "", "", "0.05", "199"],
+            ),
+            row(
+                "R1",
+                "earn_reward_income",
+                "ETH",
+                ["0.01", "", "40", "", "", "40", "0.06", "239"],
+            ),
+        ]
+    }
+
+    #[test]
+    fn beancount_transactions_balance_in_cad() {
+        let (transactions, events) = transactions(&report(), &[], 2025).unwrap();
+        assert_eq!(events, 3);
+        let text = beancount(&transactions, 2025);
+        for line in [
+            "2025-01-01 open Assets:Kraken:CAD",
+            "  Assets:Kraken:SOL  1 SOL @@ 100 CAD\n  Equity:Opening-Balances  -100 CAD\n",
+            "  Assets:Kraken:SOL  1 SOL @@ 150 CAD\n  Assets:Kraken:CAD  -150 CAD\n",
+            // Proceeds of 200 went to 199 of ETH and a fee of 1.
+            "  Assets:Kraken:SOL  -1 SOL @@ 125 CAD\n  Income:Kraken:CapitalGains  -75 CAD\n  \
+             Assets:Kraken:ETH  0.05 ETH @@ 199 CAD\n  Expenses:Kraken:Fees  1 CAD\n",
+            "  Assets:Kraken:ETH  0.01 ETH @@ 40 CAD\n  Income:Kraken:Staking  -40 CAD\n",
+            // The SOL the report does not show leaving by year end.
+            "2025-12-31 * \"Pool changes without report rows (transfers, migrations)\"\n  \
+             Assets:Kraken:ETH  -0.06 ETH @@ 239 CAD\n  Assets:Kraken:SOL  -1 SOL @@ 125 CAD\n",
+        ] {
+            assert!(text.contains(line), "missing {:?} in\n{}", line, text);
+        }
+        assert_eq!(commodity("1inch"), "X1INCH");
+    }
+
+    #[test]
+    fn journal_entries_use_the_chart_of_accounts() {
+        let (transactions, _) = transactions(&report(), &[], 2025).unwrap();
+        let chart = ChartOfAccounts::parse(
+            "# assets\n\
+             Assets:Kraken:* = 1500 Digital assets\n\
+             Assets:Kraken:CAD = 1010 Kraken CAD\n\
+             Income:* = 4900 Crypto income\n\
+             Equity:* = 3000 Equity\n",
+        )
+        .unwrap();
+        let err = journal_rows(&transactions, Some(&chart)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the chart of accounts has no account for Expenses:Kraken:Fees"
+        );
+
+        let chart = ChartOfAccounts::parse(
+            "Assets:Kraken:* = 1500 Digital assets\n\
+             Assets:Kraken:CAD = 1010 Kraken CAD\n\
+             * = 9999 Suspense\n",
+        )
+        .unwrap();
+        let rows = journal_rows(&transactions, Some(&chart)).unwrap();
+        let lines: Vec<_> = rows
+            .iter()
+            .filter(|r| r.entry == 2)
+            .map(|r| {
+                (
+                    r.account.as_str(),
+                    r.debit.as_str(),
+                    r.credit.as_str(),
+                    r.memo.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (
+                    "1500 Digital assets",
+                    "150.00",
+                    "",
+                    "trade_acquisition SOL (B1)"
+                ),
+                (
+                    "1010 Kraken CAD",
+                    "",
+                    "150.00",
+                    "trade_acquisition SOL (B1)"
+                ),
+            ]
+        );
+        // Every entry balances.
+        for entry in 1..=transactions.len() {
+            let side = |f: fn(&JournalRow) -> &str| -> Decimal {
+                rows.iter()
+                    .filter(|r| r.entry == entry)
+                    .map(|r| amount(f(r)).unwrap())
+                    .sum()
+            };
+            assert_eq!(side(|r| &r.debit), side(|r| &r.credit), "entry {}", entry);
+        }
+    }
+}
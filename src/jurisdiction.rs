@@ -0,0 +1,126 @@
+use chrono::{Datelike, NaiveDateTime};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// The rules of one country's return that the engine needs. Times are the
+// taxpayer's local wall clock (see `--timezone`).
+pub trait Jurisdiction: fmt::Debug + Send + Sync {
+    // The name `--jurisdiction` selects it by.
+    fn name(&self) -> &'static str;
+
+    // The tax year `time` falls in.
+    fn tax_year(&self, time: NaiveDateTime) -> i32;
+
+    // Days before and after a disposal at a loss in which buying the asset
+    // back can deny the loss (superficial loss, wash sale); `None` if the
+    // country has no such rule.
+    fn loss_window_days(&self) -> Option<i64>;
+
+    // Share of the net capital gain or loss for `year` that is taxable.
+    fn inclusion_rate(&self, year: i32) -> Decimal;
+
+    // Years back a net capital loss can be carried; forward is unlimited.
+    fn loss_carryback_years(&self) -> i32;
+
+    // The `--cost-basis` methods the return accepts.
+    fn cost_basis_methods(&self) -> &'static [&'static str];
+
+    // Report columns the return's capital gains schedule is filled from.
+    fn required_columns(&self) -> &'static [&'static str];
+
+    // How much of a year's net gain or loss on foreign currency is exempt,
+    // either way.
+    fn fx_exemption_cad(&self) -> Decimal;
+}
+
+// Canada: calendar tax years, the 30-day superficial loss rule, one-half
+// inclusion, three-year carryback, adjusted cost base only, and the $200
+// personal exemption on foreign exchange.
+#[derive(Debug)]
+pub struct Canada;
+
+impl Jurisdiction for Canada {
+    fn name(&self) -> &'static str {
+        "canada"
+    }
+
+    fn tax_year(&self, time: NaiveDateTime) -> i32 {
+        time.year()
+    }
+
+    fn loss_window_days(&self) -> Option<i64> {
+        Some(30)
+    }
+
+    fn inclusion_rate(&self, _year: i32) -> Decimal {
+        dec!(0.5)
+    }
+
+    fn loss_carryback_years(&self) -> i32 {
+        3
+    }
+
+    fn cost_basis_methods(&self) -> &'static [&'static str] {
+        &["average"]
+    }
+
+    // Schedule 3: description, proceeds, ACB and gain, by disposal date.
+    fn required_columns(&self) -> &'static [&'static str] {
+        &[
+            "time",
+            "asset",
+            "units_out",
+            "proceeds_cad",
+            "acb_disposed_cad",
+            "gain_cad",
+        ]
+    }
+
+    // Subsection 39(1.1), for individuals.
+    fn fx_exemption_cad(&self) -> Decimal {
+        dec!(200)
+    }
+}
+
+pub(crate) fn by_name(name: &str) -> Result<Arc<dyn Jurisdiction>, Box<dyn Error>> {
+    match name.trim().to_lowercase().as_str() {
+        "canada" | "ca" => Ok(Arc::new(Canada)),
+        other => Err(format!("unknown jurisdiction: {}", other).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::{ProcessOptions, parse_time, process};
+
+    #[test]
+    fn canada_flags_buybacks_and_halves_the_gain() {
+        let entries = vec![
+            leg("B1", "2024-12-20 00:00:00", "CAD", dec!(-300)),
+            leg("B1", "2024-12-20 00:00:00", "SOL", dec!(2)),
+            leg("S1", "2025-01-05 00:00:00", "SOL", dec!(-1)),
+            leg("S1", "2025-01-05 00:00:00", "CAD", dec!(100)),
+            leg("S2", "2025-03-01 00:00:00", "SOL", dec!(-1)),
+            leg("S2", "2025-03-01 00:00:00", "CAD", dec!(250)),
+        ];
+        let new_year = parse_time("2024-12-31 23:59:59").unwrap();
+        assert_eq!(Canada.tax_year(new_year), 2024);
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let notes = |refid: &str| {
+            let row = out.report.iter().find(|r| &*r.refid == refid).unwrap();
+            row.notes.clone()
+        };
+        assert!(notes("S1").contains("Possible superficial loss: SOL acquired on 2024-12-20"));
+        // A gain, with the buy more than 30 days before.
+        assert!(!notes("S2").contains("superficial"));
+        assert_eq!(out.totals.capital_gain_cad, dec!(50));
+        assert_eq!(out.totals.taxable_capital_gain_cad, dec!(25));
+        assert!(by_name("atlantis").is_err());
+    }
+}
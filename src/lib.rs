@@ -0,0 +1,7763 @@
+mod accounts;
+mod anonymize;
+mod basis;
+mod bench;
+#[cfg(feature = "cli")]
+mod chain;
+mod checkpoint;
+mod checksums;
+mod cli;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod compare;
+mod config;
+#[cfg(feature = "cli")]
+mod credentials;
+#[cfg(feature = "cli")]
+mod daemon;
+#[cfg(feature = "sqlite")]
+mod db;
+mod diff;
+mod engine;
+mod events;
+mod export;
+#[cfg(test)]
+mod fixtures;
+#[cfg(feature = "cli")]
+mod http;
+mod import;
+mod instalments;
+mod invariants;
+mod journal;
+mod jurisdiction;
+mod locale;
+mod markdown;
+mod meta;
+mod mutation;
+mod output;
+mod prices;
+mod round_trip;
+#[cfg(feature = "cli")]
+mod serve;
+mod split;
+mod summary;
+#[cfg(feature = "cli")]
+mod sync;
+mod table;
+mod tax_rates;
+mod timeline;
+mod trades;
+#[cfg(feature = "tui")]
+mod tui;
+mod verify;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use chrono::{
+    DateTime, Datelike, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset,
+    TimeZone,
+};
+use chrono_tz::Tz;
+use csv::ReaderBuilder;
+use rayon::prelude::*;
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use accounts::AccountPools;
+use cli::{is_subcommand, parse_args_from, positional_args, take_flag, take_switch};
+use events::Pass;
+use export::ExportFormat;
+use import::ImportFormat;
+use locale::Locale;
+use mutation::PoolBook;
+use output::{
+    CsvStyle, ReportColumn, ReportPaths, WriteJob, create_output, parse_report_columns,
+    read_report, side_report_path, summary_color, summary_writer, use_color, write_all, write_csv,
+    write_json, write_report,
+};
+use split::Attribution;
+use summary::Summary;
+use tax_rates::Province;
+
+pub use basis::{AverageCost, CostBasisEngine, Fifo, Hifo, Lot, LotSelections, SpecificId, Taken};
+pub use cli::run;
+pub use engine::{EngineOptions, ReportBundle, process_csv, process_csv_with_cost_basis};
+pub use jurisdiction::{Canada, Jurisdiction};
+pub use mutation::{Applied, Effect, Mutation, PoolMutation, replay};
+
+#[derive(Debug, Deserialize, Clone)]
+struct LedgerRow {
+    txid: String,
+    refid: String,
+    time: String,
+    #[serde(rename = "type")]
+    row_type: String,
+    #[serde(default)]
+    subtype: String,
+    asset: String,
+    amount: String,
+    fee: String,
+    #[serde(default)]
+    balance: String,
+}
+
+#[derive(Debug, Clone)]
+struct LedgerEntry {
+    txid: Arc<str>,
+    refid: Arc<str>,
+    time: NaiveDateTime,
+    row_type: String,
+    subtype: String,
+    asset: Arc<str>,
+    amount: Decimal,
+    fee: Decimal,
+    net_delta: Decimal,
+    // Running asset balance reported by the source ledger, when it has one.
+    balance: Option<Decimal>,
+    // The input file the row came from, for per-wallet pools.
+    wallet: Option<Arc<str>>,
+    // The UTC offset `time` is at once `--timezone` has moved it onto the
+    // wall clock, which in the repeated hour of a DST change `time` alone
+    // cannot tell; `None` while it is UTC.
+    utc_offset: Option<FixedOffset>,
+}
+
+impl LedgerEntry {
+    fn stamp(&self) -> DateTime<FixedOffset> {
+        stamp(self.time, self.utc_offset)
+    }
+}
+
+// Shares one allocation per distinct asset or refid across every row, group,
+// event and report row built from a ledger.
+#[derive(Debug, Default)]
+struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(sym) = self.strings.get(s) {
+            return sym.clone();
+        }
+        let sym: Arc<str> = Arc::from(s);
+        self.strings.insert(sym.clone());
+        sym
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TradeGroup {
+    refid: Arc<str>,
+    time: NaiveDateTime,
+    txid: Arc<str>,
+    entries: Vec<LedgerEntry>,
+}
+
+impl TradeGroup {
+    // `time` with the offset of the leg it came from.
+    fn stamp(&self) -> DateTime<FixedOffset> {
+        let offset = self.entries.iter().find(|e| e.time == self.time);
+        stamp(self.time, offset.and_then(|e| e.utc_offset))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Trade(TradeGroup),
+    // Into or out of a receipt token under the separate wrap policy; the legs
+    // may be trade, deposit or withdrawal rows.
+    Conversion(TradeGroup),
+    Entry(LedgerEntry),
+    Migration(Migration),
+    // Emigration: every pool is deemed sold and re-acquired at FMV.
+    DeemedDisposition(NaiveDateTime),
+}
+
+#[derive(Debug, Deserialize)]
+struct MigrationRow {
+    from: String,
+    to: String,
+    date: String,
+    #[serde(default)]
+    factor: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WrapRow {
+    wrapped: String,
+    underlying: String,
+    policy: String,
+}
+
+// How trading a wrapped token for the asset it wraps, or back, is treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WrapPolicy {
+    // The token is held in the underlying asset's pool, so converting
+    // between them disposes of nothing.
+    Merge,
+    // Converting is a disposition like any trade.
+    Taxable,
+    // A receipt token (rETH, an LP token) with a pool of its own; the cost of
+    // what goes into it carries over, and back out when it is redeemed.
+    Separate,
+}
+
+impl WrapPolicy {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "merge" => Ok(WrapPolicy::Merge),
+            "taxable" => Ok(WrapPolicy::Taxable),
+            "separate" => Ok(WrapPolicy::Separate),
+            other => Err(format!("unknown wrap policy: {:?}", other).into()),
+        }
+    }
+}
+
+// A wrapped token (e.g. WBTC) and the asset it wraps (BTC). Only a receipt
+// token under the separate policy may stand for several assets, as a
+// liquidity pool token does.
+#[derive(Debug, Clone)]
+struct Wrap {
+    wrapped: Arc<str>,
+    underlying: Vec<Arc<str>>,
+    policy: WrapPolicy,
+}
+
+// Rename/redenomination: at `time`, the `from` pool moves into `to` with
+// units multiplied by `factor` and ACB unchanged.
+#[derive(Debug, Clone)]
+struct Migration {
+    from: Arc<str>,
+    to: Arc<str>,
+    time: NaiveDateTime,
+    factor: Decimal,
+}
+
+// One asset's holdings. `lots` stays empty under average cost.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pool {
+    pub units: Decimal,
+    pub acb_cad: Decimal,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lots: Vec<Lot>,
+    // Unit-weighted mean acquisition time of the units held, for holding
+    // periods under average cost. `None` when empty, or when a checkpoint
+    // written before it was kept left it unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acquired_at: Option<NaiveDateTime>,
+    // The first and last acquisition of the units held, `None` as above.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acquisitions: Option<Acquisitions>,
+}
+
+// The span and number of the acquisitions behind a pool's units, since it
+// was last empty. Under average cost every unit disposed of comes from all
+// of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acquisitions {
+    pub first: NaiveDateTime,
+    pub last: NaiveDateTime,
+    pub count: u32,
+}
+
+impl Acquisitions {
+    fn at(time: NaiveDateTime) -> Self {
+        Acquisitions {
+            first: time,
+            last: time,
+            count: 1,
+        }
+    }
+
+    // Unknown when either side is.
+    fn join(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        let (a, b) = (a?, b?);
+        Some(Acquisitions {
+            first: a.first.min(b.first),
+            last: a.last.max(b.last),
+            count: a.count + b.count,
+        })
+    }
+}
+
+impl Pool {
+    // Takes in a migrated pool whose units are scaled by `factor`; the lots
+    // keep their dates and costs.
+    fn absorb(&mut self, old: Pool, factor: Decimal) {
+        let acquisitions = if self.units.is_zero() {
+            old.acquisitions
+        } else if old.units.is_zero() {
+            self.acquisitions
+        } else {
+            Acquisitions::join(self.acquisitions, old.acquisitions)
+        };
+        self.hold(old.units * factor, old.acquired_at);
+        self.acquisitions = acquisitions;
+        self.units += old.units * factor;
+        self.acb_cad += old.acb_cad;
+        self.lots.extend(old.lots.into_iter().map(|lot| Lot {
+            units: lot.units * factor,
+            ..lot
+        }));
+        self.lots.sort_by_key(|lot| lot.time);
+    }
+
+    // Resets the cost to `cost_cad` as of `time`, as a deemed re-acquisition
+    // does; lot-based pools become a single lot.
+    fn restart(&mut self, cost_cad: Decimal, time: NaiveDateTime, refid: &Arc<str>) {
+        self.acb_cad = cost_cad;
+        self.acquired_at = Some(time);
+        self.acquisitions = Some(Acquisitions::at(time));
+        if !self.lots.is_empty() {
+            self.lots = vec![Lot {
+                time,
+                refid: refid.clone(),
+                units: self.units,
+                cost_cad,
+            }];
+        }
+    }
+
+    // Moves `acquired_at` and widens `acquisitions` for `units` about to be
+    // added, acquired at `time`.
+    fn hold(&mut self, units: Decimal, time: Option<NaiveDateTime>) {
+        let added = time.map(Acquisitions::at);
+        self.acquisitions = if self.units.is_zero() {
+            added
+        } else {
+            Acquisitions::join(self.acquisitions, added)
+        };
+        self.acquired_at = match (self.acquired_at, time) {
+            _ if self.units.is_zero() => time,
+            (Some(held), Some(time)) => {
+                let shift =
+                    Decimal::from((time - held).num_seconds()) * units / (self.units + units);
+                Some(held + chrono::Duration::seconds(shift.round().to_i64().unwrap_or(0)))
+            }
+            _ => None,
+        };
+    }
+
+    fn avg_cost_cad_per_unit(&self) -> Decimal {
+        if self.units.is_zero() {
+            dec!(0)
+        } else {
+            self.acb_cad / self.units
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    proceeds_cad: Decimal,
+    acb_disposed_cad: Decimal,
+    capital_gain_cad: Decimal,
+    reward_income_cad: Decimal,
+    interest_income_cad: Decimal,
+    credit_income_cad: Decimal,
+    dividend_income_cad: Decimal,
+    other_income_cad: Decimal,
+    trade_fee_expense_cad: Decimal,
+    fee_rebate_cad: Decimal,
+    rebate_income_cad: Decimal,
+    gift_fmv_cad: Decimal,
+    donation_amount_cad: Decimal,
+    deemed_proceeds_cad: Decimal,
+    deemed_gain_cad: Decimal,
+    // Net gain or loss on disposing of foreign currency, and the part of it
+    // the jurisdiction exempts; `capital_gain_cad` leaves the exempt part out.
+    fx_gain_cad: Decimal,
+    fx_exempt_cad: Decimal,
+    // Gain or loss on spends tagged exempt by `--exempt-spends-under`, also
+    // left out of `capital_gain_cad`.
+    personal_use_exempt_cad: Decimal,
+    // Net capital gain times the jurisdiction's inclusion rate.
+    taxable_capital_gain_cad: Decimal,
+    warning_count: usize,
+    // Valuations at prices older than `--max-price-age`.
+    stale_price_count: usize,
+    // Capital totals split by how long the disposed units were held.
+    holding_periods: BTreeMap<HoldingPeriod, HoldingTotals>,
+    activity: Activity,
+    fees: FeeTotals,
+    // How far the rounded report rows add up from these totals.
+    row_drift: RowDrift,
+}
+
+// The report's rounded rows summed, less the totals, which are kept at full
+// precision. Each row is rounded on its own, so the sums can drift from the
+// totals by up to one unit in the last place per row counted.
+#[derive(Debug, Default, Serialize)]
+struct RowDrift {
+    proceeds_cad: Decimal,
+    acb_disposed_cad: Decimal,
+    // Against the net capital gain before the foreign exchange exemption,
+    // which no row carries.
+    capital_gain_cad: Decimal,
+    income_cad: Decimal,
+    // The most rounding can explain in any one of the above.
+    max_rounding_cad: Decimal,
+}
+
+impl RowDrift {
+    fn of(
+        report: &[ReportRow],
+        totals: &Totals,
+        rounding: &Rounding,
+    ) -> Result<Self, Box<dyn Error>> {
+        let rows = grouped_totals(report.iter().map(|r| ((), r)))?
+            .remove(&())
+            .unwrap_or_default();
+        let counted = report
+            .iter()
+            .filter(|r| !r.gain_cad.is_empty() || !r.income_cad.is_empty())
+            .count();
+        let unit = if rounding.full_precision {
+            dec!(0)
+        } else {
+            Decimal::new(1, rounding.proceeds_dp.min(rounding.acb_dp))
+        };
+        let income = totals.reward_income_cad
+            + totals.interest_income_cad
+            + totals.credit_income_cad
+            + totals.dividend_income_cad
+            + totals.other_income_cad
+            + totals.rebate_income_cad;
+        Ok(RowDrift {
+            proceeds_cad: rows.proceeds_cad - totals.proceeds_cad,
+            acb_disposed_cad: rows.acb_disposed_cad - totals.acb_disposed_cad,
+            capital_gain_cad: rows.gain_cad
+                - (totals.capital_gain_cad + totals.fx_exempt_cad + totals.personal_use_exempt_cad),
+            income_cad: rows.income_cad - income,
+            max_rounding_cad: unit * Decimal::from(counted),
+        })
+    }
+
+    fn amounts(&self) -> [Decimal; 4] {
+        [
+            self.proceeds_cad,
+            self.acb_disposed_cad,
+            self.capital_gain_cad,
+            self.income_cad,
+        ]
+    }
+
+    fn within_rounding(&self) -> bool {
+        self.amounts()
+            .iter()
+            .all(|d| d.abs() <= self.max_rounding_cad)
+    }
+}
+
+// Fees paid in the tax year by kind, at market value, for filers who claim
+// them as expenses.
+#[derive(Debug, Default, Serialize)]
+struct FeeTotals {
+    trading_cad: Decimal,
+    withdrawal_cad: Decimal,
+    margin_cad: Decimal,
+    // Fees without a price, left out of the amounts above.
+    unpriced_count: usize,
+}
+
+impl FeeTotals {
+    fn total_cad(&self) -> Decimal {
+        self.trading_cad + self.withdrawal_cad + self.margin_cad
+    }
+}
+
+// How actively the year was traded: what an accountant weighs when asking
+// whether the gains are business income instead.
+#[derive(Debug, Default, Serialize)]
+struct Activity {
+    trades: usize,
+    assets_traded: usize,
+    // CAD value of what the trades received.
+    volume_cad: Decimal,
+    // Mean days disposed units were held, weighted by their proceeds.
+    avg_holding_days: Option<Decimal>,
+    largest_gain: Option<GainRef>,
+    largest_loss: Option<GainRef>,
+    #[serde(skip)]
+    held_days_proceeds: Decimal,
+    #[serde(skip)]
+    held_proceeds: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct GainRef {
+    refid: Arc<str>,
+    asset: Arc<str>,
+    gain_cad: Decimal,
+}
+
+// How long disposed units were held: under 30 days, up to a year, or more
+// than a year (the US long-term line).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+enum HoldingPeriod {
+    #[serde(rename = "under_30_days")]
+    Under30Days,
+    #[serde(rename = "under_1_year")]
+    UnderOneYear,
+    #[serde(rename = "over_1_year")]
+    OverOneYear,
+    // Units from a checkpoint written before acquisition times were kept.
+    #[serde(rename = "unknown")]
+    Unknown,
+}
+
+impl HoldingPeriod {
+    fn of(acquired: Option<NaiveDateTime>, disposed: NaiveDateTime) -> Self {
+        let Some(acquired) = acquired else {
+            return HoldingPeriod::Unknown;
+        };
+        if (disposed - acquired).num_days() < 30 {
+            HoldingPeriod::Under30Days
+        } else if disposed.date() <= acquired.date() + chrono::Months::new(12) {
+            HoldingPeriod::UnderOneYear
+        } else {
+            HoldingPeriod::OverOneYear
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            HoldingPeriod::Under30Days => "under_30_days",
+            HoldingPeriod::UnderOneYear => "under_1_year",
+            HoldingPeriod::OverOneYear => "over_1_year",
+            HoldingPeriod::Unknown => "unknown",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            HoldingPeriod::Under30Days => "Under 30 days",
+            HoldingPeriod::UnderOneYear => "30 days to 1 year",
+            HoldingPeriod::OverOneYear => "Over 1 year",
+            HoldingPeriod::Unknown => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct HoldingTotals {
+    proceeds_cad: Decimal,
+    acb_disposed_cad: Decimal,
+    gain_cad: Decimal,
+}
+
+impl Totals {
+    // Labelled CAD totals in summary order, flagging gain/loss amounts.
+    fn rows(&self) -> [(&'static str, Decimal, bool); 17] {
+        [
+            ("Proceeds", self.proceeds_cad, false),
+            ("ACB disposed", self.acb_disposed_cad, false),
+            ("Net capital gain/loss", self.capital_gain_cad, true),
+            (
+                "Taxable capital gain/loss",
+                self.taxable_capital_gain_cad,
+                true,
+            ),
+            ("Reward income", self.reward_income_cad, false),
+            ("Interest income", self.interest_income_cad, false),
+            ("Credit/bonus income", self.credit_income_cad, false),
+            ("Dividend income", self.dividend_income_cad, false),
+            ("Other income", self.other_income_cad, false),
+            ("Trading fees expensed", self.trade_fee_expense_cad, false),
+            ("Maker rebates", self.fee_rebate_cad, false),
+            ("Maker rebate income", self.rebate_income_cad, false),
+            ("Gifts at FMV", self.gift_fmv_cad, false),
+            ("Charitable donations", self.donation_amount_cad, false),
+            ("Foreign exchange gain/loss", self.fx_gain_cad, true),
+            ("FX exemption applied", self.fx_exempt_cad, true),
+            (
+                "Personal-use exemption applied",
+                self.personal_use_exempt_cad,
+                true,
+            ),
+        ]
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct PriceState {
+    usd_cad_last: Option<Decimal>,
+    asset_price_usd: HashMap<Arc<str>, Decimal>,
+    asset_price_cad: HashMap<Arc<str>, Decimal>,
+    // FX baked into CAD prices that were derived from USD prices.
+    asset_price_cad_fx: HashMap<Arc<str>, (Decimal, FxSource)>,
+    // When each asset's price was last traded; `USD` is the USD/CAD rate.
+    // Empty in checkpoints written before it was tracked.
+    #[serde(default)]
+    observed_at: HashMap<Arc<str>, NaiveDateTime>,
+}
+
+impl PriceState {
+    // When the observed USD/CAD rate was traded; `None` on the fallback.
+    fn fx_time(&self) -> Option<NaiveDateTime> {
+        self.usd_cad_last
+            .and_then(|_| self.observed_at.get("USD").copied())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum FxSource {
+    ObservedTrade,
+    Fallback,
+    Override,
+    BankOfCanada,
+}
+
+impl FxSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FxSource::ObservedTrade => "observed_trade",
+            FxSource::Fallback => "fallback",
+            FxSource::Override => "override",
+            FxSource::BankOfCanada => "bank_of_canada",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PriceSource {
+    Fiat,
+    TradeImplied,
+    DailyClose,
+    Override,
+    CoinGecko,
+}
+
+impl PriceSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PriceSource::Fiat => "fiat",
+            PriceSource::TradeImplied => "trade_implied",
+            PriceSource::DailyClose => "daily_close",
+            PriceSource::Override => "override",
+            PriceSource::CoinGecko => "coingecko",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FeeMode {
+    // Fees stay in the leg Kraken charged them on (net amounts).
+    Embedded,
+    Acb,
+    Proceeds,
+    Expense,
+}
+
+impl FeeMode {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "embedded" => Ok(FeeMode::Embedded),
+            "acb" => Ok(FeeMode::Acb),
+            "proceeds" => Ok(FeeMode::Proceeds),
+            "expense" => Ok(FeeMode::Expense),
+            other => Err(format!("unknown fee mode: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FeeMode::Embedded => "embedded",
+            FeeMode::Acb => "acb",
+            FeeMode::Proceeds => "proceeds",
+            FeeMode::Expense => "expense",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RebateMode {
+    // Negative fees flow through the fee mode as a reduction of trading cost.
+    Cost,
+    Income,
+}
+
+impl RebateMode {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "cost" => Ok(RebateMode::Cost),
+            "income" => Ok(RebateMode::Income),
+            other => Err(format!("unknown rebate mode: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RebateMode::Cost => "cost",
+            RebateMode::Income => "income",
+        }
+    }
+}
+
+// Whether trading away a stablecoin valued as fiat is a taxable disposition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StablecoinDispositions {
+    Report,
+    Skip,
+}
+
+impl StablecoinDispositions {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "report" => Ok(StablecoinDispositions::Report),
+            "skip" => Ok(StablecoinDispositions::Skip),
+            other => Err(format!("unknown stablecoin dispositions mode: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            StablecoinDispositions::Report => "report",
+            StablecoinDispositions::Skip => "skip",
+        }
+    }
+}
+
+// Whether each input file holds its own pools. Under `PerWallet` an asset's
+// pool is split by the wallet (input file) its rows come from, and a matched
+// transfer between two wallets moves its share of the ACB along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pooling {
+    Universal,
+    PerWallet,
+}
+
+impl Pooling {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "universal" => Ok(Pooling::Universal),
+            "per-wallet" => Ok(Pooling::PerWallet),
+            other => Err(format!("unknown pooling: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Pooling::Universal => "universal",
+            Pooling::PerWallet => "per-wallet",
+        }
+    }
+}
+
+// `--tie-break`: how events at the same timestamp are ordered. Whatever the
+// policy, the order depends only on the rows, never on their order in the
+// export; the last keys are always the event kind and the refid, txid and
+// asset strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TieBreak {
+    Id,
+    // Fiat purchases and deposits before sales and withdrawals.
+    AcquisitionsFirst,
+    DispositionsFirst,
+}
+
+impl TieBreak {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "id" => Ok(TieBreak::Id),
+            "acquisitions-first" => Ok(TieBreak::AcquisitionsFirst),
+            "dispositions-first" => Ok(TieBreak::DispositionsFirst),
+            other => Err(format!("unknown tie-break policy: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            TieBreak::Id => "id",
+            TieBreak::AcquisitionsFirst => "acquisitions-first",
+            TieBreak::DispositionsFirst => "dispositions-first",
+        }
+    }
+
+    // Rank of `e` among events at its timestamp, before the kind and ids. A
+    // trade acquires when it pays in CAD or a foreign currency; other rows
+    // by the sign of their amount. Migrations and deemed dispositions stay
+    // last.
+    fn rank(&self, e: &Event) -> u8 {
+        let acquires = match e {
+            Event::Trade(g) | Event::Conversion(g) => g
+                .entries
+                .iter()
+                .find(|x| x.net_delta < dec!(0))
+                .is_some_and(|x| &*x.asset == "CAD" || is_foreign_currency(&x.asset)),
+            Event::Entry(x) => x.net_delta > dec!(0),
+            Event::Migration(_) | Event::DeemedDisposition(_) => return 2,
+        };
+        match (self, acquires) {
+            (TieBreak::Id, _) => 0,
+            (TieBreak::AcquisitionsFirst, true) | (TieBreak::DispositionsFirst, false) => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdjustmentMode {
+    // Positive adjustments add units at zero cost.
+    Acquisition,
+    Income,
+}
+
+impl AdjustmentMode {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "acquisition" => Ok(AdjustmentMode::Acquisition),
+            "income" => Ok(AdjustmentMode::Income),
+            other => Err(format!("unknown adjustment mode: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            AdjustmentMode::Acquisition => "acquisition",
+            AdjustmentMode::Income => "income",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DonationProceeds {
+    // Proceeds at FMV; `Acb` designates the lower of ACB and FMV instead
+    // (ITA 118.1(6)), which is also the donation receipt amount.
+    Fmv,
+    Acb,
+}
+
+impl DonationProceeds {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "fmv" => Ok(DonationProceeds::Fmv),
+            "acb" => Ok(DonationProceeds::Acb),
+            other => Err(format!("unknown donation proceeds: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            DonationProceeds::Fmv => "fmv",
+            DonationProceeds::Acb => "acb",
+        }
+    }
+}
+
+// `--income-valuation`: the price staking rewards are valued at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IncomeValuation {
+    // The last price observed in the ledger's own trades.
+    LastTrade,
+    // The receipt date's close from a `--daily-closes` file.
+    DailyClose,
+    // The receipt date's close fetched from Kraken.
+    Provider,
+}
+
+impl IncomeValuation {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "last-trade" => Ok(IncomeValuation::LastTrade),
+            "daily-close" => Ok(IncomeValuation::DailyClose),
+            "provider" => Ok(IncomeValuation::Provider),
+            other => Err(format!("unknown income valuation: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            IncomeValuation::LastTrade => "last-trade",
+            IncomeValuation::DailyClose => "daily-close",
+            IncomeValuation::Provider => "provider",
+        }
+    }
+}
+
+// `--stale-prices`: what a valuation at a price older than
+// `--max-price-age` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StalePrices {
+    // Note the row and count it as a warning.
+    Warn,
+    // Fail the run.
+    Refuse,
+}
+
+impl StalePrices {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "warn" => Ok(StalePrices::Warn),
+            "refuse" => Ok(StalePrices::Refuse),
+            other => Err(format!("unknown stale price mode: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            StalePrices::Warn => "warn",
+            StalePrices::Refuse => "refuse",
+        }
+    }
+}
+
+// `--exempt-fx-under` and `--exempt-spends-under`: dispositions small
+// enough to tag as exempt and leave out of the taxable totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Exemptions {
+    // Each foreign currency disposition whose gain or loss is within this
+    // many CAD, in place of the jurisdiction's exemption on the year's net.
+    fx_per_disposition: Option<Decimal>,
+    // Each spend (card payment, purchase) with proceeds of at most this many
+    // CAD, as personal use.
+    spends_under: Option<Decimal>,
+}
+
+impl Exemptions {
+    fn parse_limit(name: &str, value: &str) -> Result<Decimal, Box<dyn Error>> {
+        let limit = parse_decimal(value)?;
+        if limit.is_sign_negative() {
+            return Err(format!("--{} expects an amount in CAD, got {:?}", name, value).into());
+        }
+        Ok(limit)
+    }
+}
+
+// `--format`: what the report is also written as. The CSV, which the side
+// reports and `verify` are keyed on, is always written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    Csv,
+    // `<out>.md`, the summary and each asset's events in Markdown.
+    Markdown,
+    // `<out>.parquet`, the report rows with typed columns.
+    Parquet,
+    // `<out>.tsv`, the report tab-separated.
+    Tsv,
+}
+
+impl ReportFormat {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ReportFormat::Csv),
+            "md" | "markdown" => Ok(ReportFormat::Markdown),
+            "parquet" => Ok(ReportFormat::Parquet),
+            "tsv" => Ok(ReportFormat::Tsv),
+            other => Err(format!("unknown report format: {}", other).into()),
+        }
+    }
+}
+
+// `--csv-dialect`: how the report files are laid out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum CsvDialect {
+    #[default]
+    Standard,
+    // A UTF-8 byte order mark, CRLF line endings and every text column
+    // quoted, for opening in Excel.
+    Excel,
+}
+
+impl CsvDialect {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "standard" => Ok(CsvDialect::Standard),
+            "excel" => Ok(CsvDialect::Excel),
+            other => Err(format!("unknown CSV dialect: {}", other).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SplitBy {
+    Asset,
+    Year,
+    EventType,
+}
+
+impl SplitBy {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "asset" => Ok(SplitBy::Asset),
+            "year" => Ok(SplitBy::Year),
+            "event-type" | "event_type" => Ok(SplitBy::EventType),
+            other => Err(format!("unknown split: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SplitBy::Asset => "asset",
+            SplitBy::Year => "year",
+            SplitBy::EventType => "event-type",
+        }
+    }
+
+    fn key<'a>(&self, row: &'a ReportRow) -> &'a str {
+        match self {
+            SplitBy::Asset => &row.asset,
+            SplitBy::Year => row.time.get(..4).unwrap_or(""),
+            SplitBy::EventType => &row.event_type,
+        }
+    }
+}
+
+const DEFAULT_SPLIT_TEMPLATE: &str = "{stem}_{group}.csv";
+
+// Groups report rows by `split` and names each file from `template`, where
+// `{stem}` is the report path without `.csv`, `{group}` the group value and
+// `{year}` the tax year.
+fn split_report<'a>(
+    report: impl IntoIterator<Item = &'a ReportRow>,
+    split: SplitBy,
+    template: &str,
+    stem: &str,
+    tax_year: i32,
+) -> Vec<(String, Vec<&'a ReportRow>)> {
+    let mut groups: BTreeMap<&str, Vec<&ReportRow>> = BTreeMap::new();
+    for r in report {
+        groups.entry(split.key(r)).or_default().push(r);
+    }
+    let stem = stem.strip_suffix(".csv").unwrap_or(stem);
+    groups
+        .into_iter()
+        .map(|(group, rows)| {
+            let group: String = group
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let path = template
+                .replace("{stem}", stem)
+                .replace("{group}", &group)
+                .replace("{year}", &tax_year.to_string());
+            (path, rows)
+        })
+        .collect()
+}
+
+// `--from`/`--to`/`--assets`: which events the report files show. Dates are
+// inclusive and compared with each row's (local) date; pools and totals still
+// cover the full history.
+#[derive(Debug, Default, Clone)]
+struct ReportFilter {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    assets: Option<Vec<String>>,
+}
+
+impl ReportFilter {
+    fn parse_date(flag: &str, value: &str) -> Result<NaiveDate, Box<dyn Error>> {
+        NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("--{} expects YYYY-MM-DD, got {:?}", flag, value).into())
+    }
+
+    fn parse_assets(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(|a| a.trim().to_uppercase())
+            .filter(|a| !a.is_empty())
+            .collect()
+    }
+
+    fn is_active(&self) -> bool {
+        self.from.is_some() || self.to.is_some() || self.assets.is_some()
+    }
+
+    fn keeps(&self, row: &ReportRow) -> bool {
+        if let Some(assets) = &self.assets
+            && !assets.iter().any(|a| a.eq_ignore_ascii_case(&row.asset))
+        {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        let Some(date) = row
+            .time
+            .get(..10)
+            .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        else {
+            return false;
+        };
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+}
+
+// Default `--aggregate-fills` window between one fill and the next.
+const DEFAULT_FILL_WINDOW_SECS: u32 = 60;
+
+// The assets a trade disposed of and acquired (empty for CAD, which has no
+// row), and its time.
+type FillKey = ((Arc<str>, Arc<str>), DateTime<chrono::FixedOffset>);
+
+// A plain trade's key, from its report rows; `None` for anything else.
+fn fill_key(rows: &[ReportRow]) -> Option<FillKey> {
+    let mut pair: (Arc<str>, Arc<str>) = (Arc::from(""), Arc::from(""));
+    for r in rows {
+        match r.event_type.as_str() {
+            "trade_disposition" => pair.0 = r.asset.clone(),
+            "trade_acquisition" => pair.1 = r.asset.clone(),
+            _ => return None,
+        }
+    }
+    let time = DateTime::parse_from_rfc3339(&rows.first()?.time).ok()?;
+    Some((pair, time))
+}
+
+// Report columns that add up when fills are merged.
+fn summed_columns(r: &ReportRow) -> [&str; 7] {
+    [
+        &r.units_in,
+        &r.units_out,
+        &r.proceeds_cad,
+        &r.acb_disposed_cad,
+        &r.gain_cad,
+        &r.acb_added_cad,
+        &r.fee_cad,
+    ]
+}
+
+// Replaces a run of two or more fills with one row per event type, carrying
+// the sums and the last fill's pool; the fills' own rows go to `appendix`.
+fn flush_fills(
+    run: &mut Vec<Vec<ReportRow>>,
+    out: &mut Vec<ReportRow>,
+    appendix: &mut Vec<ReportRow>,
+) -> Result<(), Box<dyn Error>> {
+    if run.len() < 2 {
+        out.extend(run.drain(..).flatten());
+        return Ok(());
+    }
+    let (first, last) = (run[0][0].refid.clone(), run[run.len() - 1][0].refid.clone());
+    for event_type in ["trade_disposition", "trade_acquisition"] {
+        let rows: Vec<&ReportRow> = run
+            .iter()
+            .flatten()
+            .filter(|r| r.event_type == event_type)
+            .collect();
+        let (Some(head), Some(tail)) = (rows.first(), rows.last()) else {
+            continue;
+        };
+        let mut sums: [Option<Decimal>; 7] = [None; 7];
+        for r in &rows {
+            for (sum, value) in sums.iter_mut().zip(summed_columns(r)) {
+                if !value.is_empty() {
+                    *sum = Some(sum.unwrap_or_default() + parse_decimal(value)?);
+                }
+            }
+        }
+        let [
+            units_in,
+            units_out,
+            proceeds,
+            acb_disposed,
+            gain,
+            acb_added,
+            fee,
+        ] = sums.map(|s| s.map(|d| d.to_string()).unwrap_or_default());
+        let mut merged = ReportRow {
+            units_in,
+            units_out,
+            proceeds_cad: proceeds,
+            acb_disposed_cad: acb_disposed,
+            gain_cad: gain,
+            acb_added_cad: acb_added,
+            fee_cad: fee,
+            pool_units_after: tail.pool_units_after.clone(),
+            pool_acb_cad_after: tail.pool_acb_cad_after.clone(),
+            notes: format!(
+                "{} fills merged, refids {} to {}; see the fills report",
+                rows.len(),
+                first,
+                last
+            ),
+            ..(**head).clone()
+        };
+        // Notes every fill shares (e.g. the fee treatment) still apply.
+        for note in head.notes.split("; ").filter(|n| !n.is_empty()) {
+            if rows.iter().all(|r| r.notes.split("; ").any(|n| n == note)) {
+                add_note(&mut merged, note);
+            }
+        }
+        out.push(merged);
+    }
+    appendix.extend(run.drain(..).flatten());
+    Ok(())
+}
+
+// `--aggregate-fills`: merges runs of adjacent plain trades of the same pair,
+// each within `window` of the one before, into one disposition and/or
+// acquisition row. Pools and totals are unchanged, since the fills drew on
+// the same pool back to back. Returns the report and the merged fills' rows.
+fn aggregate_fills(
+    report: Vec<ReportRow>,
+    window: chrono::TimeDelta,
+) -> Result<(Vec<ReportRow>, Vec<ReportRow>), Box<dyn Error>> {
+    // Adjacent rows of one refid come from one event.
+    let mut events: Vec<Vec<ReportRow>> = Vec::new();
+    for r in report {
+        match events.last_mut() {
+            Some(rows) if rows[0].refid == r.refid => rows.push(r),
+            _ => events.push(vec![r]),
+        }
+    }
+    let mut out = Vec::new();
+    let mut appendix = Vec::new();
+    let mut run: Vec<Vec<ReportRow>> = Vec::new();
+    for rows in events {
+        let key = fill_key(&rows);
+        let joins = match (run.last().and_then(|prev| fill_key(prev)), &key) {
+            (Some((prev_pair, prev_time)), Some((pair, time))) => {
+                prev_pair == *pair && *time >= prev_time && *time - prev_time <= window
+            }
+            _ => false,
+        };
+        if !joins {
+            flush_fills(&mut run, &mut out, &mut appendix)?;
+        }
+        if key.is_some() {
+            run.push(rows);
+        } else {
+            out.extend(rows);
+        }
+    }
+    flush_fills(&mut run, &mut out, &mut appendix)?;
+    Ok((out, appendix))
+}
+
+// `--aggregate-rewards`: how much reward income one report row covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RewardPeriod {
+    Daily,
+    Monthly,
+}
+
+impl RewardPeriod {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "daily" => Ok(RewardPeriod::Daily),
+            "monthly" => Ok(RewardPeriod::Monthly),
+            other => Err(format!("unknown reward period: {}", other).into()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RewardPeriod::Daily => "daily",
+            RewardPeriod::Monthly => "monthly",
+        }
+    }
+
+    // The row's local date or month, from its report time.
+    fn key<'a>(&self, row: &'a ReportRow) -> &'a str {
+        let len = match self {
+            RewardPeriod::Daily => 10,
+            RewardPeriod::Monthly => 7,
+        };
+        row.time.get(..len).unwrap_or(&row.time)
+    }
+}
+
+// Sums each asset's `earn_reward_income` rows per period into one row, placed
+// where the period's last reward was and showing the pool after it. Pools and
+// totals are unchanged.
+fn aggregate_rewards(
+    report: Vec<ReportRow>,
+    period: RewardPeriod,
+) -> Result<Vec<ReportRow>, Box<dyn Error>> {
+    let is_reward = |r: &ReportRow| r.event_type == "earn_reward_income";
+    let mut groups: HashMap<(Arc<str>, &str), Vec<usize>> = HashMap::new();
+    for (i, r) in report.iter().enumerate().filter(|(_, r)| is_reward(r)) {
+        groups
+            .entry((r.asset.clone(), period.key(r)))
+            .or_default()
+            .push(i);
+    }
+    let mut merged: HashMap<usize, ReportRow> = HashMap::new();
+    let mut dropped = HashSet::new();
+    for ((_, key), rows) in &groups {
+        let Some((&last, earlier)) = rows.split_last() else {
+            continue;
+        };
+        if earlier.is_empty() {
+            continue;
+        }
+        let mut row = report[last].clone();
+        let (mut units, mut income, mut acb) = (dec!(0), dec!(0), dec!(0));
+        for &i in rows {
+            let r = &report[i];
+            units += parse_decimal(&r.units_in)?;
+            income += parse_decimal(&r.income_cad)?;
+            acb += parse_decimal(&r.acb_added_cad)?;
+            if r.price_source != row.price_source {
+                row.price_source = "mixed".to_string();
+            }
+            if r.fx_source != row.fx_source {
+                row.fx_source = "mixed".to_string();
+            }
+        }
+        row.units_in = units.to_string();
+        row.income_cad = income.to_string();
+        row.acb_added_cad = acb.to_string();
+        row.notes = format!(
+            "{} rewards for {} summed; refid and txid are the last one's",
+            rows.len(),
+            key
+        );
+        dropped.extend(earlier.iter().copied());
+        merged.insert(last, row);
+    }
+    Ok(report
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !dropped.contains(i))
+        .map(|(i, r)| merged.remove(&i).unwrap_or(r))
+        .collect())
+}
+
+// Built-in behaviors that user rules can map ledger type/subtype pairs onto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RowBehavior {
+    Internal,
+    Trade,
+    Reward,
+    Income,
+    Dividend,
+    Deposit,
+    Withdrawal,
+    Spend,
+    Adjustment,
+}
+
+impl RowBehavior {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "internal" => Ok(RowBehavior::Internal),
+            "trade" => Ok(RowBehavior::Trade),
+            "reward" => Ok(RowBehavior::Reward),
+            "income" => Ok(RowBehavior::Income),
+            "dividend" => Ok(RowBehavior::Dividend),
+            "deposit" => Ok(RowBehavior::Deposit),
+            "withdrawal" => Ok(RowBehavior::Withdrawal),
+            "spend" => Ok(RowBehavior::Spend),
+            "adjustment" => Ok(RowBehavior::Adjustment),
+            other => Err(format!("unknown row behavior: {}", other).into()),
+        }
+    }
+
+    // The ledger type/subtype whose handler implements this behavior.
+    fn canonical(&self) -> (&'static str, &'static str) {
+        match self {
+            RowBehavior::Internal => ("internal", ""),
+            RowBehavior::Trade => ("trade", ""),
+            RowBehavior::Reward => ("earn", "reward"),
+            RowBehavior::Income => ("credit", ""),
+            RowBehavior::Dividend => ("dividend", ""),
+            RowBehavior::Deposit => ("deposit", ""),
+            RowBehavior::Withdrawal => ("withdrawal", ""),
+            RowBehavior::Spend => ("spend", ""),
+            RowBehavior::Adjustment => ("adjustment", ""),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ClassificationRule {
+    row_type: String,
+    // `None` matches any subtype.
+    subtype: Option<String>,
+    behavior: RowBehavior,
+}
+
+#[derive(Debug, Clone)]
+struct ProcessOptions {
+    tax_year: i32,
+    fallback_fx: Decimal,
+    fee_mode: FeeMode,
+    rebate_mode: RebateMode,
+    adjustment_mode: AdjustmentMode,
+    donation_proceeds: DonationProceeds,
+    migrations: Vec<Migration>,
+    wraps: Vec<Wrap>,
+    deemed_disposition: Option<NaiveDateTime>,
+    // Ledger times have already been shifted to this zone's wall clock; only
+    // the report's offset suffix depends on it.
+    timezone: Option<Tz>,
+    // State carried in from a checkpoint; empty for a full replay.
+    start_pools: HashMap<Arc<str>, Pool>,
+    start_prices: PriceState,
+    // Kraken trades.csv fills by trade refid, for pre-fee prices.
+    trade_fills: BTreeMap<Arc<str>, trades::TradeFill>,
+    income_valuation: IncomeValuation,
+    // Closes for rewards under `daily-close`/`provider`; empty otherwise.
+    daily_closes: prices::DailyCloses,
+    price_chain: prices::PriceChain,
+    // Days a cached price may be used for before `stale_prices` applies.
+    max_price_age: Option<u32>,
+    stale_prices: StalePrices,
+    cost_basis: Arc<dyn CostBasisEngine>,
+    jurisdiction: Arc<dyn Jurisdiction>,
+    // How far apart a buy and sell may be to be flagged as a round trip;
+    // `None` turns detection off.
+    round_trip_window: Option<chrono::Duration>,
+    // Applies to the assets `price_chain` values at USD par.
+    stablecoin_dispositions: StablecoinDispositions,
+    pooling: Pooling,
+    rounding: Rounding,
+    tie_break: TieBreak,
+    // Check the pools and totals after every event (see `invariants`).
+    check_invariants: bool,
+    exemptions: Exemptions,
+}
+
+impl ProcessOptions {
+    fn new(tax_year: i32, fallback_fx: Decimal) -> Self {
+        ProcessOptions {
+            tax_year,
+            fallback_fx,
+            fee_mode: FeeMode::Embedded,
+            rebate_mode: RebateMode::Cost,
+            adjustment_mode: AdjustmentMode::Acquisition,
+            donation_proceeds: DonationProceeds::Fmv,
+            migrations: Vec::new(),
+            wraps: Vec::new(),
+            deemed_disposition: None,
+            timezone: None,
+            start_pools: HashMap::new(),
+            start_prices: PriceState::default(),
+            trade_fills: BTreeMap::new(),
+            income_valuation: IncomeValuation::LastTrade,
+            daily_closes: prices::DailyCloses::new(),
+            price_chain: prices::PriceChain::new(fallback_fx),
+            max_price_age: None,
+            stale_prices: StalePrices::Warn,
+            cost_basis: Arc::new(AverageCost),
+            jurisdiction: Arc::new(Canada),
+            round_trip_window: Some(chrono::Duration::minutes(
+                round_trip::DEFAULT_WINDOW_MINUTES,
+            )),
+            stablecoin_dispositions: StablecoinDispositions::Report,
+            pooling: Pooling::Universal,
+            rounding: Rounding::default(),
+            tie_break: TieBreak::Id,
+            check_invariants: false,
+            exemptions: Exemptions::default(),
+        }
+    }
+
+    // Whether `time` falls in the tax year being reported.
+    fn in_year(&self, time: NaiveDateTime) -> bool {
+        self.jurisdiction.tax_year(time) == self.tax_year
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Valuation {
+    cad: Decimal,
+    price_source: PriceSource,
+    fx: Option<(Decimal, FxSource)>,
+    // When the oldest cached price or rate used was traded; `None` for
+    // amounts valued from their own fiat leg.
+    price_time: Option<NaiveDateTime>,
+}
+
+impl Valuation {
+    fn cad(cad: Decimal) -> Self {
+        Valuation {
+            cad,
+            price_source: PriceSource::Fiat,
+            fx: None,
+            price_time: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ReportRow {
+    time: String,
+    refid: Arc<str>,
+    txid: Arc<str>,
+    event_type: String,
+    asset: Arc<str>,
+    units_in: String,
+    units_out: String,
+    proceeds_cad: String,
+    acb_disposed_cad: String,
+    gain_cad: String,
+    income_cad: String,
+    acb_added_cad: String,
+    fee_cad: String,
+    pool_units_after: String,
+    pool_acb_cad_after: String,
+    price_source: String,
+    fx_source: String,
+    notes: String,
+    // Not written: see `Booked`.
+    #[serde(skip)]
+    booked: Option<Booked>,
+}
+
+// A disposition's proceeds and gain as booked, before the row is rounded.
+// Totals worked out from the report rows after rounding (the foreign exchange
+// net, exemptions) use these, so they do not depend on `--decimal-places`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Booked {
+    proceeds_cad: Decimal,
+    gain_cad: Decimal,
+    // The part of the gain an exemption leaves out of the totals, once the
+    // row is tagged exempt.
+    exempt_cad: Option<Decimal>,
+}
+
+impl ReportRow {
+    fn set_disposal(
+        &mut self,
+        proceeds_cad: Decimal,
+        acb_disposed_cad: Decimal,
+        gain_cad: Decimal,
+    ) {
+        self.proceeds_cad = proceeds_cad.to_string();
+        self.acb_disposed_cad = acb_disposed_cad.to_string();
+        self.gain_cad = gain_cad.to_string();
+        self.booked = Some(Booked {
+            proceeds_cad,
+            gain_cad,
+            exempt_cad: None,
+        });
+    }
+
+    // The part of the row's gain an exemption leaves out of the totals.
+    pub(crate) fn exempt_gain_cad(&self) -> Decimal {
+        self.booked.and_then(|b| b.exempt_cad).unwrap_or_default()
+    }
+
+    // Whether an exemption leaves the row's whole gain out of the totals, so
+    // the tax schedules do not list it.
+    pub(crate) fn is_exempt(&self) -> bool {
+        self.booked
+            .is_some_and(|b| b.exempt_cad == Some(b.gain_cad))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FxAuditRow {
+    time: String,
+    refid: Arc<str>,
+    txid: Arc<str>,
+    event_type: String,
+    asset: Arc<str>,
+    value_cad: String,
+    usd_cad_fx: String,
+    fx_source: String,
+}
+
+// Row of the fee expense report: one fee paid in the tax year.
+#[derive(Debug, Serialize)]
+struct FeeRow {
+    time: String,
+    refid: Arc<str>,
+    // `trading`, `withdrawal` or `margin`.
+    category: &'static str,
+    asset: Arc<str>,
+    units: String,
+    // Blank when no price was found.
+    value_cad: String,
+}
+
+// Row of the lot audit: one acquisition, or the pool average, behind the ACB
+// of one disposition.
+#[derive(Debug, Serialize)]
+struct LineageRow {
+    disposition_time: String,
+    disposition_refid: Arc<str>,
+    event_type: String,
+    asset: Arc<str>,
+    basis: &'static str,
+    acquisition_time: String,
+    acquisition_refid: Arc<str>,
+    // Not written: for `pool_average`, the span the pool's units were
+    // acquired over, which the capital-gains exports date it by.
+    #[serde(skip)]
+    acquisitions: Option<Acquisitions>,
+    units: String,
+    acb_cad: String,
+    proceeds_cad: String,
+    gain_cad: String,
+    holding_days: String,
+    holding_period: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct IgnoredRowSummary {
+    row_type: String,
+    subtype: String,
+    count: usize,
+    sample_refids: String,
+}
+
+// Per-asset (or per-account) sums of the tax-year report rows, for the
+// console summary.
+#[derive(Debug, Default, PartialEq)]
+struct AssetTotals {
+    proceeds_cad: Decimal,
+    acb_disposed_cad: Decimal,
+    gain_cad: Decimal,
+    income_cad: Decimal,
+}
+
+// Assets with no disposal or income (e.g. only transfers) are left out.
+fn asset_totals(report: &[ReportRow]) -> Result<BTreeMap<Arc<str>, AssetTotals>, Box<dyn Error>> {
+    grouped_totals(report.iter().map(|r| (r.asset.clone(), r)))
+}
+
+// Sums the disposal and income rows under each row's key.
+fn grouped_totals<'a, K: Ord>(
+    rows: impl IntoIterator<Item = (K, &'a ReportRow)>,
+) -> Result<BTreeMap<K, AssetTotals>, Box<dyn Error>> {
+    let amount = |s: &str| {
+        if s.is_empty() {
+            Ok(dec!(0))
+        } else {
+            parse_decimal(s)
+        }
+    };
+    let mut out: BTreeMap<K, AssetTotals> = BTreeMap::new();
+    for (key, r) in rows {
+        let proceeds = amount(&r.proceeds_cad)?;
+        let acb = amount(&r.acb_disposed_cad)?;
+        let gain = amount(&r.gain_cad)?;
+        let income = amount(&r.income_cad)?;
+        if r.gain_cad.is_empty() && r.income_cad.is_empty() {
+            continue;
+        }
+        let t = out.entry(key).or_default();
+        t.proceeds_cad += proceeds;
+        t.acb_disposed_cad += acb;
+        t.gain_cad += gain;
+        t.income_cad += income;
+    }
+    Ok(out)
+}
+
+// A problem with the inputs themselves, found while merging them: the same
+// txid loaded twice, or two exports of one kind covering the same period.
+#[derive(Debug, Serialize, PartialEq)]
+struct DataIssue {
+    kind: &'static str,
+    time: String,
+    txid: String,
+    refid: String,
+    asset: String,
+    detail: String,
+}
+
+const DUPLICATE_ROW: &str = "duplicate_row";
+const CONFLICTING_TXID: &str = "conflicting_txid";
+const OVERLAPPING_INPUTS: &str = "overlapping_inputs";
+const INACTIVE_MONTHS: &str = "inactive_months";
+const DISPOSAL_BEFORE_ACQUISITION: &str = "disposal_before_acquisition";
+
+impl DataIssue {
+    // Months without rows are as often a quiet buy-and-hold account as a
+    // missing export, so they are listed without failing the run.
+    fn is_note(&self) -> bool {
+        self.kind == INACTIVE_MONTHS
+    }
+
+    fn describe(&self) -> String {
+        match self.kind {
+            OVERLAPPING_INPUTS | INACTIVE_MONTHS => format!("{}: {}", self.kind, self.detail),
+            _ => format!(
+                "{}: {} row {} (refid {}) at {}: {}",
+                self.kind, self.asset, self.txid, self.refid, self.time, self.detail
+            ),
+        }
+    }
+
+    fn for_row(kind: &'static str, e: &LedgerEntry, detail: String) -> Self {
+        DataIssue {
+            kind,
+            time: e.time.to_string(),
+            txid: e.txid.to_string(),
+            refid: e.refid.to_string(),
+            asset: e.asset.to_string(),
+            detail,
+        }
+    }
+}
+
+// A ledger `balance` that disagrees with the sum of the rows before it.
+#[derive(Debug, Serialize)]
+struct BalanceMismatch {
+    time: String,
+    refid: Arc<str>,
+    txid: Arc<str>,
+    asset: Arc<str>,
+    ledger_balance: Decimal,
+    computed_balance: Decimal,
+    difference: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct LossCarryoverRow {
+    year: i32,
+    net_gain_cad: Decimal,
+    // Losses from other years absorbed by this year's gain.
+    loss_applied_cad: Decimal,
+    adjusted_gain_cad: Decimal,
+    // Part of this year's loss carried back to the previous years.
+    loss_carried_back_cad: Decimal,
+    carryforward_balance_cad: Decimal,
+}
+
+// Row of the ending pools report: one pool as it stood at the end of the tax
+// year. The price and value are empty when the price chain has no price.
+#[derive(Debug, Serialize)]
+struct EndingPoolRow {
+    asset: Arc<str>,
+    units: Decimal,
+    acb_cad: Decimal,
+    avg_cost_cad: Decimal,
+    last_price_cad: Option<Decimal>,
+    fmv_cad: Option<Decimal>,
+    price_source: &'static str,
+}
+
+#[derive(Debug, Default)]
+struct ProcessOutput {
+    report: Vec<ReportRow>,
+    fx_audit: Vec<FxAuditRow>,
+    lineage: Vec<LineageRow>,
+    fees: Vec<FeeRow>,
+    round_trips: Vec<round_trip::RoundTrip>,
+    ignored: Vec<IgnoredRowSummary>,
+    totals: Totals,
+    pools: HashMap<Arc<str>, Pool>,
+    prices: PriceState,
+    // Every pool change through the tax year, in the order applied.
+    mutations: Vec<Applied>,
+    // `event_sequence_hash` of the events processed.
+    event_hash: String,
+}
+
+const DEFAULT_TAX_YEAR: i32 = 2025;
+const DEFAULT_FALLBACK_FX: &str = "1.3978";
+// How often long-running modes check the inputs for edits.
+const INPUT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct Args {
+    input: String,
+    tax_year: i32,
+    output: String,
+    fallback_usd_cad_fx: Decimal,
+    fee_mode: FeeMode,
+    rebate_mode: RebateMode,
+    adjustment_mode: AdjustmentMode,
+    donation_proceeds: DonationProceeds,
+    income_valuation: IncomeValuation,
+    daily_closes: Option<String>,
+    max_price_age: Option<u32>,
+    stale_prices: StalePrices,
+    // Minutes; 0 turns round-trip detection off.
+    round_trip_window: u32,
+    stablecoins_as_fiat: BTreeSet<Arc<str>>,
+    stablecoin_dispositions: StablecoinDispositions,
+    pooling: Pooling,
+    rounding: Rounding,
+    tie_break: TieBreak,
+    check_invariants: bool,
+    price_chain: String,
+    price_overrides: Option<String>,
+    price_cache: Option<String>,
+    offline: bool,
+    cost_basis: String,
+    lot_selection: Option<String>,
+    jurisdiction: Arc<dyn Jurisdiction>,
+    migrations: Option<String>,
+    wraps: Option<String>,
+    trades: Option<String>,
+    gifts: Option<String>,
+    exclude_refids: Option<String>,
+    overrides: Option<String>,
+    deemed_disposition: Option<NaiveDateTime>,
+    timezone: Option<Tz>,
+    loss_carryover_from: Option<i32>,
+    exemptions: Exemptions,
+    // `--province`, for the estimated tax payable.
+    province: Option<Province>,
+    other_income: Option<Decimal>,
+    db: Option<String>,
+    checkpoint: Option<String>,
+    classify: Option<String>,
+    import_format: ImportFormat,
+    mapping: Option<String>,
+    extra_inputs: Vec<(ImportFormat, String)>,
+    // `(name, path)` from each `--account name=path`.
+    accounts: Vec<(String, String)>,
+    account_pools: AccountPools,
+    split: Option<Attribution>,
+    split_names: Vec<String>,
+    // The taxpayer of `split_names` whose share this run reports.
+    split_side: Option<usize>,
+    csv_locale: CsvLocale,
+    columns: Option<Vec<ReportColumn>>,
+    json: bool,
+    formats: Vec<ReportFormat>,
+    csv_dialect: CsvDialect,
+    checksums: bool,
+    pool_mutations: bool,
+    split_by: Option<SplitBy>,
+    split_template: String,
+    exports: Vec<ExportFormat>,
+    chart_of_accounts: Option<String>,
+    match_transfers: bool,
+    no_color: bool,
+    locale: Locale,
+    watch: bool,
+    report_filter: ReportFilter,
+    repair_trades: bool,
+    aggregate_fills: Option<chrono::TimeDelta>,
+    aggregate_rewards: Option<RewardPeriod>,
+    // Renames applied to asset codes as inputs load, from `--asset-alias`.
+    asset_aliases: BTreeMap<Arc<str>, Arc<str>>,
+    // The config file and profile `config::expand` applied.
+    config: Option<String>,
+    profile: Option<String>,
+    // Leaves the console summary out, for reruns such as `verify` makes.
+    quiet: bool,
+}
+
+// Also accepts `1,234.56` thousands separators and scientific notation
+// (`1.2E-7`), which some exports use for large and dust amounts.
+fn parse_decimal(s: &str) -> Result<Decimal, Box<dyn Error>> {
+    let s = s.trim();
+    let s = if is_thousands_grouped(s) {
+        s.replace(',', "")
+    } else {
+        s.to_string()
+    };
+    if s.contains(['e', 'E']) {
+        return Ok(Decimal::from_scientific(&s)?);
+    }
+    Ok(Decimal::from_str(&s)?)
+}
+
+// `1,234` and `-12,345,678.9`, but not `1,5`, `12,34` or `0,123`.
+fn is_thousands_grouped(s: &str) -> bool {
+    let int_part = s.trim_start_matches(['-', '+']);
+    let int_part = int_part.split('.').next().unwrap_or("");
+    let mut groups = int_part.split(',');
+    let first = groups.next().unwrap_or("");
+    let mut rest = groups.peekable();
+    rest.peek().is_some()
+        && (1..=3).contains(&first.len())
+        && !first.starts_with('0')
+        && first.bytes().all(|b| b.is_ascii_digit())
+        && rest.all(|g| g.len() == 3 && g.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Epoch values at or above this are taken as milliseconds (year 5138 in seconds).
+const EPOCH_MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+// Ledger timestamps are naive UTC. Also accepts a `T` separator, a `Z`/`UTC`
+// suffix or numeric offset (converted to UTC), and Unix epoch seconds or
+// milliseconds, with or without a fraction.
+fn parse_time(s: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    let s = s.trim();
+    for fmt in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Ok(t);
+        }
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.naive_utc());
+    }
+    for fmt in ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"] {
+        if let Ok(t) = DateTime::parse_from_str(s, fmt) {
+            return Ok(t.naive_utc());
+        }
+    }
+    if let Some(naive) = s
+        .strip_suffix('Z')
+        .or_else(|| s.strip_suffix("UTC"))
+        .map(str::trim_end)
+        && naive != s
+    {
+        return parse_time(naive);
+    }
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        let epoch = Decimal::from_str(s)?;
+        let millis = if epoch.trunc() >= Decimal::from(EPOCH_MILLIS_THRESHOLD) {
+            epoch
+        } else {
+            epoch * dec!(1000)
+        };
+        let millis = millis
+            .trunc()
+            .to_i64()
+            .ok_or("epoch timestamp out of range")?;
+        return DateTime::from_timestamp_millis(millis)
+            .map(|t| t.naive_utc())
+            .ok_or_else(|| format!("epoch timestamp out of range: {}", s).into());
+    }
+    Err(format!("unsupported timestamp format: {}", s).into())
+}
+
+// Accepts a bare date (start of day) or a full ledger timestamp.
+fn parse_timezone(name: &str) -> Result<Tz, Box<dyn Error>> {
+    Ok(name
+        .parse::<Tz>()
+        .map_err(|_| format!("unknown timezone: {}", name))?)
+}
+
+fn parse_date_or_time(s: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    if let Ok(d) = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d") {
+        return Ok(d.and_time(NaiveTime::MIN));
+    }
+    parse_time(s)
+}
+
+// Shifts naive UTC ledger times onto the taxpayer's wall clock, so the
+// tax-year filter and report timestamps follow local dates. Each row keeps
+// the offset it was shifted by.
+fn localize_entries(entries: &mut [LedgerEntry], tz: Tz) {
+    for e in entries {
+        let local = tz.from_utc_datetime(&e.time);
+        e.time = local.naive_local();
+        e.utc_offset = Some(local.offset().fix());
+    }
+}
+
+// A wall-clock time at `offset`, UTC when there is none.
+fn stamp(time: NaiveDateTime, offset: Option<FixedOffset>) -> DateTime<FixedOffset> {
+    let offset = offset.unwrap_or(FixedOffset::east_opt(0).expect("zero offset"));
+    DateTime::from_naive_utc_and_offset(time - offset, offset)
+}
+
+// A wall-clock time that no ledger row carries the offset of: a date from
+// the command line or a file, or a lot or pool's acquisition time. In the
+// repeated hour when clocks fall back it takes the first (daylight) offset,
+// and in the hour skipped when they spring forward the one before the jump.
+fn wall_clock(time: NaiveDateTime, tz: Option<Tz>) -> DateTime<FixedOffset> {
+    let Some(tz) = tz else {
+        return stamp(time, None);
+    };
+    let offset = match tz.from_local_datetime(&time) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.offset().fix(),
+        LocalResult::None => tz
+            .offset_from_utc_datetime(&(time - chrono::Duration::days(1)))
+            .fix(),
+    };
+    stamp(time, Some(offset))
+}
+
+fn report_time(time: DateTime<FixedOffset>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.f%:z").to_string()
+}
+
+fn q2(x: Decimal) -> Decimal {
+    x.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+// CAD amount for summary tables: always two decimals, and no `-0.00`.
+fn cad(x: Decimal) -> String {
+    let x = q2(x);
+    if x.is_zero() {
+        "0.00".to_string()
+    } else {
+        format!("{:.2}", x)
+    }
+}
+
+fn q8(x: Decimal) -> Decimal {
+    x.round_dp_with_strategy(8, RoundingStrategy::MidpointAwayFromZero)
+}
+
+// How report amounts are rounded: `--rounding-strategy` and
+// `--decimal-places`. Rows are built unrounded and rounded once, at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rounding {
+    strategy: RoundingStrategy,
+    // Proceeds, gains, income and fees.
+    proceeds_dp: u32,
+    // ACB disposed, added and left in the pool.
+    acb_dp: u32,
+    units_dp: u32,
+    // `--no-rounding`: every amount is written at full precision.
+    full_precision: bool,
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Rounding {
+            strategy: RoundingStrategy::MidpointAwayFromZero,
+            proceeds_dp: 2,
+            acb_dp: 2,
+            units_dp: 8,
+            full_precision: false,
+        }
+    }
+}
+
+impl Rounding {
+    fn parse_strategy(s: &str) -> Result<RoundingStrategy, Box<dyn Error>> {
+        match s.trim().to_lowercase().as_str() {
+            "half-up" => Ok(RoundingStrategy::MidpointAwayFromZero),
+            "half-even" => Ok(RoundingStrategy::MidpointNearestEven),
+            "half-down" => Ok(RoundingStrategy::MidpointTowardZero),
+            "up" => Ok(RoundingStrategy::AwayFromZero),
+            "down" => Ok(RoundingStrategy::ToZero),
+            other => Err(format!("unknown rounding strategy: {}", other).into()),
+        }
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        match self.strategy {
+            RoundingStrategy::MidpointNearestEven => "half-even",
+            RoundingStrategy::MidpointTowardZero => "half-down",
+            RoundingStrategy::AwayFromZero => "up",
+            RoundingStrategy::ToZero => "down",
+            _ => "half-up",
+        }
+    }
+
+    // `proceeds=4,acb=4,units=8`; columns left out keep their places.
+    fn set_places(&mut self, s: &str) -> Result<(), Box<dyn Error>> {
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (column, places) = part.split_once('=').ok_or_else(|| {
+                format!(
+                    "--decimal-places expects COLUMN=PLACES, such as acb=4, got {:?}",
+                    part
+                )
+            })?;
+            let places: u32 = places
+                .trim()
+                .parse()
+                .ok()
+                .filter(|p| *p <= 18)
+                .ok_or_else(|| format!("--decimal-places: bad places for {}", column.trim()))?;
+            match column.trim().to_lowercase().as_str() {
+                "proceeds" => self.proceeds_dp = places,
+                "acb" => self.acb_dp = places,
+                "units" => self.units_dp = places,
+                other => {
+                    return Err(format!(
+                        "--decimal-places: unknown column {} (proceeds, acb or units)",
+                        other
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn round_field(&self, field: &mut String, dp: u32) -> Result<(), Box<dyn Error>> {
+        if !self.full_precision && !field.is_empty() {
+            let x = parse_decimal(field)?;
+            *field = x.round_dp_with_strategy(dp, self.strategy).to_string();
+        }
+        Ok(())
+    }
+
+    fn round_row(&self, rr: &mut ReportRow) -> Result<(), Box<dyn Error>> {
+        let places = [
+            (&mut rr.proceeds_cad, self.proceeds_dp),
+            (&mut rr.gain_cad, self.proceeds_dp),
+            (&mut rr.income_cad, self.proceeds_dp),
+            (&mut rr.fee_cad, self.proceeds_dp),
+            (&mut rr.acb_disposed_cad, self.acb_dp),
+            (&mut rr.acb_added_cad, self.acb_dp),
+            (&mut rr.pool_acb_cad_after, self.acb_dp),
+            (&mut rr.units_in, self.units_dp),
+            (&mut rr.units_out, self.units_dp),
+            (&mut rr.pool_units_after, self.units_dp),
+        ];
+        for (field, dp) in places {
+            self.round_field(field, dp)?;
+        }
+        Ok(())
+    }
+}
+
+fn load_entries(
+    text: &str,
+    locale: CsvLocale,
+    interner: &mut Interner,
+) -> Result<Vec<LedgerEntry>, Box<dyn Error>> {
+    let (delimiter, decimal_comma) = locale.resolve(text);
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+    let mut out = Vec::new();
+
+    for row in rdr.deserialize::<LedgerRow>() {
+        let row = row?;
+        let amount = parse_amount(&row.amount, decimal_comma)?;
+        let fee = parse_amount(&row.fee, decimal_comma)?;
+        let balance = match row.balance.trim() {
+            "" => None,
+            b => Some(parse_amount(b, decimal_comma)?),
+        };
+        out.push(LedgerEntry {
+            txid: row.txid.into(),
+            refid: interner.intern(&row.refid),
+            time: parse_time(&row.time)?,
+            row_type: row.row_type.trim().to_lowercase(),
+            subtype: row.subtype.trim().to_lowercase(),
+            asset: interner.intern(&row.asset.trim().to_uppercase()),
+            amount,
+            fee,
+            net_delta: amount - fee,
+            balance,
+            wallet: None,
+            utc_offset: None,
+        });
+    }
+
+    sort_entries(&mut out);
+    Ok(out)
+}
+
+// In the order the rows happened, which after `--timezone` is not always
+// their wall-clock order.
+fn sort_entries(entries: &mut [LedgerEntry]) {
+    entries.sort_by(|a, b| {
+        a.stamp()
+            .cmp(&b.stamp())
+            .then(a.refid.cmp(&b.refid))
+            .then(a.txid.cmp(&b.txid))
+            .then(a.asset.cmp(&b.asset))
+    });
+}
+
+// Spot trades, instant buy/sell and converts all land as `trade` rows, with
+// `tradespot`, empty, or flow-specific subtypes.
+// NFT purchases and sales (`nfttrade`) pair the NFT (one unit of a unique
+// asset identifier) with the payment leg the same way.
+// Pre-2023 exports book staking rewards as `staking` rows.
+fn is_reward_row(e: &LedgerEntry) -> bool {
+    (e.row_type == "earn" && e.subtype == "reward") || e.row_type == "staking"
+}
+
+fn is_trade_row(e: &LedgerEntry) -> bool {
+    e.row_type == "trade" || e.row_type == "nfttrade"
+}
+
+fn is_nft_row(e: &LedgerEntry) -> bool {
+    e.row_type.starts_with("nft")
+}
+
+// Instant buys can also be booked as a `spend` (what was paid) plus a
+// `receive` (what was bought) sharing a refid.
+fn is_spend_receive_row(e: &LedgerEntry) -> bool {
+    e.row_type == "spend" || e.row_type == "receive"
+}
+
+fn load_migrations(path: &str) -> Result<Vec<Migration>, Box<dyn Error>> {
+    let f = File::open(path)?;
+    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(f);
+    let mut out = Vec::new();
+
+    for row in rdr.deserialize::<MigrationRow>() {
+        let row = row?;
+        let factor = if row.factor.trim().is_empty() {
+            dec!(1)
+        } else {
+            parse_decimal(&row.factor)?
+        };
+        if factor <= dec!(0) {
+            return Err(
+                format!("migration {}->{} factor must be positive", row.from, row.to).into(),
+            );
+        }
+        out.push(Migration {
+            from: row.from.trim().to_uppercase().into(),
+            to: row.to.trim().to_uppercase().into(),
+            time: parse_date_or_time(&row.date)?,
+            factor,
+        });
+    }
+
+    Ok(out)
+}
+
+fn load_wraps(path: &str) -> Result<Vec<Wrap>, Box<dyn Error>> {
+    let f = File::open(path)?;
+    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(f);
+    let mut out: Vec<Wrap> = Vec::new();
+    for row in rdr.deserialize::<WrapRow>() {
+        let row = row?;
+        let wrap = Wrap {
+            wrapped: row.wrapped.trim().to_uppercase().into(),
+            underlying: row
+                .underlying
+                .split('+')
+                .map(|a| Arc::from(a.trim().to_uppercase()))
+                .collect(),
+            policy: WrapPolicy::parse(&row.policy)?,
+        };
+        if wrap
+            .underlying
+            .iter()
+            .any(|a| a.is_empty() || *a == wrap.wrapped)
+            || &*wrap.wrapped == "CAD"
+            || wrap.underlying.iter().any(|a| &**a == "CAD")
+        {
+            return Err(format!("wrap {}->{}: not a wrap", wrap.wrapped, row.underlying).into());
+        }
+        if wrap.underlying.len() > 1 && wrap.policy != WrapPolicy::Separate {
+            return Err(format!(
+                "wrap {}: only the separate policy takes several underlying assets",
+                wrap.wrapped
+            )
+            .into());
+        }
+        if out.iter().any(|w| w.wrapped == wrap.wrapped) {
+            return Err(format!("wrap {} is listed twice", wrap.wrapped).into());
+        }
+        out.push(wrap);
+    }
+    Ok(out)
+}
+
+// Rules file format, one per line: `type/subtype = behavior`, where subtype
+// may be empty or `*`; `#` starts a comment.
+fn parse_classification_rules(text: &str) -> Result<Vec<ClassificationRule>, Box<dyn Error>> {
+    let mut rules = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, behavior) = line.split_once('=').ok_or_else(|| {
+            format!(
+                "classification rule line {}: expected key = behavior",
+                i + 1
+            )
+        })?;
+        let (row_type, subtype) = key.trim().split_once('/').unwrap_or((key.trim(), ""));
+        let subtype = subtype.trim().to_lowercase();
+        rules.push(ClassificationRule {
+            row_type: row_type.trim().to_lowercase(),
+            subtype: if subtype == "*" { None } else { Some(subtype) },
+            behavior: RowBehavior::parse(behavior)?,
+        });
+    }
+    Ok(rules)
+}
+
+fn load_classification_rules(path: &str) -> Result<Vec<ClassificationRule>, Box<dyn Error>> {
+    parse_classification_rules(&std::fs::read_to_string(path)?)
+}
+
+// Rewrites matching rows to the type/subtype of their configured behavior;
+// the first matching rule wins.
+fn classify_entries(entries: &mut [LedgerEntry], rules: &[ClassificationRule]) {
+    for e in entries.iter_mut() {
+        let rule = rules.iter().find(|r| {
+            r.row_type == e.row_type && r.subtype.as_ref().is_none_or(|s| *s == e.subtype)
+        });
+        if let Some(rule) = rule {
+            let (row_type, subtype) = rule.behavior.canonical();
+            e.row_type = row_type.to_string();
+            e.subtype = subtype.to_string();
+        }
+    }
+}
+
+// Gifts file format, one per line: `refid = gift|donation`; `#` starts a comment.
+fn parse_gift_refids(text: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut out = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (refid, kind) = line
+            .split_once('=')
+            .ok_or_else(|| format!("gifts line {}: expected refid = gift|donation", i + 1))?;
+        let kind = kind.trim().to_lowercase();
+        if kind != "gift" && kind != "donation" {
+            return Err(format!("gifts line {}: unknown kind {}", i + 1, kind).into());
+        }
+        out.insert(refid.trim().to_string(), kind);
+    }
+    Ok(out)
+}
+
+fn load_gift_refids(path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    parse_gift_refids(&std::fs::read_to_string(path)?)
+}
+
+// Retypes the listed withdrawals (or spends) as `withdrawal/gift` or
+// `withdrawal/donation`.
+fn flag_gifts(
+    entries: &mut [LedgerEntry],
+    gifts: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut found = HashSet::new();
+    for e in entries.iter_mut() {
+        if let Some(kind) = gifts.get(&*e.refid)
+            && (e.row_type == "withdrawal" || e.row_type == "spend")
+        {
+            e.row_type = "withdrawal".to_string();
+            e.subtype = kind.clone();
+            found.insert(e.refid.clone());
+        }
+    }
+    if let Some(missing) = gifts.keys().find(|r| !found.contains(r.as_str())) {
+        return Err(format!("gift refid {} matches no withdrawal", missing).into());
+    }
+    Ok(())
+}
+
+// Lot selections for `--cost-basis spec-id`; every refid listed, disposal or
+// lot, must be in the ledger.
+fn load_lot_selection(
+    path: &str,
+    entries: &[LedgerEntry],
+) -> Result<LotSelections, Box<dyn Error>> {
+    let selections = basis::parse_selections(&std::fs::read_to_string(path)?)?;
+    let refids: HashSet<&str> = entries.iter().map(|e| &*e.refid).collect();
+    let listed = selections
+        .iter()
+        .flat_map(|(refid, lots)| std::iter::once(refid).chain(lots));
+    for refid in listed {
+        if !refids.contains(&**refid) {
+            return Err(format!("lot selection refid {} matches no ledger row", refid).into());
+        }
+    }
+    Ok(selections)
+}
+
+// Exclusions file format: one refid per line; `#` starts a comment.
+fn parse_excluded_refids(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn load_excluded_refids(path: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+    Ok(parse_excluded_refids(&std::fs::read_to_string(path)?))
+}
+
+// Overrides CSV row: `txid,amount,fee`, where an empty amount or fee keeps
+// the ledger's value.
+#[derive(Debug, Deserialize)]
+struct OverrideRow {
+    txid: String,
+    #[serde(default)]
+    amount: String,
+    #[serde(default)]
+    fee: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RowOverride {
+    amount: Option<Decimal>,
+    fee: Option<Decimal>,
+}
+
+fn load_overrides(path: &str) -> Result<HashMap<String, RowOverride>, Box<dyn Error>> {
+    let f = File::open(path)?;
+    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(f);
+    let optional = |s: &str| -> Result<Option<Decimal>, Box<dyn Error>> {
+        if s.trim().is_empty() {
+            Ok(None)
+        } else {
+            parse_decimal(s).map(Some)
+        }
+    };
+    let mut out = HashMap::new();
+    for row in rdr.deserialize::<OverrideRow>() {
+        let row = row?;
+        let txid = row.txid.trim().to_string();
+        let o = RowOverride {
+            amount: optional(&row.amount)?,
+            fee: optional(&row.fee)?,
+        };
+        if out.insert(txid.clone(), o).is_some() {
+            return Err(format!("{}: txid {} is overridden twice", path, txid).into());
+        }
+    }
+    Ok(out)
+}
+
+// A ledger row dropped by `--exclude-refids` or changed by `--overrides`,
+// listed with the warnings so no edit goes unnoticed.
+#[derive(Debug, PartialEq)]
+struct LedgerEdit {
+    time: NaiveDateTime,
+    refid: Arc<str>,
+    txid: Arc<str>,
+    asset: Arc<str>,
+    note: String,
+}
+
+impl LedgerEdit {
+    fn new(e: &LedgerEntry, note: String) -> Self {
+        LedgerEdit {
+            time: e.time,
+            refid: e.refid.clone(),
+            txid: e.txid.clone(),
+            asset: e.asset.clone(),
+            note,
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} row {} (refid {}) at {} {}",
+            self.asset, self.txid, self.refid, self.time, self.note
+        )
+    }
+}
+
+// Drops every row of the excluded refids and applies the overrides. Each
+// listed refid and txid must exist, so typos don't pass silently.
+fn apply_ledger_edits(
+    entries: &mut Vec<LedgerEntry>,
+    excluded: &HashSet<String>,
+    overrides: &HashMap<String, RowOverride>,
+) -> Result<Vec<LedgerEdit>, Box<dyn Error>> {
+    let mut edits = Vec::new();
+    let mut found = HashSet::new();
+    entries.retain(|e| {
+        if !excluded.contains(&*e.refid) {
+            return true;
+        }
+        found.insert(e.refid.clone());
+        edits.push(LedgerEdit::new(
+            e,
+            format!("excluded (amount {}, fee {})", e.amount, e.fee),
+        ));
+        false
+    });
+    if let Some(missing) = excluded.iter().find(|r| !found.contains(r.as_str())) {
+        return Err(format!("excluded refid {} matches no ledger row", missing).into());
+    }
+
+    let mut found = HashSet::new();
+    for e in entries.iter_mut() {
+        let Some(o) = overrides.get(&*e.txid) else {
+            continue;
+        };
+        found.insert(e.txid.clone());
+        let amount = o.amount.unwrap_or(e.amount);
+        let fee = o.fee.unwrap_or(e.fee);
+        edits.push(LedgerEdit::new(
+            e,
+            format!(
+                "overridden: amount {} -> {}, fee {} -> {}",
+                e.amount, amount, e.fee, fee
+            ),
+        ));
+        e.amount = amount;
+        e.fee = fee;
+        e.net_delta = amount - fee;
+    }
+    if let Some(missing) = overrides.keys().find(|t| !found.contains(t.as_str())) {
+        return Err(format!("override txid {} matches no ledger row", missing).into());
+    }
+    Ok(edits)
+}
+
+const TRANSFER_MATCH_BEFORE_HOURS: i64 = 1;
+const TRANSFER_MATCH_AFTER_HOURS: i64 = 72;
+
+// Pairs each withdrawal with the first later deposit of the same asset and
+// amount (from any input) and retypes both as `transfer/out` / `transfer/in`.
+// Returns the number of pairs matched.
+fn match_transfers(entries: &mut [LedgerEntry]) -> usize {
+    let mut matched = 0;
+    for w in 0..entries.len() {
+        if entries[w].row_type != "withdrawal"
+            || !entries[w].subtype.is_empty()
+            || is_fiat(&entries[w].asset)
+        {
+            continue;
+        }
+        let earliest = entries[w].time - chrono::Duration::hours(TRANSFER_MATCH_BEFORE_HOURS);
+        let latest = entries[w].time + chrono::Duration::hours(TRANSFER_MATCH_AFTER_HOURS);
+        let found = entries.iter().position(|d| {
+            d.row_type == "deposit"
+                && d.subtype.is_empty()
+                && d.asset == entries[w].asset
+                && d.amount == -entries[w].amount
+                && d.time >= earliest
+                && d.time <= latest
+        });
+        if let Some(d) = found {
+            entries[w].row_type = "transfer".to_string();
+            entries[w].subtype = "out".to_string();
+            entries[d].row_type = "transfer".to_string();
+            entries[d].subtype = "in".to_string();
+            matched += 1;
+        }
+    }
+    matched
+}
+
+// The wallet each matched `transfer/out` row went to, by txid, pairing them
+// with `transfer/in` rows as `match_transfers` did. Only transfers between
+// two different wallets are kept.
+fn wallet_transfers(entries: &[LedgerEntry]) -> HashMap<Arc<str>, Arc<str>> {
+    let mut used = vec![false; entries.len()];
+    let mut out = HashMap::new();
+    for w in entries
+        .iter()
+        .filter(|e| e.row_type == "transfer" && e.subtype == "out")
+    {
+        let earliest = w.time - chrono::Duration::hours(TRANSFER_MATCH_BEFORE_HOURS);
+        let latest = w.time + chrono::Duration::hours(TRANSFER_MATCH_AFTER_HOURS);
+        let found = entries.iter().enumerate().position(|(i, d)| {
+            !used[i]
+                && d.row_type == "transfer"
+                && d.subtype == "in"
+                && d.asset == w.asset
+                && d.amount == -w.amount
+                && d.time >= earliest
+                && d.time <= latest
+        });
+        if let Some(d) = found {
+            used[d] = true;
+            if let (Some(from), Some(to)) = (&w.wallet, &entries[d].wallet)
+                && from != to
+            {
+                out.insert(w.txid.clone(), to.clone());
+            }
+        }
+    }
+    out
+}
+
+// A trade leg that only carries a fee, e.g. one charged in a third asset.
+fn is_fee_only_leg(e: &LedgerEntry) -> bool {
+    e.amount.is_zero() && e.fee > dec!(0)
+}
+
+// Explains a trade group that cannot be paired: its rows, the likely cause
+// and how to fix it. One line, like every other error.
+fn trade_group_diagnostic(rows: &[LedgerEntry]) -> String {
+    let listed: Vec<String> = rows
+        .iter()
+        .map(|e| {
+            format!(
+                "{} {} {} amount {} fee {}",
+                e.txid, e.time, e.asset, e.amount, e.fee
+            )
+        })
+        .collect();
+    let assets: HashSet<&str> = rows.iter().map(|e| e.asset.as_ref()).collect();
+    let (cause, fix) = if rows.iter().any(is_fee_only_leg) {
+        (
+            "a fee-only leg",
+            "--repair-trades books fee-only legs as zero-proceeds fee disposals",
+        )
+    } else if rows.len() == 1 {
+        (
+            "the other leg is missing, usually because the export's date range cuts \
+             through this trade or the leg is in a file that was not loaded",
+            "re-export a range covering this time, add the missing leg with --manual, \
+             or drop the refid with --exclude-refids",
+        )
+    } else if rows.len() > 2 && assets.len() == 2 {
+        (
+            "a fill split into several rows of the same asset",
+            "--repair-trades merges the rows of each asset",
+        )
+    } else if rows.len() > 2 {
+        (
+            "rows of more than two assets share this refid",
+            "drop the refid with --exclude-refids and record the trade with --manual",
+        )
+    } else {
+        (
+            "the legs were booked at different times",
+            "--repair-trades moves every leg to the earliest time",
+        )
+    };
+    format!(
+        "rows [{}]; likely cause: {}; fix: {}",
+        listed.join("; "),
+        cause,
+        fix
+    )
+}
+
+// `--repair-trades`: rewrites malformed trade groups the engine would reject.
+// Fee-only legs become zero-proceeds `adjustment/tradefee` rows, rows of one
+// asset within a trade are merged, and legs booked at different times move to
+// the earliest. Orphan legs are left alone, since the missing side can't be
+// inferred. Returns every change for the warnings output.
+pub(crate) fn repair_trade_groups(entries: &mut Vec<LedgerEntry>) -> Vec<LedgerEdit> {
+    let mut edits = Vec::new();
+    let mut groups: BTreeMap<Arc<str>, Vec<usize>> = BTreeMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if is_trade_row(e) {
+            groups.entry(e.refid.clone()).or_default().push(i);
+        }
+    }
+    let mut merged = HashSet::new();
+    for rows in groups.values() {
+        if rows.len() == 2 && entries[rows[0]].time == entries[rows[1]].time {
+            continue;
+        }
+        let mut legs = Vec::new();
+        for &i in rows {
+            let e = &mut entries[i];
+            if is_fee_only_leg(e) {
+                e.row_type = "adjustment".to_string();
+                e.subtype = "tradefee".to_string();
+                edits.push(LedgerEdit::new(
+                    e,
+                    "repaired: fee-only trade leg booked as a zero-proceeds fee disposal"
+                        .to_string(),
+                ));
+            } else {
+                legs.push(i);
+            }
+        }
+        legs.sort_by(|&a, &b| entries[a].txid.cmp(&entries[b].txid));
+        let mut kept: Vec<usize> = Vec::new();
+        for i in legs {
+            let Some(&k) = kept.iter().find(|&&k| entries[k].asset == entries[i].asset) else {
+                kept.push(i);
+                continue;
+            };
+            let (amount, fee) = (entries[i].amount, entries[i].fee);
+            let time = entries[i].time;
+            let target = &mut entries[k];
+            target.amount += amount;
+            target.fee += fee;
+            target.net_delta = target.amount - target.fee;
+            target.time = target.time.min(time);
+            target.balance = None;
+            edits.push(LedgerEdit::new(
+                &entries[i],
+                format!("repaired: merged into {}", entries[k].txid),
+            ));
+            merged.insert(i);
+        }
+        if let Some(earliest) = kept.iter().map(|&k| entries[k].time).min() {
+            for &k in &kept {
+                let e = &mut entries[k];
+                if e.time != earliest {
+                    edits.push(LedgerEdit::new(
+                        e,
+                        format!("repaired: time moved to {} to match its trade", earliest),
+                    ));
+                    e.time = earliest;
+                }
+            }
+        }
+    }
+    let mut i = 0;
+    entries.retain(|_| {
+        i += 1;
+        !merged.contains(&(i - 1))
+    });
+    sort_entries(entries);
+    edits
+}
+
+// Takes the legs of conversions into or out of receipt tokens under the
+// separate wrap policy out of `entries`: a refid whose trade, deposit and
+// withdrawal rows are the token on one side and only its underlying assets on
+// the other, as the on-chain import books a liquidity deposit.
+fn split_conversions(entries: &mut Vec<LedgerEntry>, wraps: &[Wrap]) -> Vec<TradeGroup> {
+    let receipts: Vec<&Wrap> = wraps
+        .iter()
+        .filter(|w| w.policy == WrapPolicy::Separate)
+        .collect();
+    if receipts.is_empty() {
+        return Vec::new();
+    }
+    let mut legs: BTreeMap<Arc<str>, Vec<usize>> = BTreeMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if matches!(e.row_type.as_str(), "trade" | "deposit" | "withdrawal") {
+            legs.entry(e.refid.clone()).or_default().push(i);
+        }
+    }
+    let mut taken = HashSet::new();
+    let mut out = Vec::new();
+    for (refid, idx) in legs {
+        let rows: Vec<&LedgerEntry> = idx.iter().map(|i| &entries[*i]).collect();
+        let converts = |w: &&Wrap| {
+            let token: Vec<&&LedgerEntry> = rows.iter().filter(|e| e.asset == w.wrapped).collect();
+            let [token] = token[..] else {
+                return false;
+            };
+            rows.len() > 1
+                && !token.net_delta.is_zero()
+                && rows.iter().all(|e| {
+                    e.asset == w.wrapped
+                        || (w.underlying.contains(&e.asset)
+                            && e.net_delta.is_sign_negative() != token.net_delta.is_sign_negative())
+                })
+        };
+        if !receipts.iter().any(converts) {
+            continue;
+        }
+        let mut rows: Vec<LedgerEntry> = rows.into_iter().cloned().collect();
+        rows.sort_by(|a, b| a.txid.cmp(&b.txid).then(a.asset.cmp(&b.asset)));
+        out.push(TradeGroup {
+            refid,
+            time: rows.iter().map(|e| e.time).min().unwrap_or_default(),
+            txid: rows[0].txid.clone(),
+            entries: rows,
+        });
+        taken.extend(idx);
+    }
+    let mut i = 0;
+    entries.retain(|_| {
+        i += 1;
+        !taken.contains(&(i - 1))
+    });
+    out
+}
+
+fn build_trade_groups(
+    entries: &[LedgerEntry],
+    jurisdiction: &dyn Jurisdiction,
+    tax_year: i32,
+) -> Result<HashMap<Arc<str>, TradeGroup>, Box<dyn Error>> {
+    let mut tmp: HashMap<Arc<str>, Vec<LedgerEntry>> = HashMap::new();
+    let mut spend_receive: HashMap<Arc<str>, Vec<LedgerEntry>> = HashMap::new();
+    for e in entries {
+        if jurisdiction.tax_year(e.time) > tax_year {
+            continue;
+        }
+        if is_trade_row(e) {
+            tmp.entry(e.refid.clone()).or_default().push(e.clone());
+        } else if is_spend_receive_row(e) {
+            spend_receive
+                .entry(e.refid.clone())
+                .or_default()
+                .push(e.clone());
+        }
+    }
+
+    let mut groups = HashMap::new();
+    for (refid, mut rows) in spend_receive {
+        // Unpaired spend/receive rows are left for the entry handlers.
+        if rows.len() != 2 || rows[0].row_type == rows[1].row_type {
+            continue;
+        }
+        rows.sort_by(|a, b| a.txid.cmp(&b.txid).then(a.asset.cmp(&b.asset)));
+        let time = rows[0].time.min(rows[1].time);
+        groups.insert(
+            refid.clone(),
+            TradeGroup {
+                refid,
+                time,
+                txid: rows[0].txid.clone(),
+                entries: rows,
+            },
+        );
+    }
+
+    for (refid, mut rows) in tmp {
+        rows.sort_by(|a, b| a.txid.cmp(&b.txid).then(a.asset.cmp(&b.asset)));
+        if rows.len() != 2 {
+            return Err(format!(
+                "trade refid {} expected 2 rows, got {}: {}",
+                refid,
+                rows.len(),
+                trade_group_diagnostic(&rows)
+            )
+            .into());
+        }
+        let time = rows[0].time;
+        if rows[1].time != time {
+            return Err(format!(
+                "trade refid {} has mismatched times: {}",
+                refid,
+                trade_group_diagnostic(&rows)
+            )
+            .into());
+        }
+        groups.insert(
+            refid.clone(),
+            TradeGroup {
+                refid,
+                time,
+                txid: rows[0].txid.clone(),
+                entries: rows,
+            },
+        );
+    }
+
+    Ok(groups)
+}
+
+// The events through the tax year, in processing order: by the instant they
+// happened (local times repeat when clocks fall back), then
+// `opts.tie_break`, then kind and ids.
+fn build_events(
+    entries: &[LedgerEntry],
+    trade_groups: &HashMap<Arc<str>, TradeGroup>,
+    conversions: &[TradeGroup],
+    opts: &ProcessOptions,
+) -> Vec<Event> {
+    let (jurisdiction, tax_year) = (&*opts.jurisdiction, opts.tax_year);
+    let mut events = Vec::new();
+    let mut emitted_trade = HashSet::new();
+
+    for e in entries {
+        if jurisdiction.tax_year(e.time) > tax_year {
+            continue;
+        }
+        if (is_trade_row(e) || is_spend_receive_row(e)) && trade_groups.contains_key(&e.refid) {
+            if emitted_trade.insert(e.refid.clone())
+                && let Some(g) = trade_groups.get(&e.refid)
+            {
+                events.push(Event::Trade(g.clone()));
+            }
+        } else {
+            events.push(Event::Entry(e.clone()));
+        }
+    }
+    for g in conversions {
+        if jurisdiction.tax_year(g.time) <= tax_year {
+            events.push(Event::Conversion(g.clone()));
+        }
+    }
+    for m in &opts.migrations {
+        if jurisdiction.tax_year(m.time) <= tax_year {
+            events.push(Event::Migration(m.clone()));
+        }
+    }
+    if let Some(t) = opts.deemed_disposition
+        && jurisdiction.tax_year(t) <= tax_year
+    {
+        events.push(Event::DeemedDisposition(t));
+    }
+
+    events.sort_by(|a, b| {
+        let (_, ka, sa) = event_sort_keys(a);
+        let (_, kb, sb) = event_sort_keys(b);
+        let instant = |e| event_instant(e, opts.timezone);
+        instant(a)
+            .cmp(&instant(b))
+            .then(opts.tie_break.rank(a).cmp(&opts.tie_break.rank(b)))
+            .then(ka.cmp(&kb))
+            .then(sa.cmp(&sb))
+    });
+
+    events
+}
+
+// SHA-256 of the events' times and sort keys in processing order: equal
+// hashes mean two runs booked the same events in the same order.
+fn event_sequence_hash(events: &[Event]) -> String {
+    let mut hasher = Sha256::new();
+    for e in events {
+        let (time, kind, key) = event_sort_keys(e);
+        hasher.update(format!("{}|{}|{}\n", time, kind, key).as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn event_sort_keys(e: &Event) -> (NaiveDateTime, i32, String) {
+    match e {
+        Event::Trade(t) | Event::Conversion(t) => (t.time, 0, format!("{}:{}", t.refid, t.txid)),
+        Event::Entry(x) => (x.time, 1, format!("{}:{}:{}", x.refid, x.txid, x.asset)),
+        Event::Migration(m) => (m.time, 2, format!("{}:{}", m.from, m.to)),
+        Event::DeemedDisposition(t) => (*t, 3, String::new()),
+    }
+}
+
+// When `e` happened, in UTC.
+fn event_instant(e: &Event, tz: Option<Tz>) -> NaiveDateTime {
+    match e {
+        Event::Trade(t) | Event::Conversion(t) => t.stamp().naive_utc(),
+        Event::Entry(x) => x.stamp().naive_utc(),
+        Event::Migration(m) => wall_clock(m.time, tz).naive_utc(),
+        Event::DeemedDisposition(t) => wall_clock(*t, tz).naive_utc(),
+    }
+}
+
+fn set_exempt(rr: &mut ReportRow, exempt_cad: Decimal) {
+    if let Some(booked) = &mut rr.booked {
+        booked.exempt_cad = Some(exempt_cad);
+    }
+}
+
+// Tags the foreign exchange dispositions the year's net exemption covers:
+// all of them when the net is within it, otherwise `exempt_cad` of the gains
+// (or losses) on the net's side, in report order.
+fn exempt_fx_net(report: &mut [ReportRow], net_cad: Decimal, mut exempt_cad: Decimal) {
+    if exempt_cad.is_zero() {
+        return;
+    }
+    let whole = exempt_cad == net_cad;
+    for rr in report.iter_mut().filter(|r| is_foreign_currency(&r.asset)) {
+        let Some(gain) = rr.booked.map(|b| b.gain_cad) else {
+            continue;
+        };
+        if whole {
+            set_exempt(rr, gain);
+        } else if !exempt_cad.is_zero() && gain.is_sign_negative() == exempt_cad.is_sign_negative()
+        {
+            let part = if gain.is_sign_negative() {
+                gain.max(exempt_cad)
+            } else {
+                gain.min(exempt_cad)
+            };
+            set_exempt(rr, part);
+            exempt_cad -= part;
+        }
+    }
+}
+
+fn split_trade_legs(g: &TradeGroup) -> Result<(LedgerEntry, LedgerEntry), Box<dyn Error>> {
+    let a = &g.entries[0];
+    let b = &g.entries[1];
+    let (out, inn) = if a.net_delta < dec!(0) && b.net_delta > dec!(0) {
+        (a.clone(), b.clone())
+    } else if b.net_delta < dec!(0) && a.net_delta > dec!(0) {
+        (b.clone(), a.clone())
+    } else {
+        return Err(format!(
+            "trade refid {} not reducible to one outflow and one inflow",
+            g.refid
+        )
+        .into());
+    };
+
+    if out.net_delta.is_zero() || inn.net_delta.is_zero() {
+        return Err(format!("trade refid {} has zero net leg", g.refid).into());
+    }
+
+    Ok((out, inn))
+}
+
+fn usd_cad_rate(
+    state: &PriceState,
+    prices: prices::Lookup,
+    ctx: &str,
+) -> Result<prices::Rate, Box<dyn Error>> {
+    prices
+        .usd_cad(state)?
+        .ok_or_else(|| format!("missing USD/CAD rate in {}", ctx).into())
+}
+
+fn asset_value_cad(
+    asset: &str,
+    units: Decimal,
+    state: &PriceState,
+    prices: prices::Lookup,
+    ctx: &str,
+) -> Result<Valuation, Box<dyn Error>> {
+    if units.is_zero() {
+        return Ok(Valuation::cad(dec!(0)));
+    }
+
+    if asset == "CAD" {
+        return Ok(Valuation::cad(units));
+    }
+    if asset == "USD" {
+        let rate = usd_cad_rate(state, prices, ctx)?;
+        return Ok(Valuation {
+            cad: units * rate.fx,
+            price_source: PriceSource::Fiat,
+            fx: Some((rate.fx, rate.source)),
+            price_time: rate.time,
+        });
+    }
+    let Some(quote) = prices.quote(asset, state)? else {
+        return Err(format!("missing valuation price for {} in {}", asset, ctx).into());
+    };
+    if !quote.usd {
+        return Ok(Valuation {
+            cad: units * quote.price,
+            price_source: quote.source,
+            fx: quote.fx,
+            price_time: quote.time,
+        });
+    }
+    let rate = usd_cad_rate(state, prices, ctx)?;
+    Ok(Valuation {
+        cad: units * quote.price * rate.fx,
+        price_source: quote.source,
+        fx: Some((rate.fx, rate.source)),
+        price_time: prices::older(quote.time, rate.time),
+    })
+}
+
+#[derive(Debug)]
+struct TradeAmounts {
+    // Valuations backing the acquisition ACB and the disposition proceeds.
+    acquisition_val: Valuation,
+    disposition_val: Valuation,
+    acb_added_cad: Decimal,
+    proceeds_cad: Decimal,
+    acquisition_fee_cad: Decimal,
+    disposition_fee_cad: Decimal,
+    expensed_fee_cad: Decimal,
+    // CAD value of negative fees (maker rebates) included above.
+    rebate_cad: Decimal,
+    // CAD value of the fee on the out and in legs.
+    leg_fees_cad: [Decimal; 2],
+}
+
+const USD_STABLECOINS: &[&str] = &["USDC", "USDT", "DAI", "PYUSD", "USDG"];
+
+fn is_fiat(asset: &str) -> bool {
+    asset == "CAD" || asset == "USD"
+}
+
+// Fiat currencies other than CAD; disposing of them is a foreign exchange
+// gain or loss.
+fn is_foreign_currency(asset: &str) -> bool {
+    matches!(asset, "USD" | "EUR" | "GBP" | "CHF" | "AUD" | "JPY")
+}
+
+// The `--treat-stablecoins-as-fiat` list; only USD stablecoins qualify.
+fn fiat_stablecoins<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<BTreeSet<Arc<str>>, Box<dyn Error>> {
+    let mut out = BTreeSet::new();
+    for name in names.into_iter().map(|n| n.trim().to_uppercase()) {
+        if name.is_empty() {
+            continue;
+        }
+        if !USD_STABLECOINS.contains(&name.as_str()) {
+            return Err(format!(
+                "{} is not a USD stablecoin ({})",
+                name,
+                USD_STABLECOINS.join(", ")
+            )
+            .into());
+        }
+        out.insert(Arc::from(name));
+    }
+    Ok(out)
+}
+
+fn is_yield_asset(asset: &str) -> bool {
+    is_fiat(asset) || USD_STABLECOINS.contains(&asset)
+}
+
+fn is_earn_internal(subtype: &str) -> bool {
+    matches!(subtype, "autoallocation" | "allocation" | "deallocation")
+}
+
+// Values `units` of `leg` in CAD, or falls back to the other leg's value. A
+// fiat leg, or a stablecoin valued as fiat, is used first; so is the other
+// leg when `prefer_other` (it is the quote asset of a trades.csv fill).
+fn leg_value(
+    leg: (&str, Decimal),
+    other: (&str, Decimal),
+    prefer_other: bool,
+    state: &PriceState,
+    prices: prices::Lookup,
+    ctx: &str,
+) -> Result<Valuation, Box<dyn Error>> {
+    let fiat = |asset: &str| is_fiat(asset) || prices.at_par(asset);
+    let (first, second) = if !fiat(leg.0) && (fiat(other.0) || prefer_other) {
+        (other, leg)
+    } else {
+        (leg, other)
+    };
+    if fiat(first.0) {
+        return asset_value_cad(first.0, first.1, state, prices, ctx);
+    }
+    asset_value_cad(first.0, first.1, state, prices, ctx)
+        .or_else(|_| asset_value_cad(second.0, second.1, state, prices, ctx))
+}
+
+// `quote_is_in` says which leg is the quote asset when trades.csv lists the
+// trade; crypto-crypto trades are then valued from the quote leg first.
+fn trade_amounts(
+    out: &LedgerEntry,
+    inn: &LedgerEntry,
+    state: &PriceState,
+    prices: prices::Lookup,
+    fee_mode: FeeMode,
+    quote_is_in: Option<bool>,
+    refid: &str,
+) -> Result<TradeAmounts, Box<dyn Error>> {
+    let out_units = -out.net_delta;
+    let in_units = inn.net_delta;
+    let gross_out_units = -out.amount;
+    let gross_in_units = inn.amount;
+
+    // Gross trade value excluding fees; fees are priced off the same rate.
+    // Crypto-crypto legs without a known price (e.g. an NFT) are valued from
+    // the other leg.
+    let gross = leg_value(
+        (&out.asset, gross_out_units),
+        (&inn.asset, gross_in_units),
+        quote_is_in == Some(true),
+        state,
+        prices,
+        &format!("trade {}", refid),
+    )?;
+    let fee_cad = |fee: Decimal, gross_units: Decimal| {
+        if gross_units.is_zero() {
+            dec!(0)
+        } else {
+            fee * gross.cad / gross_units
+        }
+    };
+    let out_fee_cad = fee_cad(out.fee, gross_out_units);
+    let in_fee_cad = fee_cad(inn.fee, gross_in_units);
+    let total_fee_cad = out_fee_cad + in_fee_cad;
+    // A negative fee is a rebate. Negating a zero fee would give a `-0`,
+    // which the totals would then carry into the metadata file.
+    let rebate = |fee_cad: Decimal| {
+        if fee_cad < dec!(0) { -fee_cad } else { dec!(0) }
+    };
+    let rebate_cad = rebate(out_fee_cad) + rebate(in_fee_cad);
+
+    let mut amounts = TradeAmounts {
+        acquisition_val: gross,
+        disposition_val: gross,
+        acb_added_cad: gross.cad,
+        proceeds_cad: gross.cad,
+        acquisition_fee_cad: dec!(0),
+        disposition_fee_cad: dec!(0),
+        expensed_fee_cad: dec!(0),
+        rebate_cad,
+        leg_fees_cad: [out_fee_cad, in_fee_cad],
+    };
+
+    match fee_mode {
+        FeeMode::Embedded => {
+            let out_val = leg_value(
+                (&out.asset, out_units),
+                (&inn.asset, in_units),
+                quote_is_in == Some(true),
+                state,
+                prices,
+                &format!("trade {} out leg", refid),
+            )?;
+            let in_val = leg_value(
+                (&inn.asset, in_units),
+                (&out.asset, out_units),
+                quote_is_in == Some(false),
+                state,
+                prices,
+                &format!("trade {} in leg", refid),
+            )?;
+
+            amounts.acquisition_val = out_val;
+            amounts.disposition_val = in_val;
+            amounts.acb_added_cad = out_val.cad;
+            amounts.proceeds_cad = in_val.cad;
+            amounts.acquisition_fee_cad = out_fee_cad;
+            amounts.disposition_fee_cad = in_fee_cad;
+        }
+        // Selling into CAD leaves no pool to capitalize into, and buying with
+        // CAD has no proceeds to reduce, so each mode falls back to the other.
+        FeeMode::Acb | FeeMode::Proceeds => {
+            let to_acb = if fee_mode == FeeMode::Acb {
+                &*inn.asset != "CAD"
+            } else {
+                &*out.asset == "CAD"
+            };
+            if to_acb {
+                amounts.acb_added_cad += total_fee_cad;
+                amounts.acquisition_fee_cad = total_fee_cad;
+            } else {
+                amounts.proceeds_cad -= total_fee_cad;
+                amounts.disposition_fee_cad = total_fee_cad;
+            }
+        }
+        FeeMode::Expense => {
+            amounts.expensed_fee_cad = total_fee_cad;
+        }
+    }
+
+    Ok(amounts)
+}
+
+// `units` of `asset` at `date`'s close, when `closes` has one.
+fn close_value_cad(
+    asset: &Arc<str>,
+    units: Decimal,
+    date: NaiveDate,
+    closes: &prices::DailyCloses,
+    state: &PriceState,
+    prices: prices::Lookup,
+) -> Result<Option<Valuation>, Box<dyn Error>> {
+    let Some(close) = closes.get(&(asset.clone(), date)) else {
+        return Ok(None);
+    };
+    let rate = match close.usd {
+        true => Some(usd_cad_rate(
+            state,
+            prices,
+            &format!("daily close of {}", asset),
+        )?),
+        false => None,
+    };
+    Ok(Some(Valuation {
+        cad: units * close.price * rate.map_or(dec!(1), |r| r.fx),
+        price_source: PriceSource::DailyClose,
+        fx: rate.map(|r| (r.fx, r.source)),
+        price_time: rate.and_then(|r| r.time),
+    }))
+}
+
+fn income_value_cad(
+    asset: &str,
+    units: Decimal,
+    state: &PriceState,
+    prices: prices::Lookup,
+    ctx: &str,
+) -> Result<Valuation, Box<dyn Error>> {
+    match asset_value_cad(asset, units, state, prices, ctx) {
+        // Stablecoins without an observed price are valued at USD par.
+        Err(_) if USD_STABLECOINS.contains(&asset) => {
+            asset_value_cad("USD", units, state, prices, ctx)
+        }
+        other => other,
+    }
+}
+
+fn remove_units_at_acb(
+    pool: &mut Pool,
+    units: Decimal,
+    ctx: &str,
+) -> Result<Decimal, Box<dyn Error>> {
+    if units < dec!(0) {
+        return Err(format!("negative removal units in {}", ctx).into());
+    }
+    if units > pool.units {
+        return Err(format!(
+            "insufficient units in {}: remove={}, pool={}",
+            ctx, units, pool.units
+        )
+        .into());
+    }
+
+    let avg = pool.avg_cost_cad_per_unit();
+    let acb = avg * units;
+    pool.units -= units;
+    pool.acb_cad -= acb;
+    if pool.units.is_zero() {
+        pool.acb_cad = dec!(0);
+    }
+    Ok(acb)
+}
+
+// `units` are the legs' pre-fee (out, in) units from trades.csv, when known;
+// otherwise the price is implied by the ledger's net amounts.
+fn update_prices_from_trade(
+    out: &LedgerEntry,
+    inn: &LedgerEntry,
+    units: Option<(Decimal, Decimal)>,
+    state: &mut PriceState,
+    prices: prices::Lookup,
+) -> Result<(), Box<dyn Error>> {
+    let (out_units, in_units) = units.unwrap_or((-out.net_delta, inn.net_delta));
+    if out_units <= dec!(0) || in_units <= dec!(0) {
+        return Ok(());
+    }
+
+    if (&*out.asset == "USD" && &*inn.asset == "CAD")
+        || (&*out.asset == "CAD" && &*inn.asset == "USD")
+    {
+        let usd = if &*out.asset == "USD" {
+            out_units
+        } else {
+            in_units
+        };
+        let cad = if &*out.asset == "CAD" {
+            out_units
+        } else {
+            in_units
+        };
+        if usd > dec!(0) {
+            let fx = cad / usd;
+            state.usd_cad_last = Some(fx);
+            state.asset_price_cad.insert("USD".into(), fx);
+            state.observed_at.insert("USD".into(), out.time);
+        }
+    }
+
+    // Only the crypto leg of a trade against USD or CAD gets a new price.
+    let priced = match (is_fiat(&out.asset), is_fiat(&inn.asset)) {
+        (true, false) => Some(&inn.asset),
+        (false, true) => Some(&out.asset),
+        _ => None,
+    };
+    if let Some(asset) = priced {
+        state.observed_at.insert(asset.clone(), out.time);
+    }
+
+    if &*out.asset == "USD" && &*inn.asset != "CAD" {
+        state
+            .asset_price_usd
+            .insert(inn.asset.clone(), out_units / in_units);
+    }
+    if &*inn.asset == "USD" && &*out.asset != "CAD" {
+        state
+            .asset_price_usd
+            .insert(out.asset.clone(), in_units / out_units);
+    }
+
+    if &*out.asset == "CAD" && &*inn.asset != "USD" {
+        state
+            .asset_price_cad
+            .insert(inn.asset.clone(), out_units / in_units);
+        state.asset_price_cad_fx.remove(&inn.asset);
+    }
+    if &*inn.asset == "CAD" && &*out.asset != "USD" {
+        state
+            .asset_price_cad
+            .insert(out.asset.clone(), in_units / out_units);
+        state.asset_price_cad_fx.remove(&out.asset);
+    }
+
+    if state.asset_price_usd.is_empty() {
+        return Ok(());
+    }
+    let Some(rate) = prices.usd_cad(state)? else {
+        return Ok(());
+    };
+    for (asset, p_usd) in state.asset_price_usd.clone() {
+        state.asset_price_cad.insert(asset.clone(), p_usd * rate.fx);
+        state
+            .asset_price_cad_fx
+            .insert(asset, (rate.fx, rate.source));
+    }
+    Ok(())
+}
+
+fn make_row(
+    time: DateTime<FixedOffset>,
+    refid: &Arc<str>,
+    txid: &Arc<str>,
+    event_type: &str,
+    asset: &Arc<str>,
+) -> ReportRow {
+    ReportRow {
+        time: report_time(time),
+        refid: refid.clone(),
+        txid: txid.clone(),
+        event_type: event_type.to_string(),
+        asset: asset.clone(),
+        units_in: String::new(),
+        units_out: String::new(),
+        proceeds_cad: String::new(),
+        acb_disposed_cad: String::new(),
+        gain_cad: String::new(),
+        income_cad: String::new(),
+        acb_added_cad: String::new(),
+        fee_cad: String::new(),
+        pool_units_after: String::new(),
+        pool_acb_cad_after: String::new(),
+        price_source: String::new(),
+        fx_source: String::new(),
+        notes: String::new(),
+        booked: None,
+    }
+}
+
+fn add_note(rr: &mut ReportRow, note: &str) {
+    if !rr.notes.is_empty() {
+        rr.notes.push_str("; ");
+    }
+    rr.notes.push_str(note);
+}
+
+fn set_trade_fee(rr: &mut ReportRow, fee: Decimal, expensed: bool) {
+    if fee.is_zero() {
+        return;
+    }
+    rr.fee_cad = fee.to_string();
+    if expensed {
+        add_note(rr, "Trading fee tracked as expense");
+    }
+    if fee < dec!(0) {
+        add_note(rr, "Maker rebate reduced trading cost");
+    }
+}
+
+// Adds an income receipt to its pool at FMV (CAD is not pooled).
+fn book_income(
+    e: &LedgerEntry,
+    event_type: &str,
+    income_val: &Valuation,
+    book: &mut PoolBook,
+) -> Result<ReportRow, Box<dyn Error>> {
+    let mut rr = make_row(e.stamp(), &e.refid, &e.txid, event_type, &e.asset);
+    rr.units_in = e.net_delta.to_string();
+    rr.income_cad = income_val.cad.to_string();
+    if &*e.asset != "CAD" {
+        let pool = book.apply(
+            PoolMutation::new(
+                e.time,
+                &e.refid,
+                &e.asset,
+                Mutation::Income {
+                    units: e.net_delta,
+                    value_cad: income_val.cad,
+                },
+            ),
+            "",
+        )?;
+        rr.acb_added_cad = income_val.cad.to_string();
+        rr.pool_units_after = pool.units_after.to_string();
+        rr.pool_acb_cad_after = pool.acb_after.to_string();
+    }
+    set_valuation_sources(&mut rr, income_val);
+    Ok(rr)
+}
+
+// Times and refids of the trades and rewards that acquired each asset, in
+// time order.
+fn acquisition_times(entries: &[LedgerEntry]) -> HashMap<Arc<str>, Vec<(NaiveDateTime, Arc<str>)>> {
+    let mut out: HashMap<Arc<str>, Vec<(NaiveDateTime, Arc<str>)>> = HashMap::new();
+    for e in entries {
+        if e.net_delta > dec!(0) && &*e.asset != "CAD" && (is_trade_row(e) || is_reward_row(e)) {
+            out.entry(e.asset.clone())
+                .or_default()
+                .push((e.time, e.refid.clone()));
+        }
+    }
+    out
+}
+
+// Notes a loss when the asset was also acquired within the jurisdiction's
+// loss window around it. Whether the loss is denied also depends on holdings
+// at the window's end and on affiliated persons' accounts, so it is only
+// flagged.
+fn flag_superficial_loss(
+    rr: &mut ReportRow,
+    gain: Decimal,
+    time: NaiveDateTime,
+    acquisitions: &HashMap<Arc<str>, Vec<(NaiveDateTime, Arc<str>)>>,
+    jurisdiction: &dyn Jurisdiction,
+) {
+    let Some(days) = jurisdiction.loss_window_days() else {
+        return;
+    };
+    if gain >= dec!(0) {
+        return;
+    }
+    let window = chrono::Duration::days(days);
+    let bought = acquisitions.get(&rr.asset).and_then(|times| {
+        times
+            .iter()
+            .find(|(t, refid)| *refid != rr.refid && (*t - time).abs() <= window)
+    });
+    if let Some((t, refid)) = bought {
+        let note = format!(
+            "Possible superficial loss: {} acquired on {} (refid {}), within {} days",
+            rr.asset,
+            t.date(),
+            refid,
+            days
+        );
+        add_note(rr, &note);
+    }
+}
+
+// Removes units at ACB for nothing in return; the loss equals the ACB removed.
+fn book_zero_proceeds_disposition(
+    e: &LedgerEntry,
+    event_type: &str,
+    ctx: &str,
+    book: &mut PoolBook,
+) -> Result<(ReportRow, Effect), Box<dyn Error>> {
+    let units = -e.net_delta;
+    let pool = book.apply(
+        PoolMutation::new(e.time, &e.refid, &e.asset, Mutation::Remove { units }),
+        ctx,
+    )?;
+    let acb = pool.cost_cad;
+
+    let mut rr = make_row(e.stamp(), &e.refid, &e.txid, event_type, &e.asset);
+    rr.units_out = units.to_string();
+    rr.set_disposal(dec!(0), acb, -acb);
+    rr.pool_units_after = pool.units_after.to_string();
+    rr.pool_acb_cad_after = pool.acb_after.to_string();
+    Ok((rr, pool))
+}
+
+// Strips negative fees off the legs so rebates can be booked as income.
+fn take_rebates(legs: [&mut LedgerEntry; 2]) -> Vec<(Arc<str>, Decimal)> {
+    let mut rebates = Vec::new();
+    for leg in legs {
+        if leg.fee < dec!(0) {
+            rebates.push((leg.asset.clone(), -leg.fee));
+            leg.fee = dec!(0);
+            leg.net_delta = leg.amount;
+        }
+    }
+    rebates
+}
+
+fn set_valuation_sources(rr: &mut ReportRow, v: &Valuation) {
+    rr.price_source = v.price_source.as_str().to_string();
+    if let Some((_, source)) = v.fx {
+        rr.fx_source = source.as_str().to_string();
+    }
+}
+
+// Whether `v` relied on a price traded more than `--max-price-age` days before
+// `at`; the row is noted, or under `--stale-prices refuse` the run fails.
+fn check_price_age(
+    rr: &mut ReportRow,
+    v: &Valuation,
+    at: NaiveDateTime,
+    opts: &ProcessOptions,
+) -> Result<bool, Box<dyn Error>> {
+    let (Some(max_days), Some(price_time)) = (opts.max_price_age, v.price_time) else {
+        return Ok(false);
+    };
+    let days = (at - price_time).num_days();
+    if days <= i64::from(max_days) {
+        return Ok(false);
+    }
+    let detail = format!(
+        "valued at a price last traded on {}, {} days earlier",
+        price_time.date(),
+        days
+    );
+    if opts.stale_prices == StalePrices::Refuse {
+        return Err(format!(
+            "{} {}: {} (--max-price-age {})",
+            rr.event_type, rr.refid, detail, max_days
+        )
+        .into());
+    }
+    add_note(rr, &format!("Stale price: {}", detail));
+    Ok(true)
+}
+
+// Adds the lot audit rows of one disposition: the lot portions its ACB was
+// taken from or, under average cost, a single row for the pool average. Each
+// portion's share of the proceeds and gain also goes to the totals of its
+// holding period.
+fn record_lineage(
+    lineage: &mut Vec<LineageRow>,
+    totals: &mut Totals,
+    rr: &ReportRow,
+    time: NaiveDateTime,
+    proceeds: Decimal,
+    taken: &Effect,
+    tz: Option<Tz>,
+) -> Result<(), Box<dyn Error>> {
+    let units = parse_decimal(&rr.units_out)?;
+    let no_refid: Arc<str> = Arc::from("");
+    let portions = if taken.lots.is_empty() {
+        vec![(
+            "pool_average",
+            &no_refid,
+            taken.held_since,
+            units,
+            taken.cost_cad,
+        )]
+    } else {
+        taken
+            .lots
+            .iter()
+            .map(|lot| ("lot", &lot.refid, Some(lot.time), lot.units, lot.cost_cad))
+            .collect()
+    };
+    for (basis, refid, acquired, lot_units, acb) in portions {
+        let share = if units.is_zero() {
+            proceeds
+        } else {
+            proceeds * lot_units / units
+        };
+        let gain = share - acb;
+        let period = HoldingPeriod::of(acquired, time);
+        let sums = totals.holding_periods.entry(period).or_default();
+        sums.proceeds_cad += share;
+        sums.acb_disposed_cad += acb;
+        sums.gain_cad += gain;
+        if let Some(acquired) = acquired {
+            let days = Decimal::from((time - acquired).num_seconds()) / dec!(86400);
+            totals.activity.held_days_proceeds += days * share;
+            totals.activity.held_proceeds += share;
+        }
+        lineage.push(LineageRow {
+            disposition_time: rr.time.clone(),
+            disposition_refid: rr.refid.clone(),
+            event_type: rr.event_type.clone(),
+            asset: rr.asset.clone(),
+            basis,
+            acquisition_time: acquired
+                .map(|t| report_time(wall_clock(t, tz)))
+                .unwrap_or_default(),
+            acquisition_refid: refid.clone(),
+            acquisitions: taken
+                .lots
+                .is_empty()
+                .then_some(taken.acquisitions)
+                .flatten(),
+            units: lot_units.to_string(),
+            acb_cad: acb.to_string(),
+            proceeds_cad: share.to_string(),
+            gain_cad: gain.to_string(),
+            holding_days: acquired
+                .map(|a| (time - a).num_days().to_string())
+                .unwrap_or_default(),
+            holding_period: period.as_str(),
+        });
+    }
+    Ok(())
+}
+
+// A fee paid in the tax year, added to `totals` under `category`; `value` is
+// `None` when no price was found.
+fn fee_row(
+    time: DateTime<FixedOffset>,
+    refid: &Arc<str>,
+    category: &'static str,
+    asset: &Arc<str>,
+    units: Decimal,
+    value: Option<Decimal>,
+    totals: &mut FeeTotals,
+) -> FeeRow {
+    match value {
+        Some(cad) => {
+            *match category {
+                "trading" => &mut totals.trading_cad,
+                "withdrawal" => &mut totals.withdrawal_cad,
+                _ => &mut totals.margin_cad,
+            } += cad;
+        }
+        None => totals.unpriced_count += 1,
+    }
+    FeeRow {
+        time: report_time(time),
+        refid: refid.clone(),
+        category,
+        asset: asset.clone(),
+        units: units.to_string(),
+        value_cad: value.map(|v| v.to_string()).unwrap_or_default(),
+    }
+}
+
+fn fx_audit_row(rr: &ReportRow, v: &Valuation) -> Option<FxAuditRow> {
+    let (fx, source) = v.fx?;
+    Some(FxAuditRow {
+        time: rr.time.clone(),
+        refid: rr.refid.clone(),
+        txid: rr.txid.clone(),
+        event_type: rr.event_type.clone(),
+        asset: rr.asset.clone(),
+        value_cad: v.cad.to_string(),
+        usd_cad_fx: fx.to_string(),
+        fx_source: source.as_str().to_string(),
+    })
+}
+
+// The pools other than CAD, by asset, each valued at the price `prices`
+// finds for its asset at the end of the run. Per-wallet pools are priced by
+// the asset before the `@`.
+fn ending_pool_rows(
+    pools: &HashMap<Arc<str>, Pool>,
+    state: &PriceState,
+    prices: prices::Lookup,
+) -> Vec<EndingPoolRow> {
+    let mut rows: Vec<EndingPoolRow> = pools
+        .iter()
+        .filter(|(asset, _)| asset.as_ref() != "CAD")
+        .map(|(asset, p)| {
+            let base = asset.split('@').next().unwrap_or(asset);
+            let price = asset_value_cad(base, dec!(1), state, prices, "ending pools").ok();
+            EndingPoolRow {
+                asset: asset.clone(),
+                units: p.units,
+                acb_cad: p.acb_cad,
+                avg_cost_cad: p.avg_cost_cad_per_unit(),
+                last_price_cad: price.as_ref().map(|v| v.cad),
+                fmv_cad: price.as_ref().map(|v| v.cad * p.units),
+                price_source: price.map_or("", |v| v.price_source.as_str()),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| a.asset.cmp(&b.asset));
+    rows
+}
+
+// Applies each year's net capital loss back up to `carryback_years` years
+// (earliest year first), then forward indefinitely against later gains.
+// `gains` must be in year order.
+fn loss_carryover(gains: &[(i32, Decimal)], carryback_years: i32) -> Vec<LossCarryoverRow> {
+    let mut rows: Vec<LossCarryoverRow> = gains
+        .iter()
+        .map(|(year, gain)| LossCarryoverRow {
+            year: *year,
+            net_gain_cad: *gain,
+            loss_applied_cad: dec!(0),
+            adjusted_gain_cad: *gain,
+            loss_carried_back_cad: dec!(0),
+            carryforward_balance_cad: dec!(0),
+        })
+        .collect();
+    let mut balance = dec!(0);
+
+    for i in 0..rows.len() {
+        if rows[i].net_gain_cad > dec!(0) {
+            let used = balance.min(rows[i].adjusted_gain_cad);
+            rows[i].loss_applied_cad += used;
+            rows[i].adjusted_gain_cad -= used;
+            balance -= used;
+        } else if rows[i].net_gain_cad < dec!(0) {
+            let mut loss = -rows[i].net_gain_cad;
+            let year = rows[i].year;
+            for j in 0..i {
+                if rows[j].year < year - carryback_years || rows[j].adjusted_gain_cad <= dec!(0) {
+                    continue;
+                }
+                let used = loss.min(rows[j].adjusted_gain_cad);
+                rows[j].loss_applied_cad += used;
+                rows[j].adjusted_gain_cad -= used;
+                rows[i].loss_carried_back_cad += used;
+                loss -= used;
+            }
+            balance += loss;
+        }
+        rows[i].carryforward_balance_cad = balance;
+    }
+
+    rows
+}
+
+// `-` in place of a file path reads stdin or writes stdout.
+const STDIO_PATH: &str = "-";
+
+fn open_input(path: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    if path == STDIO_PATH {
+        return Ok(Box::new(std::io::stdin()));
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+// Reads a whole input as UTF-8 text. A UTF-8 byte-order mark is dropped and
+// UTF-16 files (with a BOM, or little-endian without one, as some Excel
+// "Unicode text" exports are) are transcoded.
+fn read_input_text(path: &str) -> Result<String, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    open_input(path)?.read_to_end(&mut bytes)?;
+    decode_text(&bytes).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+fn decode_text(bytes: &[u8]) -> Result<String, String> {
+    let utf16 = |bytes: &[u8], little_endian: bool| {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| {
+                if little_endian {
+                    u16::from_le_bytes([c[0], c[1]])
+                } else {
+                    u16::from_be_bytes([c[0], c[1]])
+                }
+            })
+            .collect();
+        String::from_utf16(&units).map_err(|e| e.to_string())
+    };
+    match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => {
+            String::from_utf8(rest.to_vec()).map_err(|e| e.to_string())
+        }
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, true),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, false),
+        [c, 0, ..] if *c != 0 => utf16(bytes, true),
+        _ => String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string()),
+    }
+}
+
+// CSV dialect overrides from `--delimiter` / `--decimal-comma`; unset fields
+// are detected per file.
+#[derive(Debug, Default, Clone, Copy)]
+struct CsvLocale {
+    delimiter: Option<u8>,
+    decimal_comma: Option<bool>,
+}
+
+impl CsvLocale {
+    // The most frequent of `,`, `;` and tab in the header line is the
+    // delimiter; semicolon files (European Excel) default to decimal commas.
+    fn resolve(self, text: &str) -> (u8, bool) {
+        let header = text.lines().next().unwrap_or("");
+        let delimiter = self.delimiter.unwrap_or_else(|| {
+            [b',', b';', b'\t']
+                .into_iter()
+                .max_by_key(|d| (header.bytes().filter(|b| b == d).count(), *d == b','))
+                .unwrap_or(b',')
+        });
+        let decimal_comma = self.decimal_comma.unwrap_or(delimiter == b';');
+        (delimiter, decimal_comma)
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, Box<dyn Error>> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!("delimiter must be a single character or `tab`: {}", s).into()),
+    }
+}
+
+// With decimal commas, `1.234,5` means 1234.5. Values without a comma are
+// left alone so files mixing point decimals still parse.
+fn parse_amount(s: &str, decimal_comma: bool) -> Result<Decimal, Box<dyn Error>> {
+    if decimal_comma && s.contains(',') {
+        return parse_decimal(&s.replace('.', "").replace(',', "."));
+    }
+    parse_decimal(s)
+}
+
+const IGNORED_SAMPLE_REFIDS: usize = 5;
+
+fn record_ignored(ignored: &mut Vec<IgnoredRowSummary>, e: &LedgerEntry) {
+    let idx = match ignored
+        .iter()
+        .position(|s| s.row_type == e.row_type && s.subtype == e.subtype)
+    {
+        Some(idx) => idx,
+        None => {
+            ignored.push(IgnoredRowSummary {
+                row_type: e.row_type.clone(),
+                subtype: e.subtype.clone(),
+                count: 0,
+                sample_refids: String::new(),
+            });
+            ignored.len() - 1
+        }
+    };
+    let summary = &mut ignored[idx];
+    if summary.count < IGNORED_SAMPLE_REFIDS {
+        if !summary.sample_refids.is_empty() {
+            summary.sample_refids.push(' ');
+        }
+        summary.sample_refids.push_str(&e.refid);
+    }
+    summary.count += 1;
+}
+
+// Replays, per asset, the rows that carry a ledger `balance` and reports where
+// the running total stops matching it. Rows sharing a timestamp are sorted by
+// refid rather than in ledger order, so such a group matches when any of its
+// balances does. After a mismatch the total restarts from the ledger's figure
+// so each gap is reported once.
+fn reconcile_balances(entries: &[LedgerEntry]) -> Vec<BalanceMismatch> {
+    let mut groups: BTreeMap<(NaiveDateTime, &Arc<str>), Vec<&LedgerEntry>> = BTreeMap::new();
+    for e in entries.iter().filter(|e| e.balance.is_some()) {
+        groups.entry((e.time, &e.asset)).or_default().push(e);
+    }
+    let mut running: HashMap<&Arc<str>, Decimal> = HashMap::new();
+    let mut out = Vec::new();
+    for ((time, asset), rows) in groups {
+        let total = running.entry(asset).or_default();
+        *total += rows.iter().map(|e| e.net_delta).sum::<Decimal>();
+        if rows.iter().any(|e| e.balance == Some(*total)) {
+            continue;
+        }
+        let last = rows[rows.len() - 1];
+        let ledger_balance = last.balance.unwrap_or_default();
+        out.push(BalanceMismatch {
+            time: report_time(stamp(time, None)),
+            refid: last.refid.clone(),
+            txid: last.txid.clone(),
+            asset: asset.clone(),
+            ledger_balance,
+            computed_balance: *total,
+            difference: ledger_balance - *total,
+        });
+        *total = ledger_balance;
+    }
+    out
+}
+
+const EXIT_WARNINGS: u8 = 2;
+const EXIT_IGNORED_ROWS: u8 = 3;
+const EXIT_BALANCE_MISMATCH: u8 = 4;
+const EXIT_DATA_QUALITY: u8 = 5;
+
+// Exit status of a completed run; the most serious condition wins.
+fn exit_code(
+    warnings: usize,
+    ignored_rows: usize,
+    balance_mismatches: usize,
+    data_issues: usize,
+) -> u8 {
+    if data_issues > 0 {
+        EXIT_DATA_QUALITY
+    } else if balance_mismatches > 0 {
+        EXIT_BALANCE_MISMATCH
+    } else if ignored_rows > 0 {
+        EXIT_IGNORED_ROWS
+    } else if warnings > 0 {
+        EXIT_WARNINGS
+    } else {
+        0
+    }
+}
+
+fn process(
+    entries: Vec<LedgerEntry>,
+    opts: &ProcessOptions,
+) -> Result<ProcessOutput, Box<dyn Error>> {
+    let tax_year = opts.tax_year;
+    let jurisdiction = &*opts.jurisdiction;
+    // Wrapped tokens under the merge policy are booked as their underlying
+    // asset; the rows renamed are kept by txid, and the ledger asset by refid
+    // for the notes.
+    let mut entries = entries;
+    let mut merged_txids: HashSet<Arc<str>> = HashSet::new();
+    let mut merged_from: HashMap<Arc<str>, Arc<str>> = HashMap::new();
+    for e in entries.iter_mut() {
+        if let Some(w) = opts
+            .wraps
+            .iter()
+            .find(|w| w.policy == WrapPolicy::Merge && w.wrapped == e.asset)
+        {
+            merged_txids.insert(e.txid.clone());
+            merged_from.insert(e.refid.clone(), e.asset.clone());
+            e.asset = w.underlying[0].clone();
+        }
+    }
+    let per_wallet = opts.pooling == Pooling::PerWallet;
+    if per_wallet && (!opts.migrations.is_empty() || opts.deemed_disposition.is_some()) {
+        return Err(
+            "--pooling per-wallet does not support --migrations or --deemed-disposition-date"
+                .into(),
+        );
+    }
+    let wallet_moves = if per_wallet {
+        wallet_transfers(&entries)
+    } else {
+        HashMap::new()
+    };
+    let acquisitions = acquisition_times(&entries);
+    let conversions = split_conversions(&mut entries, &opts.wraps);
+    let trade_groups = build_trade_groups(&entries, jurisdiction, tax_year)?;
+    let events = build_events(&entries, &trade_groups, &conversions, opts);
+    let event_hash = event_sequence_hash(&events);
+
+    let mut pass = Pass {
+        opts,
+        acquisitions: &acquisitions,
+        merged_txids: &merged_txids,
+        merged_from: &merged_from,
+        wallet_moves: &wallet_moves,
+        book: PoolBook::new(opts.start_pools.clone(), opts.cost_basis.clone()),
+        state: opts.start_prices.clone(),
+        report: Vec::new(),
+        fx_audit: Vec::new(),
+        lineage: Vec::new(),
+        fees: Vec::new(),
+        ignored: Vec::new(),
+        totals: Totals::default(),
+        traded: HashSet::new(),
+        no_id: Arc::from(""),
+    };
+    let mut checker = opts
+        .check_invariants
+        .then(|| invariants::Checker::new(&opts.start_pools));
+
+    for ev in events {
+        if let Some(checker) = &mut checker {
+            checker.event(&ev, &pass.book, &pass.report, &pass.totals)?;
+        }
+        pass.event(ev)?;
+    }
+    let Pass {
+        book,
+        state,
+        mut report,
+        mut fx_audit,
+        mut lineage,
+        mut fees,
+        mut ignored,
+        mut totals,
+        traded,
+        ..
+    } = pass;
+
+    if let Some(checker) = checker {
+        checker.finish(&book, &report, &totals)?;
+    }
+
+    for rr in report.iter_mut() {
+        opts.rounding.round_row(rr)?;
+    }
+    // The side reports keep the default places, whatever the report's.
+    let side = Rounding {
+        full_precision: opts.rounding.full_precision,
+        ..Rounding::default()
+    };
+    for r in lineage.iter_mut() {
+        side.round_field(&mut r.units, side.units_dp)?;
+        side.round_field(&mut r.acb_cad, side.acb_dp)?;
+        side.round_field(&mut r.proceeds_cad, side.proceeds_dp)?;
+        side.round_field(&mut r.gain_cad, side.proceeds_dp)?;
+    }
+    for r in fees.iter_mut() {
+        side.round_field(&mut r.units, side.units_dp)?;
+        side.round_field(&mut r.value_cad, side.proceeds_dp)?;
+    }
+    for r in fx_audit.iter_mut() {
+        side.round_field(&mut r.value_cad, side.proceeds_dp)?;
+    }
+
+    let round_trips = match opts.round_trip_window {
+        Some(window) => {
+            let mut groups: Vec<&TradeGroup> = trade_groups
+                .values()
+                .filter(|g| opts.in_year(g.time))
+                .collect();
+            groups.sort_by(|a, b| a.stamp().cmp(&b.stamp()).then(a.refid.cmp(&b.refid)));
+            round_trip::detect(&groups, window)
+        }
+        None => Vec::new(),
+    };
+    let reversed_by: HashMap<&Arc<str>, &Arc<str>> = round_trips
+        .iter()
+        .flat_map(|t| {
+            [
+                (&t.first_refid, &t.second_refid),
+                (&t.second_refid, &t.first_refid),
+            ]
+        })
+        .collect();
+    for rr in report.iter_mut() {
+        if let Some(other) = reversed_by.get(&rr.refid) {
+            let note = format!(
+                "Rapid round trip with refid {}; see the round-trip report",
+                other
+            );
+            add_note(rr, &note);
+        }
+    }
+
+    let activity = &mut totals.activity;
+    activity.assets_traded = traded.len();
+    if !activity.held_proceeds.is_zero() {
+        activity.avg_holding_days = Some(activity.held_days_proceeds / activity.held_proceeds);
+    }
+    for rr in report.iter().filter(|r| !r.gain_cad.is_empty()) {
+        let gain = parse_decimal(&rr.gain_cad)?;
+        let slot = match gain.cmp(&Decimal::ZERO) {
+            std::cmp::Ordering::Greater => &mut activity.largest_gain,
+            std::cmp::Ordering::Less => &mut activity.largest_loss,
+            std::cmp::Ordering::Equal => continue,
+        };
+        if slot.as_ref().is_none_or(|g| gain.abs() > g.gain_cad.abs()) {
+            *slot = Some(GainRef {
+                refid: rr.refid.clone(),
+                asset: rr.asset.clone(),
+                gain_cad: gain,
+            });
+        }
+    }
+
+    for rr in report
+        .iter_mut()
+        .filter(|r| !matches!(r.event_type.as_str(), "wrap" | "unwrap"))
+    {
+        let wrapped = merged_from.get(&rr.refid).filter(|w| {
+            opts.wraps
+                .iter()
+                .any(|x| x.wrapped == **w && x.underlying[0] == rr.asset)
+        });
+        if let Some(wrapped) = wrapped {
+            let note = format!(
+                "Ledger asset {}, held in the {} pool under the merge wrap policy",
+                wrapped, rr.asset
+            );
+            add_note(rr, &note);
+        }
+    }
+
+    let exemption = jurisdiction.fx_exemption_cad();
+    let exempt_note = "exempt, left out of the taxable totals";
+    for rr in report.iter_mut().filter(|r| is_foreign_currency(&r.asset)) {
+        let Some(Booked { gain_cad: gain, .. }) = rr.booked else {
+            continue;
+        };
+        totals.fx_gain_cad += gain;
+        match opts.exemptions.fx_per_disposition {
+            Some(limit) if gain.abs() <= limit => {
+                totals.fx_exempt_cad += gain;
+                set_exempt(rr, gain);
+                let note = format!(
+                    "Foreign exchange gain/loss within {} CAD; {}",
+                    limit, exempt_note
+                );
+                add_note(rr, &note);
+            }
+            Some(_) => {}
+            None if !exemption.is_zero() => {
+                let note = format!(
+                    "Foreign exchange gain/loss; the year's net within {} CAD is exempt",
+                    exemption
+                );
+                add_note(rr, &note);
+            }
+            None => {}
+        }
+    }
+    if opts.exemptions.fx_per_disposition.is_none() {
+        totals.fx_exempt_cad = totals.fx_gain_cad.clamp(-exemption, exemption);
+        exempt_fx_net(&mut report, totals.fx_gain_cad, totals.fx_exempt_cad);
+    }
+    if let Some(limit) = opts.exemptions.spends_under {
+        for rr in report
+            .iter_mut()
+            .filter(|r| r.event_type == "spend_disposition")
+        {
+            let Some(booked) = rr.booked else {
+                continue;
+            };
+            if booked.proceeds_cad <= limit {
+                totals.personal_use_exempt_cad += booked.gain_cad;
+                set_exempt(rr, booked.gain_cad);
+                let note = format!(
+                    "Personal-use spend of {} CAD or less; {}",
+                    limit, exempt_note
+                );
+                add_note(rr, &note);
+            }
+        }
+    }
+    totals.capital_gain_cad -= totals.fx_exempt_cad + totals.personal_use_exempt_cad;
+
+    ignored.sort_by(|a, b| a.row_type.cmp(&b.row_type).then(a.subtype.cmp(&b.subtype)));
+    totals.taxable_capital_gain_cad =
+        totals.capital_gain_cad * jurisdiction.inclusion_rate(tax_year);
+    totals.row_drift = RowDrift::of(&report, &totals, &opts.rounding)?;
+
+    let (pools, mutations) = book.finish();
+    Ok(ProcessOutput {
+        report,
+        fx_audit,
+        lineage,
+        fees,
+        round_trips,
+        ignored,
+        totals,
+        pools,
+        prices: state,
+        mutations,
+        event_hash,
+    })
+}
+
+// Reads the main input and every `--extra-input`/`--manual` file into one
+// time-sorted ledger, with any duplicate or overlap found between them.
+fn load_inputs(
+    args: &Args,
+    interner: &mut Interner,
+) -> Result<(Vec<LedgerEntry>, Vec<DataIssue>), Box<dyn Error>> {
+    let mapping = match &args.mapping {
+        Some(path) => Some(import::load_generic_mapping(path)?),
+        None => None,
+    };
+    let inputs = std::iter::once((args.import_format, &args.input)).chain(
+        args.extra_inputs
+            .iter()
+            .map(|(format, path)| (*format, path)),
+    );
+    let mut files = Vec::new();
+    for (i, (format, path)) in inputs.enumerate() {
+        if path != STDIO_PATH && !PathBuf::from(path).exists() {
+            return Err(format!("CSV not found: {:?}", path).into());
+        }
+        // Generated ids of extra inputs are tagged by position.
+        let tag = if i == 0 { String::new() } else { i.to_string() };
+        let mut rows = import::load_input(
+            format,
+            path,
+            mapping.as_ref(),
+            &tag,
+            args.csv_locale,
+            interner,
+        )?;
+        let wallet = wallet_name(path);
+        for e in &mut rows {
+            e.wallet = Some(wallet.clone());
+            if let Some(to) = args.asset_aliases.get(&e.asset) {
+                e.asset = to.clone();
+            }
+        }
+        files.push((path.as_str(), format, rows));
+    }
+    let (mut entries, issues) = merge_inputs(files);
+    sort_entries(&mut entries);
+    Ok((entries, issues))
+}
+
+// An input's wallet under `--pooling per-wallet`: its file name without the
+// extension.
+fn wallet_name(path: &str) -> Arc<str> {
+    if path == STDIO_PATH {
+        return "stdin".into();
+    }
+    std::path::Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.into(), |s| s.to_string_lossy().into())
+}
+
+// Concatenates the loaded files. A txid seen again with identical contents
+// (an export range loaded twice) is dropped; with different contents both
+// rows are kept, since there is no telling which is right. Either way, and
+// for files of the same format whose time ranges overlap, an issue is
+// reported instead of silently double-counting.
+fn merge_inputs(
+    files: Vec<(&str, ImportFormat, Vec<LedgerEntry>)>,
+) -> (Vec<LedgerEntry>, Vec<DataIssue>) {
+    let mut issues = Vec::new();
+    let ranges: Vec<_> = files
+        .iter()
+        .filter_map(|(path, format, rows)| {
+            let first = rows.iter().map(|e| e.time).min()?;
+            let last = rows.iter().map(|e| e.time).max()?;
+            Some((*path, *format, first, last))
+        })
+        .collect();
+    for (i, a) in ranges.iter().enumerate() {
+        for b in &ranges[i + 1..] {
+            if a.1 == b.1 && a.2 <= b.3 && b.2 <= a.3 {
+                issues.push(DataIssue {
+                    kind: OVERLAPPING_INPUTS,
+                    time: a.2.max(b.2).to_string(),
+                    txid: String::new(),
+                    refid: String::new(),
+                    asset: String::new(),
+                    detail: format!(
+                        "{} ({} to {}) and {} ({} to {}) cover the same period up to {}",
+                        a.0,
+                        a.2,
+                        a.3,
+                        b.0,
+                        b.2,
+                        b.3,
+                        a.3.min(b.3)
+                    ),
+                });
+            }
+        }
+    }
+
+    let mut entries: Vec<LedgerEntry> = Vec::new();
+    // First row and file of each txid.
+    let mut seen: HashMap<Arc<str>, (usize, &str)> = HashMap::new();
+    for (path, _, rows) in files {
+        for e in rows {
+            if e.txid.is_empty() {
+                entries.push(e);
+                continue;
+            }
+            let Some(&(first, first_path)) = seen.get(&e.txid) else {
+                seen.insert(e.txid.clone(), (entries.len(), path));
+                entries.push(e);
+                continue;
+            };
+            let f = &entries[first];
+            let same = f.refid == e.refid
+                && f.time == e.time
+                && f.row_type == e.row_type
+                && f.subtype == e.subtype
+                && f.asset == e.asset
+                && f.amount == e.amount
+                && f.fee == e.fee;
+            if same {
+                issues.push(DataIssue::for_row(
+                    DUPLICATE_ROW,
+                    &e,
+                    format!("also in {}; the copy in {} was dropped", first_path, path),
+                ));
+            } else {
+                issues.push(DataIssue::for_row(
+                    CONFLICTING_TXID,
+                    &e,
+                    format!(
+                        "{} has amount {} fee {} for {} at {}; {} has amount {} fee {} for {} \
+                         at {}; both kept",
+                        first_path,
+                        f.amount,
+                        f.fee,
+                        f.asset,
+                        f.time,
+                        path,
+                        e.amount,
+                        e.fee,
+                        e.asset,
+                        e.time
+                    ),
+                ));
+                entries.push(e);
+            }
+        }
+    }
+    (entries, issues)
+}
+
+// Signs that an export range is missing: calendar months without a single
+// row between active ones, and pools whose running balance goes negative
+// before enough was acquired (CAD is cash, not a pool). Expects time-sorted
+// entries.
+fn ledger_gaps(entries: &[LedgerEntry]) -> Vec<DataIssue> {
+    let mut issues = Vec::new();
+    let month = |t: NaiveDateTime| t.year() * 12 + t.month0() as i32;
+    let active: BTreeSet<i32> = entries.iter().map(|e| month(e.time)).collect();
+    let label = |m: i32| format!("{:04}-{:02}", m.div_euclid(12), m.rem_euclid(12) + 1);
+    for (a, b) in active.iter().zip(active.iter().skip(1)) {
+        if b - a > 1 {
+            let (first, last) = (a + 1, b - 1);
+            issues.push(DataIssue {
+                kind: INACTIVE_MONTHS,
+                time: label(first),
+                txid: String::new(),
+                refid: String::new(),
+                asset: String::new(),
+                detail: format!(
+                    "no ledger rows from {} to {} ({} month(s)) between active periods; \
+                     if the account was used then, an export range may be missing",
+                    label(first),
+                    label(last),
+                    last - first + 1
+                ),
+            });
+        }
+    }
+
+    let mut balances: HashMap<Arc<str>, Decimal> = HashMap::new();
+    let mut flagged = HashSet::new();
+    for e in entries.iter().filter(|e| e.asset.as_ref() != "CAD") {
+        let balance = balances.entry(e.asset.clone()).or_default();
+        *balance += e.net_delta;
+        if *balance < dec!(0) && flagged.insert(e.asset.clone()) {
+            issues.push(DataIssue::for_row(
+                DISPOSAL_BEFORE_ACQUISITION,
+                e,
+                format!(
+                    "the rows so far leave {} {}; an earlier export range may be missing",
+                    balance, e.asset
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+// A run that failed over an incomplete ledger (typically selling more than
+// was bought) also lists the gaps that may explain it.
+fn explain_failure(e: Box<dyn Error>, issues: &[DataIssue]) -> Box<dyn Error> {
+    let gaps: Vec<String> = issues
+        .iter()
+        .filter(|d| d.kind == INACTIVE_MONTHS || d.kind == DISPOSAL_BEFORE_ACQUISITION)
+        .map(DataIssue::describe)
+        .collect();
+    if gaps.is_empty() {
+        return e;
+    }
+    // One line, since `main` prints errors with their escapes.
+    format!("{}; the inputs may be incomplete: {}", e, gaps.join("; ")).into()
+}
+
+// What the preparation passes did, for the summary.
+#[derive(Debug, Default)]
+struct Prepared {
+    edits: Vec<LedgerEdit>,
+    // Found on the prepared ledger, so edits and stored rows count.
+    gaps: Vec<DataIssue>,
+    matched_transfers: usize,
+}
+
+// Runs the optional passes over the loaded ledger (timezone, exclusions and
+// overrides, trade repairs, classification rules, gifts, transfer matching),
+// then looks for gaps.
+fn prepare_entries(
+    args: &Args,
+    entries: &mut Vec<LedgerEntry>,
+) -> Result<Prepared, Box<dyn Error>> {
+    if let Some(tz) = args.timezone {
+        localize_entries(entries, tz);
+    }
+    let mut edits = if args.exclude_refids.is_some() || args.overrides.is_some() {
+        let excluded = match &args.exclude_refids {
+            Some(path) => load_excluded_refids(path)?,
+            None => HashSet::new(),
+        };
+        let overrides = match &args.overrides {
+            Some(path) => load_overrides(path)?,
+            None => HashMap::new(),
+        };
+        apply_ledger_edits(entries, &excluded, &overrides)?
+    } else {
+        Vec::new()
+    };
+    if args.repair_trades {
+        edits.extend(repair_trade_groups(entries));
+    }
+    if let Some(path) = &args.classify {
+        classify_entries(entries, &load_classification_rules(path)?);
+    }
+    if let Some(path) = &args.gifts {
+        flag_gifts(entries, &load_gift_refids(path)?)?;
+    }
+    let matched_transfers = if args.match_transfers {
+        match_transfers(entries)
+    } else {
+        0
+    };
+    if let (Some(split), Some(side)) = (&args.split, args.split_side) {
+        split::attribute_entries(entries, split, side)?;
+    }
+    Ok(Prepared {
+        edits,
+        gaps: ledger_gaps(entries),
+        matched_transfers,
+    })
+}
+
+// Every file a run reads, so long-running modes can notice edits.
+fn input_paths(args: &Args) -> Vec<&str> {
+    let mut paths = vec![args.input.as_str()];
+    paths.extend(args.extra_inputs.iter().map(|(_, path)| path.as_str()));
+    paths.extend(
+        [
+            &args.mapping,
+            &args.classify,
+            &args.gifts,
+            &args.exclude_refids,
+            &args.overrides,
+            &args.migrations,
+            &args.wraps,
+            &args.trades,
+            &args.daily_closes,
+            &args.price_overrides,
+            &args.lot_selection,
+            &args.chart_of_accounts,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str),
+    );
+    paths
+}
+
+// Modification times of `paths`; any difference between two stamps means the
+// inputs changed.
+fn input_stamp(paths: &[&str]) -> Vec<Option<std::time::SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Polls the input files forever and calls `on_change` after any of them is
+// modified.
+fn watch_inputs(args: &Args, mut on_change: impl FnMut()) {
+    let paths = input_paths(args);
+    let mut stamp = input_stamp(&paths);
+    loop {
+        std::thread::sleep(INPUT_POLL_INTERVAL);
+        let current = input_stamp(&paths);
+        if current != stamp {
+            stamp = current;
+            on_change();
+        }
+    }
+}
+
+// Dates of the staking rewards in `entries`, by asset.
+#[cfg(feature = "cli")]
+fn reward_days(entries: &[LedgerEntry]) -> BTreeSet<(Arc<str>, NaiveDate)> {
+    entries
+        .iter()
+        .filter(|e| is_reward_row(e) && &*e.asset != "CAD")
+        .map(|e| (e.asset.clone(), e.time.date()))
+        .collect()
+}
+
+// `entries` are the prepared rows, for the reward dates `provider` fetches.
+fn process_options(args: &Args, entries: &[LedgerEntry]) -> Result<ProcessOptions, Box<dyn Error>> {
+    let migrations = match &args.migrations {
+        Some(path) => load_migrations(path)?,
+        None => Vec::new(),
+    };
+    let wraps = match &args.wraps {
+        Some(path) => load_wraps(path)?,
+        None => Vec::new(),
+    };
+    let trade_fills = match &args.trades {
+        Some(path) => trades::load(path)?,
+        None => BTreeMap::new(),
+    };
+    let price_chain = prices::PriceChain::build(
+        &args.price_chain,
+        args.fallback_usd_cad_fx,
+        match &args.price_overrides {
+            Some(path) => prices::load(path)?,
+            None => prices::DailyCloses::new(),
+        },
+        match &args.price_cache {
+            Some(path) if std::path::Path::new(path).exists() => prices::load(path)?,
+            _ => prices::DailyCloses::new(),
+        },
+        args.offline,
+    )?;
+    let selections = match &args.lot_selection {
+        Some(path) => Some(load_lot_selection(path, entries)?),
+        None => None,
+    };
+    let daily_closes = match (args.income_valuation, &args.daily_closes) {
+        (IncomeValuation::LastTrade, _) => prices::DailyCloses::new(),
+        (IncomeValuation::Provider, _) if args.offline => {
+            return Err(
+                "--income-valuation provider fetches closes; not allowed with --offline".into(),
+            );
+        }
+        (IncomeValuation::DailyClose, Some(path)) => prices::load(path)?,
+        (IncomeValuation::DailyClose, None) => {
+            return Err("--income-valuation daily-close needs --daily-closes <closes.csv>".into());
+        }
+        #[cfg(feature = "cli")]
+        (IncomeValuation::Provider, _) => prices::fetch_kraken(&reward_days(entries))?,
+        #[cfg(not(feature = "cli"))]
+        (IncomeValuation::Provider, _) => {
+            let _ = entries;
+            return Err(
+                "--income-valuation provider requires a build with the `cli` feature".into(),
+            );
+        }
+    };
+    Ok(ProcessOptions {
+        fee_mode: args.fee_mode,
+        rebate_mode: args.rebate_mode,
+        adjustment_mode: args.adjustment_mode,
+        donation_proceeds: args.donation_proceeds,
+        deemed_disposition: args.deemed_disposition,
+        timezone: args.timezone,
+        migrations,
+        wraps,
+        trade_fills,
+        income_valuation: args.income_valuation,
+        daily_closes,
+        max_price_age: args.max_price_age,
+        stale_prices: args.stale_prices,
+        round_trip_window: (args.round_trip_window > 0)
+            .then(|| chrono::Duration::minutes(i64::from(args.round_trip_window))),
+        stablecoin_dispositions: args.stablecoin_dispositions,
+        pooling: args.pooling,
+        rounding: args.rounding,
+        tie_break: args.tie_break,
+        check_invariants: args.check_invariants,
+        exemptions: args.exemptions,
+        price_chain: price_chain.with_usd_par(args.stablecoins_as_fiat.clone()),
+        cost_basis: basis::by_name(&args.cost_basis, selections)?,
+        jurisdiction: args.jurisdiction.clone(),
+        ..ProcessOptions::new(args.tax_year, args.fallback_usd_cad_fx)
+    })
+}
+
+// One report run as the command line asks for it: writes the report and side
+// reports, prints the summary, and returns the totals, exit status and report
+// rows.
+fn run_report(args: &Args) -> Result<(Totals, u8, Vec<ReportRow>), Box<dyn Error>> {
+    // On stdout the report has no file name for side reports to sit next
+    // to, so none is written; options that only make files are refused.
+    let side_reports = args.output != STDIO_PATH;
+    let side_stem = if !side_reports {
+        format!("kraken_tax_report_{}.csv", args.tax_year)
+    } else {
+        args.output.clone()
+    };
+    let paths = ReportPaths::new(&side_stem, args.tax_year);
+    let (mut entries, mut data_issues) = load_inputs(args, &mut Interner::default())?;
+    // Stored rows carry no ledger balances, so check the inputs before merging.
+    let balance_mismatches = reconcile_balances(&entries);
+    #[cfg(feature = "sqlite")]
+    let mut db = match &args.db {
+        Some(path) => {
+            let mut db = db::Db::open(path)?;
+            entries = db.merge_ledger(&entries)?;
+            Some(db)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "sqlite"))]
+    if args.db.is_some() {
+        return Err("--db requires a build with `--features sqlite`".into());
+    }
+    #[cfg(not(feature = "parquet"))]
+    if args.formats.contains(&ReportFormat::Parquet) {
+        return Err("--format parquet requires a build with `--features parquet`".into());
+    }
+    let Prepared {
+        edits,
+        gaps,
+        matched_transfers,
+    } = prepare_entries(args, &mut entries)?;
+    data_issues.extend(gaps);
+    let mut export_outputs = args
+        .exports
+        .iter()
+        .filter(|format| format.is_ledger_history())
+        .map(|format| {
+            let path = format.path(&side_stem);
+            export::write_export(*format, &path, &entries, &*args.jurisdiction, args.tax_year)
+                .map(|count| (path, count))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut opts = process_options(args, &entries)?;
+    let joined_trades = trades::joined(&entries, &opts.trade_fills);
+    let mut resumed_from = None;
+    let mut checkpoint_source = Vec::new();
+    if let Some(path) = &args.checkpoint {
+        // Multi-year and database runs need the full history in hand.
+        let can_resume = args.loss_carryover_from.is_none() && args.db.is_none();
+        if let Some(cp) = checkpoint::load(path)?.filter(|_| can_resume)
+            && let Some(skip) = checkpoint::covered_prefix(&cp, &entries, &opts)
+        {
+            checkpoint_source = entries.clone();
+            entries.drain(..skip);
+            resumed_from = Some(cp.year);
+            opts.start_pools = cp.pools;
+            opts.start_prices = cp.prices;
+        }
+    }
+    // Each earlier year is a full independent replay; run them side by side.
+    let mut yearly_gains = match args.loss_carryover_from {
+        Some(from) => (from..args.tax_year)
+            .into_par_iter()
+            .map(|year| {
+                let year_opts = ProcessOptions {
+                    tax_year: year,
+                    ..opts.clone()
+                };
+                process(entries.clone(), &year_opts)
+                    .map(|out| (year, out.totals.capital_gain_cad))
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+        None => Vec::new(),
+    };
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = db.as_mut() {
+        db.store_events(&entries, &opts)?;
+    }
+    if args.checkpoint.is_some() && resumed_from.is_none() {
+        checkpoint_source = entries.clone();
+    }
+    let ProcessOutput {
+        report,
+        fx_audit,
+        lineage,
+        fees,
+        round_trips,
+        ignored,
+        totals,
+        pools,
+        prices,
+        mutations,
+        event_hash,
+    } = process(entries, &opts).map_err(|e| explain_failure(e, &data_issues))?;
+    if let Some(path) = &args.checkpoint {
+        checkpoint::save(path, &checkpoint_source, &opts, &pools, &prices)?;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = db.as_mut() {
+        db.store_results(args.tax_year, &report, &pools)?;
+    }
+
+    let (report, fills) = match args.aggregate_fills {
+        Some(window) => aggregate_fills(report, window)?,
+        None => (report, Vec::new()),
+    };
+    let report = match args.aggregate_rewards {
+        Some(period) => aggregate_rewards(report, period)?,
+        None => report,
+    };
+    let deemed_rows: Vec<&ReportRow> = report
+        .iter()
+        .filter(|r| r.event_type.starts_with("deemed_"))
+        .collect();
+    let fallback_fx_uses = fx_audit
+        .iter()
+        .filter(|r| r.fx_source == FxSource::Fallback.as_str())
+        .count();
+    let ignored_count: usize = ignored.iter().map(|s| s.count).sum();
+    let mutation_rows: Vec<_> = if args.pool_mutations {
+        mutations
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                a.row(
+                    i + 1,
+                    report_time(wall_clock(a.mutation.time, args.timezone)),
+                    args.rounding.full_precision,
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let carryover: Vec<_> = if args.loss_carryover_from.is_some() {
+        yearly_gains.push((args.tax_year, totals.capital_gain_cad));
+        loss_carryover(&yearly_gains, args.jurisdiction.loss_carryback_years())
+            .into_iter()
+            .map(|mut r| {
+                r.net_gain_cad = q2(r.net_gain_cad);
+                r.loss_applied_cad = q2(r.loss_applied_cad);
+                r.adjusted_gain_cad = q2(r.adjusted_gain_cad);
+                r.loss_carried_back_cad = q2(r.loss_carried_back_cad);
+                r.carryforward_balance_cad = q2(r.carryforward_balance_cad);
+                r
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Valued at the last moment of the tax year, for archiving and T1135.
+    let year_end = NaiveDate::from_ymd_opt(args.tax_year, 12, 31)
+        .and_then(|d| d.and_hms_opt(23, 59, 59))
+        .ok_or("tax year out of range")?;
+    let ending_pools: Vec<_> = ending_pool_rows(&pools, &prices, opts.price_chain.at(year_end))
+        .into_iter()
+        .map(|mut r| {
+            if !args.rounding.full_precision {
+                r.units = q8(r.units);
+                r.acb_cad = q2(r.acb_cad);
+                r.avg_cost_cad = q8(r.avg_cost_cad);
+                r.last_price_cad = r.last_price_cad.map(q8);
+                r.fmv_cad = r.fmv_cad.map(q2);
+            }
+            r
+        })
+        .collect();
+
+    let chart = match &args.chart_of_accounts {
+        Some(path) => Some(journal::ChartOfAccounts::load(path)?),
+        None => None,
+    };
+    for format in args.exports.iter().filter(|f| !f.is_ledger_history()) {
+        let path = format.path(&side_stem);
+        let count = match format {
+            ExportFormat::Beancount => {
+                journal::write_beancount(&path, &report, &ending_pools, args.tax_year)?
+            }
+            ExportFormat::AdjustedCostBase => {
+                export::write_adjustedcostbase(&path, &report, &ending_pools, args.tax_year)?
+            }
+            ExportFormat::Journal => journal::write_journal(
+                &path,
+                &report,
+                &ending_pools,
+                args.tax_year,
+                chart.as_ref(),
+            )?,
+            ExportFormat::Schedule3 => {
+                export::write_schedule_3(&path, &report, &lineage, args.locale)?
+            }
+            ExportFormat::WealthsimpleTax | ExportFormat::TurboTax => {
+                export::write_capital_gains(*format, &path, &report, &lineage)?
+            }
+            _ => export::write_schedule_g(&path, &report, args.locale)?,
+        };
+        export_outputs.push((path, count));
+    }
+
+    let report_meta = meta::report_meta(args, &opts, &args.output, &totals, &event_hash)?;
+
+    let shown: Vec<&ReportRow> = report
+        .iter()
+        .filter(|r| args.report_filter.keeps(r))
+        .collect();
+    let shown_fills: Vec<&ReportRow> = fills
+        .iter()
+        .filter(|r| args.report_filter.keeps(r))
+        .collect();
+    let split_outputs = match args.split_by {
+        Some(split) => split_report(
+            shown.iter().copied(),
+            split,
+            &args.split_template,
+            &side_stem,
+            args.tax_year,
+        ),
+        None => Vec::new(),
+    };
+    // Without --columns, the report's headers follow --locale.
+    let localized = match &args.columns {
+        Some(_) => None,
+        None => args.locale.report_columns(),
+    };
+    let columns = args.columns.as_deref().or(localized.as_deref());
+    let style = CsvStyle {
+        delimiter: b',',
+        dialect: args.csv_dialect,
+    };
+    let tsv_style = CsvStyle {
+        delimiter: b'\t',
+        ..style
+    };
+    let markdown_report = args.formats.contains(&ReportFormat::Markdown).then(|| {
+        let locale = args.locale;
+        markdown::MarkdownReport {
+            locale,
+            settings: vec![
+                locale.fill("Tax year: {}", &[&args.tax_year]),
+                locale.fill("Jurisdiction: {}", &[&args.jurisdiction.name()]),
+                locale.fill("Cost basis: {}", &[&opts.cost_basis.name()]),
+            ],
+            totals: &totals,
+            report: &report,
+            ending_pools: &ending_pools,
+            warnings: markdown::warnings(
+                args,
+                &totals,
+                &ignored,
+                &balance_mismatches,
+                &data_issues,
+                &edits,
+            ),
+        }
+    });
+    // Every file this run writes, with the job that writes it.
+    let mut jobs: Vec<(&str, WriteJob)> = vec![
+        (
+            &args.output,
+            Box::new(|| write_report(&args.output, &shown, columns, style)),
+        ),
+        (
+            &paths.fx_audit,
+            Box::new(|| write_csv(&paths.fx_audit, &fx_audit)),
+        ),
+        (
+            &paths.lineage,
+            Box::new(|| write_csv(&paths.lineage, &lineage)),
+        ),
+        (
+            &paths.round_trips,
+            Box::new(|| write_csv(&paths.round_trips, &round_trips)),
+        ),
+        (&paths.fees, Box::new(|| write_csv(&paths.fees, &fees))),
+        (
+            &paths.ignored,
+            Box::new(|| write_csv(&paths.ignored, &ignored)),
+        ),
+        (
+            &paths.balance,
+            Box::new(|| write_csv(&paths.balance, &balance_mismatches)),
+        ),
+        (
+            &paths.data_quality,
+            Box::new(|| write_csv(&paths.data_quality, &data_issues)),
+        ),
+        (
+            &paths.pools,
+            Box::new(|| write_csv(&paths.pools, &ending_pools)),
+        ),
+        (
+            &paths.meta,
+            Box::new(|| meta::write(&paths.meta, &report_meta)),
+        ),
+    ];
+    if args.json {
+        jobs.push((&paths.json, Box::new(|| write_json(&paths.json, &shown))));
+        jobs.push((
+            &paths.pools_json,
+            Box::new(|| write_json(&paths.pools_json, &ending_pools)),
+        ));
+    }
+    if let Some(md) = &markdown_report {
+        let path = &paths.markdown;
+        jobs.push((path, Box::new(move || markdown::write(path, md))));
+    }
+    if args.formats.contains(&ReportFormat::Tsv) {
+        jobs.push((
+            &paths.tsv,
+            Box::new(|| write_report(&paths.tsv, &shown, columns, tsv_style)),
+        ));
+    }
+    #[cfg(feature = "parquet")]
+    if args.formats.contains(&ReportFormat::Parquet) {
+        jobs.push((
+            &paths.parquet,
+            Box::new(|| columnar::write(&paths.parquet, &shown)),
+        ));
+    }
+    if args.pool_mutations {
+        jobs.push((
+            &paths.mutations,
+            Box::new(|| write_csv(&paths.mutations, &mutation_rows)),
+        ));
+    }
+    for (path, rows) in &split_outputs {
+        jobs.push((
+            path,
+            Box::new(move || {
+                // Templates may place groups in subdirectories.
+                if let Some(dir) = std::path::Path::new(path).parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                write_report(path, rows, columns, style)
+            }),
+        ));
+    }
+    if args.deemed_disposition.is_some() {
+        jobs.push((
+            &paths.deemed,
+            Box::new(|| write_report(&paths.deemed, &deemed_rows, columns, style)),
+        ));
+    }
+    if args.loss_carryover_from.is_some() {
+        jobs.push((
+            &paths.carryover,
+            Box::new(|| write_csv(&paths.carryover, &carryover)),
+        ));
+    }
+    if args.income_valuation == IncomeValuation::Provider {
+        jobs.push((
+            &paths.daily_closes,
+            Box::new(|| prices::write(&paths.daily_closes, &opts.daily_closes)),
+        ));
+    }
+    let fetched_prices = opts.price_chain.fetched();
+    if let Some(path) = args.price_cache.as_deref().filter(|_| fetched_prices > 0) {
+        jobs.push((path, Box::new(|| opts.price_chain.save_cache(path))));
+    }
+    if args.aggregate_fills.is_some() {
+        jobs.push((
+            &paths.fills,
+            Box::new(|| write_report(&paths.fills, &shown_fills, columns, style)),
+        ));
+    }
+    if !side_reports {
+        jobs.retain(|(path, _)| *path == args.output || Some(*path) == args.price_cache.as_deref());
+    }
+    let (mut written, jobs): (Vec<&str>, Vec<WriteJob>) = jobs.into_iter().unzip();
+    write_all(jobs)?;
+    if args.checksums {
+        written.extend(export_outputs.iter().map(|(path, _)| path.as_str()));
+        let mut read = input_paths(args);
+        read.extend(args.config.as_deref());
+        checksums::write(&paths.checksums, &read, &written)?;
+    }
+
+    let data_warnings = data_issues.iter().filter(|d| !d.is_note()).count();
+    Summary {
+        args,
+        opts: &opts,
+        paths: &paths,
+        totals: &totals,
+        report: &report,
+        shown: &shown,
+        shown_fills: &shown_fills,
+        pools: &pools,
+        deemed_rows: &deemed_rows,
+        carryover: &carryover,
+        round_trips: &round_trips,
+        ignored: &ignored,
+        ignored_count,
+        balance_mismatches: &balance_mismatches,
+        data_issues: &data_issues,
+        data_warnings,
+        edits: &edits,
+        matched_transfers,
+        joined_trades,
+        fallback_fx_uses,
+        resumed_from,
+        mutation_rows: &mutation_rows,
+        split_outputs: &split_outputs,
+        export_outputs: &export_outputs,
+        side_reports,
+    }
+    .print()?;
+    let code = exit_code(
+        totals.warning_count + totals.stale_price_count,
+        ignored_count,
+        balance_mismatches.len(),
+        data_warnings,
+    );
+    Ok((totals, code, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{entry, leg};
+
+    #[test]
+    fn interner_shares_one_allocation_per_string() {
+        let mut interner = Interner::default();
+        let a = interner.intern("BTC");
+        let b = interner.intern(&"btc".to_uppercase());
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &interner.intern("ETH")));
+    }
+
+    #[test]
+    fn parses_timestamp_with_fraction() {
+        let t = parse_time("2025-01-04 00:05:16.8462").unwrap();
+        assert_eq!(t.year(), 2025);
+        assert_eq!(t.month(), 1);
+    }
+
+    #[test]
+    fn parses_offsets_and_epoch_timestamps_as_utc() {
+        let expected = parse_time("2025-01-04 05:30:00").unwrap();
+        for raw in [
+            "2025-01-04T05:30:00",
+            "2025-01-04T05:30:00Z",
+            "2025-01-04 05:30:00 UTC",
+            "2025-01-04T00:30:00-05:00",
+            "2025-01-04 00:30:00-0500",
+            "1735968600",
+            "1735968600000",
+            "1735968600.0000",
+        ] {
+            assert_eq!(parse_time(raw).unwrap(), expected, "{}", raw);
+        }
+        assert!(parse_time("04/01/2025").is_err());
+    }
+
+    #[test]
+    fn excel_semicolon_utf16_ledger_parses() {
+        let csv = "txid;refid;time;type;subtype;asset;amount;fee\n\
+                   T1;R1;2025-01-01 00:00:00;deposit;;ETH;1.234,5;0,01\n";
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(csv.encode_utf16().flat_map(u16::to_le_bytes));
+        let text = decode_text(&bytes).unwrap();
+        assert_eq!(decode_text(&[0xEF, 0xBB, 0xBF, b'a']).unwrap(), "a");
+
+        let entries = load_entries(&text, CsvLocale::default(), &mut Interner::default()).unwrap();
+        assert_eq!(&*entries[0].asset, "ETH");
+        assert_eq!(entries[0].amount, dec!(1234.5));
+        assert_eq!(entries[0].fee, dec!(0.01));
+
+        let point = CsvLocale {
+            decimal_comma: Some(false),
+            ..CsvLocale::default()
+        };
+        assert_eq!(point.resolve("a;b\n"), (b';', false));
+        assert_eq!(CsvLocale::default().resolve("a,b;c,d\n"), (b',', false));
+    }
+
+    #[test]
+    fn balance_reconciliation_reports_each_gap_once() {
+        let csv = "txid,refid,time,type,subtype,asset,amount,fee,balance\n\
+                   T1,R1,2025-01-01 00:00:00,deposit,,ETH,2,0,2\n\
+                   T2,R3,2025-01-02 00:00:00,trade,tradespot,ETH,-0.5,0.01,1.49\n\
+                   T3,R2,2025-01-02 00:00:00,trade,tradespot,ETH,1,0,2.49\n\
+                   T4,R4,2025-01-03 00:00:00,withdrawal,,ETH,-1,0,0.49\n\
+                   T5,R5,2025-01-04 00:00:00,withdrawal,,ETH,-0.49,0,0\n";
+        let entries = load_entries(csv, CsvLocale::default(), &mut Interner::default()).unwrap();
+        let mismatches = reconcile_balances(&entries);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(&*mismatches[0].refid, "R4");
+        assert_eq!(mismatches[0].computed_balance, dec!(1.49));
+        assert_eq!(mismatches[0].difference, dec!(-1));
+        assert_eq!(exit_code(0, 0, 0, 0), 0);
+        assert_eq!(exit_code(1, 0, 0, 0), EXIT_WARNINGS);
+        assert_eq!(exit_code(1, 2, 0, 0), EXIT_IGNORED_ROWS);
+        assert_eq!(exit_code(1, 2, 1, 0), EXIT_BALANCE_MISMATCH);
+        assert_eq!(exit_code(1, 2, 1, 1), EXIT_DATA_QUALITY);
+    }
+
+    #[test]
+    fn parses_scientific_and_grouped_amounts() {
+        assert_eq!(parse_decimal("1.2E-7").unwrap(), dec!(0.00000012));
+        assert_eq!(parse_decimal("-3e2").unwrap(), dec!(-300));
+        assert_eq!(parse_decimal("1,234.56").unwrap(), dec!(1234.56));
+        assert_eq!(parse_decimal("-12,345,678").unwrap(), dec!(-12345678));
+        assert!(parse_decimal("1,5").is_err());
+        assert!(parse_decimal("12,34.5").is_err());
+        // A European `0,123` is a fraction, not a grouped 123.
+        assert!(parse_decimal("0,123").is_err());
+        assert!(parse_decimal("-0,123.5").is_err());
+        assert_eq!(parse_amount("0,123", true).unwrap(), dec!(0.123));
+        assert_eq!(parse_amount("1.234,5", true).unwrap(), dec!(1234.5));
+    }
+
+    #[test]
+    fn split_report_groups_rows_into_templated_files() {
+        let id: Arc<str> = Arc::from("R1");
+        let time = stamp(parse_time("2025-03-01 00:00:00").unwrap(), None);
+        let report = vec![
+            make_row(time, &id, &id, "trade_disposition", &Arc::from("BTC")),
+            make_row(time, &id, &id, "trade_acquisition", &Arc::from("ETH")),
+            make_row(time, &id, &id, "earn_reward_income", &Arc::from("BTC")),
+        ];
+
+        let files = split_report(
+            &report,
+            SplitBy::Asset,
+            DEFAULT_SPLIT_TEMPLATE,
+            "report_2025.csv",
+            2025,
+        );
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "report_2025_BTC.csv");
+        assert_eq!(files[0].1.len(), 2);
+
+        let files = split_report(&report, SplitBy::Year, "{group}/{year}.csv", "r.csv", 2025);
+        assert_eq!(files[0].0, "2025/2025.csv");
+        assert_eq!(files[0].1.len(), 3);
+    }
+
+    #[test]
+    fn net_delta_calculates_with_fee() {
+        let e = entry(
+            "2025-01-01 00:00:00",
+            "T1",
+            "R1",
+            "withdrawal",
+            "",
+            "ETH",
+            "-1.0",
+            "0.01",
+        );
+        assert_eq!(e.net_delta, dec!(-1.01));
+    }
+
+    #[test]
+    fn trade_group_requires_two_rows() {
+        let entries = vec![entry(
+            "2025-01-01 00:00:00",
+            "T1",
+            "R1",
+            "trade",
+            "tradespot",
+            "USD",
+            "-100",
+            "1",
+        )];
+        let err = build_trade_groups(&entries, &Canada, 2025)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expected 2 rows"));
+    }
+
+    #[test]
+    fn usd_cad_fallback_is_used() {
+        let state = PriceState::default();
+        let chain = prices::PriceChain::new(dec!(1.4));
+        let at = parse_time("2025-01-01 00:00:00").unwrap();
+        let rate = usd_cad_rate(&state, chain.at(at), "test").unwrap();
+        assert_eq!((rate.fx, rate.source), (dec!(1.4), FxSource::Fallback));
+    }
+
+    #[test]
+    fn withdrawal_fee_creates_loss() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-140.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "withdrawal",
+                "",
+                "SOL",
+                "-0.5",
+                "0.1",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert!(
+            out.report
+                .iter()
+                .any(|r| r.event_type == "withdrawal_fee_disposition")
+        );
+        assert!(out.totals.capital_gain_cad < dec!(0));
+        let sol = out.pools.get("SOL").unwrap();
+        assert_eq!(q8(sol.units), dec!(0.4));
+    }
+
+    #[test]
+    fn flagged_donation_can_designate_acb_as_proceeds() {
+        let mut entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-200.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-02-01 00:00:00",
+                "T5",
+                "R3",
+                "withdrawal",
+                "",
+                "SOL",
+                "-1.0",
+                "0",
+            ),
+        ];
+        let gifts = parse_gift_refids("R3 = donation # to registered charity\n").unwrap();
+        flag_gifts(&mut entries, &gifts).unwrap();
+
+        let opts = ProcessOptions {
+            donation_proceeds: DonationProceeds::Acb,
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let row = out
+            .report
+            .iter()
+            .find(|r| r.event_type == "donation_disposition")
+            .unwrap();
+        assert_eq!(parse_decimal(&row.proceeds_cad).unwrap(), dec!(150));
+        assert!(parse_decimal(&row.gain_cad).unwrap().is_zero());
+        assert_eq!(out.totals.donation_amount_cad, dec!(150));
+    }
+
+    #[test]
+    fn timezone_moves_new_year_utc_rows_into_prior_tax_year() {
+        let mut entries = vec![
+            entry(
+                "2025-06-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2025-06-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2026-01-01T03:00:00Z",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "SOL",
+                "-1.0",
+                "0",
+            ),
+            entry(
+                "2026-01-01T03:00:00Z",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "150.0",
+                "0",
+            ),
+        ];
+        let tz: Tz = "America/Toronto".parse().unwrap();
+        localize_entries(&mut entries, tz);
+        let opts = ProcessOptions {
+            timezone: Some(tz),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+
+        let out = process(entries, &opts).unwrap();
+        assert_eq!(out.totals.capital_gain_cad, dec!(50));
+        let sale = out
+            .report
+            .iter()
+            .find(|r| r.event_type == "trade_disposition")
+            .unwrap();
+        assert_eq!(sale.time, "2025-12-31T22:00:00-05:00");
+        assert!(out.report[0].time.ends_with("-04:00"));
+    }
+
+    #[test]
+    fn timezone_processes_the_repeated_hour_in_utc_order() {
+        // Bought at 01:30 daylight time, sold at 01:10 standard time, 40
+        // minutes later.
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let mut entries = vec![
+            leg("2025-11-02 05:30:00", "R1", "CAD", "-200"),
+            leg("2025-11-02 05:30:00", "R1", "SOL", "2"),
+            leg("2025-11-02 06:10:00", "R3", "SOL", "-2"),
+            leg("2025-11-02 06:10:00", "R3", "CAD", "250"),
+        ];
+        let tz: Tz = "America/Toronto".parse().unwrap();
+        localize_entries(&mut entries, tz);
+        let opts = ProcessOptions {
+            timezone: Some(tz),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+
+        let out = process(entries, &opts).unwrap();
+        assert_eq!(out.totals.capital_gain_cad, dec!(50));
+        let times: Vec<&str> = out.report.iter().map(|r| r.time.as_str()).collect();
+        assert_eq!(
+            times,
+            ["2025-11-02T01:30:00-04:00", "2025-11-02T01:10:00-05:00"]
+        );
+    }
+
+    #[test]
+    fn timezone_labels_the_repeated_hour_with_each_rows_offset() {
+        // 05:30 and 06:30 UTC are both 01:30 in Toronto the night clocks
+        // fall back: first daylight time, then standard time.
+        let mut entries = vec![
+            entry(
+                "2025-11-02 05:30:00",
+                "T1",
+                "R1",
+                "deposit",
+                "",
+                "CAD",
+                "100.0",
+                "0",
+            ),
+            entry(
+                "2025-11-02 06:30:00",
+                "T2",
+                "R2",
+                "deposit",
+                "",
+                "CAD",
+                "100.0",
+                "0",
+            ),
+        ];
+        let tz: Tz = "America/Toronto".parse().unwrap();
+        localize_entries(&mut entries, tz);
+        assert_eq!(entries[0].time, entries[1].time);
+        assert_eq!(entries[0].stamp().to_rfc3339(), "2025-11-02T01:30:00-04:00");
+        assert_eq!(entries[1].stamp().to_rfc3339(), "2025-11-02T01:30:00-05:00");
+        assert_eq!(
+            entries[1].stamp().naive_utc(),
+            parse_time("2025-11-02 06:30:00").unwrap()
+        );
+
+        // A time no row carries takes the daylight offset in that hour.
+        let time = entries[0].time;
+        assert_eq!(
+            report_time(wall_clock(time, Some(tz))),
+            "2025-11-02T01:30:00-04:00"
+        );
+        assert_eq!(
+            report_time(wall_clock(time, None)),
+            "2025-11-02T01:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn deemed_disposition_steps_acb_to_fmv() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-03-01 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-300.0",
+                "0",
+            ),
+            entry(
+                "2025-03-01 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+        ];
+        let opts = ProcessOptions {
+            deemed_disposition: Some(parse_date_or_time("2025-06-30").unwrap()),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+
+        let out = process(entries, &opts).unwrap();
+        assert_eq!(out.totals.deemed_proceeds_cad, dec!(600));
+        assert_eq!(out.totals.deemed_gain_cad, dec!(200));
+        assert_eq!(out.totals.capital_gain_cad, dec!(200));
+        assert_eq!(out.pools["SOL"].acb_cad, dec!(600));
+        assert!(
+            out.report
+                .iter()
+                .any(|r| r.event_type == "deemed_reacquisition")
+        );
+    }
+
+    #[test]
+    fn loss_is_carried_back_then_forward() {
+        let rows = loss_carryover(
+            &[
+                (2020, dec!(500)),
+                (2021, dec!(300)),
+                (2024, dec!(-1000)),
+                (2025, dec!(400)),
+            ],
+            Canada.loss_carryback_years(),
+        );
+        // 2020 is outside the three-year carryback window of 2024.
+        assert_eq!(rows[0].adjusted_gain_cad, dec!(500));
+        assert_eq!(rows[1].loss_applied_cad, dec!(300));
+        assert_eq!(rows[1].adjusted_gain_cad, dec!(0));
+        assert_eq!(rows[2].loss_carried_back_cad, dec!(300));
+        assert_eq!(rows[2].carryforward_balance_cad, dec!(700));
+        assert_eq!(rows[3].adjusted_gain_cad, dec!(0));
+        assert_eq!(rows[3].carryforward_balance_cad, dec!(300));
+    }
+
+    #[test]
+    fn matched_transfer_keeps_acb_in_pool() {
+        let mut entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-140.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "withdrawal",
+                "",
+                "SOL",
+                "-0.9",
+                "0.1",
+            ),
+            entry(
+                "2025-01-01 00:20:00",
+                "0xabc-SOL",
+                "0xabc",
+                "deposit",
+                "",
+                "SOL",
+                "0.9",
+                "0",
+            ),
+        ];
+
+        assert_eq!(match_transfers(&mut entries), 1);
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert!(
+            out.report
+                .iter()
+                .all(|r| r.event_type != "warning_unpriced_transfer_in")
+        );
+        assert!(
+            out.report
+                .iter()
+                .any(|r| r.event_type == "transfer_fee_disposition")
+        );
+        let sol = out.pools.get("SOL").unwrap();
+        assert_eq!(q8(sol.units), dec!(0.9));
+        assert_eq!(q2(sol.acb_cad), dec!(126.00));
+    }
+
+    #[test]
+    fn reward_income_adds_acb() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-140.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "earn",
+                "reward",
+                "SOL",
+                "0.2",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert!(out.totals.reward_income_cad > dec!(0));
+        let sol = out.pools.get("SOL").unwrap();
+        assert_eq!(q8(sol.units), dec!(1.2));
+        assert!(sol.acb_cad > dec!(0));
+    }
+
+    #[test]
+    fn report_rows_name_their_price_and_fx_sources() {
+        let entries = vec![
+            entry(
+                "2024-11-30 00:00:00",
+                "T0",
+                "R0",
+                "deposit",
+                "",
+                "USD",
+                "100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "USD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "earn",
+                "reward",
+                "SOL",
+                "0.2",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let reward = out
+            .report
+            .iter()
+            .find(|r| r.event_type == "earn_reward_income")
+            .unwrap();
+        // Valued at the SOL/USD trade, converted at the fallback USD rate.
+        assert_eq!(reward.price_source, "trade_implied");
+        assert_eq!(reward.fx_source, "fallback");
+    }
+
+    #[test]
+    fn fx_audit_flags_fallback_rate() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T0",
+                "R0",
+                "deposit",
+                "",
+                "USD",
+                "100.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "USD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.fx_audit.len(), 2);
+        assert!(out.fx_audit.iter().all(|r| r.fx_source == "fallback"));
+        let acq = out
+            .fx_audit
+            .iter()
+            .find(|r| r.event_type == "trade_acquisition")
+            .unwrap();
+        assert_eq!(&*acq.asset, "SOL");
+        assert_eq!(acq.value_cad, "140.00");
+    }
+
+    #[test]
+    fn lot_audit_links_dispositions_to_lots() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B1", "SOL", "1"),
+            leg("2025-02-01 00:00:00", "B2", "CAD", "-300"),
+            leg("2025-02-01 00:00:00", "B2", "SOL", "1"),
+            leg("2025-03-01 00:00:00", "S1", "SOL", "-1.5"),
+            leg("2025-03-01 00:00:00", "S1", "CAD", "600"),
+        ];
+
+        let out = process(entries.clone(), &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.lineage.len(), 1);
+        let avg = &out.lineage[0];
+        assert_eq!((avg.basis, &*avg.acquisition_refid), ("pool_average", ""));
+        assert_eq!((avg.units.as_str(), avg.acb_cad.as_str()), ("1.5", "300.0"));
+
+        let opts = ProcessOptions {
+            cost_basis: Arc::new(Fifo),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let lots: Vec<_> = out
+            .lineage
+            .iter()
+            .map(|r| {
+                (
+                    &*r.disposition_refid,
+                    &*r.acquisition_refid,
+                    r.units.as_str(),
+                    r.acb_cad.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            lots,
+            [("S1", "B1", "1", "100"), ("S1", "B2", "0.5", "150.0")]
+        );
+        assert_eq!(out.lineage[1].acquisition_time, "2025-02-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn holding_periods_split_gains_by_lot_age() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2024-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2024-01-01 00:00:00", "B1", "SOL", "1"),
+            leg("2025-02-15 00:00:00", "B2", "CAD", "-300"),
+            leg("2025-02-15 00:00:00", "B2", "SOL", "1"),
+            leg("2025-03-01 00:00:00", "S1", "SOL", "-1.5"),
+            leg("2025-03-01 00:00:00", "S1", "CAD", "600"),
+        ];
+        let opts = ProcessOptions {
+            cost_basis: Arc::new(Fifo),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries.clone(), &opts).unwrap();
+        let periods = &out.totals.holding_periods;
+        assert_eq!(periods[&HoldingPeriod::OverOneYear].gain_cad, dec!(300));
+        assert_eq!(periods[&HoldingPeriod::Under30Days].gain_cad, dec!(50));
+        assert_eq!(out.lineage[1].holding_days, "14");
+
+        // Under average cost, the pool's mean acquisition time.
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let row = &out.lineage[0];
+        assert_eq!(row.acquisition_time, "2024-07-24T12:00:00+00:00");
+        assert_eq!(row.holding_period, "under_1_year");
+    }
+
+    #[test]
+    fn fee_expenses_are_summed_by_kind() {
+        let row =
+            |time: &str, refid: &str, row_type: &str, asset: &str, amount: &str, fee: &str| {
+                let subtype = if row_type == "trade" { "tradespot" } else { "" };
+                entry(
+                    time,
+                    &format!("T{}{}", refid, asset),
+                    refid,
+                    row_type,
+                    subtype,
+                    asset,
+                    amount,
+                    fee,
+                )
+            };
+        let entries = vec![
+            row("2025-01-01 00:00:00", "B1", "trade", "CAD", "-140", "1.4"),
+            row("2025-01-01 00:00:00", "B1", "trade", "SOL", "1", "0"),
+            row(
+                "2025-02-01 00:00:00",
+                "W1",
+                "withdrawal",
+                "SOL",
+                "-0.5",
+                "0.01",
+            ),
+            row("2025-03-01 00:00:00", "M1", "rollover", "CAD", "0", "0.5"),
+            row("2025-03-02 00:00:00", "M2", "margin", "XYZ", "0", "2"),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let fees = &out.totals.fees;
+        assert_eq!(fees.trading_cad, dec!(1.4));
+        // At the last trade price, 141.40 with its fee.
+        assert_eq!(fees.withdrawal_cad, dec!(1.414));
+        assert_eq!(fees.margin_cad, dec!(0.5));
+        assert_eq!((fees.total_cad(), fees.unpriced_count), (dec!(3.314), 1));
+        let kinds: Vec<_> = out.fees.iter().map(|f| f.category).collect();
+        assert_eq!(kinds, ["trading", "withdrawal", "margin", "margin"]);
+        assert_eq!(out.fees[3].value_cad, "");
+    }
+
+    #[test]
+    fn usd_gains_within_the_fx_exemption_are_not_taxed() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "U1", "CAD", "-1400"),
+            leg("2025-01-01 00:00:00", "U1", "USD", "1000"),
+            leg("2025-02-01 00:00:00", "U2", "USD", "-500"),
+            leg("2025-02-01 00:00:00", "U2", "CAD", "750"),
+            leg("2025-03-01 00:00:00", "U3", "USD", "-500"),
+            leg("2025-03-01 00:00:00", "U3", "CAD", "1000"),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let t = &out.totals;
+        assert_eq!((t.fx_gain_cad, t.fx_exempt_cad), (dec!(350), dec!(200)));
+        assert_eq!(t.capital_gain_cad, dec!(150));
+        assert_eq!(t.taxable_capital_gain_cad, dec!(75));
+        assert!(out.report[1].notes.contains("Foreign exchange gain/loss"));
+    }
+
+    #[test]
+    fn fx_gain_is_summed_before_rounding() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "U1", "CAD", "-1400"),
+            leg("2025-01-01 00:00:00", "U1", "USD", "1000"),
+            leg("2025-02-01 00:00:00", "U2", "USD", "-500"),
+            leg("2025-02-01 00:00:00", "U2", "CAD", "750.40"),
+            leg("2025-03-01 00:00:00", "U3", "USD", "-500"),
+            leg("2025-03-01 00:00:00", "U3", "CAD", "1000.40"),
+        ];
+        let run = |proceeds_dp: u32| {
+            let opts = ProcessOptions {
+                rounding: Rounding {
+                    proceeds_dp,
+                    ..Rounding::default()
+                },
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            process(entries.clone(), &opts).unwrap()
+        };
+        let whole = run(0);
+        assert_eq!(whole.report[1].gain_cad, "50");
+        assert_eq!(whole.totals.fx_gain_cad, dec!(350.80));
+        assert_eq!(whole.totals.fx_gain_cad, run(2).totals.fx_gain_cad);
+    }
+
+    #[test]
+    fn small_fx_dispositions_and_spends_are_tagged_exempt() {
+        let leg = |time: &str, refid: &str, row_type: &str, asset: &str, amount: &str| {
+            let subtype = if row_type == "trade" { "tradespot" } else { "" };
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                row_type,
+                subtype,
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "U1", "trade", "CAD", "-1400"),
+            leg("2025-01-01 00:00:00", "U1", "trade", "USD", "1000"),
+            leg("2025-02-01 00:00:00", "U2", "trade", "USD", "-500"),
+            leg("2025-02-01 00:00:00", "U2", "trade", "CAD", "750"),
+            leg("2025-03-01 00:00:00", "U3", "trade", "USD", "-500"),
+            leg("2025-03-01 00:00:00", "U3", "trade", "CAD", "1000"),
+            leg("2025-04-01 00:00:00", "B1", "trade", "CAD", "-100"),
+            leg("2025-04-01 00:00:00", "B1", "trade", "SOL", "1"),
+            leg("2025-05-01 00:00:00", "S1", "trade", "SOL", "-0.5"),
+            leg("2025-05-01 00:00:00", "S1", "trade", "CAD", "100"),
+            leg("2025-06-01 00:00:00", "P1", "spend", "SOL", "-0.1"),
+            leg("2025-06-02 00:00:00", "P2", "spend", "SOL", "-0.3"),
+        ];
+        let opts = ProcessOptions {
+            exemptions: Exemptions {
+                fx_per_disposition: Some(dec!(200)),
+                spends_under: Some(dec!(50)),
+            },
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let t = &out.totals;
+        // U2's 50 is within the limit, U3's 300 is not; the year's net
+        // exemption no longer applies.
+        assert_eq!((t.fx_gain_cad, t.fx_exempt_cad), (dec!(350), dec!(50)));
+        // P1 (20 CAD of SOL) is exempt, P2 (60 CAD) is not.
+        assert_eq!(t.personal_use_exempt_cad, dec!(10));
+        // U3 300, S1 50 and P2 30.
+        assert_eq!(t.capital_gain_cad, dec!(380));
+        assert!(t.row_drift.within_rounding());
+        let notes = |refid: &str| {
+            let r = out
+                .report
+                .iter()
+                .find(|r| &*r.refid == refid && !r.gain_cad.is_empty());
+            r.unwrap()
+                .notes
+                .contains("exempt, left out of the taxable totals")
+        };
+        assert!(notes("U2") && notes("P1"));
+        assert!(!notes("U3") && !notes("P2") && !notes("S1"));
+    }
+
+    #[test]
+    fn exemptions_do_not_depend_on_rounding() {
+        let leg = |time: &str, refid: &str, row_type: &str, asset: &str, amount: &str| {
+            let subtype = if row_type == "trade" { "tradespot" } else { "" };
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                row_type,
+                subtype,
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "U1", "trade", "CAD", "-1400"),
+            leg("2025-01-01 00:00:00", "U1", "trade", "USD", "1000"),
+            leg("2025-02-01 00:00:00", "U2", "trade", "USD", "-1000"),
+            leg("2025-02-01 00:00:00", "U2", "trade", "CAD", "1600.40"),
+            leg("2025-04-01 00:00:00", "B1", "trade", "CAD", "-100.40"),
+            leg("2025-04-01 00:00:00", "B1", "trade", "SOL", "2"),
+            leg("2025-05-01 00:00:00", "S1", "trade", "SOL", "-0.5"),
+            leg("2025-05-01 00:00:00", "S1", "trade", "CAD", "30"),
+            leg("2025-06-01 00:00:00", "P1", "spend", "SOL", "-0.8367"),
+        ];
+        let run = |rounding: Rounding| {
+            let opts = ProcessOptions {
+                exemptions: Exemptions {
+                    fx_per_disposition: Some(dec!(200)),
+                    spends_under: Some(dec!(50)),
+                },
+                rounding,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            process(entries.clone(), &opts).unwrap()
+        };
+        let out = run(Rounding {
+            proceeds_dp: 0,
+            ..Rounding::default()
+        });
+        let shown: Vec<_> = out
+            .report
+            .iter()
+            .filter(|r| !r.gain_cad.is_empty())
+            .map(|r| (r.proceeds_cad.as_str(), r.gain_cad.as_str()))
+            .collect();
+        assert_eq!(shown, [("1600", "200"), ("30", "5"), ("50", "8")]);
+        // The FX gain of 200.40 and the spend's proceeds of 50.202 are over
+        // their limits, whatever the rows show.
+        let whole = &out.totals;
+        assert_eq!(whole.fx_exempt_cad, dec!(0));
+        assert_eq!(whole.personal_use_exempt_cad, dec!(0));
+        let default = &run(Rounding::default()).totals;
+        assert_eq!(
+            (whole.capital_gain_cad, whole.taxable_capital_gain_cad),
+            (default.capital_gain_cad, default.taxable_capital_gain_cad)
+        );
+    }
+
+    #[test]
+    fn stablecoins_as_fiat_are_valued_at_the_usd_rate() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-1350"),
+            leg("2025-01-01 00:00:00", "B1", "USDC", "1000"),
+            leg("2025-02-01 00:00:00", "S1", "USDC", "-500"),
+            leg("2025-02-01 00:00:00", "S1", "SOL", "2"),
+        ];
+        let plain = process(entries.clone(), &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(plain.totals.capital_gain_cad, dec!(0));
+
+        let base = ProcessOptions::new(2025, dec!(1.4));
+        let par = ProcessOptions {
+            price_chain: base
+                .price_chain
+                .clone()
+                .with_usd_par(fiat_stablecoins(["usdc"]).unwrap()),
+            ..base
+        };
+        // 500 USDC at the 1.4 fallback rate, against 675 of ACB.
+        let out = process(entries.clone(), &par).unwrap();
+        assert_eq!(out.totals.capital_gain_cad, dec!(25));
+        assert_eq!(out.pools["SOL"].acb_cad, dec!(700));
+
+        let skip = ProcessOptions {
+            stablecoin_dispositions: StablecoinDispositions::Skip,
+            ..par
+        };
+        let out = process(entries, &skip).unwrap();
+        assert_eq!(out.totals.capital_gain_cad, dec!(0));
+        assert_eq!(out.report[1].gain_cad, "");
+        assert_eq!(out.pools["SOL"].acb_cad, dec!(700));
+        assert!(fiat_stablecoins(["SOL"]).is_err());
+    }
+
+    #[test]
+    fn wrap_policy_merges_pools_or_books_a_disposition() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B1", "BTC", "1"),
+            leg("2025-02-01 00:00:00", "W1", "BTC", "-1"),
+            leg("2025-02-01 00:00:00", "W1", "WBTC", "0.999"),
+            leg("2025-03-01 00:00:00", "S1", "WBTC", "-0.999"),
+            leg("2025-03-01 00:00:00", "S1", "CAD", "150"),
+        ];
+        let with_policy = |policy: WrapPolicy| ProcessOptions {
+            wraps: vec![Wrap {
+                wrapped: "WBTC".into(),
+                underlying: vec!["BTC".into()],
+                policy,
+            }],
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+
+        let out = process(entries.clone(), &with_policy(WrapPolicy::Merge)).unwrap();
+        let kinds: Vec<_> = out.report.iter().map(|r| r.event_type.as_str()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "trade_acquisition",
+                "wrap",
+                "wrap_fee_disposition",
+                "trade_disposition"
+            ]
+        );
+        // 0.1 of ACB lost to the wrap, then 150 against the other 99.9.
+        assert_eq!(out.report[2].gain_cad, "-0.10");
+        assert_eq!(out.report[3].gain_cad, "50.10");
+        assert!(out.report[3].notes.contains("Ledger asset WBTC"));
+        assert!(!out.pools.contains_key("WBTC"));
+
+        let out = process(entries, &with_policy(WrapPolicy::Taxable)).unwrap();
+        let wrap = &out.report[1];
+        assert_eq!(
+            (wrap.event_type.as_str(), &*wrap.asset),
+            ("trade_disposition", "BTC")
+        );
+        assert!(wrap.notes.contains("taxable wrap policy"));
+        assert_eq!(out.totals.capital_gain_cad, dec!(50));
+    }
+
+    #[test]
+    fn separate_receipt_tokens_carry_the_cost_in_and_out() {
+        let leg = |time: &str, refid: &str, row_type: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                row_type,
+                "",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "trade", "CAD", "-3000"),
+            leg("2025-01-01 00:00:00", "B1", "trade", "ETH", "1"),
+            leg("2025-01-01 00:00:00", "B2", "trade", "CAD", "-1400"),
+            leg("2025-01-01 00:00:00", "B2", "trade", "USDC", "1000"),
+            // A liquidity deposit and its withdrawal, as the on-chain import
+            // books them.
+            leg("2025-02-01 00:00:00", "0xaa", "withdrawal", "ETH", "-1"),
+            leg("2025-02-01 00:00:00", "0xaa", "withdrawal", "USDC", "-1000"),
+            leg("2025-02-01 00:00:00", "0xaa", "deposit", "UNI-V2", "10"),
+            leg("2025-03-01 00:00:00", "0xbb", "withdrawal", "UNI-V2", "-10"),
+            leg("2025-03-01 00:00:00", "0xbb", "deposit", "ETH", "1.2"),
+            leg("2025-03-01 00:00:00", "0xbb", "deposit", "USDC", "800"),
+        ];
+        let opts = ProcessOptions {
+            wraps: vec![Wrap {
+                wrapped: "UNI-V2".into(),
+                underlying: vec!["ETH".into(), "USDC".into()],
+                policy: WrapPolicy::Separate,
+            }],
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let rows: Vec<_> = out
+            .report
+            .iter()
+            .filter(|r| r.event_type.starts_with("receipt_"))
+            .map(|r| (r.event_type.as_str(), &*r.asset, r.acb_added_cad.as_str()))
+            .collect();
+        // Redeemed at 3600 of ETH and 1120 of USDC: the 4400 splits by value.
+        assert_eq!(
+            rows,
+            [
+                ("receipt_mint", "ETH", ""),
+                ("receipt_mint", "USDC", ""),
+                ("receipt_mint", "UNI-V2", "4400.00"),
+                ("receipt_redeem", "UNI-V2", ""),
+                ("receipt_redeem", "ETH", "3355.93"),
+                ("receipt_redeem", "USDC", "1044.07"),
+            ]
+        );
+        assert!(out.report[2].notes.contains("not a disposition"));
+        assert_eq!(out.totals.capital_gain_cad, dec!(0));
+        assert_eq!(
+            out.pools.get("UNI-V2").map_or(dec!(0), |p| p.units),
+            dec!(0)
+        );
+        let path =
+            std::env::temp_dir().join(format!("kraken_acb_wraps_{}.csv", std::process::id()));
+        std::fs::write(&path, "wrapped,underlying,policy\nLP,ETH+USDC,merge\n").unwrap();
+        let loaded = load_wraps(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn per_wallet_pools_move_acb_with_transfers() {
+        let leg = |wallet: &str, time: &str, refid: &str, row_type: &str, asset: &str, amount| {
+            LedgerEntry {
+                wallet: Some(wallet.into()),
+                ..entry(
+                    time,
+                    &format!("T{}{}", refid, asset),
+                    refid,
+                    row_type,
+                    if row_type == "trade" { "tradespot" } else { "" },
+                    asset,
+                    amount,
+                    "0",
+                )
+            }
+        };
+        let mut entries = vec![
+            leg(
+                "kraken",
+                "2025-01-01 00:00:00",
+                "B1",
+                "trade",
+                "CAD",
+                "-100",
+            ),
+            leg("kraken", "2025-01-01 00:00:00", "B1", "trade", "BTC", "1"),
+            leg(
+                "kraken",
+                "2025-01-02 00:00:00",
+                "B2",
+                "trade",
+                "CAD",
+                "-300",
+            ),
+            leg("kraken", "2025-01-02 00:00:00", "B2", "trade", "BTC", "1"),
+            leg(
+                "kraken",
+                "2025-02-01 00:00:00",
+                "W1",
+                "withdrawal",
+                "BTC",
+                "-1",
+            ),
+            leg("cold", "2025-02-01 01:00:00", "D1", "deposit", "BTC", "1"),
+            leg("cold", "2025-03-01 00:00:00", "B3", "trade", "CAD", "-500"),
+            leg("cold", "2025-03-01 00:00:00", "B3", "trade", "BTC", "1"),
+            leg("cold", "2025-04-01 00:00:00", "S1", "trade", "BTC", "-1"),
+            leg("cold", "2025-04-01 00:00:00", "S1", "trade", "CAD", "400"),
+        ];
+        assert_eq!(match_transfers(&mut entries), 1);
+        let run = |pooling| {
+            let opts = ProcessOptions {
+                pooling,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            process(entries.clone(), &opts).unwrap()
+        };
+        // One pool of 3 BTC at 900, or cold's 1 BTC at the 200 average moved
+        // in plus 1 at 500.
+        assert_eq!(run(Pooling::Universal).totals.capital_gain_cad, dec!(100));
+        let out = run(Pooling::PerWallet);
+        assert_eq!(out.totals.capital_gain_cad, dec!(50));
+        let acb = |key: &str| out.pools.get(key).map(|p| p.acb_cad);
+        assert_eq!(acb("BTC@kraken"), Some(dec!(200)));
+        assert_eq!(acb("BTC@cold"), Some(dec!(350)));
+        let moved: Vec<_> = out
+            .report
+            .iter()
+            .filter(|r| r.event_type.starts_with("wallet_transfer"))
+            .map(|r| (r.event_type.as_str(), r.pool_acb_cad_after.as_str()))
+            .collect();
+        assert_eq!(
+            moved,
+            [
+                ("wallet_transfer_out", "200"),
+                ("wallet_transfer_in", "200")
+            ]
+        );
+    }
+
+    #[test]
+    fn report_amounts_follow_the_rounding_options() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B1", "SOL", "3"),
+            leg("2025-02-01 00:00:00", "S1", "SOL", "-1"),
+            leg("2025-02-01 00:00:00", "S1", "CAD", "40.00005"),
+        ];
+        let mut rounding = Rounding {
+            strategy: Rounding::parse_strategy("half-even").unwrap(),
+            ..Rounding::default()
+        };
+        rounding.set_places("proceeds=4, acb=4").unwrap();
+        let run = |rounding| {
+            let opts = ProcessOptions {
+                rounding,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            let out = process(entries.clone(), &opts).unwrap();
+            let sell = out.report.into_iter().find(|r| &*r.refid == "S1").unwrap();
+            (
+                sell.proceeds_cad,
+                sell.acb_disposed_cad,
+                sell.gain_cad,
+                sell.pool_acb_cad_after,
+            )
+        };
+        assert_eq!(
+            run(Rounding::default()),
+            (
+                "40.00".into(),
+                "33.33".into(),
+                "6.67".into(),
+                "66.67".into()
+            )
+        );
+        // The midpoint 40.00005 goes to the even digit.
+        assert_eq!(
+            run(rounding),
+            (
+                "40.0000".into(),
+                "33.3333".into(),
+                "6.6667".into(),
+                "66.6667".into()
+            )
+        );
+        assert!(rounding.set_places("fees=4").is_err());
+        assert!(Rounding::parse_strategy("sideways").is_err());
+
+        // `--no-rounding` leaves the report and side reports exact.
+        let (proceeds, acb, gain, _) = run(Rounding {
+            full_precision: true,
+            ..Rounding::default()
+        });
+        assert_eq!(proceeds, "40.00005");
+        let (acb, gain) = (parse_decimal(&acb).unwrap(), parse_decimal(&gain).unwrap());
+        assert_eq!(acb * dec!(3), dec!(100));
+        assert_eq!(acb + gain, dec!(40.00005));
+    }
+
+    #[test]
+    fn round_row_breaks_ties_by_strategy_and_column() {
+        let refid: Arc<str> = Arc::from("S1");
+        let mut row = make_row(
+            stamp(parse_time("2025-02-01 00:00:00").unwrap(), None),
+            &refid,
+            &refid,
+            "trade_disposition",
+            &"SOL".into(),
+        );
+        row.proceeds_cad = "2.345".to_string();
+        row.gain_cad = "-2.345".to_string();
+        row.acb_disposed_cad = "4.69".to_string();
+        row.units_out = "0.123456785".to_string();
+        let rounded = |rounding: Rounding| {
+            let mut r = row.clone();
+            rounding.round_row(&mut r).unwrap();
+            (r.proceeds_cad, r.gain_cad, r.acb_disposed_cad, r.units_out)
+        };
+        assert_eq!(
+            rounded(Rounding::default()),
+            (
+                "2.35".into(),
+                "-2.35".into(),
+                "4.69".into(),
+                "0.12345679".into()
+            )
+        );
+        let mut half_even = Rounding {
+            strategy: Rounding::parse_strategy("half-even").unwrap(),
+            ..Rounding::default()
+        };
+        assert_eq!(
+            rounded(half_even),
+            (
+                "2.34".into(),
+                "-2.34".into(),
+                "4.69".into(),
+                "0.12345678".into()
+            )
+        );
+        // Only the named column changes its places.
+        half_even.set_places("acb=1").unwrap();
+        assert_eq!(
+            rounded(half_even),
+            (
+                "2.34".into(),
+                "-2.34".into(),
+                "4.7".into(),
+                "0.12345678".into()
+            )
+        );
+        let exact = Rounding {
+            full_precision: true,
+            ..half_even
+        };
+        assert_eq!(rounded(exact).0, "2.345");
+    }
+
+    #[test]
+    fn rounded_rows_drift_from_the_totals_by_at_most_rounding() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B1", "SOL", "3"),
+            leg("2025-02-01 00:00:00", "S1", "SOL", "-1"),
+            leg("2025-02-01 00:00:00", "S1", "CAD", "40.004"),
+            leg("2025-03-01 00:00:00", "S2", "SOL", "-1"),
+            leg("2025-03-01 00:00:00", "S2", "CAD", "40.004"),
+        ];
+        let drift = |rounding| {
+            let opts = ProcessOptions {
+                rounding,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            process(entries.clone(), &opts).unwrap().totals.row_drift
+        };
+        // Two rows of 40.00 against 80.008, and ACB of 33.33 twice against
+        // two thirds of 100.
+        let rounded = drift(Rounding::default());
+        assert_eq!(rounded.proceeds_cad, dec!(-0.008));
+        assert_eq!(rounded.acb_disposed_cad.round_dp(6), dec!(-0.006667));
+        assert_eq!(rounded.capital_gain_cad.round_dp(6), dec!(-0.001333));
+        assert_eq!(rounded.max_rounding_cad, dec!(0.02));
+        assert!(rounded.within_rounding());
+
+        let exact = drift(Rounding {
+            full_precision: true,
+            ..Rounding::default()
+        });
+        assert_eq!(exact.amounts(), [dec!(0); 4]);
+        assert!(exact.within_rounding());
+    }
+
+    #[test]
+    fn ending_pools_are_valued_at_the_last_price() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2025-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B1", "SOL", "2"),
+            leg("2025-06-01 00:00:00", "B2", "CAD", "-60"),
+            leg("2025-06-01 00:00:00", "B2", "SOL", "1"),
+        ];
+        let opts = ProcessOptions::new(2025, dec!(1.4));
+        let out = process(entries, &opts).unwrap();
+        let end = parse_time("2025-12-31 23:59:59").unwrap();
+        let rows = ending_pool_rows(&out.pools, &out.prices, opts.price_chain.at(end));
+        assert_eq!(rows.len(), 1);
+        let sol = &rows[0];
+        assert_eq!(&*sol.asset, "SOL");
+        assert_eq!((sol.units, sol.acb_cad), (dec!(3), dec!(160)));
+        assert_eq!(sol.last_price_cad, Some(dec!(60)));
+        assert_eq!(sol.fmv_cad, Some(dec!(180)));
+        assert_eq!(sol.price_source, "trade_implied");
+    }
+
+    #[test]
+    fn same_time_events_follow_the_tie_break_policy() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let mut entries = vec![
+            leg("2025-01-01 00:00:00", "B0", "CAD", "-100"),
+            leg("2025-01-01 00:00:00", "B0", "SOL", "1"),
+            // A sale and a purchase in the same second; the sale's refid
+            // sorts first.
+            leg("2025-02-01 00:00:00", "Z1", "CAD", "-300"),
+            leg("2025-02-01 00:00:00", "Z1", "SOL", "1"),
+            leg("2025-02-01 00:00:00", "A1", "SOL", "-1"),
+            leg("2025-02-01 00:00:00", "A1", "CAD", "200"),
+        ];
+        let run = |entries: Vec<LedgerEntry>, tie_break| {
+            let opts = ProcessOptions {
+                tie_break,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            let out = process(entries, &opts).unwrap();
+            (out.totals.capital_gain_cad, out.event_hash)
+        };
+        let (by_id, id_hash) = run(entries.clone(), TieBreak::Id);
+        assert_eq!(by_id, dec!(100));
+        let (buys_first, buys_hash) = run(entries.clone(), TieBreak::AcquisitionsFirst);
+        assert_eq!(buys_first, dec!(0));
+        assert_ne!(id_hash, buys_hash);
+        // The export's row order does not matter.
+        entries.reverse();
+        assert_eq!(run(entries, TieBreak::Id), (by_id, id_hash));
+        assert!(TieBreak::parse("file").is_err());
+    }
+
+    #[test]
+    fn trading_activity_sums_the_years_trades() {
+        let leg = |time: &str, refid: &str, asset: &str, amount: &str| {
+            entry(
+                time,
+                &format!("T{}{}", refid, asset),
+                refid,
+                "trade",
+                "tradespot",
+                asset,
+                amount,
+                "0",
+            )
+        };
+        let entries = vec![
+            leg("2024-01-01 00:00:00", "B1", "CAD", "-100"),
+            leg("2024-01-01 00:00:00", "B1", "SOL", "1"),
+            leg("2025-01-01 00:00:00", "B2", "CAD", "-200"),
+            leg("2025-01-01 00:00:00", "B2", "ETH", "1"),
+            leg("2025-01-11 00:00:00", "S1", "SOL", "-1"),
+            leg("2025-01-11 00:00:00", "S1", "CAD", "300"),
+            leg("2025-01-21 00:00:00", "S2", "ETH", "-1"),
+            leg("2025-01-21 00:00:00", "S2", "CAD", "150"),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let a = &out.totals.activity;
+        assert_eq!((a.trades, a.assets_traded), (3, 2));
+        assert_eq!(a.volume_cad, dec!(650));
+        // 376 days on 300 of proceeds, 20 days on 150.
+        assert_eq!(a.avg_holding_days.unwrap().round_dp(2), dec!(257.33));
+        let gain = a.largest_gain.as_ref().unwrap();
+        assert_eq!((&*gain.refid, gain.gain_cad), ("S1", dec!(200)));
+        let loss = a.largest_loss.as_ref().unwrap();
+        assert_eq!((&*loss.refid, loss.gain_cad), ("S2", dec!(-50)));
+    }
+
+    #[test]
+    fn expense_fee_mode_keeps_fee_out_of_acb() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "1.0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+        ];
+
+        let embedded = process(entries.clone(), &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(embedded.pools.get("SOL").unwrap().acb_cad, dec!(101.0));
+
+        let opts = ProcessOptions {
+            fee_mode: FeeMode::Expense,
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let expensed = process(entries, &opts).unwrap();
+        assert_eq!(expensed.pools.get("SOL").unwrap().acb_cad, dec!(100.0));
+        assert_eq!(expensed.totals.trade_fee_expense_cad, dec!(1.0));
+        assert_eq!(expensed.report[0].fee_cad, "1.0");
+    }
+
+    #[test]
+    fn merging_inputs_drops_repeats_and_reports_conflicts_and_overlaps() {
+        let jan = vec![
+            entry(
+                "2025-01-05 00:00:00",
+                "T1",
+                "R1",
+                "deposit",
+                "",
+                "BTC",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-01-20 00:00:00",
+                "T2",
+                "R2",
+                "deposit",
+                "",
+                "ETH",
+                "2",
+                "0",
+            ),
+        ];
+        let jan_feb = vec![
+            entry(
+                "2025-01-20 00:00:00",
+                "T2",
+                "R2",
+                "deposit",
+                "",
+                "ETH",
+                "2",
+                "0",
+            ),
+            entry(
+                "2025-01-05 00:00:00",
+                "T1",
+                "R1",
+                "deposit",
+                "",
+                "BTC",
+                "1.5",
+                "0",
+            ),
+            entry(
+                "2025-02-10 00:00:00",
+                "T3",
+                "R3",
+                "deposit",
+                "",
+                "BTC",
+                "1",
+                "0",
+            ),
+        ];
+        let manual = vec![entry(
+            "2025-01-10 00:00:00",
+            "M1",
+            "M1",
+            "deposit",
+            "",
+            "SOL",
+            "3",
+            "0",
+        )];
+        let (entries, issues) = merge_inputs(vec![
+            ("jan.csv", ImportFormat::Kraken, jan),
+            ("jan_feb.csv", ImportFormat::Kraken, jan_feb),
+            ("manual.csv", ImportFormat::Manual, manual),
+        ]);
+        let txids: Vec<&str> = entries.iter().map(|e| e.txid.as_ref()).collect();
+        assert_eq!(txids, vec!["T1", "T2", "T1", "T3", "M1"]);
+        let kinds: Vec<(&str, &str)> = issues.iter().map(|d| (d.kind, d.txid.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (OVERLAPPING_INPUTS, ""),
+                (DUPLICATE_ROW, "T2"),
+                (CONFLICTING_TXID, "T1")
+            ]
+        );
+        assert_eq!(
+            issues[0].describe(),
+            "overlapping_inputs: jan.csv (2025-01-05 00:00:00 to 2025-01-20 00:00:00) and \
+             jan_feb.csv (2025-01-05 00:00:00 to 2025-02-10 00:00:00) cover the same period \
+             up to 2025-01-20 00:00:00"
+        );
+    }
+
+    #[test]
+    fn gaps_flag_quiet_months_and_sales_before_purchases() {
+        let entries = vec![
+            entry(
+                "2024-11-03 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100",
+                "0",
+            ),
+            entry(
+                "2024-11-03 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "BTC",
+                "0.01",
+                "0",
+            ),
+            entry(
+                "2025-03-09 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "BTC",
+                "-0.02",
+                "0",
+            ),
+            entry(
+                "2025-03-09 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "190",
+                "0",
+            ),
+            entry(
+                "2025-04-01 00:00:00",
+                "T5",
+                "R3",
+                "withdrawal",
+                "",
+                "BTC",
+                "-1",
+                "0",
+            ),
+        ];
+        let issues = ledger_gaps(&entries);
+        let described: Vec<String> = issues.iter().map(DataIssue::describe).collect();
+        assert_eq!(
+            described,
+            vec![
+                "inactive_months: no ledger rows from 2024-12 to 2025-02 (3 month(s)) between \
+                 active periods; if the account was used then, an export range may be missing",
+                "disposal_before_acquisition: BTC row T3 (refid R2) at 2025-03-09 00:00:00: the \
+                 rows so far leave -0.01 BTC; an earlier export range may be missing",
+            ]
+        );
+        // A quiet month is only a note; the negative balance sets exit code 5.
+        assert!(issues[0].is_note() && !issues[1].is_note());
+        let err = explain_failure("insufficient units".into(), &issues).to_string();
+        assert!(
+            err.starts_with("insufficient units; the inputs may be incomplete: inactive_months")
+        );
+        assert_eq!(explain_failure("bad".into(), &[]).to_string(), "bad");
+    }
+
+    #[test]
+    fn exclusions_and_overrides_edit_rows_and_are_listed() {
+        let mut entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "deposit",
+                "",
+                "BTC",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T2",
+                "R2",
+                "deposit",
+                "",
+                "BTC",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-01-03 00:00:00",
+                "T3",
+                "R3",
+                "withdrawal",
+                "",
+                "BTC",
+                "-0.5",
+                "0.001",
+            ),
+        ];
+        let excluded = parse_excluded_refids("# reversed deposit\nR2\n\n");
+        let overrides = HashMap::from([(
+            "T3".to_string(),
+            RowOverride {
+                amount: Some(dec!(-0.4)),
+                fee: None,
+            },
+        )]);
+        let edits = apply_ledger_edits(&mut entries, &excluded, &overrides).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].net_delta, dec!(-0.401));
+        assert_eq!(edits.len(), 2);
+        assert_eq!(
+            edits[0].describe(),
+            "BTC row T2 (refid R2) at 2025-01-02 00:00:00 excluded (amount 1, fee 0)"
+        );
+        assert!(
+            edits[1]
+                .note
+                .contains("amount -0.5 -> -0.4, fee 0.001 -> 0.001")
+        );
+
+        let typo = HashSet::from(["R9".to_string()]);
+        assert!(apply_ledger_edits(&mut entries, &typo, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn malformed_trade_groups_are_explained_and_repaired() {
+        let row = |time, txid, asset, amount, fee| {
+            entry(time, txid, "R1", "trade", "tradespot", asset, amount, fee)
+        };
+        let mut entries = vec![
+            row("2025-01-01 00:00:00", "T1", "CAD", "-100", "0"),
+            row("2025-01-01 00:00:00", "T2", "BTC", "0.4", "0"),
+            row("2025-01-01 00:00:00", "T3", "BTC", "0.6", "0"),
+            row("2025-01-01 00:00:02", "T4", "SOL", "0", "0.1"),
+        ];
+        let err = build_trade_groups(&entries, &Canada, 2025)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expected 2 rows, got 4"));
+        assert!(err.contains("T4 2025-01-01 00:00:02 SOL amount 0 fee 0.1"));
+        assert!(err.contains("likely cause: a fee-only leg"));
+
+        let edits = repair_trade_groups(&mut entries);
+        assert_eq!(edits.len(), 2);
+        assert!(edits[1].describe().contains("merged into T2"));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].amount, dec!(1));
+        assert_eq!(
+            (entries[2].row_type.as_str(), entries[2].net_delta),
+            ("adjustment", dec!(-0.1))
+        );
+        assert_eq!(
+            build_trade_groups(&entries, &Canada, 2025).unwrap().len(),
+            1
+        );
+
+        let mut late = vec![
+            row("2025-01-01 00:00:00", "T1", "CAD", "-100", "0"),
+            row("2025-01-01 00:00:05", "T2", "BTC", "1", "0"),
+        ];
+        let err = build_trade_groups(&late, &Canada, 2025)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("mismatched times"));
+        assert_eq!(repair_trade_groups(&mut late).len(), 1);
+        assert_eq!(late[1].time, late[0].time);
+    }
+
+    #[test]
+    fn aggregate_fills_merges_nearby_fills_of_one_pair() {
+        let trade = |time, refid: &str, cad: &str, sol: &str| {
+            vec![
+                entry(
+                    time,
+                    &format!("{}C", refid),
+                    refid,
+                    "trade",
+                    "tradespot",
+                    "CAD",
+                    cad,
+                    "0",
+                ),
+                entry(
+                    time,
+                    &format!("{}S", refid),
+                    refid,
+                    "trade",
+                    "tradespot",
+                    "SOL",
+                    sol,
+                    "0",
+                ),
+            ]
+        };
+        let entries = [
+            trade("2025-01-01 00:00:00", "R1", "-100", "1"),
+            trade("2025-01-01 00:00:30", "R2", "-210", "2"),
+            trade("2025-01-01 00:01:00", "R3", "120", "-1"),
+            trade("2025-01-01 00:01:20", "R4", "-50", "0.5"),
+            trade("2025-01-01 00:05:00", "R5", "-50", "0.5"),
+        ]
+        .concat();
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let (report, fills) = aggregate_fills(out.report, chrono::TimeDelta::seconds(60)).unwrap();
+
+        let refids: Vec<&str> = report.iter().map(|r| r.refid.as_ref()).collect();
+        assert_eq!(refids, vec!["R1", "R3", "R4", "R5"]);
+        let merged = &report[0];
+        assert_eq!(
+            (merged.units_in.as_str(), merged.acb_added_cad.as_str()),
+            ("3", "310")
+        );
+        assert_eq!(merged.pool_units_after, "3");
+        assert_eq!(
+            merged.notes,
+            "2 fills merged, refids R1 to R2; see the fills report"
+        );
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[1].refid.as_ref(), "R2");
+    }
+
+    #[test]
+    fn aggregate_rewards_sums_income_per_asset_and_day() {
+        let buy = |refid: &str, cad: &str, asset, units| {
+            vec![
+                entry(
+                    "2025-01-01 00:00:00",
+                    &format!("{}C", refid),
+                    refid,
+                    "trade",
+                    "tradespot",
+                    "CAD",
+                    cad,
+                    "0",
+                ),
+                entry(
+                    "2025-01-01 00:00:00",
+                    &format!("{}A", refid),
+                    refid,
+                    "trade",
+                    "tradespot",
+                    asset,
+                    units,
+                    "0",
+                ),
+            ]
+        };
+        let reward = |time, txid, asset, amount| {
+            entry(time, txid, txid, "earn", "reward", asset, amount, "0")
+        };
+        let entries = [
+            buy("B1", "-100", "SOL", "1"),
+            buy("B2", "-10", "DOT", "1"),
+            vec![
+                reward("2025-01-02 01:00:00", "W1", "SOL", "0.01"),
+                reward("2025-01-02 09:00:00", "W2", "SOL", "0.02"),
+                reward("2025-01-02 10:00:00", "W3", "DOT", "0.5"),
+                reward("2025-01-03 01:00:00", "W4", "SOL", "0.01"),
+            ],
+        ]
+        .concat();
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let report = aggregate_rewards(out.report, RewardPeriod::Daily).unwrap();
+
+        let txids: Vec<&str> = report.iter().map(|r| r.txid.as_ref()).collect();
+        assert_eq!(txids, vec!["B1A", "B2A", "W2", "W3", "W4"]);
+        assert_eq!(
+            (report[2].units_in.as_str(), report[2].income_cad.as_str()),
+            ("0.03", "3.00")
+        );
+        assert_eq!(report[2].pool_units_after, "1.03");
+        assert!(
+            report[2]
+                .notes
+                .starts_with("2 rewards for 2025-01-02 summed")
+        );
+        let income: Decimal = report
+            .iter()
+            .filter(|r| !r.income_cad.is_empty())
+            .map(|r| parse_decimal(&r.income_cad).unwrap())
+            .sum();
+        assert_eq!(income, out.totals.reward_income_cad);
+    }
+
+    #[test]
+    fn daily_closes_value_rewards_and_missing_days_fall_back() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "B1",
+                "B",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "B2",
+                "B",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-01-02 08:00:00",
+                "W1",
+                "W1",
+                "earn",
+                "reward",
+                "SOL",
+                "0.1",
+                "0",
+            ),
+            entry(
+                "2025-01-03 08:00:00",
+                "W2",
+                "W2",
+                "earn",
+                "reward",
+                "SOL",
+                "0.1",
+                "0",
+            ),
+        ];
+        let opts = ProcessOptions {
+            income_valuation: IncomeValuation::DailyClose,
+            daily_closes: prices::parse("date,asset,close,currency\n2025-01-02,SOL,100,USD\n")
+                .unwrap(),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let (closed, missing) = (&out.report[1], &out.report[2]);
+        assert_eq!(closed.income_cad, "14.00");
+        assert_eq!(
+            (closed.price_source.as_str(), closed.fx_source.as_str()),
+            ("daily_close", "fallback")
+        );
+        assert_eq!(missing.income_cad, "10.0");
+        assert_eq!(missing.price_source, "trade_implied");
+        assert!(
+            missing
+                .notes
+                .contains("No daily close for SOL on 2025-01-03")
+        );
+    }
+
+    #[test]
+    fn stale_prices_warn_or_refuse_past_max_age() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "B1",
+                "B",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "B2",
+                "B",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-03-01 00:00:00",
+                "W1",
+                "W1",
+                "earn",
+                "reward",
+                "SOL",
+                "0.1",
+                "0",
+            ),
+            entry(
+                "2025-06-01 00:00:00",
+                "W2",
+                "W2",
+                "earn",
+                "reward",
+                "SOL",
+                "0.1",
+                "0",
+            ),
+        ];
+        let opts = ProcessOptions {
+            max_price_age: Some(90),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries.clone(), &opts).unwrap();
+        assert_eq!(out.totals.stale_price_count, 1);
+        assert!(out.report[1].notes.is_empty());
+        assert_eq!(
+            out.report[2].notes,
+            "Stale price: valued at a price last traded on 2025-01-01, 151 days earlier"
+        );
+
+        let refuse = ProcessOptions {
+            stale_prices: StalePrices::Refuse,
+            ..opts
+        };
+        let err = process(entries, &refuse).unwrap_err().to_string();
+        assert!(err.starts_with("earn_reward_income W2: valued at a price last traded"));
+    }
+
+    #[test]
+    fn rebate_income_mode_books_rebate_separately() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "-0.5",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+        ];
+
+        let cost = process(entries.clone(), &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(cost.pools.get("SOL").unwrap().acb_cad, dec!(99.5));
+        assert_eq!(cost.totals.fee_rebate_cad, dec!(0.5));
+        assert!(cost.report[0].notes.contains("Maker rebate"));
+
+        let opts = ProcessOptions {
+            rebate_mode: RebateMode::Income,
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let income = process(entries, &opts).unwrap();
+        assert_eq!(income.pools.get("SOL").unwrap().acb_cad, dec!(100.0));
+        assert_eq!(income.totals.rebate_income_cad, dec!(0.5));
+        assert!(
+            income
+                .report
+                .iter()
+                .any(|r| r.event_type == "trade_fee_rebate_income")
+        );
+    }
+
+    #[test]
+    fn fee_free_trades_total_a_positive_zero_rebate() {
+        let entries = vec![
+            leg("R1", "2025-01-01 00:00:00", "CAD", dec!(-100)),
+            leg("R1", "2025-01-01 00:00:00", "SOL", dec!(1)),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let totals = serde_json::to_value(&out.totals).unwrap();
+        assert_eq!(totals["fee_rebate_cad"], "0");
+    }
+
+    #[test]
+    fn spend_receive_pair_is_processed_as_trade() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "spend",
+                "",
+                "CAD",
+                "-100.0",
+                "1.5",
+            ),
+            entry(
+                "2025-01-01 00:00:01",
+                "T2",
+                "R1",
+                "receive",
+                "",
+                "SOL",
+                "0.7",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "",
+                "SOL",
+                "-0.2",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "",
+                "CAD",
+                "30.0",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let events: Vec<_> = out.report.iter().map(|r| r.event_type.as_str()).collect();
+        assert_eq!(events, vec!["trade_acquisition", "trade_disposition"]);
+        let sol = out.pools.get("SOL").unwrap();
+        assert_eq!(q8(sol.units), dec!(0.5));
+        assert_eq!(q2(sol.acb_cad), dec!(72.50));
+    }
+
+    #[test]
+    fn unpaired_spend_is_disposition_at_fmv() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2024-12-02 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "SOL",
+                "-0.5",
+                "0",
+            ),
+            entry(
+                "2024-12-02 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "80.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T5",
+                "R3",
+                "spend",
+                "",
+                "SOL",
+                "-0.25",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let row = &out.report[0];
+        assert_eq!(row.event_type, "spend_disposition");
+        assert_eq!(row.proceeds_cad, "40.00");
+        assert_eq!(out.totals.capital_gain_cad, dec!(15.00));
+    }
+
+    #[test]
+    fn legacy_staking_rows_are_reward_income() {
+        let entries = vec![
+            entry(
+                "2021-06-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2021-06-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "",
+                "DOT",
+                "5.0",
+                "0",
+            ),
+            entry(
+                "2021-07-01 00:00:00",
+                "T3",
+                "R2",
+                "staking",
+                "",
+                "DOT",
+                "0.5",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2021, dec!(1.25))).unwrap();
+        assert_eq!(out.totals.reward_income_cad, dec!(10.0));
+        assert_eq!(out.report[1].event_type, "earn_reward_income");
+    }
+
+    #[test]
+    fn credit_rows_are_income_with_acb() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "BTC",
+                "0.001",
+                "0",
+            ),
+            entry(
+                "2025-02-01 00:00:00",
+                "T3",
+                "R2",
+                "credit",
+                "",
+                "BTC",
+                "0.0001",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.report[0].event_type, "credit_income");
+        assert_eq!(out.totals.credit_income_cad, dec!(10.0));
+        assert_eq!(out.pools.get("BTC").unwrap().acb_cad, dec!(110.0));
+    }
+
+    #[test]
+    fn dividends_have_their_own_income_bucket() {
+        let entries = vec![
+            entry(
+                "2025-03-01 00:00:00",
+                "T1",
+                "R1",
+                "dividend",
+                "",
+                "USD",
+                "5.0",
+                "0",
+            ),
+            entry(
+                "2025-03-02 00:00:00",
+                "T2",
+                "R2",
+                "earn",
+                "dividend",
+                "USDG",
+                "1.0",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.totals.dividend_income_cad, dec!(8.4));
+        assert_eq!(out.totals.interest_income_cad, dec!(0));
+        assert!(out.report.iter().all(|r| r.event_type == "dividend_income"));
+    }
+
+    #[test]
+    fn nft_trades_pool_each_identifier() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-4000.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "ETH",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "nfttrade",
+                "",
+                "ETH",
+                "-0.5",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T4",
+                "R2",
+                "nfttrade",
+                "",
+                "PUNK.1234",
+                "1",
+                "0",
+            ),
+            entry(
+                "2025-02-01 00:00:00",
+                "T5",
+                "R3",
+                "nfttrade",
+                "",
+                "PUNK.1234",
+                "-1",
+                "0",
+            ),
+            entry(
+                "2025-02-01 00:00:00",
+                "T6",
+                "R3",
+                "nfttrade",
+                "",
+                "ETH",
+                "0.75",
+                "0",
+            ),
+            entry(
+                "2025-02-01 00:00:00",
+                "T7",
+                "R4",
+                "nftcreatorfee",
+                "",
+                "ETH",
+                "-0.05",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let events: Vec<_> = out.report.iter().map(|r| r.event_type.as_str()).collect();
+        assert_eq!(
+            events,
+            vec![
+                "nft_disposition",
+                "nft_acquisition",
+                "nft_disposition",
+                "nft_acquisition",
+                "nft_fee_disposition",
+            ]
+        );
+        let punk = out.pools.get("PUNK.1234").unwrap();
+        assert!(punk.units.is_zero());
+        assert_eq!(out.report[2].proceeds_cad, "3000.00");
+        assert_eq!(out.report[2].gain_cad, "1000.00");
+    }
+
+    #[test]
+    fn adjustments_follow_configured_policy() {
+        let entries = vec![
+            entry(
+                "2024-12-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2024-12-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-01 00:00:00",
+                "T3",
+                "R2",
+                "adjustment",
+                "",
+                "SOL",
+                "1.0",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T4",
+                "R3",
+                "adjustment",
+                "",
+                "SOL",
+                "-0.5",
+                "0",
+            ),
+        ];
+
+        let zero_cost = process(entries.clone(), &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(zero_cost.report[0].event_type, "adjustment_acquisition");
+        assert_eq!(zero_cost.report[1].event_type, "adjustment_disposition");
+        assert_eq!(zero_cost.totals.capital_gain_cad, dec!(-25.0));
+        assert_eq!(zero_cost.pools.get("SOL").unwrap().acb_cad, dec!(75.0));
+
+        let opts = ProcessOptions {
+            adjustment_mode: AdjustmentMode::Income,
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let income = process(entries, &opts).unwrap();
+        assert_eq!(income.report[0].event_type, "adjustment_income");
+        assert_eq!(income.totals.other_income_cad, dec!(100.0));
+    }
+
+    #[test]
+    fn migration_carries_acb_into_new_ticker() {
+        let entries = vec![
+            entry(
+                "2021-01-01 00:00:00",
+                "T1",
+                "R1",
+                "trade",
+                "tradespot",
+                "CAD",
+                "-100.0",
+                "0",
+            ),
+            entry(
+                "2021-01-01 00:00:00",
+                "T2",
+                "R1",
+                "trade",
+                "tradespot",
+                "NANO",
+                "10.0",
+                "0",
+            ),
+            entry(
+                "2021-09-01 00:00:00",
+                "T3",
+                "R2",
+                "trade",
+                "tradespot",
+                "XNO",
+                "-5.0",
+                "0",
+            ),
+            entry(
+                "2021-09-01 00:00:00",
+                "T4",
+                "R2",
+                "trade",
+                "tradespot",
+                "CAD",
+                "80.0",
+                "0",
+            ),
+        ];
+        let opts = ProcessOptions {
+            migrations: vec![Migration {
+                from: "NANO".into(),
+                to: "XNO".into(),
+                time: parse_date_or_time("2021-06-01").unwrap(),
+                factor: dec!(1),
+            }],
+            ..ProcessOptions::new(2021, dec!(1.25))
+        };
+
+        let out = process(entries, &opts).unwrap();
+        assert!(!out.pools.contains_key("NANO"));
+        let xno = out.pools.get("XNO").unwrap();
+        assert_eq!(q8(xno.units), dec!(5));
+        assert_eq!(q2(xno.acb_cad), dec!(50));
+        assert_eq!(out.totals.capital_gain_cad, dec!(30.0));
+        assert!(out.report.iter().any(|r| r.event_type == "asset_migration"));
+    }
+
+    #[test]
+    fn unknown_rows_are_tallied_as_ignored() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "transfer",
+                "spotfromfutures",
+                "BTC",
+                "0.1",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T2",
+                "R2",
+                "transfer",
+                "spotfromfutures",
+                "BTC",
+                "0.2",
+                "0",
+            ),
+            entry(
+                "2025-01-03 00:00:00",
+                "T3",
+                "R3",
+                "margin",
+                "",
+                "BTC",
+                "0.2",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.ignored.len(), 2);
+        assert_eq!(out.ignored[1].row_type, "transfer");
+        assert_eq!(out.ignored[1].count, 2);
+        assert_eq!(out.ignored[1].sample_refids, "R1 R2");
+    }
+
+    #[test]
+    fn classification_rules_remap_unknown_rows() {
+        let rules = parse_classification_rules(
+            "# futures wallet moves\ntransfer/spotfromfutures = internal\ncustom/* = income\n",
+        )
+        .unwrap();
+        let mut entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "transfer",
+                "spotfromfutures",
+                "BTC",
+                "0.1",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T2",
+                "R2",
+                "custom",
+                "bonus",
+                "CAD",
+                "25",
+                "0",
+            ),
+        ];
+        classify_entries(&mut entries, &rules);
+        assert_eq!(entries[0].row_type, "internal");
+        assert_eq!(entries[1].row_type, "credit");
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert!(out.ignored.is_empty());
+        assert_eq!(out.totals.credit_income_cad, dec!(25));
+        assert!(parse_classification_rules("a/b = teleport").is_err());
+    }
+
+    #[test]
+    fn fiat_and_stablecoin_yield_is_interest_income() {
+        let entries = vec![
+            entry(
+                "2025-01-01 00:00:00",
+                "T1",
+                "R1",
+                "earn",
+                "reward",
+                "CAD",
+                "2.5",
+                "0",
+            ),
+            entry(
+                "2025-01-02 00:00:00",
+                "T2",
+                "R2",
+                "earn",
+                "interest",
+                "USDC",
+                "10.0",
+                "0",
+            ),
+        ];
+
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        assert_eq!(out.totals.interest_income_cad, dec!(16.5));
+        assert_eq!(out.totals.reward_income_cad, dec!(0));
+        assert!(!out.pools.contains_key("CAD"));
+        let usdc = out.pools.get("USDC").unwrap();
+        assert_eq!(usdc.acb_cad, dec!(14.0));
+        assert!(
+            out.report
+                .iter()
+                .all(|r| r.event_type == "earn_interest_income")
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::ReportColumn;
+use std::error::Error;
+use std::fmt::Display;
+
+// Language of the console summary and the report's column headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Locale {
+    En,
+    FrCa,
+}
+
+impl Locale {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s.trim().to_lowercase().replace('_', "-").as_str() {
+            "en" | "en-ca" => Ok(Locale::En),
+            "fr" | "fr-ca" => Ok(Locale::FrCa),
+            other => Err(format!("unknown locale: {}", other).into()),
+        }
+    }
+
+    // `en` in this locale. Text without a translation is kept in English.
+    pub(crate) fn text<'a>(&self, en: &'a str) -> &'a str {
+        match self {
+            Locale::En => en,
+            Locale::FrCa => fr_ca(en).unwrap_or(en),
+        }
+    }
+
+    // `template` in this locale, with each `{}` replaced by the next value.
+    pub(crate) fn fill(&self, template: &str, values: &[&dyn Display]) -> String {
+        let mut values = values.iter();
+        let mut parts = self.text(template).split("{}");
+        let mut out = parts.next().unwrap_or("").to_string();
+        for part in parts {
+            if let Some(value) = values.next() {
+                out.push_str(&value.to_string());
+            }
+            out.push_str(part);
+        }
+        out
+    }
+
+    // Every report column with its header in this locale, or `None` to keep
+    // the field names as headers.
+    pub(crate) fn report_columns(&self) -> Option<Vec<ReportColumn>> {
+        match self {
+            Locale::En => None,
+            Locale::FrCa => Some(
+                REPORT_HEADERS_FR_CA
+                    .iter()
+                    .map(|(field, header)| ReportColumn {
+                        field: field.to_string(),
+                        header: header.to_string(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Report field and its fr-CA header, in report column order.
+const REPORT_HEADERS_FR_CA: [(&str, &str); 18] = [
+    ("time", "Date et heure"),
+    ("refid", "Référence"),
+    ("txid", "Transaction"),
+    ("event_type", "Type d'événement"),
+    ("asset", "Actif"),
+    ("units_in", "Unités reçues"),
+    ("units_out", "Unités cédées"),
+    ("proceeds_cad", "Produit de disposition (CAD)"),
+    ("acb_disposed_cad", "PBR aliéné (CAD)"),
+    ("gain_cad", "Gain ou perte (CAD)"),
+    ("income_cad", "Revenu (CAD)"),
+    ("acb_added_cad", "PBR ajouté (CAD)"),
+    ("fee_cad", "Frais (CAD)"),
+    ("pool_units_after", "Unités détenues après"),
+    ("pool_acb_cad_after", "PBR total après (CAD)"),
+    ("price_source", "Source du prix"),
+    ("fx_source", "Source du taux de change"),
+    ("notes", "Remarques"),
+];
+
+// The report field behind a translated header, so localized reports read
+// back like any other.
+pub(crate) fn report_field(header: &str) -> Option<&'static str> {
+    REPORT_HEADERS_FR_CA
+        .iter()
+        .find(|(_, h)| *h == header)
+        .map(|(field, _)| *field)
+}
+
+fn fr_ca(en: &str) -> Option<&'static str> {
+    Some(match en {
+        // Headings.
+        "=== CANADIAN CRYPTO TAX SUMMARY (LEDGER / ACB) ===" => {
+            "=== SOMMAIRE FISCAL DES CRYPTOACTIFS (GRAND LIVRE / PBR) ==="
+        }
+        "=== GAINS AND INCOME BY ASSET ===" => "=== GAINS ET REVENUS PAR ACTIF ===",
+        "=== GAINS BY HOLDING PERIOD ===" => "=== GAINS PAR DURÉE DE DÉTENTION ===",
+        "=== TRADING ACTIVITY ===" => "=== ACTIVITÉ DE NÉGOCIATION ===",
+        "=== FEE EXPENSES ===" => "=== FRAIS ===",
+        "=== DEEMED DISPOSITION ON EMIGRATION ({}) ===" => {
+            "=== DISPOSITION RÉPUTÉE À L'ÉMIGRATION ({}) ==="
+        }
+        "=== CAPITAL LOSS CARRYOVER ===" => "=== REPORT DES PERTES EN CAPITAL ===",
+        "=== ESTIMATED TAX PAYABLE (ESTIMATE ONLY) ===" => {
+            "=== IMPÔT À PAYER ESTIMÉ (ESTIMATION SEULEMENT) ==="
+        }
+        "=== ENDING POOLS (units + ACB) ===" => "=== SOLDES DE FIN (unités + PBR) ===",
+        "=== EVENTS BY ASSET ===" => "=== ÉVÉNEMENTS PAR ACTIF ===",
+        "=== WARNINGS ===" => "=== AVERTISSEMENTS ===",
+        // Run settings.
+        "Tax year: {}" => "Année d'imposition : {}",
+        "Jurisdiction: {}" => "Régime fiscal : {}",
+        "Config: {}" => "Configuration : {}",
+        "Config: {} (profile {})" => "Configuration : {} (profil {})",
+        "Timezone: {}" => "Fuseau horaire : {}",
+        "Fallback USD/CAD FX: {}" => "Taux USD/CAD de repli : {}",
+        "Trading fee mode: {}" => "Traitement des frais d'opération : {}",
+        "Maker rebate mode: {}" => "Traitement des remises de teneur de marché : {}",
+        "Adjustment mode: {}" => "Traitement des ajustements : {}",
+        "Donation proceeds: {}" => "Produit des dons : {}",
+        "Reward income valuation: {}" => "Évaluation des récompenses : {}",
+        "Price chain: {}{}" => "Chaîne de prix : {}{}",
+        " (offline)" => " (hors ligne)",
+        "Stablecoins valued as USD: {} (dispositions: {})" => {
+            "Cryptomonnaies stables évaluées en USD : {} (dispositions : {})"
+        }
+        "Asset aliases: {}" => "Alias d'actifs : {}",
+        "Pooling: {}" => "Regroupement des biens : {}",
+        "Same-time events: {}" => "Événements simultanés : {}",
+        "FX exemption: each disposition within {} CAD" => {
+            "Exemption de change : chaque disposition à moins de {} CAD"
+        }
+        "Personal-use exemption: spends of {} CAD or less" => {
+            "Exemption pour usage personnel : dépenses de {} CAD ou moins"
+        }
+        "Invariant checks: on" => "Vérification des invariants : activée",
+        "Report rows minus totals (rounding): proceeds {}, ACB {}, gain {}, income {} CAD" => {
+            "Lignes du rapport moins totaux (arrondi) : produit {}, PBR {}, gain {}, revenu {} CAD"
+        }
+        "  WARNING: the report rows and totals differ by more than rounding explains ({} CAD)" => {
+            "  AVERTISSEMENT : les lignes du rapport et les totaux diffèrent de plus que l'arrondi ne l'explique ({} CAD)"
+        }
+        "Rounding: none (full precision)" => "Arrondi : aucun (pleine précision)",
+        "Rounding: {} (proceeds {}, ACB {}, units {} decimal places)" => {
+            "Arrondi : {} (produits {}, PBR {}, unités {} décimales)"
+        }
+        "Cost basis: {}" => "Méthode du coût : {}",
+        "Cost basis: {} (not accepted on a {} return)" => {
+            "Méthode du coût : {} (refusée pour une déclaration {})"
+        }
+        "  WARNING: --columns leaves out {}, which a {} return needs" => {
+            "  AVERTISSEMENT : --columns omet {}, requis pour une déclaration {}"
+        }
+        "Prices older than {} days: {}" => "Prix de plus de {} jours : {}",
+        "Reward rows: summed {}" => "Lignes de récompenses : additionnées ({})",
+        "Resumed from checkpoint: end of {}" => "Reprise au point de contrôle : fin de {}",
+        // Table headers.
+        "Total" => "Total",
+        "Asset" => "Actif",
+        "Proceeds (CAD)" => "Produit (CAD)",
+        "ACB disposed (CAD)" => "PBR aliéné (CAD)",
+        "Gain/loss (CAD)" => "Gain/perte (CAD)",
+        "Income (CAD)" => "Revenu (CAD)",
+        "Held" => "Détenu",
+        "Statistic" => "Statistique",
+        "Value" => "Valeur",
+        "Fees" => "Frais",
+        "Amount (CAD)" => "Montant (CAD)",
+        "Units" => "Unités",
+        "FMV (CAD)" => "JVM (CAD)",
+        "ACB (CAD)" => "PBR (CAD)",
+        "Avg cost (CAD/unit)" => "Coût moyen (CAD/unité)",
+        "Year" => "Année",
+        "Net gain (CAD)" => "Gain net (CAD)",
+        "Losses applied" => "Pertes appliquées",
+        "Adjusted gain" => "Gain rajusté",
+        "Carried back" => "Reportées en arrière",
+        "Carryforward balance" => "Solde reportable",
+        "Province: {}, other income: {} CAD, {} rates" => {
+            "Province : {}, autres revenus : {} CAD, taux de {}"
+        }
+        "Other income only (CAD)" => "Autres revenus seulement (CAD)",
+        "With this report (CAD)" => "Avec ce rapport (CAD)",
+        "Difference (CAD)" => "Écart (CAD)",
+        "Taxable income" => "Revenu imposable",
+        "Federal tax" => "Impôt fédéral",
+        "Provincial tax" => "Impôt provincial",
+        "Total tax" => "Impôt total",
+        "A rough estimate: tax brackets and the basic personal amount only, without other credits, deductions, surtaxes or premiums." => {
+            "Estimation approximative : tranches d'imposition et montant personnel de base seulement, sans autres crédits, déductions, surtaxes ni cotisations."
+        }
+        "Date" => "Date",
+        "Event" => "Événement",
+        "Units in" => "Unités reçues",
+        "Units out" => "Unités cédées",
+        // Totals.
+        "Proceeds" => "Produit de disposition",
+        "ACB disposed" => "PBR aliéné",
+        "Net capital gain/loss" => "Gain/perte en capital net",
+        "Taxable capital gain/loss" => "Gain/perte en capital imposable",
+        "Reward income" => "Revenu de récompenses",
+        "Interest income" => "Revenu d'intérêts",
+        "Credit/bonus income" => "Revenu de crédits/primes",
+        "Dividend income" => "Revenu de dividendes",
+        "Other income" => "Autres revenus",
+        "Trading fees expensed" => "Frais d'opération déduits",
+        "Maker rebates" => "Remises de teneur de marché",
+        "Maker rebate income" => "Revenu de remises de teneur de marché",
+        "Gifts at FMV" => "Cadeaux à la JVM",
+        "Charitable donations" => "Dons de bienfaisance",
+        "Foreign exchange gain/loss" => "Gain/perte de change",
+        "FX exemption applied" => "Exemption de change appliquée",
+        "Personal-use exemption applied" => "Exemption pour usage personnel appliquée",
+        // Holding periods.
+        "Under 30 days" => "Moins de 30 jours",
+        "30 days to 1 year" => "30 jours à 1 an",
+        "Over 1 year" => "Plus de 1 an",
+        "Unknown" => "Inconnue",
+        // Trading activity and fees.
+        "Trades" => "Opérations",
+        "Assets traded" => "Actifs négociés",
+        "Volume (CAD)" => "Volume (CAD)",
+        "Average holding time" => "Durée moyenne de détention",
+        "{} days" => "{} jours",
+        "Largest gain (CAD)" => "Gain le plus élevé (CAD)",
+        "Largest loss (CAD)" => "Perte la plus élevée (CAD)",
+        "Trading" => "Opérations",
+        "Withdrawal and transfer" => "Retraits et transferts",
+        "Margin and rollover" => "Marge et reconduction",
+        "Fees without a price (left out above): {}" => "Frais sans prix (exclus ci-dessus) : {}",
+        // Counts and warnings.
+        "Warnings (transfer-in assumed 0 ACB): {}" => {
+            "Avertissements (transfert entrant présumé à PBR nul) : {}"
+        }
+        "Valuations using fallback USD/CAD FX: {}" => "Évaluations au taux USD/CAD de repli : {}",
+        "Valuations at prices older than {} days: {}" => {
+            "Évaluations à des prix de plus de {} jours : {}"
+        }
+        "Rapid round trips (within {} minutes, price within {}%): {}" => {
+            "Allers-retours rapides (en {} minutes, prix à {} % près) : {}"
+        }
+        "Matched own-account transfers: {}" => "Transferts appariés entre vos comptes : {}",
+        "Trades found in trades.csv: {} of {}" => "Opérations trouvées dans trades.csv : {} sur {}",
+        "Ignored ledger rows (unrecognized type): {}" => {
+            "Lignes du grand livre ignorées (type non reconnu) : {}"
+        }
+        "  WARNING: ignored {} row(s) of type={} subtype={} (e.g. refid {})" => {
+            "  AVERTISSEMENT : {} ligne(s) ignorée(s) de type={} subtype={} (p. ex. refid {})"
+        }
+        "Ledger balance mismatches: {}" => "Écarts de solde du grand livre : {}",
+        "  WARNING: {} balance at {} (refid {}) is {} in the ledger but {} from its rows" => {
+            "  AVERTISSEMENT : le solde {} au {} (refid {}) est de {} au grand livre, mais de {} selon ses lignes"
+        }
+        "Data-quality issues in the inputs: {}" => "Problèmes de qualité des données d'entrée : {}",
+        "  WARNING: {}" => "  AVERTISSEMENT : {}",
+        "  NOTE: {}" => "  REMARQUE : {}",
+        "Excluded, overridden or repaired ledger rows: {}" => {
+            "Lignes du grand livre exclues, remplacées ou réparées : {}"
+        }
+        "Deemed proceeds (CAD): {}" => "Produit réputé (CAD) : {}",
+        "Deemed gain/loss (CAD): {}" => "Gain/perte réputé (CAD) : {}",
+        "Ending pool: {} units, ACB {} CAD" => "Solde de fin : {} unités, PBR de {} CAD",
+        "No warnings." => "Aucun avertissement.",
+        // Files written.
+        "Wrote tax report: {}" => "Rapport fiscal écrit : {}",
+        "  {} of {} rows, filtered by --from/--to/--assets" => {
+            "  {} lignes sur {}, filtrées par --from/--to/--assets"
+        }
+        "Wrote JSON report: {}" => "Rapport JSON écrit : {}",
+        "Wrote Markdown report: {}" => "Rapport Markdown écrit : {}",
+        "Wrote Parquet report: {}" => "Rapport Parquet écrit : {}",
+        "Wrote TSV report: {}" => "Rapport TSV écrit : {}",
+        "Wrote {} report(s) split by {}:" => "{} rapport(s) écrit(s), divisé(s) par {} :",
+        "  {} ({} rows)" => "  {} ({} lignes)",
+        "Wrote FX audit: {}" => "Audit des taux de change écrit : {}",
+        "Wrote lot audit: {}" => "Audit des lots écrit : {}",
+        "Wrote round-trip report: {}" => "Rapport des allers-retours écrit : {}",
+        "Wrote fee expense report: {}" => "Rapport des frais écrit : {}",
+        "Wrote ignored rows report: {}" => "Rapport des lignes ignorées écrit : {}",
+        "Wrote balance mismatch report: {}" => "Rapport des écarts de solde écrit : {}",
+        "Wrote data-quality report: {}" => "Rapport de qualité des données écrit : {}",
+        "Wrote ending pools: {}" => "Soldes de fin écrits : {}",
+        "Wrote ending pools JSON: {}" => "Soldes de fin JSON écrits : {}",
+        "Wrote report metadata: {}" => "Métadonnées du rapport écrites : {}",
+        "Wrote checksums: {}" => "Sommes de contrôle écrites : {}",
+        "Wrote pool mutations ({} changes): {}" => {
+            "Mouvements des soldes écrits ({} changements) : {}"
+        }
+        "Wrote deemed disposition report: {}" => "Rapport de disposition réputée écrit : {}",
+        "Wrote loss carryover report: {}" => "Rapport de report des pertes écrit : {}",
+        "Wrote fetched daily closes ({}): {}" => {
+            "Cours de clôture quotidiens téléchargés écrits ({}) : {}"
+        }
+        "Wrote price cache ({} fetched prices): {}" => {
+            "Cache de prix écrit ({} prix téléchargés) : {}"
+        }
+        "Wrote fill detail report ({} merged fill rows): {}" => {
+            "Détail des exécutions écrit ({} lignes fusionnées) : {}"
+        }
+        "Wrote export ({} rows): {}" => "Exportation écrite ({} lignes) : {}",
+        // Schedule G and Schedule 3 exports.
+        "Number" => "Nombre",
+        "Description of property" => "Désignation du bien",
+        "Proceeds of disposition" => "Produit de disposition",
+        "Adjusted cost base" => "Prix de base rajusté",
+        "Outlays and expenses" => "Dépenses",
+        "Year of acquisition" => "Année d'acquisition",
+        "Gain (or loss)" => "Gain (ou perte)",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ReportRow, make_row, parse_report_columns, stamp};
+    use chrono::NaiveDateTime;
+    use std::sync::Arc;
+
+    #[test]
+    fn fr_ca_translates_the_summary_and_report_headers() {
+        let fr = Locale::parse("fr_CA").unwrap();
+        assert_eq!(
+            fr.fill("Tax year: {}", &[&2025]),
+            "Année d'imposition : 2025"
+        );
+        assert_eq!(
+            fr.fill("Trades found in trades.csv: {} of {}", &[&3, &4]),
+            "Opérations trouvées dans trades.csv : 3 sur 4"
+        );
+        assert_eq!(fr.text("Not translated"), "Not translated");
+        assert_eq!(Locale::En.fill("Tax year: {}", &[&2025]), "Tax year: 2025");
+
+        // Every report field has a header, and each header maps back.
+        let columns = fr.report_columns().unwrap();
+        parse_report_columns(columns.iter().map(|c| c.field.as_str())).unwrap();
+        let no_id: Arc<str> = Arc::from("");
+        let row: ReportRow = make_row(stamp(NaiveDateTime::MIN, None), &no_id, &no_id, "", &no_id);
+        let fields = serde_json::to_value(row).unwrap();
+        assert_eq!(fields.as_object().unwrap().len(), columns.len());
+        for c in &columns {
+            assert_eq!(report_field(&c.header), Some(c.field.as_str()));
+        }
+        assert!(Locale::parse("de").is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::locale::Locale;
+use crate::table::{Cell, Style, Table};
+use crate::{
+    Args, BalanceMismatch, DataIssue, EndingPoolRow, IgnoredRowSummary, LedgerEdit, ReportRow,
+    Totals, asset_totals, cad, create_output,
+};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::Write;
+
+// `--format md`: the console summary as a Markdown document, for tax notes
+// kept in a Markdown vault, with a section per asset listing its
+// dispositions and income.
+pub(crate) struct MarkdownReport<'a> {
+    pub(crate) locale: Locale,
+    // Run settings, worded as in the console summary.
+    pub(crate) settings: Vec<String>,
+    pub(crate) totals: &'a Totals,
+    pub(crate) report: &'a [ReportRow],
+    pub(crate) ending_pools: &'a [EndingPoolRow],
+    pub(crate) warnings: Vec<String>,
+}
+
+fn plain(text: impl Into<String>) -> Cell {
+    (text.into(), Style::Plain)
+}
+
+// The console summary's warnings, one line each, without its `WARNING:`
+// prefix.
+pub(crate) fn warnings(
+    args: &Args,
+    totals: &Totals,
+    ignored: &[IgnoredRowSummary],
+    mismatches: &[BalanceMismatch],
+    issues: &[DataIssue],
+    edits: &[LedgerEdit],
+) -> Vec<String> {
+    let locale = args.locale;
+    let prefix = locale.fill("  WARNING: {}", &[&""]);
+    let unprefixed = |line: String| match line.strip_prefix(&prefix) {
+        Some(rest) => rest.to_string(),
+        None => line,
+    };
+    let mut lines = Vec::new();
+    if totals.warning_count > 0 {
+        lines.push(locale.fill(
+            "Warnings (transfer-in assumed 0 ACB): {}",
+            &[&totals.warning_count],
+        ));
+    }
+    if let Some(days) = args.max_price_age.filter(|_| totals.stale_price_count > 0) {
+        lines.push(locale.fill(
+            "Valuations at prices older than {} days: {}",
+            &[&days, &totals.stale_price_count],
+        ));
+    }
+    if !totals.row_drift.within_rounding() {
+        lines.push(unprefixed(locale.fill(
+            "  WARNING: the report rows and totals differ by more than rounding explains ({} CAD)",
+            &[&totals.row_drift.max_rounding_cad.normalize()],
+        )));
+    }
+    for s in ignored {
+        lines.push(unprefixed(locale.fill(
+            "  WARNING: ignored {} row(s) of type={} subtype={} (e.g. refid {})",
+            &[&s.count, &s.row_type, &s.subtype, &s.sample_refids],
+        )));
+    }
+    for m in mismatches {
+        lines.push(unprefixed(locale.fill(
+            "  WARNING: {} balance at {} (refid {}) is {} in the ledger but {} from its rows",
+            &[
+                &m.asset,
+                &m.time,
+                &m.refid,
+                &m.ledger_balance,
+                &m.computed_balance,
+            ],
+        )));
+    }
+    lines.extend(issues.iter().filter(|d| !d.is_note()).map(|d| d.describe()));
+    lines.extend(edits.iter().map(|e| e.describe()));
+    lines
+}
+
+pub(crate) fn render(md: &MarkdownReport) -> Result<String, Box<dyn Error>> {
+    let t = |text: &'static str| md.locale.text(text);
+    // `=== TITLE ===` as `TITLE`.
+    let heading = |title: &str| title.trim_matches(|c| c == '=' || c == ' ').to_string();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# {}\n",
+        heading(t("=== CANADIAN CRYPTO TAX SUMMARY (LEDGER / ACB) ==="))
+    )?;
+    for line in &md.settings {
+        writeln!(out, "- {}", line)?;
+    }
+
+    let mut totals_table = Table::new(&[t("Total"), "CAD"]);
+    for (label, amount, _) in md.totals.rows() {
+        totals_table.row(vec![plain(t(label)), plain(cad(amount))]);
+    }
+    write!(out, "\n{}", totals_table.markdown())?;
+
+    let by_asset = asset_totals(md.report)?;
+    let mut asset_table = Table::new(&[
+        t("Asset"),
+        t("Proceeds (CAD)"),
+        t("ACB disposed (CAD)"),
+        t("Gain/loss (CAD)"),
+        t("Income (CAD)"),
+    ]);
+    for (asset, a) in &by_asset {
+        asset_table.row(vec![
+            plain(asset.to_string()),
+            plain(cad(a.proceeds_cad)),
+            plain(cad(a.acb_disposed_cad)),
+            plain(cad(a.gain_cad)),
+            plain(cad(a.income_cad)),
+        ]);
+    }
+    if !asset_table.is_empty() {
+        writeln!(
+            out,
+            "\n## {}\n",
+            heading(t("=== GAINS AND INCOME BY ASSET ==="))
+        )?;
+        write!(out, "{}", asset_table.markdown())?;
+    }
+
+    let mut holding_table = Table::new(&[
+        t("Held"),
+        t("Proceeds (CAD)"),
+        t("ACB disposed (CAD)"),
+        t("Gain/loss (CAD)"),
+    ]);
+    for (period, held) in &md.totals.holding_periods {
+        holding_table.row(vec![
+            plain(t(period.label())),
+            plain(cad(held.proceeds_cad)),
+            plain(cad(held.acb_disposed_cad)),
+            plain(cad(held.gain_cad)),
+        ]);
+    }
+    if !holding_table.is_empty() {
+        writeln!(
+            out,
+            "\n## {}\n",
+            heading(t("=== GAINS BY HOLDING PERIOD ==="))
+        )?;
+        write!(out, "{}", holding_table.markdown())?;
+    }
+
+    // Each asset with a disposition or income, row by row.
+    if !by_asset.is_empty() {
+        writeln!(out, "\n## {}", heading(t("=== EVENTS BY ASSET ===")))?;
+    }
+    for asset in by_asset.keys() {
+        let mut events = Table::new(&[
+            t("Date"),
+            t("Event"),
+            t("Units in"),
+            t("Units out"),
+            t("Proceeds (CAD)"),
+            t("ACB disposed (CAD)"),
+            t("Gain/loss (CAD)"),
+            t("Income (CAD)"),
+        ]);
+        for r in md
+            .report
+            .iter()
+            .filter(|r| r.asset == *asset && (!r.gain_cad.is_empty() || !r.income_cad.is_empty()))
+        {
+            events.row(vec![
+                plain(r.time.get(..10).unwrap_or(&r.time)),
+                plain(r.event_type.as_str()),
+                plain(r.units_in.as_str()),
+                plain(r.units_out.as_str()),
+                plain(r.proceeds_cad.as_str()),
+                plain(r.acb_disposed_cad.as_str()),
+                plain(r.gain_cad.as_str()),
+                plain(r.income_cad.as_str()),
+            ]);
+        }
+        writeln!(out, "\n### {}\n", asset)?;
+        write!(out, "{}", events.markdown())?;
+        if let Some(p) = md.ending_pools.iter().find(|p| p.asset == *asset) {
+            writeln!(
+                out,
+                "\n{}",
+                md.locale.fill(
+                    "Ending pool: {} units, ACB {} CAD",
+                    &[&p.units.normalize(), &cad(p.acb_cad)]
+                )
+            )?;
+        }
+    }
+
+    writeln!(out, "\n## {}\n", heading(t("=== WARNINGS ===")))?;
+    if md.warnings.is_empty() {
+        writeln!(out, "{}", t("No warnings."))?;
+    }
+    for line in &md.warnings {
+        writeln!(out, "- {}", line)?;
+    }
+
+    let mut pool_table = Table::new(&[
+        t("Asset"),
+        t("Units"),
+        t("ACB (CAD)"),
+        t("Avg cost (CAD/unit)"),
+    ]);
+    for p in md.ending_pools.iter().filter(|p| &*p.asset != "CAD") {
+        pool_table.row(vec![
+            plain(p.asset.to_string()),
+            plain(p.units.normalize().to_string()),
+            plain(cad(p.acb_cad)),
+            plain(cad(p.avg_cost_cad)),
+        ]);
+    }
+    writeln!(
+        out,
+        "\n## {}\n",
+        heading(t("=== ENDING POOLS (units + ACB) ==="))
+    )?;
+    write!(out, "{}", pool_table.markdown())?;
+    Ok(out)
+}
+
+pub(crate) fn write(path: &str, md: &MarkdownReport) -> Result<(), Box<dyn Error>> {
+    create_output(path)?.write_all(render(md)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_row, parse_time, stamp};
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+
+    #[test]
+    fn report_has_asset_sections_and_warnings() {
+        let refid: Arc<str> = Arc::from("S1");
+        let mut sale = make_row(
+            stamp(parse_time("2025-04-01 12:00:00").unwrap(), None),
+            &refid,
+            &refid,
+            "trade_disposition",
+            &"BTC".into(),
+        );
+        sale.units_out = "0.5".to_string();
+        sale.proceeds_cad = "300.00".to_string();
+        sale.acb_disposed_cad = "200.00".to_string();
+        sale.gain_cad = "100.00".to_string();
+        let totals = Totals {
+            capital_gain_cad: dec!(100),
+            ..Totals::default()
+        };
+        let pools = [EndingPoolRow {
+            asset: "BTC".into(),
+            units: dec!(0.25),
+            acb_cad: dec!(100),
+            avg_cost_cad: dec!(400),
+            last_price_cad: None,
+            fmv_cad: None,
+            price_source: "",
+        }];
+        let report = [sale];
+        let mut md = MarkdownReport {
+            locale: Locale::En,
+            settings: vec!["Tax year: 2025".to_string()],
+            totals: &totals,
+            report: &report,
+            ending_pools: &pools,
+            warnings: vec!["ignored 2 row(s) of type=x | y".to_string()],
+        };
+        let text = render(&md).unwrap();
+        assert!(
+            text.starts_with("# CANADIAN CRYPTO TAX SUMMARY (LEDGER / ACB)\n\n- Tax year: 2025\n")
+        );
+        assert!(text.contains("| Net capital gain/loss | 100.00 |"));
+        assert!(text.contains(
+            "### BTC\n\n| Date | Event | Units in | Units out | Proceeds (CAD) \
+             | ACB disposed (CAD) | Gain/loss (CAD) | Income (CAD) |\n| --- |"
+        ));
+        assert!(
+            text.contains(
+                "| 2025-04-01 | trade_disposition |  | 0.5 | 300.00 | 200.00 | 100.00 |  |"
+            )
+        );
+        assert!(text.contains("Ending pool: 0.25 units, ACB 100.00 CAD"));
+        assert!(text.contains("## WARNINGS\n\n- ignored 2 row(s) of type=x | y\n"));
+
+        md.locale = Locale::FrCa;
+        md.warnings.clear();
+        let text = render(&md).unwrap();
+        assert!(text.contains("## AVERTISSEMENTS\n\nAucun avertissement.\n"));
+        assert!(text.contains("## SOLDES DE FIN (unités + PBR)"));
+    }
+}
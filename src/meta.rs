@@ -0,0 +1,194 @@
+use crate::{Args, ProcessOptions, STDIO_PATH, Totals, create_output, input_paths};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+// `<out>_meta.json`: what produced a report, so an archived report can be
+// traced to its inputs and rerun with the same settings.
+#[derive(Debug, Serialize)]
+pub(crate) struct ReportMeta<'a> {
+    tool: &'static str,
+    version: &'static str,
+    generated_at: String,
+    // The command line as given, without the program name.
+    arguments: Vec<String>,
+    tax_year: i32,
+    jurisdiction: &'static str,
+    cost_basis: &'static str,
+    // The settings the console summary lists, by flag name.
+    settings: BTreeMap<&'static str, String>,
+    inputs: Vec<InputFile>,
+    // The config file and profile whose flags the run used.
+    config: Option<InputFile>,
+    profile: Option<String>,
+    // SHA-256 of the events in the order they were booked; after a
+    // checkpoint resume, of the events after the checkpoint.
+    event_sequence_sha256: &'a str,
+    report: &'a str,
+    totals: &'a Totals,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) struct InputFile {
+    pub(crate) path: String,
+    // `None` for standard input, which cannot be read twice.
+    pub(crate) sha256: Option<String>,
+    bytes: Option<u64>,
+}
+
+// The parts of a `<out>_meta.json` that `verify` reruns a report from.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RecordedRun {
+    pub(crate) version: String,
+    pub(crate) arguments: Vec<String>,
+    pub(crate) inputs: Vec<InputFile>,
+    pub(crate) config: Option<InputFile>,
+}
+
+impl InputFile {
+    pub(crate) fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        if path == STDIO_PATH {
+            return Ok(InputFile {
+                path: path.to_string(),
+                sha256: None,
+                bytes: None,
+            });
+        }
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let hash: String = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(InputFile {
+            path: path.to_string(),
+            sha256: Some(hash),
+            bytes: Some(data.len() as u64),
+        })
+    }
+}
+
+pub(crate) fn report_meta<'a>(
+    args: &Args,
+    opts: &ProcessOptions,
+    report: &'a str,
+    totals: &'a Totals,
+    event_hash: &'a str,
+) -> Result<ReportMeta<'a>, Box<dyn Error>> {
+    let rounding = &args.rounding;
+    let mut settings = BTreeMap::from([
+        ("fallback-usd-cad-fx", args.fallback_usd_cad_fx.to_string()),
+        ("fee-mode", args.fee_mode.as_str().to_string()),
+        ("rebate-mode", args.rebate_mode.as_str().to_string()),
+        ("adjustment-mode", args.adjustment_mode.as_str().to_string()),
+        (
+            "donation-proceeds",
+            args.donation_proceeds.as_str().to_string(),
+        ),
+        (
+            "income-valuation",
+            args.income_valuation.as_str().to_string(),
+        ),
+        ("price-chain", opts.price_chain.names().join(",")),
+        ("pooling", args.pooling.as_str().to_string()),
+        ("tie-break", args.tie_break.as_str().to_string()),
+        (
+            "rounding-strategy",
+            if rounding.full_precision {
+                "none".to_string()
+            } else {
+                rounding.strategy_name().to_string()
+            },
+        ),
+        (
+            "decimal-places",
+            format!(
+                "proceeds={},acb={},units={}",
+                rounding.proceeds_dp, rounding.acb_dp, rounding.units_dp
+            ),
+        ),
+    ]);
+    if let Some(tz) = args.timezone {
+        settings.insert("timezone", tz.to_string());
+    }
+    if let Some(date) = args.deemed_disposition {
+        settings.insert("deemed-disposition-date", date.date().to_string());
+    }
+    if !args.stablecoins_as_fiat.is_empty() {
+        let names: Vec<&str> = args.stablecoins_as_fiat.iter().map(|a| &**a).collect();
+        settings.insert("treat-stablecoins-as-fiat", names.join(","));
+        settings.insert(
+            "stablecoin-dispositions",
+            args.stablecoin_dispositions.as_str().to_string(),
+        );
+    }
+    if !args.asset_aliases.is_empty() {
+        let aliases: Vec<String> = args
+            .asset_aliases
+            .iter()
+            .map(|(from, to)| format!("{}={}", from, to))
+            .collect();
+        settings.insert("asset-alias", aliases.join(","));
+    }
+    Ok(ReportMeta {
+        tool: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        arguments: std::env::args().skip(1).collect(),
+        tax_year: args.tax_year,
+        jurisdiction: args.jurisdiction.name(),
+        cost_basis: opts.cost_basis.name(),
+        settings,
+        inputs: input_paths(args)
+            .into_iter()
+            .map(InputFile::read)
+            .collect::<Result<_, _>>()?,
+        config: args.config.as_deref().map(InputFile::read).transpose()?,
+        profile: args.profile.clone(),
+        event_sequence_sha256: event_hash,
+        report,
+        totals,
+    })
+}
+
+// The metadata file written next to the report `output`.
+pub(crate) fn path_for(output: &str) -> String {
+    format!(
+        "{}_meta.json",
+        output.strip_suffix(".csv").unwrap_or(output)
+    )
+}
+
+pub(crate) fn read(path: &str) -> Result<RecordedRun, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+pub(crate) fn write(path: &str, meta: &ReportMeta) -> Result<(), Box<dyn Error>> {
+    let mut out = create_output(path)?;
+    serde_json::to_writer_pretty(&mut out, meta)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_are_hashed_and_stdin_is_not() {
+        let path = std::env::temp_dir().join("kraken_acb_meta_input.csv");
+        std::fs::write(&path, "abc").unwrap();
+        let file = InputFile::read(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let file = file.unwrap();
+        assert_eq!(
+            file.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(file.bytes, Some(3));
+        assert_eq!(InputFile::read(STDIO_PATH).unwrap().sha256, None);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{Acquisitions, CostBasisEngine, Lot, Pool};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+// What a pool mutation does to its asset's pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mutation {
+    // Units bought or transferred in, at `cost_cad`.
+    Add { units: Decimal, cost_cad: Decimal },
+    // Units received as income; their value is also their cost.
+    Income { units: Decimal, value_cad: Decimal },
+    // Units disposed of or sent away; the cost-basis engine picks the cost.
+    Remove { units: Decimal },
+    // The whole `from` pool moves into this one, each unit becoming `factor`.
+    Migrate { from: Arc<str>, factor: Decimal },
+    // The pool's cost becomes `cost_cad`, as on a deemed re-acquisition.
+    Restate { cost_cad: Decimal },
+}
+
+impl Mutation {
+    fn kind(&self) -> &'static str {
+        match self {
+            Mutation::Add { .. } => "add",
+            Mutation::Income { .. } => "income",
+            Mutation::Remove { .. } => "remove",
+            Mutation::Migrate { .. } => "migrate",
+            Mutation::Restate { .. } => "restate",
+        }
+    }
+}
+
+// One change to one pool. `process` decides these from the ledger; folding
+// them in order over the starting pools gives the ending pools.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolMutation {
+    pub time: NaiveDateTime,
+    pub refid: Arc<str>,
+    pub asset: Arc<str>,
+    pub change: Mutation,
+}
+
+impl PoolMutation {
+    pub fn new(time: NaiveDateTime, refid: &Arc<str>, asset: &Arc<str>, change: Mutation) -> Self {
+        PoolMutation {
+            time,
+            refid: refid.clone(),
+            asset: asset.clone(),
+            change,
+        }
+    }
+}
+
+// What applying a mutation did. `cost_cad` is the cost added, removed,
+// carried over by a migration, or replaced by a restatement; `lots` are the
+// lot portions a removal or restatement took, under lot-based methods. The
+// deltas are the signed change to the mutated pool's totals, and `held_since`
+// and `acquisitions` are the pool's mean acquisition time and span before
+// the change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effect {
+    pub cost_cad: Decimal,
+    pub held_since: Option<NaiveDateTime>,
+    pub acquisitions: Option<Acquisitions>,
+    pub units_delta: Decimal,
+    pub acb_delta: Decimal,
+    pub units_after: Decimal,
+    pub acb_after: Decimal,
+    pub lots: Vec<Lot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Applied {
+    pub mutation: PoolMutation,
+    pub effect: Effect,
+}
+
+// Row of the pool mutations report.
+#[derive(Debug, Serialize)]
+pub(crate) struct MutationRow {
+    seq: usize,
+    time: String,
+    refid: Arc<str>,
+    asset: Arc<str>,
+    kind: &'static str,
+    units: String,
+    cost_cad: String,
+    from_asset: String,
+    factor: String,
+    units_delta: String,
+    acb_delta_cad: String,
+    pool_units_after: String,
+    pool_acb_cad_after: String,
+}
+
+impl Applied {
+    // `full_precision` leaves amounts unrounded, as under `--no-rounding`.
+    pub(crate) fn row(&self, seq: usize, time: String, full_precision: bool) -> MutationRow {
+        let m = &self.mutation;
+        let units_of = |x: Decimal| if full_precision { x } else { crate::q8(x) };
+        let cad = |x: Decimal| if full_precision { x } else { crate::q2(x) };
+        let (units, from_asset, factor) = match &m.change {
+            Mutation::Add { units, .. }
+            | Mutation::Income { units, .. }
+            | Mutation::Remove { units } => (units_of(*units).to_string(), "", String::new()),
+            Mutation::Migrate { from, factor } => (String::new(), &**from, factor.to_string()),
+            Mutation::Restate { .. } => (String::new(), "", String::new()),
+        };
+        MutationRow {
+            seq,
+            time,
+            refid: m.refid.clone(),
+            asset: m.asset.clone(),
+            kind: m.change.kind(),
+            units,
+            cost_cad: cad(self.effect.cost_cad).to_string(),
+            from_asset: from_asset.to_string(),
+            factor,
+            units_delta: units_of(self.effect.units_delta).to_string(),
+            acb_delta_cad: cad(self.effect.acb_delta).to_string(),
+            pool_units_after: units_of(self.effect.units_after).to_string(),
+            pool_acb_cad_after: cad(self.effect.acb_after).to_string(),
+        }
+    }
+}
+
+// The pools and every mutation applied to them. All pool changes go through
+// `apply`, so the log replays to the same pools.
+#[derive(Debug)]
+pub(crate) struct PoolBook {
+    pools: HashMap<Arc<str>, Pool>,
+    basis: Arc<dyn CostBasisEngine>,
+    log: Vec<Applied>,
+    // Under per-wallet pooling, the wallet whose pools mutations apply to;
+    // its pools are keyed `ASSET@wallet`.
+    wallet: Option<Arc<str>>,
+}
+
+impl PoolBook {
+    pub(crate) fn new(pools: HashMap<Arc<str>, Pool>, basis: Arc<dyn CostBasisEngine>) -> Self {
+        PoolBook {
+            pools,
+            basis,
+            log: Vec::new(),
+            wallet: None,
+        }
+    }
+
+    pub(crate) fn pools(&self) -> &HashMap<Arc<str>, Pool> {
+        &self.pools
+    }
+
+    pub(crate) fn log(&self) -> &[Applied] {
+        &self.log
+    }
+
+    // The current wallet's pool of `asset`.
+    pub(crate) fn pool(&self, asset: &Arc<str>) -> Option<&Pool> {
+        self.pools.get(&self.key(asset))
+    }
+
+    pub(crate) fn set_wallet(&mut self, wallet: Option<Arc<str>>) {
+        self.wallet = wallet;
+    }
+
+    fn key(&self, asset: &Arc<str>) -> Arc<str> {
+        match &self.wallet {
+            Some(wallet) => format!("{}@{}", asset, wallet).into(),
+            None => asset.clone(),
+        }
+    }
+
+    // `ctx` names the event in errors. The mutation is logged with the key of
+    // the pool it changed.
+    pub(crate) fn apply(
+        &mut self,
+        mut m: PoolMutation,
+        ctx: &str,
+    ) -> Result<Effect, Box<dyn Error>> {
+        m.asset = self.key(&m.asset);
+        if let Mutation::Migrate { from, .. } = &mut m.change {
+            *from = self.key(from);
+        }
+        let basis = &*self.basis;
+        let before = self
+            .pools
+            .get(&m.asset)
+            .map_or((Decimal::ZERO, Decimal::ZERO, None, None), |p| {
+                (p.units, p.acb_cad, p.acquired_at, p.acquisitions)
+            });
+        let (cost_cad, lots) = match &m.change {
+            Mutation::Add { units, cost_cad } => {
+                let pool = self.pools.entry(m.asset.clone()).or_default();
+                pool.hold(*units, Some(m.time));
+                basis.acquire(pool, *units, *cost_cad, m.time, &m.refid);
+                (*cost_cad, Vec::new())
+            }
+            Mutation::Income { units, value_cad } => {
+                let pool = self.pools.entry(m.asset.clone()).or_default();
+                pool.hold(*units, Some(m.time));
+                basis.acquire(pool, *units, *value_cad, m.time, &m.refid);
+                (*value_cad, Vec::new())
+            }
+            Mutation::Remove { units } => {
+                let pool = self.pools.entry(m.asset.clone()).or_default();
+                let taken = basis.dispose(pool, *units, &m.refid, ctx)?;
+                if pool.units.is_zero() {
+                    pool.acquired_at = None;
+                    pool.acquisitions = None;
+                }
+                (taken.cost_cad, taken.lots)
+            }
+            Mutation::Migrate { from, factor } => {
+                let old = self.pools.remove(from).unwrap_or_default();
+                let cost = old.acb_cad;
+                self.pools
+                    .entry(m.asset.clone())
+                    .or_default()
+                    .absorb(old, *factor);
+                (cost, Vec::new())
+            }
+            Mutation::Restate { cost_cad } => {
+                let pool = self.pools.entry(m.asset.clone()).or_default();
+                let old = (pool.acb_cad, pool.lots.clone());
+                pool.restart(*cost_cad, m.time, &m.refid);
+                old
+            }
+        };
+        let pool = &self.pools[&m.asset];
+        let effect = Effect {
+            cost_cad,
+            held_since: before.2,
+            acquisitions: before.3,
+            units_delta: pool.units - before.0,
+            acb_delta: pool.acb_cad - before.1,
+            units_after: pool.units,
+            acb_after: pool.acb_cad,
+            lots,
+        };
+        self.log.push(Applied {
+            mutation: m,
+            effect: effect.clone(),
+        });
+        Ok(effect)
+    }
+
+    pub(crate) fn finish(self) -> (HashMap<Arc<str>, Pool>, Vec<Applied>) {
+        (self.pools, self.log)
+    }
+}
+
+// Folds `mutations` over `start` with `basis`, as the run that logged them
+// did, and returns the ending pools.
+pub fn replay(
+    start: HashMap<Arc<str>, Pool>,
+    mutations: impl IntoIterator<Item = PoolMutation>,
+    basis: Arc<dyn CostBasisEngine>,
+) -> Result<HashMap<Arc<str>, Pool>, Box<dyn Error>> {
+    let mut book = PoolBook::new(start, basis);
+    for m in mutations {
+        let ctx = format!("replay {} {}", m.refid, m.asset);
+        book.apply(m, &ctx)?;
+    }
+    Ok(book.finish().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{entry, leg, row};
+    use crate::{AverageCost, Fifo, ProcessOptions, parse_time, process};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn logged_mutations_replay_to_the_ending_pools() {
+        let entries = vec![
+            leg("B1", "2025-01-01 00:00:00", "CAD", dec!(-100)),
+            leg("B1", "2025-01-01 00:00:00", "SOL", dec!(1)),
+            row(
+                "R1",
+                "2025-01-15 00:00:00",
+                "earn",
+                "reward",
+                "SOL",
+                dec!(0.1),
+            ),
+            leg("S1", "2025-02-01 00:00:00", "SOL", dec!(-0.5)),
+            leg("S1", "2025-02-01 00:00:00", "CAD", dec!(80)),
+        ];
+        let opts = ProcessOptions {
+            cost_basis: Arc::new(Fifo),
+            ..ProcessOptions::new(2025, dec!(1.4))
+        };
+        let out = process(entries, &opts).unwrap();
+        let kinds: Vec<_> = out
+            .mutations
+            .iter()
+            .map(|a| a.mutation.change.kind())
+            .collect();
+        assert_eq!(kinds, ["add", "income", "remove"]);
+        assert_eq!(out.mutations[2].effect.cost_cad, dec!(50));
+        assert_eq!(out.mutations[2].effect.units_delta, dec!(-0.5));
+        assert_eq!(out.mutations[2].effect.acb_delta, dec!(-50));
+        let replayed = replay(
+            HashMap::new(),
+            out.mutations.iter().map(|a| a.mutation.clone()),
+            Arc::new(Fifo),
+        )
+        .unwrap();
+        assert_eq!(replayed, out.pools);
+
+        // One rule on its own: removing more than the pool holds fails.
+        let mut book = PoolBook::new(HashMap::new(), Arc::new(AverageCost));
+        let time = parse_time("2025-01-01 00:00:00").unwrap();
+        let sol: Arc<str> = "SOL".into();
+        let remove =
+            PoolMutation::new(time, &"X".into(), &sol, Mutation::Remove { units: dec!(1) });
+        assert!(book.apply(remove, "test").is_err());
+    }
+
+    #[test]
+    fn report_rows_carry_signed_unit_and_acb_deltas() {
+        let entries = vec![
+            leg("B1", "2025-01-01 00:00:00", "CAD", dec!(-100)),
+            leg("B1", "2025-01-01 00:00:00", "SOL", dec!(1)),
+            leg("S1", "2025-02-01 00:00:00", "SOL", dec!(-0.25)),
+            leg("S1", "2025-02-01 00:00:00", "CAD", dec!(40)),
+            entry(
+                "2025-03-01 00:00:00",
+                "W1",
+                "W1",
+                "withdrawal",
+                "",
+                "SOL",
+                "-0.5",
+                "0.01",
+            ),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let rows: Vec<_> = out
+            .mutations
+            .iter()
+            .enumerate()
+            .map(|(i, a)| a.row(i + 1, String::new(), false))
+            .collect();
+        let deltas: Vec<_> = rows
+            .iter()
+            .map(|r| (r.kind, r.units_delta.as_str(), r.acb_delta_cad.as_str()))
+            .collect();
+        // The buy, the partial sell, then the transfer out and its fee.
+        assert_eq!(
+            deltas,
+            [
+                ("add", "1", "100"),
+                ("remove", "-0.25", "-25.00"),
+                ("remove", "-0.50", "-50.00"),
+                ("remove", "-0.01", "-1.00"),
+            ]
+        );
+        let last = rows.last().unwrap();
+        assert_eq!(
+            (
+                last.pool_units_after.as_str(),
+                last.pool_acb_cad_after.as_str()
+            ),
+            ("0.24", "24.00")
+        );
+    }
+}
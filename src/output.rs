@@ -0,0 +1,418 @@
+use crate::{CsvDialect, ReportRow, STDIO_PATH, locale, make_row, meta, read_input_text, stamp};
+use chrono::NaiveDateTime;
+use csv::{ReaderBuilder, WriterBuilder};
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+
+pub(crate) fn create_output(path: &str) -> Result<Box<dyn Write + Send>, Box<dyn Error>> {
+    if path == STDIO_PATH {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    Ok(Box::new(File::create(path)?))
+}
+
+// Console messages move to stderr when stdout carries the CSV output.
+pub(crate) fn summary_writer(output: &str) -> Box<dyn Write> {
+    if output == STDIO_PATH {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+// Colors what goes to `stream` only when it is a terminal, unless
+// `--no-color` or the `NO_COLOR` convention says otherwise.
+pub(crate) fn use_color(stream: impl IsTerminal, no_color: bool) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && stream.is_terminal()
+}
+
+// The summary goes to stderr when the report takes stdout.
+pub(crate) fn summary_color(output: &str, no_color: bool) -> bool {
+    if output == STDIO_PATH {
+        use_color(std::io::stderr(), no_color)
+    } else {
+        use_color(std::io::stdout(), no_color)
+    }
+}
+
+pub(crate) fn side_report_path(output: &str, suffix: &str) -> String {
+    let stem = output.strip_suffix(".csv").unwrap_or(output);
+    format!("{}_{}.csv", stem, suffix)
+}
+
+// Every other file a report run can write, named after the report `stem`.
+pub(crate) struct ReportPaths {
+    pub(crate) fx_audit: String,
+    pub(crate) lineage: String,
+    pub(crate) round_trips: String,
+    pub(crate) fees: String,
+    pub(crate) ignored: String,
+    pub(crate) balance: String,
+    pub(crate) data_quality: String,
+    pub(crate) pools: String,
+    pub(crate) meta: String,
+    pub(crate) deemed: String,
+    pub(crate) carryover: String,
+    pub(crate) fills: String,
+    pub(crate) daily_closes: String,
+    pub(crate) mutations: String,
+    pub(crate) pools_json: String,
+    pub(crate) json: String,
+    pub(crate) markdown: String,
+    pub(crate) tsv: String,
+    pub(crate) parquet: String,
+    pub(crate) checksums: String,
+}
+
+impl ReportPaths {
+    pub(crate) fn new(stem: &str, tax_year: i32) -> Self {
+        let side = |suffix: &str| side_report_path(stem, suffix);
+        let base = stem.strip_suffix(".csv").unwrap_or(stem);
+        let pools = side(&format!("pools_{}", tax_year));
+        ReportPaths {
+            fx_audit: side("fx_audit"),
+            lineage: side("audit"),
+            round_trips: side("round_trips"),
+            fees: side("fees"),
+            ignored: side("ignored_rows"),
+            balance: side("balance_mismatches"),
+            data_quality: side("data_quality"),
+            pools_json: format!("{}.json", pools.strip_suffix(".csv").unwrap_or(&pools)),
+            pools,
+            meta: meta::path_for(stem),
+            deemed: side("deemed_disposition"),
+            carryover: side("loss_carryover"),
+            fills: side("fills"),
+            daily_closes: side("daily_closes"),
+            mutations: side("pool_mutations"),
+            json: format!("{}.json", base),
+            markdown: format!("{}.md", base),
+            tsv: format!("{}.tsv", base),
+            parquet: format!("{}.parquet", base),
+            checksums: format!("{}.sha256", base),
+        }
+    }
+}
+
+pub(crate) fn write_csv<T: Serialize>(path: &str, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = WriterBuilder::new().from_writer(create_output(path)?);
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Selected report column: `ReportRow` field name and the CSV header to use.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ReportColumn {
+    pub(crate) field: String,
+    pub(crate) header: String,
+}
+
+// `field` or `field=Header` items, from `--columns` (comma-separated) or a
+// `--columns-file` (one per line, `#` comments).
+pub(crate) fn parse_report_columns<'a>(
+    items: impl Iterator<Item = &'a str>,
+) -> Result<Vec<ReportColumn>, Box<dyn Error>> {
+    let no_id: Arc<str> = Arc::from("");
+    let sample = serde_json::to_value(make_row(
+        stamp(NaiveDateTime::MIN, None),
+        &no_id,
+        &no_id,
+        "",
+        &no_id,
+    ))?;
+    let mut columns = Vec::new();
+    for item in items {
+        let item = item.split('#').next().unwrap_or("").trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (field, header) = item.split_once('=').unwrap_or((item, item));
+        let (field, header) = (field.trim(), header.trim());
+        if sample.get(field).is_none() {
+            return Err(format!("unknown report column: {}", field).into());
+        }
+        columns.push(ReportColumn {
+            field: field.to_string(),
+            header: header.to_string(),
+        });
+    }
+    if columns.is_empty() {
+        return Err("no report columns selected".into());
+    }
+    Ok(columns)
+}
+
+// Writes report rows with only the chosen columns, in the chosen order.
+fn write_report_columns<T: Serialize>(
+    path: &str,
+    rows: &[T],
+    columns: &[ReportColumn],
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = WriterBuilder::new().from_writer(create_output(path)?);
+    wtr.write_record(columns.iter().map(|c| c.header.as_str()))?;
+    for row in rows {
+        let value = serde_json::to_value(row)?;
+        wtr.write_record(
+            columns
+                .iter()
+                .map(|c| value.get(&c.field).and_then(|v| v.as_str()).unwrap_or("")),
+        )?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Reads back a report written by this tool; columns dropped with `--columns`
+// come back empty.
+pub(crate) fn read_report(path: &str) -> Result<Vec<ReportRow>, Box<dyn Error>> {
+    let text = read_input_text(path)?;
+    let mut rdr = ReaderBuilder::new().from_reader(text.as_bytes());
+    // Headers written under --locale name their fields again.
+    let headers: csv::StringRecord = rdr
+        .headers()?
+        .iter()
+        .map(|h| locale::report_field(h).unwrap_or(h))
+        .collect();
+    rdr.set_headers(headers);
+    let mut rows = Vec::new();
+    for row in rdr.deserialize() {
+        rows.push(row.map_err(|e| format!("{}: {}", path, e))?);
+    }
+    Ok(rows)
+}
+
+pub(crate) fn write_report<T: Serialize>(
+    path: &str,
+    rows: &[T],
+    columns: Option<&[ReportColumn]>,
+    style: CsvStyle,
+) -> Result<(), Box<dyn Error>> {
+    if style != CsvStyle::default() {
+        return style.write(path, rows, columns);
+    }
+    match columns {
+        Some(columns) => write_report_columns(path, rows, columns),
+        None => write_csv(path, rows),
+    }
+}
+
+// Report fields holding amounts; the rest are text.
+const REPORT_AMOUNT_FIELDS: [&str; 10] = [
+    "units_in",
+    "units_out",
+    "proceeds_cad",
+    "acb_disposed_cad",
+    "gain_cad",
+    "income_cad",
+    "acb_added_cad",
+    "fee_cad",
+    "pool_units_after",
+    "pool_acb_cad_after",
+];
+
+// The delimiter (`--format tsv`'s tab, or a comma) and dialect a report file
+// is written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CsvStyle {
+    pub(crate) delimiter: u8,
+    pub(crate) dialect: CsvDialect,
+}
+
+impl Default for CsvStyle {
+    fn default() -> Self {
+        CsvStyle {
+            delimiter: b',',
+            dialect: CsvDialect::Standard,
+        }
+    }
+}
+
+impl CsvStyle {
+    fn write<T: Serialize>(
+        &self,
+        path: &str,
+        rows: &[T],
+        columns: Option<&[ReportColumn]>,
+    ) -> Result<(), Box<dyn Error>> {
+        // Write the records as plain CSV first, then lay them out again.
+        let mut plain = WriterBuilder::new().from_writer(Vec::new());
+        match columns {
+            Some(columns) => {
+                plain.write_record(columns.iter().map(|c| c.field.as_str()))?;
+                for row in rows {
+                    let value = serde_json::to_value(row)?;
+                    plain.write_record(
+                        columns
+                            .iter()
+                            .map(|c| value.get(&c.field).and_then(|v| v.as_str()).unwrap_or("")),
+                    )?;
+                }
+            }
+            None => {
+                for row in rows {
+                    plain.serialize(row)?;
+                }
+            }
+        }
+        let plain = plain.into_inner().map_err(|e| e.to_string())?;
+        let mut records = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(plain.as_slice())
+            .into_records();
+        // No rows and no `--columns`: an empty file, as a plain report.
+        let Some(fields) = records.next().transpose()? else {
+            create_output(path)?;
+            return Ok(());
+        };
+        let is_text: Vec<bool> = fields
+            .iter()
+            .map(|f| !REPORT_AMOUNT_FIELDS.contains(&f))
+            .collect();
+        let headers: Vec<&str> = match columns {
+            Some(columns) => columns.iter().map(|c| c.header.as_str()).collect(),
+            None => fields.iter().collect(),
+        };
+
+        let mut out = create_output(path)?;
+        let excel = self.dialect == CsvDialect::Excel;
+        if excel {
+            out.write_all("\u{feff}".as_bytes())?;
+        }
+        let mut wtr = WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .terminator(if excel {
+                csv::Terminator::CRLF
+            } else {
+                csv::Terminator::Any(b'\n')
+            })
+            // Excel text columns are quoted below, whether or not they need it.
+            .quote_style(if excel {
+                csv::QuoteStyle::Never
+            } else {
+                csv::QuoteStyle::Necessary
+            })
+            .from_writer(out);
+        let quoted = |text: &str| format!("\"{}\"", text.replace('"', "\"\""));
+        if excel {
+            wtr.write_record(headers.iter().map(|h| quoted(h)))?;
+        } else {
+            wtr.write_record(&headers)?;
+        }
+        for record in records {
+            let record = record?;
+            if excel {
+                wtr.write_record(
+                    record.iter().zip(&is_text).map(
+                        |(v, text)| {
+                            if *text { quoted(v) } else { v.to_string() }
+                        },
+                    ),
+                )?;
+            } else {
+                wtr.write_record(&record)?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn write_json<T: Serialize>(path: &str, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    let mut out = create_output(path)?;
+    serde_json::to_writer_pretty(&mut out, rows)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+pub(crate) type WriteJob<'a> = Box<dyn FnOnce() -> Result<(), Box<dyn Error>> + 'a>;
+
+// Writes the output files in order, stopping at the first that fails.
+pub(crate) fn write_all(jobs: Vec<WriteJob<'_>>) -> Result<(), Box<dyn Error>> {
+    jobs.into_iter().try_for_each(|job| job())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_time;
+
+    #[test]
+    fn report_columns_select_rename_and_order() {
+        let columns = parse_report_columns("gain_cad=Gain (CAD), time ,asset".split(',')).unwrap();
+        assert_eq!(columns[0].field, "gain_cad");
+        assert_eq!(columns[0].header, "Gain (CAD)");
+        assert_eq!(columns[1].header, "time");
+        assert!(parse_report_columns(["gain"].into_iter()).is_err());
+
+        let path = std::env::temp_dir().join(format!("kraken_acb_cols_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let id: Arc<str> = Arc::from("R1");
+        let mut row = make_row(
+            stamp(parse_time("2025-01-01 00:00:00").unwrap(), None),
+            &id,
+            &id,
+            "trade_disposition",
+            &Arc::from("BTC"),
+        );
+        row.gain_cad = "12.50".to_string();
+        write_report_columns(path, &[row], &columns).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            written,
+            "Gain (CAD),time,asset\n12.50,2025-01-01T00:00:00+00:00,BTC\n"
+        );
+    }
+
+    #[test]
+    fn excel_dialect_quotes_text_columns_and_tsv_uses_tabs() {
+        let path =
+            std::env::temp_dir().join(format!("kraken_acb_excel_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let id: Arc<str> = Arc::from("1234567890123456789");
+        let mut row = make_row(
+            stamp(parse_time("2025-01-01 00:00:00").unwrap(), None),
+            &id,
+            &id,
+            "trade_disposition",
+            &Arc::from("BTC"),
+        );
+        row.gain_cad = "12.50".to_string();
+        row.notes = "a \"quoted\" note".to_string();
+        let excel = CsvStyle {
+            delimiter: b',',
+            dialect: CsvDialect::Excel,
+        };
+        let columns = parse_report_columns("txid,gain_cad=Gain,notes".split(',')).unwrap();
+        write_report(path, &[&row], Some(&columns), excel).unwrap();
+        let written = std::fs::read(path).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "\u{feff}\"txid\",\"Gain\",\"notes\"\r\n\
+             \"1234567890123456789\",12.50,\"a \"\"quoted\"\" note\"\r\n"
+        );
+
+        // `verify` and `diff` read it back like a plain report.
+        let read = read_report(path).unwrap();
+        assert_eq!(
+            (&*read[0].txid, &*read[0].notes),
+            (&*id, "a \"quoted\" note")
+        );
+
+        let tsv = CsvStyle {
+            delimiter: b'\t',
+            ..CsvStyle::default()
+        };
+        write_report(path, &[&row], None, tsv).unwrap();
+        let written = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(written.starts_with("time\trefid\ttxid\t"));
+        assert!(written.ends_with("\t12.50\t\t\t\t\t\t\t\t\"a \"\"quoted\"\" note\"\n"));
+    }
+}
@@ -0,0 +1,950 @@
+use crate::{FxSource, PriceSource, PriceState, create_output, parse_decimal};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "cli")]
+const KRAKEN_OHLC_URL: &str = "https://api.kraken.com/0/public/OHLC";
+#[cfg(feature = "cli")]
+const BANK_OF_CANADA_URL: &str = "https://www.bankofcanada.ca/valet/observations/FXUSDCAD/json";
+#[cfg(feature = "cli")]
+const COINGECKO_URL: &str = "https://api.coingecko.com/api/v3/coins";
+
+// `--price-chain` when not given: the ledger's own trades, then the
+// fallback USD/CAD rate, with no network lookups.
+pub(crate) const DEFAULT_PRICE_CHAIN: &str = "override,cache,trade,fallback";
+
+// CoinGecko coin ids of common ledger symbols; others are not looked up.
+#[cfg(feature = "cli")]
+const COINGECKO_IDS: &[(&str, &str)] = &[
+    ("ADA", "cardano"),
+    ("ALGO", "algorand"),
+    ("ATOM", "cosmos"),
+    ("AVAX", "avalanche-2"),
+    ("BTC", "bitcoin"),
+    ("DAI", "dai"),
+    ("DOGE", "dogecoin"),
+    ("DOT", "polkadot"),
+    ("ETH", "ethereum"),
+    ("KSM", "kusama"),
+    ("LINK", "chainlink"),
+    ("LTC", "litecoin"),
+    ("MATIC", "matic-network"),
+    ("SOL", "solana"),
+    ("TRX", "tron"),
+    ("USDC", "usd-coin"),
+    ("USDT", "tether"),
+    ("XBT", "bitcoin"),
+    ("XLM", "stellar"),
+    ("XRP", "ripple"),
+    ("XTZ", "tezos"),
+];
+
+// One day's closing price of an asset, in CAD or USD.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Close {
+    pub(crate) price: Decimal,
+    pub(crate) usd: bool,
+}
+
+pub(crate) type DailyCloses = BTreeMap<(Arc<str>, NaiveDate), Close>;
+
+#[derive(Debug, Deserialize)]
+struct CloseRow {
+    date: String,
+    asset: String,
+    close: String,
+    #[serde(default)]
+    currency: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CloseOut<'a> {
+    date: NaiveDate,
+    asset: &'a str,
+    close: Decimal,
+    currency: &'static str,
+}
+
+pub(crate) fn load(path: &str) -> Result<DailyCloses, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+// `date,asset,close[,currency]` rows; currency is `CAD` (the default) or
+// `USD`. The same asset and date may only be listed once.
+pub(crate) fn parse(text: &str) -> Result<DailyCloses, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut closes = DailyCloses::new();
+    for (i, row) in rdr.deserialize::<CloseRow>().enumerate() {
+        let row = row?;
+        let line = i + 2;
+        let date = NaiveDate::parse_from_str(row.date.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("line {}: date must be YYYY-MM-DD", line))?;
+        let usd = match row.currency.trim().to_uppercase().as_str() {
+            "" | "CAD" => false,
+            "USD" => true,
+            other => return Err(format!("line {}: unknown currency {}", line, other).into()),
+        };
+        let close = Close {
+            price: parse_decimal(&row.close)?,
+            usd,
+        };
+        if close.price <= Decimal::ZERO {
+            return Err(format!("line {}: close must be positive", line).into());
+        }
+        let asset: Arc<str> = row.asset.trim().to_uppercase().into();
+        if closes.insert((asset.clone(), date), close).is_some() {
+            return Err(format!("{} on {} is listed twice", asset, date).into());
+        }
+    }
+    Ok(closes)
+}
+
+pub(crate) fn write(path: &str, closes: &DailyCloses) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(create_output(path)?);
+    for ((asset, date), close) in closes {
+        wtr.serialize(CloseOut {
+            date: *date,
+            asset,
+            close: close.price,
+            currency: if close.usd { "USD" } else { "CAD" },
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// Daily closes for each (asset, date) in `wanted`, from Kraken's public OHLC
+// endpoint, trying the CAD pair and then the USD pair. Kraken only serves the
+// last 720 days; dates it does not cover are left out.
+#[cfg(feature = "cli")]
+pub(crate) fn fetch_kraken(
+    wanted: &std::collections::BTreeSet<(Arc<str>, NaiveDate)>,
+) -> Result<DailyCloses, Box<dyn Error>> {
+    let mut by_asset: BTreeMap<&Arc<str>, Vec<NaiveDate>> = BTreeMap::new();
+    for (asset, date) in wanted {
+        by_asset.entry(asset).or_default().push(*date);
+    }
+    let mut closes = DailyCloses::new();
+    for (asset, dates) in by_asset {
+        let since = dates[0]
+            .and_hms_opt(0, 0, 0)
+            .map(|t| t.and_utc().timestamp() - 86_400)
+            .unwrap_or_default();
+        for (quote, usd) in [("CAD", false), ("USD", true)] {
+            let candles = fetch_ohlc(&format!("{}{}", asset, quote), since)?;
+            if candles.is_empty() {
+                continue;
+            }
+            for (date, price) in candles {
+                if dates.binary_search(&date).is_ok() {
+                    closes.insert((asset.clone(), date), Close { price, usd });
+                }
+            }
+            break;
+        }
+    }
+    Ok(closes)
+}
+
+// (UTC date, close) per daily candle; empty when Kraken has no such pair.
+#[cfg(feature = "cli")]
+fn fetch_ohlc(pair: &str, since: i64) -> Result<Vec<(NaiveDate, Decimal)>, Box<dyn Error>> {
+    let since = since.to_string();
+    let query = [("pair", pair), ("interval", "1440"), ("since", &since)];
+    let resp: serde_json::Value = crate::http::get(KRAKEN_OHLC_URL, &query)?.into_json()?;
+    if resp["error"].as_array().is_some_and(|e| !e.is_empty()) {
+        return Ok(Vec::new());
+    }
+    let mut out = Vec::new();
+    let result = resp["result"].as_object().ok_or("OHLC: missing result")?;
+    for (key, candles) in result {
+        if key == "last" {
+            continue;
+        }
+        for c in candles.as_array().into_iter().flatten() {
+            // [time, open, high, low, close, vwap, volume, count]
+            let (Some(time), Some(close)) = (c[0].as_i64(), c[4].as_str()) else {
+                return Err(format!("OHLC {}: unexpected candle {}", pair, c).into());
+            };
+            let date = chrono::DateTime::from_timestamp(time, 0)
+                .ok_or("OHLC: bad candle time")?
+                .date_naive();
+            out.push((date, parse_decimal(close)?));
+        }
+    }
+    Ok(out)
+}
+
+// A price from one link of the chain. A `usd` price is converted at the
+// chain's USD/CAD rate; `fx` is a rate already folded into a CAD price.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Quote {
+    pub(crate) price: Decimal,
+    pub(crate) usd: bool,
+    pub(crate) source: PriceSource,
+    pub(crate) fx: Option<(Decimal, FxSource)>,
+    // When a carried-forward price was set; `None` for the day's own price.
+    pub(crate) time: Option<NaiveDateTime>,
+}
+
+// CAD per USD.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rate {
+    pub(crate) fx: Decimal,
+    pub(crate) source: FxSource,
+    pub(crate) time: Option<NaiveDateTime>,
+}
+
+// One link of the price chain. A link answers what it can and returns `None`
+// to leave the lookup to the next one.
+pub(crate) trait PriceProvider: Send + Sync {
+    // The name `--price-chain` lists it by.
+    fn name(&self) -> &'static str;
+
+    // Whether it calls out to the network; `--offline` leaves these out.
+    fn online(&self) -> bool {
+        false
+    }
+
+    // One unit of `asset` (never CAD or USD) at `at`.
+    fn quote(
+        &self,
+        _asset: &str,
+        _at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    fn usd_cad(
+        &self,
+        _at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+fn midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_time(NaiveTime::MIN)
+}
+
+// `--price-overrides`: the latest listed price on or before the date. `USD`
+// rows are USD/CAD rates.
+struct Overrides(Arc<DailyCloses>);
+
+impl Overrides {
+    fn latest(&self, asset: &str, at: NaiveDateTime) -> Option<(NaiveDate, Close)> {
+        let asset: Arc<str> = asset.into();
+        self.0
+            .range((asset.clone(), NaiveDate::MIN)..=(asset, at.date()))
+            .next_back()
+            .map(|((_, date), close)| (*date, *close))
+    }
+}
+
+impl PriceProvider for Overrides {
+    fn name(&self) -> &'static str {
+        "override"
+    }
+
+    fn quote(
+        &self,
+        asset: &str,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        Ok(self.latest(asset, at).map(|(date, close)| Quote {
+            price: close.price,
+            usd: close.usd,
+            source: PriceSource::Override,
+            fx: None,
+            time: Some(midnight(date)),
+        }))
+    }
+
+    fn usd_cad(
+        &self,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        Ok(self.latest("USD", at).map(|(date, close)| Rate {
+            fx: close.price,
+            source: FxSource::Override,
+            time: Some(midnight(date)),
+        }))
+    }
+}
+
+// Prices the network links fetched, by asset and date (`USD` for USD/CAD),
+// seeded from `--price-cache`. Asset prices come from CoinGecko and rates
+// from the Bank of Canada, the only network links.
+#[derive(Default)]
+struct PriceCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    prices: DailyCloses,
+    // Lookups a network link has already been asked, found or not.
+    asked: HashSet<(&'static str, Arc<str>, NaiveDate)>,
+    fetched: usize,
+}
+
+impl PriceCache {
+    fn get(&self, asset: &str, date: NaiveDate) -> Option<Close> {
+        let state = self.state.lock().expect("price cache lock");
+        state.prices.get(&(Arc::from(asset), date)).copied()
+    }
+
+    // Whether `link` has yet to be asked for `asset` on `date`; it is marked
+    // as asked.
+    fn first_ask(&self, link: &'static str, asset: &str, date: NaiveDate) -> bool {
+        let mut state = self.state.lock().expect("price cache lock");
+        state.asked.insert((link, asset.into(), date))
+    }
+
+    fn store(&self, asset: &str, date: NaiveDate, close: Close) {
+        let mut state = self.state.lock().expect("price cache lock");
+        state.prices.insert((asset.into(), date), close);
+        state.fetched += 1;
+    }
+}
+
+impl PriceProvider for PriceCache {
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    fn quote(
+        &self,
+        asset: &str,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        Ok(self.get(asset, at.date()).map(|close| Quote {
+            price: close.price,
+            usd: close.usd,
+            source: PriceSource::CoinGecko,
+            fx: None,
+            time: None,
+        }))
+    }
+
+    fn usd_cad(
+        &self,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        Ok(self.get("USD", at.date()).map(|close| Rate {
+            fx: close.price,
+            source: FxSource::BankOfCanada,
+            time: None,
+        }))
+    }
+}
+
+// Stablecoins valued as the US dollars they track, at the USD/CAD rate
+// (`--treat-stablecoins-as-fiat`).
+struct UsdPar(BTreeSet<Arc<str>>);
+
+impl PriceProvider for UsdPar {
+    fn name(&self) -> &'static str {
+        "usd-par"
+    }
+
+    fn quote(
+        &self,
+        asset: &str,
+        _at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        Ok(self.0.contains(asset).then_some(Quote {
+            price: Decimal::ONE,
+            usd: true,
+            source: PriceSource::Fiat,
+            fx: None,
+            time: None,
+        }))
+    }
+}
+
+// The last price implied by the ledger's own trades against CAD or USD.
+struct TradeImplied;
+
+impl PriceProvider for TradeImplied {
+    fn name(&self) -> &'static str {
+        "trade"
+    }
+
+    fn quote(
+        &self,
+        asset: &str,
+        _at: NaiveDateTime,
+        state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        let time = state.observed_at.get(asset).copied();
+        if let Some(p) = state.asset_price_cad.get(asset) {
+            let fx = state.asset_price_cad_fx.get(asset).copied();
+            return Ok(Some(Quote {
+                price: *p,
+                usd: false,
+                source: PriceSource::TradeImplied,
+                fx,
+                // A CAD price derived from a USD one is as old as the older
+                // of the two.
+                time: if fx.is_some() {
+                    older(time, state.fx_time())
+                } else {
+                    time
+                },
+            }));
+        }
+        Ok(state.asset_price_usd.get(asset).map(|p| Quote {
+            price: *p,
+            usd: true,
+            source: PriceSource::TradeImplied,
+            fx: None,
+            time,
+        }))
+    }
+
+    fn usd_cad(
+        &self,
+        _at: NaiveDateTime,
+        state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        Ok(state.usd_cad_last.map(|fx| Rate {
+            fx,
+            source: FxSource::ObservedTrade,
+            time: state.fx_time(),
+        }))
+    }
+}
+
+// The fallback USD/CAD rate given on the command line.
+struct Fallback(Decimal);
+
+impl PriceProvider for Fallback {
+    fn name(&self) -> &'static str {
+        "fallback"
+    }
+
+    fn usd_cad(
+        &self,
+        _at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        Ok(Some(Rate {
+            fx: self.0,
+            source: FxSource::Fallback,
+            time: None,
+        }))
+    }
+}
+
+// The Bank of Canada's daily USD/CAD rate, from its Valet API; a weekend or
+// holiday takes the last business day's rate. One request per year.
+#[cfg(feature = "cli")]
+#[derive(Default)]
+struct BankOfCanada {
+    years: Mutex<BTreeMap<i32, BTreeMap<NaiveDate, Decimal>>>,
+}
+
+#[cfg(feature = "cli")]
+impl PriceProvider for BankOfCanada {
+    fn name(&self) -> &'static str {
+        "bank-of-canada"
+    }
+
+    fn online(&self) -> bool {
+        true
+    }
+
+    fn usd_cad(
+        &self,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Rate>, Box<dyn Error>> {
+        use chrono::Datelike;
+        let date = at.date();
+        let mut years = self.years.lock().expect("rate lock");
+        let rates = match years.entry(date.year()) {
+            std::collections::btree_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::btree_map::Entry::Vacant(e) => e.insert(fetch_boc_year(date.year())?),
+        };
+        Ok(rates.range(..=date).next_back().map(|(_, fx)| Rate {
+            fx: *fx,
+            source: FxSource::BankOfCanada,
+            time: None,
+        }))
+    }
+}
+
+// Rates for `year`, starting in the last days of the year before so that
+// 1 January has a preceding business day.
+#[cfg(feature = "cli")]
+fn fetch_boc_year(year: i32) -> Result<BTreeMap<NaiveDate, Decimal>, Box<dyn Error>> {
+    let start = format!("{}-12-20", year - 1);
+    let end = format!("{}-12-31", year);
+    let query = [("start_date", &*start), ("end_date", &*end)];
+    let resp: serde_json::Value = crate::http::get(BANK_OF_CANADA_URL, &query)?.into_json()?;
+    let mut rates = BTreeMap::new();
+    for obs in resp["observations"].as_array().into_iter().flatten() {
+        let (Some(d), Some(v)) = (obs["d"].as_str(), obs["FXUSDCAD"]["v"].as_str()) else {
+            return Err(format!("unexpected observation {}", obs).into());
+        };
+        let date = NaiveDate::parse_from_str(d, "%Y-%m-%d")?;
+        rates.insert(date, parse_decimal(v)?);
+    }
+    Ok(rates)
+}
+
+// CoinGecko's daily CAD price (00:00 UTC) of the assets in `COINGECKO_IDS`.
+#[cfg(feature = "cli")]
+struct CoinGecko;
+
+#[cfg(feature = "cli")]
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    fn online(&self) -> bool {
+        true
+    }
+
+    fn quote(
+        &self,
+        asset: &str,
+        at: NaiveDateTime,
+        _state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        let Some((_, id)) = COINGECKO_IDS.iter().find(|(symbol, _)| *symbol == asset) else {
+            return Ok(None);
+        };
+        let url = format!("{}/{}/history", COINGECKO_URL, id);
+        let date = at.format("%d-%m-%Y").to_string();
+        let resp = match crate::http::get(&url, &[("date", &date), ("localization", "false")]) {
+            Ok(resp) => resp,
+            Err(e) => match *e {
+                ureq::Error::Status(404, _) => return Ok(None),
+                ureq::Error::Status(429, _) => {
+                    return Err(
+                        "rate limited after retries; retry later, or keep a --price-cache".into(),
+                    );
+                }
+                e => return Err(e.into()),
+            },
+        };
+        let body: serde_json::Value = resp.into_json()?;
+        let serde_json::Value::Number(cad) = &body["market_data"]["current_price"]["cad"] else {
+            return Ok(None);
+        };
+        Ok(Some(Quote {
+            price: parse_decimal(&cad.to_string())?,
+            usd: false,
+            source: PriceSource::CoinGecko,
+            fx: None,
+            time: None,
+        }))
+    }
+}
+
+pub(crate) fn older(a: Option<NaiveDateTime>, b: Option<NaiveDateTime>) -> Option<NaiveDateTime> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// The `--price-chain` links in order. Network links share a cache, so each
+// asset and date is fetched once.
+#[derive(Clone)]
+pub(crate) struct PriceChain {
+    links: Vec<Arc<dyn PriceProvider>>,
+    overrides: Arc<DailyCloses>,
+    cache: Arc<PriceCache>,
+    // Assets valued at USD par ahead of every link.
+    par: BTreeSet<Arc<str>>,
+}
+
+impl fmt::Debug for PriceChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriceChain")
+            .field("links", &self.names())
+            .field("overrides", &self.overrides)
+            .field("par", &self.par)
+            .finish()
+    }
+}
+
+impl PriceChain {
+    // `DEFAULT_PRICE_CHAIN` with no overrides or cache file.
+    pub(crate) fn new(fallback_fx: Decimal) -> Self {
+        PriceChain::build(
+            DEFAULT_PRICE_CHAIN,
+            fallback_fx,
+            DailyCloses::new(),
+            DailyCloses::new(),
+            false,
+        )
+        .expect("default price chain builds")
+    }
+
+    // Links named in the comma-separated `spec`; `offline` leaves out the
+    // network ones.
+    pub(crate) fn build(
+        spec: &str,
+        fallback_fx: Decimal,
+        overrides: DailyCloses,
+        cached: DailyCloses,
+        offline: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Some(((_, date), _)) = overrides
+            .iter()
+            .find(|((asset, _), close)| &**asset == "USD" && close.usd)
+        {
+            return Err(format!("the USD override on {} must be a CAD rate", date).into());
+        }
+        let overrides = Arc::new(overrides);
+        let cache = Arc::new(PriceCache {
+            state: Mutex::new(CacheState {
+                prices: cached,
+                ..CacheState::default()
+            }),
+        });
+        let mut links: Vec<Arc<dyn PriceProvider>> = Vec::new();
+        for name in spec.split(',').map(str::trim) {
+            let link: Arc<dyn PriceProvider> = match name {
+                "override" => Arc::new(Overrides(overrides.clone())),
+                "cache" => cache.clone(),
+                "trade" => Arc::new(TradeImplied),
+                "fallback" => Arc::new(Fallback(fallback_fx)),
+                #[cfg(feature = "cli")]
+                "bank-of-canada" => Arc::new(BankOfCanada::default()),
+                #[cfg(feature = "cli")]
+                "coingecko" => Arc::new(CoinGecko),
+                #[cfg(not(feature = "cli"))]
+                "bank-of-canada" | "coingecko" => {
+                    return Err(format!(
+                        "price provider {} requires a build with the `cli` feature",
+                        name
+                    )
+                    .into());
+                }
+                other => return Err(format!("unknown price provider: {:?}", other).into()),
+            };
+            if links.iter().any(|l| l.name() == link.name()) {
+                return Err(format!("price provider {} is listed twice", name).into());
+            }
+            if !(offline && link.online()) {
+                links.push(link);
+            }
+        }
+        Ok(PriceChain {
+            links,
+            overrides,
+            cache,
+            par: BTreeSet::new(),
+        })
+    }
+
+    // Values `assets` at one US dollar each before asking any link, and has
+    // trades value them as fiat.
+    pub(crate) fn with_usd_par(mut self, assets: BTreeSet<Arc<str>>) -> Self {
+        if !assets.is_empty() {
+            self.links.insert(0, Arc::new(UsdPar(assets.clone())));
+        }
+        self.par = assets;
+        self
+    }
+
+    pub(crate) fn names(&self) -> Vec<&'static str> {
+        self.links.iter().map(|l| l.name()).collect()
+    }
+
+    pub(crate) fn at(&self, time: NaiveDateTime) -> Lookup<'_> {
+        Lookup { chain: self, time }
+    }
+
+    // Prices the network links fetched this run.
+    pub(crate) fn fetched(&self) -> usize {
+        self.cache.state.lock().expect("price cache lock").fetched
+    }
+
+    // Writes the cache, with what was fetched this run, to `path`.
+    pub(crate) fn save_cache(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        write(
+            path,
+            &self.cache.state.lock().expect("price cache lock").prices,
+        )
+    }
+
+    // Asks a network link once per asset and date, keeping what it finds.
+    fn ask_online<T>(
+        &self,
+        link: &dyn PriceProvider,
+        asset: &str,
+        date: NaiveDate,
+        ask: impl FnOnce() -> Result<Option<T>, Box<dyn Error>>,
+        close: impl Fn(&T) -> Close,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        if !self.cache.first_ask(link.name(), asset, date) {
+            return Ok(None);
+        }
+        let found = ask().map_err(|e| format!("{}: {}", link.name(), e))?;
+        if let Some(found) = &found {
+            self.cache.store(asset, date, close(found));
+        }
+        Ok(found)
+    }
+}
+
+// The chain at one event's time.
+#[derive(Clone, Copy)]
+pub(crate) struct Lookup<'a> {
+    chain: &'a PriceChain,
+    time: NaiveDateTime,
+}
+
+impl Lookup<'_> {
+    pub(crate) fn at_par(&self, asset: &str) -> bool {
+        self.chain.par.contains(asset)
+    }
+
+    pub(crate) fn quote(
+        &self,
+        asset: &str,
+        state: &PriceState,
+    ) -> Result<Option<Quote>, Box<dyn Error>> {
+        let date = self.time.date();
+        for link in &self.chain.links {
+            let found = if link.online() {
+                if let Some(hit) = self.chain.cache.quote(asset, self.time, state)? {
+                    return Ok(Some(hit));
+                }
+                self.chain.ask_online(
+                    link.as_ref(),
+                    asset,
+                    date,
+                    || link.quote(asset, self.time, state),
+                    |q| Close {
+                        price: q.price,
+                        usd: q.usd,
+                    },
+                )?
+            } else {
+                link.quote(asset, self.time, state)?
+            };
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn usd_cad(&self, state: &PriceState) -> Result<Option<Rate>, Box<dyn Error>> {
+        let date = self.time.date();
+        for link in &self.chain.links {
+            let found = if link.online() {
+                if let Some(hit) = self.chain.cache.usd_cad(self.time, state)? {
+                    return Ok(Some(hit));
+                }
+                self.chain.ask_online(
+                    link.as_ref(),
+                    "USD",
+                    date,
+                    || link.usd_cad(self.time, state),
+                    |r| Close {
+                        price: r.fx,
+                        usd: false,
+                    },
+                )?
+            } else {
+                link.usd_cad(self.time, state)?
+            };
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn closes_parse_write_and_reject_repeats() {
+        let text = "date,asset,close,currency\n\
+                    2025-01-02,sol,250.5,\n\
+                    2025-01-02,DOT,7.1,USD\n";
+        let closes = parse(text).unwrap();
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        assert_eq!(
+            closes[&(Arc::from("SOL"), day)],
+            Close {
+                price: dec!(250.5),
+                usd: false
+            }
+        );
+        assert!(closes[&(Arc::from("DOT"), day)].usd);
+
+        let path =
+            std::env::temp_dir().join(format!("kraken_acb_closes_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        write(path, &closes).unwrap();
+        assert_eq!(load(path).unwrap(), closes);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse("date,asset,close\n2025-01-02,SOL,1\n2025-01-02,SOL,2\n").is_err());
+        assert!(parse("date,asset,close,currency\n2025-01-02,SOL,1,EUR\n").is_err());
+    }
+
+    #[test]
+    fn chain_asks_links_in_order() {
+        let overrides =
+            parse("date,asset,close,currency\n2025-01-01,DOT,7,USD\n2025-03-01,USD,1.35,\n")
+                .unwrap();
+        let mut state = PriceState::default();
+        state.asset_price_cad.insert("DOT".into(), dec!(12));
+        let at = |s: &str| crate::parse_time(s).unwrap();
+
+        let chain = PriceChain::build(
+            "override,trade,fallback",
+            dec!(1.4),
+            overrides.clone(),
+            DailyCloses::new(),
+            false,
+        )
+        .unwrap();
+        // The latest override on or before the date wins over the trade price.
+        let quote = chain
+            .at(at("2025-02-01 00:00:00"))
+            .quote("DOT", &state)
+            .unwrap()
+            .unwrap();
+        assert_eq!((quote.price, quote.usd), (dec!(7), true));
+        assert_eq!(quote.time, Some(at("2025-01-01 00:00:00")));
+        let rate = |t| chain.at(at(t)).usd_cad(&state).unwrap().unwrap();
+        assert_eq!(rate("2025-02-01 00:00:00").source, FxSource::Fallback);
+        assert_eq!(
+            (
+                rate("2025-03-02 00:00:00").fx,
+                rate("2025-03-02 00:00:00").source
+            ),
+            (dec!(1.35), FxSource::Override)
+        );
+
+        let trade_first = PriceChain::build(
+            "trade,override",
+            dec!(1.4),
+            overrides,
+            DailyCloses::new(),
+            false,
+        )
+        .unwrap();
+        let lookup = trade_first.at(at("2025-02-01 00:00:00"));
+        assert_eq!(
+            lookup.quote("DOT", &state).unwrap().unwrap().price,
+            dec!(12)
+        );
+        assert!(lookup.usd_cad(&state).unwrap().is_none());
+
+        #[cfg(feature = "cli")]
+        {
+            let offline = PriceChain::build(
+                "trade,coingecko,fallback",
+                dec!(1.4),
+                DailyCloses::new(),
+                DailyCloses::new(),
+                true,
+            )
+            .unwrap();
+            assert_eq!(offline.names(), ["trade", "fallback"]);
+        }
+        let build = |spec| {
+            PriceChain::build(
+                spec,
+                dec!(1.4),
+                DailyCloses::new(),
+                DailyCloses::new(),
+                false,
+            )
+        };
+        assert!(build("trade,oracle").is_err());
+        assert!(build("trade,trade").is_err());
+    }
+
+    // A network link that counts how often it is asked.
+    struct Counting(std::sync::atomic::AtomicUsize);
+
+    impl PriceProvider for Counting {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn online(&self) -> bool {
+            true
+        }
+
+        fn quote(
+            &self,
+            _asset: &str,
+            _at: NaiveDateTime,
+            _state: &PriceState,
+        ) -> Result<Option<Quote>, Box<dyn Error>> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Some(Quote {
+                price: dec!(5),
+                usd: false,
+                source: PriceSource::CoinGecko,
+                fx: None,
+                time: None,
+            }))
+        }
+    }
+
+    #[test]
+    fn network_links_are_asked_once_per_day_and_cached() {
+        let counting = Arc::new(Counting(Default::default()));
+        let chain = PriceChain {
+            links: vec![counting.clone()],
+            overrides: Arc::default(),
+            cache: Arc::default(),
+            par: BTreeSet::new(),
+        };
+        let state = PriceState::default();
+        for time in ["2025-01-02 01:00:00", "2025-01-02 23:00:00"] {
+            let at = crate::parse_time(time).unwrap();
+            let quote = chain.at(at).quote("SOL", &state).unwrap().unwrap();
+            assert_eq!(quote.price, dec!(5));
+        }
+        assert_eq!(counting.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(chain.fetched(), 1);
+
+        let path =
+            std::env::temp_dir().join(format!("kraken_acb_price_cache_{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        chain.save_cache(path).unwrap();
+        let day = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        assert_eq!(
+            load(path).unwrap().keys().collect::<Vec<_>>(),
+            [&(Arc::from("SOL"), day)]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{TradeGroup, q2, q8, report_time, split_trade_legs};
+use chrono::Duration;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use std::sync::Arc;
+
+// Minutes a buy and a sell may be apart to count as a round trip, by default.
+pub(crate) const DEFAULT_WINDOW_MINUTES: i64 = 5;
+// Largest relative difference between the two prices of a round trip.
+pub(crate) const PRICE_TOLERANCE: Decimal = dec!(0.005);
+// Assets treated as the price side of a pair; between two others, the one
+// that sorts last is.
+const QUOTES: [&str; 6] = ["CAD", "USD", "EUR", "GBP", "USDT", "USDC"];
+
+// A trade and the opposite trade of the same pair soon after, at nearly the
+// same price: a wash or self-trade pattern worth explaining.
+#[derive(Debug, Serialize)]
+pub(crate) struct RoundTrip {
+    asset: Arc<str>,
+    quote: Arc<str>,
+    first_time: String,
+    pub(crate) first_refid: Arc<str>,
+    // `buy` or `sell` of `asset`.
+    first_side: &'static str,
+    second_time: String,
+    pub(crate) second_refid: Arc<str>,
+    seconds_apart: i64,
+    first_units: String,
+    second_units: String,
+    first_price: String,
+    second_price: String,
+    price_diff_pct: String,
+}
+
+// One trade as a buy or sell of the pair's asset.
+struct Side<'a> {
+    group: &'a TradeGroup,
+    asset: Arc<str>,
+    quote: Arc<str>,
+    buy: bool,
+    units: Decimal,
+    price: Decimal,
+}
+
+fn side(group: &TradeGroup) -> Option<Side<'_>> {
+    let (out, inn) = split_trade_legs(group).ok()?;
+    let quote_is_out = match (QUOTES.contains(&&*out.asset), QUOTES.contains(&&*inn.asset)) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => out.asset > inn.asset,
+    };
+    let (base, quote) = if quote_is_out {
+        (&inn, &out)
+    } else {
+        (&out, &inn)
+    };
+    let units = base.amount.abs();
+    if units.is_zero() {
+        return None;
+    }
+    Some(Side {
+        group,
+        asset: base.asset.clone(),
+        quote: quote.asset.clone(),
+        buy: quote_is_out,
+        units,
+        price: quote.amount.abs() / units,
+    })
+}
+
+// Pairs each trade with the first opposite trade of the same pair within
+// `window` after it, at a price within `PRICE_TOLERANCE`. A trade is in at
+// most one pair. `groups` must be in time order.
+pub(crate) fn detect(groups: &[&TradeGroup], window: Duration) -> Vec<RoundTrip> {
+    let sides: Vec<Side> = groups.iter().filter_map(|g| side(g)).collect();
+    let mut used = vec![false; sides.len()];
+    let mut out = Vec::new();
+    for i in 0..sides.len() {
+        if used[i] {
+            continue;
+        }
+        let a = &sides[i];
+        let matched = (i + 1..sides.len())
+            .take_while(|&j| sides[j].group.stamp() - a.group.stamp() <= window)
+            .find(|&j| {
+                let b = &sides[j];
+                !used[j]
+                    && b.asset == a.asset
+                    && b.quote == a.quote
+                    && b.buy != a.buy
+                    && ((b.price - a.price) / a.price).abs() <= PRICE_TOLERANCE
+            });
+        let Some(j) = matched else {
+            continue;
+        };
+        used[i] = true;
+        used[j] = true;
+        let b = &sides[j];
+        out.push(RoundTrip {
+            asset: a.asset.clone(),
+            quote: a.quote.clone(),
+            first_time: report_time(a.group.stamp()),
+            first_refid: a.group.refid.clone(),
+            first_side: if a.buy { "buy" } else { "sell" },
+            second_time: report_time(b.group.stamp()),
+            second_refid: b.group.refid.clone(),
+            seconds_apart: (b.group.stamp() - a.group.stamp()).num_seconds(),
+            first_units: q8(a.units).to_string(),
+            second_units: q8(b.units).to_string(),
+            first_price: q8(a.price).to_string(),
+            second_price: q8(b.price).to_string(),
+            price_diff_pct: q2((b.price - a.price) / a.price * dec!(100)).to_string(),
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::parse_time;
+
+    #[test]
+    fn flags_quick_reversals_at_the_same_price() {
+        let group = |refid: &str, time: &str, legs: [(&str, Decimal); 2]| TradeGroup {
+            refid: refid.into(),
+            time: parse_time(time).unwrap(),
+            txid: "".into(),
+            entries: legs.iter().map(|(a, x)| leg(refid, time, a, *x)).collect(),
+        };
+        let groups = [
+            group(
+                "B1",
+                "2025-01-01 10:00:00",
+                [("CAD", dec!(-200)), ("SOL", dec!(1))],
+            ),
+            group(
+                "S1",
+                "2025-01-01 10:02:00",
+                [("SOL", dec!(-1)), ("CAD", dec!(200.5))],
+            ),
+            // Too far from the buy before it, then too far in price.
+            group(
+                "B2",
+                "2025-01-01 11:00:00",
+                [("CAD", dec!(-200)), ("SOL", dec!(1))],
+            ),
+            group(
+                "S2",
+                "2025-01-01 11:30:00",
+                [("SOL", dec!(-1)), ("CAD", dec!(200))],
+            ),
+            group(
+                "B3",
+                "2025-01-01 11:31:00",
+                [("CAD", dec!(-220)), ("SOL", dec!(1))],
+            ),
+        ];
+        let refs: Vec<&TradeGroup> = groups.iter().collect();
+        let found = detect(&refs, Duration::minutes(5));
+        assert_eq!(found.len(), 1);
+        let trip = &found[0];
+        assert_eq!((&*trip.first_refid, &*trip.second_refid), ("B1", "S1"));
+        assert_eq!((trip.first_side, trip.seconds_apart), ("buy", 120));
+        assert_eq!(trip.price_diff_pct, "0.25");
+    }
+}
@@ -0,0 +1,427 @@
+use crate::{
+    Args, BalanceMismatch, DataIssue, Interner, LedgerEdit, ProcessOutput, STDIO_PATH,
+    asset_totals, cad, explain_failure, input_paths, load_inputs, parse_args_from, parse_decimal,
+    prepare_entries, process, process_options, q8, reconcile_balances, take_flag, watch_inputs,
+};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::Mutex;
+use tiny_http::{Header, Response, Server};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+// The rendered dashboard; `version` changes whenever it is rebuilt so open
+// pages know to reload.
+struct Page {
+    version: u64,
+    html: String,
+}
+
+// `serve [--listen ADDR] <ledger.csv> [tax_year] ... [options]`: renders the
+// report as a local web page and rebuilds it whenever an input file changes.
+pub(crate) fn serve(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let listen = take_flag(&mut raw, "listen")?.unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+    let args = parse_args_from(raw)?;
+    if input_paths(&args).contains(&STDIO_PATH) {
+        return Err("serve: inputs must be files, not stdin".into());
+    }
+    if args.db.is_some() {
+        return Err("serve: --db is not supported".into());
+    }
+
+    let page = Mutex::new(Page {
+        version: 1,
+        html: build_page(&args, 1),
+    });
+    let server = Server::http(&listen).map_err(|e| format!("serve: {}: {}", listen, e))?;
+    eprintln!(
+        "Serving tax year {} at http://{}/ (Ctrl-C to stop)",
+        args.tax_year, listen
+    );
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            watch_inputs(&args, || {
+                let version = page.lock().map(|p| p.version + 1).unwrap_or(1);
+                let html = build_page(&args, version);
+                if let Ok(mut p) = page.lock() {
+                    *p = Page { version, html };
+                }
+                eprintln!("Inputs changed; rebuilt the report");
+            })
+        });
+        for request in server.incoming_requests() {
+            let (body, content_type, status) = match request.url() {
+                "/" => match page.lock() {
+                    Ok(p) => (p.html.clone(), "text/html; charset=utf-8", 200),
+                    Err(_) => ("internal error".to_string(), "text/plain", 500),
+                },
+                "/version" => match page.lock() {
+                    Ok(p) => (p.version.to_string(), "text/plain", 200),
+                    Err(_) => ("internal error".to_string(), "text/plain", 500),
+                },
+                _ => ("not found".to_string(), "text/plain", 404),
+            };
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type_header(content_type));
+            if let Err(e) = request.respond(response) {
+                eprintln!("serve: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+pub(crate) fn content_type_header(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("valid header")
+}
+
+// Loads and processes the inputs; a failure is shown on the page instead of
+// stopping the server, since the file may be mid-edit.
+fn build_page(args: &Args, version: u64) -> String {
+    let result = (|| -> Result<String, Box<dyn Error>> {
+        let (mut entries, mut data_issues) = load_inputs(args, &mut Interner::default())?;
+        let balance_mismatches = reconcile_balances(&entries);
+        let prepared = prepare_entries(args, &mut entries)?;
+        data_issues.extend(prepared.gaps);
+        let opts = process_options(args, &entries)?;
+        let out = process(entries, &opts).map_err(|e| explain_failure(e, &data_issues))?;
+        render_dashboard(
+            args.tax_year,
+            &out,
+            &balance_mismatches,
+            &data_issues,
+            &prepared.edits,
+        )
+    })();
+    let body = result.unwrap_or_else(|e| {
+        format!(
+            "<h1>Tax year {}</h1><p class=\"loss\">Could not build the report: {}</p>",
+            args.tax_year,
+            escape(&e.to_string())
+        )
+    });
+    format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Kraken ACB {year}</title>\n\
+         <style>{css}</style></head><body>\n{body}\n\
+         <script>\nsetInterval(() => fetch('/version').then(r => r.text())\
+         .then(v => {{ if (v !== '{version}') location.reload(); }}).catch(() => {{}}), 2000);\n\
+         </script></body></html>\n",
+        year = args.tax_year,
+        css = CSS,
+        body = body,
+        version = version,
+    )
+}
+
+const CSS: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}td,th{padding:2px 10px;border-bottom:1px solid #ddd}\
+td.num{text-align:right;font-variant-numeric:tabular-nums}.gain{color:#1a7f37}.loss{color:#cf222e}\
+.warn{color:#9a6700}section{margin-bottom:2em}svg{display:block;margin:0.5em 0}";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn signed_class(x: Decimal) -> &'static str {
+    if x > Decimal::ZERO {
+        "num gain"
+    } else if x < Decimal::ZERO {
+        "num loss"
+    } else {
+        "num"
+    }
+}
+
+fn render_dashboard(
+    tax_year: i32,
+    out: &ProcessOutput,
+    balance_mismatches: &[BalanceMismatch],
+    data_issues: &[DataIssue],
+    edits: &[LedgerEdit],
+) -> Result<String, Box<dyn Error>> {
+    let mut html = String::new();
+    writeln!(html, "<h1>Tax year {}</h1>", tax_year)?;
+
+    html.push_str("<section><h2>Totals</h2><table>");
+    for (label, amount, signed) in out.totals.rows() {
+        let class = if signed { signed_class(amount) } else { "num" };
+        writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"{}\">{}</td></tr>",
+            label,
+            class,
+            cad(amount)
+        )?;
+    }
+    html.push_str("</table></section>\n");
+
+    html.push_str("<section><h2>Warnings</h2><ul>");
+    let mut warnings = 0;
+    for r in out
+        .report
+        .iter()
+        .filter(|r| r.event_type.starts_with("warning_"))
+    {
+        warnings += 1;
+        writeln!(
+            html,
+            "<li class=\"warn\">{} {} {} (refid {}): {}</li>",
+            escape(&r.time),
+            escape(&r.asset),
+            escape(&r.event_type),
+            escape(&r.refid),
+            escape(&r.notes)
+        )?;
+    }
+    for s in &out.ignored {
+        warnings += 1;
+        writeln!(
+            html,
+            "<li class=\"warn\">Ignored {} row(s) of type={} subtype={} (e.g. refid {})</li>",
+            s.count,
+            escape(&s.row_type),
+            escape(&s.subtype),
+            escape(&s.sample_refids)
+        )?;
+    }
+    for m in balance_mismatches {
+        warnings += 1;
+        writeln!(
+            html,
+            "<li class=\"warn\">{} balance at {} (refid {}) is {} in the ledger but {} from its rows</li>",
+            escape(&m.asset),
+            escape(&m.time),
+            escape(&m.refid),
+            m.ledger_balance,
+            m.computed_balance
+        )?;
+    }
+    for d in data_issues {
+        warnings += 1;
+        let class = if d.is_note() { "" } else { " class=\"warn\"" };
+        writeln!(html, "<li{}>{}</li>", class, escape(&d.describe()))?;
+    }
+    for e in edits {
+        warnings += 1;
+        writeln!(html, "<li class=\"warn\">{}</li>", escape(&e.describe()))?;
+    }
+    if warnings == 0 {
+        html.push_str("<li>None</li>");
+    }
+    html.push_str("</ul></section>\n");
+
+    let assets = asset_totals(&out.report)?;
+    html.push_str("<section><h2>Gains and income by asset</h2>");
+    let gains: Vec<(String, Decimal)> = assets
+        .iter()
+        .map(|(asset, t)| (asset.to_string(), t.gain_cad))
+        .collect();
+    html.push_str(&bar_chart(&gains));
+    html.push_str(
+        "<table><tr><th>Asset</th><th>Proceeds (CAD)</th><th>ACB disposed (CAD)</th>\
+         <th>Gain/loss (CAD)</th><th>Income (CAD)</th></tr>",
+    );
+    for (asset, t) in &assets {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"{}\">{}</td><td class=\"num\">{}</td></tr>",
+            escape(asset),
+            cad(t.proceeds_cad),
+            cad(t.acb_disposed_cad),
+            signed_class(t.gain_cad),
+            cad(t.gain_cad),
+            cad(t.income_cad)
+        )?;
+    }
+    html.push_str("</table></section>\n");
+
+    html.push_str("<section><h2>Pools</h2>");
+    let mut history: BTreeMap<&str, Vec<Decimal>> = BTreeMap::new();
+    for r in out
+        .report
+        .iter()
+        .filter(|r| !r.pool_acb_cad_after.is_empty())
+    {
+        history
+            .entry(&r.asset)
+            .or_default()
+            .push(parse_decimal(&r.pool_acb_cad_after)?);
+    }
+    let mut pools: Vec<_> = out
+        .pools
+        .iter()
+        .filter(|(asset, _)| asset.as_ref() != "CAD")
+        .collect();
+    pools.sort_by(|a, b| a.0.cmp(b.0));
+    for (asset, pool) in pools {
+        writeln!(
+            html,
+            "<h3>{}</h3><p>Ending units {}, ACB {} CAD</p>",
+            escape(asset),
+            q8(pool.units),
+            cad(pool.acb_cad)
+        )?;
+        if let Some(points) = history.get(asset.as_ref()) {
+            html.push_str(&line_chart(points));
+        }
+    }
+    html.push_str("</section>\n");
+
+    html.push_str(
+        "<section><h2>Report rows</h2><table><tr><th>Time</th><th>Event</th><th>Asset</th>\
+         <th>In</th><th>Out</th><th>Proceeds</th><th>ACB disposed</th><th>Gain</th>\
+         <th>Income</th><th>Notes</th></tr>",
+    );
+    for r in &out.report {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+             <td class=\"num\">{}</td><td>{}</td></tr>",
+            escape(&r.time),
+            escape(&r.event_type),
+            escape(&r.asset),
+            r.units_in,
+            r.units_out,
+            r.proceeds_cad,
+            r.acb_disposed_cad,
+            r.gain_cad,
+            r.income_cad,
+            escape(&r.notes)
+        )?;
+    }
+    html.push_str("</table></section>\n");
+    Ok(html)
+}
+
+const CHART_WIDTH: f64 = 600.0;
+const BAR_HEIGHT: f64 = 22.0;
+const LABEL_WIDTH: f64 = 90.0;
+
+// Horizontal bars around a zero line, green for gains and red for losses.
+fn bar_chart(bars: &[(String, Decimal)]) -> String {
+    if bars.is_empty() {
+        return String::new();
+    }
+    let max = bars
+        .iter()
+        .map(|(_, x)| x.abs().to_f64().unwrap_or(0.0))
+        .fold(0.0, f64::max)
+        .max(f64::MIN_POSITIVE);
+    let has_losses = bars.iter().any(|(_, x)| *x < Decimal::ZERO);
+    let span = CHART_WIDTH - LABEL_WIDTH;
+    let zero = if has_losses {
+        LABEL_WIDTH + span / 2.0
+    } else {
+        LABEL_WIDTH
+    };
+    let scale = if has_losses { span / 2.0 } else { span } / max;
+    let height = BAR_HEIGHT * bars.len() as f64;
+    let mut svg = format!(
+        "<svg width=\"{}\" height=\"{}\" role=\"img\">",
+        CHART_WIDTH, height
+    );
+    for (i, (label, x)) in bars.iter().enumerate() {
+        let y = i as f64 * BAR_HEIGHT;
+        let w = x.to_f64().unwrap_or(0.0) * scale;
+        let (left, color) = if w < 0.0 {
+            (zero + w, "#cf222e")
+        } else {
+            (zero, "#1a7f37")
+        };
+        let _ = write!(
+            svg,
+            "<text x=\"0\" y=\"{:.1}\" font-size=\"13\">{}</text>\
+             <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\">\
+             <title>{} {} CAD</title></rect>",
+            y + BAR_HEIGHT * 0.7,
+            escape(label),
+            left,
+            y + 3.0,
+            w.abs(),
+            BAR_HEIGHT - 6.0,
+            color,
+            escape(label),
+            cad(*x)
+        );
+    }
+    let _ = write!(
+        svg,
+        "<line x1=\"{0:.1}\" y1=\"0\" x2=\"{0:.1}\" y2=\"{1:.1}\" stroke=\"#888\"/></svg>",
+        zero, height
+    );
+    svg
+}
+
+const LINE_HEIGHT: f64 = 120.0;
+
+// Pool ACB after each event, evenly spaced by event.
+fn line_chart(points: &[Decimal]) -> String {
+    let max = points
+        .iter()
+        .map(|x| x.to_f64().unwrap_or(0.0))
+        .fold(0.0, f64::max)
+        .max(f64::MIN_POSITIVE);
+    let step = if points.len() > 1 {
+        CHART_WIDTH / (points.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let coords: Vec<String> = points
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let y = LINE_HEIGHT - x.to_f64().unwrap_or(0.0) / max * (LINE_HEIGHT - 10.0);
+            format!("{:.1},{:.1}", i as f64 * step, y)
+        })
+        .collect();
+    format!(
+        "<svg width=\"{}\" height=\"{}\" role=\"img\"><title>Pool ACB over time, max {} CAD</title>\
+         <polyline fill=\"none\" stroke=\"#0969da\" stroke-width=\"2\" points=\"{}\"/></svg>",
+        CHART_WIDTH,
+        LINE_HEIGHT,
+        cad(Decimal::from_f64_retain(max).unwrap_or_default()),
+        coords.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessOptions;
+    use crate::fixtures::{leg, row};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn dashboard_shows_totals_charts_and_escaped_warnings() {
+        let entries = vec![
+            leg("R1", "2025-01-01 00:00:00", "CAD", dec!(-100)),
+            leg("R1", "2025-01-01 00:00:00", "SOL", dec!(1)),
+            leg("R2", "2025-02-01 00:00:00", "SOL", dec!(-0.5)),
+            leg("R2", "2025-02-01 00:00:00", "CAD", dec!(80)),
+            row("<R3>", "2025-03-01 00:00:00", "deposit", "", "ETH", dec!(1)),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let html = render_dashboard(2025, &out, &[], &[], &[]).unwrap();
+        assert!(html.contains("<td>Net capital gain/loss</td><td class=\"num gain\">30.00</td>"));
+        assert!(html.contains("<title>SOL 30.00 CAD</title>"));
+        assert!(html.contains("refid &lt;R3&gt;"));
+        assert!(html.contains("<h3>SOL</h3><p>Ending units 0.5"));
+        assert!(html.contains("<polyline"));
+        assert_eq!(
+            take_flag(&mut vec!["--listen=:9".into()], "listen")
+                .unwrap()
+                .as_deref(),
+            Some(":9")
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::accounts::write_overview;
+use crate::table::{Style, paint};
+use crate::{
+    Args, LedgerEntry, STDIO_PATH, grouped_totals, is_foreign_currency, parse_decimal, run_report,
+    side_report_path, summary_color, summary_writer,
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+// `--split 50:50[,ASSET=60:40...]`: each taxpayer's share of a jointly held
+// account, overall and for listed assets. Shares are fractions summing to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Attribution {
+    shares: [Decimal; 2],
+    by_asset: BTreeMap<Arc<str>, [Decimal; 2]>,
+}
+
+impl Attribution {
+    pub(crate) fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let mut shares = None;
+        let mut by_asset = BTreeMap::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((asset, ratio)) => {
+                    let asset: Arc<str> = asset.trim().to_uppercase().into();
+                    if by_asset
+                        .insert(asset.clone(), parse_ratio(ratio)?)
+                        .is_some()
+                    {
+                        return Err(format!("--split: {} is listed twice", asset).into());
+                    }
+                }
+                None if shares.is_none() => shares = Some(parse_ratio(part)?),
+                None => return Err("--split: give one overall ratio".into()),
+            }
+        }
+        Ok(Attribution {
+            shares: shares.ok_or("--split expects a ratio such as 50:50")?,
+            by_asset,
+        })
+    }
+
+    fn shares_of(&self, asset: &str) -> [Decimal; 2] {
+        self.by_asset.get(asset).copied().unwrap_or(self.shares)
+    }
+}
+
+// `60:40` as the fractions 0.6 and 0.4.
+fn parse_ratio(s: &str) -> Result<[Decimal; 2], Box<dyn Error>> {
+    let (a, b) = s
+        .split_once(':')
+        .ok_or_else(|| format!("--split: expected a ratio such as 50:50, got {:?}", s))?;
+    let (a, b) = (parse_decimal(a)?, parse_decimal(b)?);
+    if a <= Decimal::ZERO || b <= Decimal::ZERO {
+        return Err(format!("--split: both shares of {} must be positive", s).into());
+    }
+    Ok([a / (a + b), b / (a + b)])
+}
+
+// Scales every row to taxpayer `side`'s share. All rows of a refid take one
+// share, so a trade stays balanced: that of its assets other than CAD and
+// foreign currencies, or of its currencies when it has nothing else. A refid
+// whose assets have different shares would move value between the taxpayers,
+// so it is an error.
+pub(crate) fn attribute_entries(
+    entries: &mut [LedgerEntry],
+    split: &Attribution,
+    side: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut assets: BTreeMap<Arc<str>, BTreeSet<Arc<str>>> = BTreeMap::new();
+    for e in entries.iter() {
+        assets
+            .entry(e.refid.clone())
+            .or_default()
+            .insert(e.asset.clone());
+    }
+    let mut shares: BTreeMap<Arc<str>, Decimal> = BTreeMap::new();
+    for (refid, assets) in assets {
+        let fiat = |a: &Arc<str>| &**a == "CAD" || is_foreign_currency(a);
+        let mut held: Vec<&Arc<str>> = assets.iter().filter(|a| !fiat(a)).collect();
+        if held.is_empty() {
+            held = assets.iter().collect();
+        }
+        let share = split.shares_of(held[0])[side];
+        if let Some(other) = held.iter().find(|a| split.shares_of(a)[side] != share) {
+            return Err(format!(
+                "--split: refid {} moves {} and {}, which are split differently",
+                refid, held[0], other
+            )
+            .into());
+        }
+        shares.insert(refid, share);
+    }
+    for e in entries.iter_mut() {
+        let share = shares[&e.refid];
+        e.amount *= share;
+        e.fee *= share;
+        e.net_delta *= share;
+        e.balance = e.balance.map(|b| b * share);
+    }
+    Ok(())
+}
+
+// Runs the report once per taxpayer, each on their share of the ledger, into
+// `<out>_<name>.csv`, then prints the two side by side.
+pub(crate) fn run(args: &Args) -> Result<u8, Box<dyn Error>> {
+    if args.output == STDIO_PATH {
+        return Err("--split needs a report file, not stdout".into());
+    }
+    if args.watch || args.db.is_some() || args.checkpoint.is_some() {
+        return Err("--split does not support --watch, --db or --checkpoint".into());
+    }
+    let color = summary_color(&args.output, args.no_color);
+    let mut out = summary_writer(&args.output);
+    let mut code = 0;
+    let mut overview = Vec::new();
+    for (side, name) in args.split_names.iter().enumerate() {
+        writeln!(
+            out,
+            "{}",
+            paint(&format!("=== TAXPAYER {} ===", name), Style::Heading, color)
+        )?;
+        let mut taxpayer = args.clone();
+        taxpayer.output = side_report_path(&args.output, name);
+        taxpayer.split_side = Some(side);
+        let (_, taxpayer_code, report) = run_report(&taxpayer)?;
+        code = code.max(taxpayer_code);
+        let totals = grouped_totals(report.iter().map(|r| ((), r)))?
+            .remove(&())
+            .unwrap_or_default();
+        overview.push((name.clone(), totals));
+        writeln!(out)?;
+    }
+    let overview_output = side_report_path(&args.output, "split");
+    write_overview(
+        &mut out,
+        color,
+        "=== SPLIT BETWEEN TAXPAYERS ===",
+        "Taxpayer",
+        &overview,
+        &overview_output,
+    )?;
+    writeln!(out, "\nWrote split overview: {}", overview_output)?;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::{ProcessOptions, process};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn each_taxpayer_gets_their_share_of_the_gains() {
+        let entries = vec![
+            leg("B1", "2025-01-01 00:00:00", "CAD", dec!(-100)),
+            leg("B1", "2025-01-01 00:00:00", "SOL", dec!(1)),
+            leg("B2", "2025-01-02 00:00:00", "CAD", dec!(-200)),
+            leg("B2", "2025-01-02 00:00:00", "BTC", dec!(0.01)),
+            leg("S1", "2025-02-01 00:00:00", "SOL", dec!(-1)),
+            leg("S1", "2025-02-01 00:00:00", "CAD", dec!(150)),
+            leg("S2", "2025-02-02 00:00:00", "BTC", dec!(-0.01)),
+            leg("S2", "2025-02-02 00:00:00", "CAD", dec!(300)),
+        ];
+        let split = Attribution::parse("50:50, btc=70:30").unwrap();
+        let gains: Vec<Decimal> = (0..2)
+            .map(|side| {
+                let mut share = entries.clone();
+                attribute_entries(&mut share, &split, side).unwrap();
+                let out = process(share, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+                out.totals.capital_gain_cad
+            })
+            .collect();
+        // SOL's 50 halved, BTC's 100 split 70:30.
+        assert_eq!(gains, [dec!(95), dec!(55)]);
+
+        let mut swap = vec![
+            leg("X1", "2025-03-01 00:00:00", "SOL", dec!(-1)),
+            leg("X1", "2025-03-01 00:00:00", "BTC", dec!(0.01)),
+        ];
+        assert!(attribute_entries(&mut swap, &split, 0).is_err());
+        assert!(Attribution::parse("100:0").is_err());
+    }
+}
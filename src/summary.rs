@@ -0,0 +1,826 @@
+use crate::mutation::MutationRow;
+use crate::output::ReportPaths;
+use crate::round_trip::{self, RoundTrip};
+use crate::table::{Cell, Style, Table, paint};
+use crate::{
+    Args, BalanceMismatch, DataIssue, GainRef, IgnoredRowSummary, IncomeValuation, LedgerEdit,
+    LossCarryoverRow, Pool, Pooling, ProcessOptions, ReportFormat, ReportRow, Rounding, STDIO_PATH,
+    TieBreak, Totals, asset_totals, cad, parse_decimal, q2, q8, summary_color, summary_writer,
+    tax_rates,
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+// What the console summary of a report run shows.
+pub(crate) struct Summary<'a> {
+    pub(crate) args: &'a Args,
+    pub(crate) opts: &'a ProcessOptions,
+    pub(crate) paths: &'a ReportPaths,
+    pub(crate) totals: &'a Totals,
+    pub(crate) report: &'a [ReportRow],
+    pub(crate) shown: &'a [&'a ReportRow],
+    pub(crate) shown_fills: &'a [&'a ReportRow],
+    pub(crate) pools: &'a HashMap<Arc<str>, Pool>,
+    pub(crate) deemed_rows: &'a [&'a ReportRow],
+    pub(crate) carryover: &'a [LossCarryoverRow],
+    pub(crate) round_trips: &'a [RoundTrip],
+    pub(crate) ignored: &'a [IgnoredRowSummary],
+    pub(crate) ignored_count: usize,
+    pub(crate) balance_mismatches: &'a [BalanceMismatch],
+    pub(crate) data_issues: &'a [DataIssue],
+    pub(crate) data_warnings: usize,
+    pub(crate) edits: &'a [LedgerEdit],
+    pub(crate) matched_transfers: usize,
+    pub(crate) joined_trades: (usize, usize),
+    pub(crate) fallback_fx_uses: usize,
+    pub(crate) resumed_from: Option<i32>,
+    pub(crate) mutation_rows: &'a [MutationRow],
+    pub(crate) split_outputs: &'a [(String, Vec<&'a ReportRow>)],
+    pub(crate) export_outputs: &'a [(String, usize)],
+    pub(crate) side_reports: bool,
+}
+
+impl Summary<'_> {
+    // Prints the run's settings, totals, warnings and ending pools, then the
+    // files it wrote, to the console (nothing under `--quiet`).
+    pub(crate) fn print(&self) -> Result<(), Box<dyn Error>> {
+        let &Summary {
+            args,
+            opts,
+            paths,
+            totals,
+            report,
+            shown,
+            shown_fills,
+            pools,
+            deemed_rows,
+            carryover,
+            round_trips,
+            ignored,
+            ignored_count,
+            balance_mismatches,
+            data_issues,
+            data_warnings,
+            edits,
+            matched_transfers,
+            joined_trades,
+            fallback_fx_uses,
+            resumed_from,
+            mutation_rows,
+            split_outputs,
+            export_outputs,
+            side_reports,
+        } = self;
+        let mut out: Box<dyn Write> = if args.quiet {
+            Box::new(std::io::sink())
+        } else {
+            summary_writer(&args.output)
+        };
+        let fetched_prices = opts.price_chain.fetched();
+        let color = summary_color(&args.output, args.no_color);
+        let t = |text: &'static str| args.locale.text(text);
+        let tr =
+            |template: &str, values: &[&dyn std::fmt::Display]| args.locale.fill(template, values);
+        let heading = |title: &str| paint(title, Style::Heading, color);
+        let warn = |text: String, count: usize| {
+            let style = if count > 0 {
+                Style::Warning
+            } else {
+                Style::Plain
+            };
+            paint(&text, style, color)
+        };
+        writeln!(
+            out,
+            "\n{}",
+            heading(t("=== CANADIAN CRYPTO TAX SUMMARY (LEDGER / ACB) ==="))
+        )?;
+        writeln!(out, "{}", tr("Tax year: {}", &[&args.tax_year]))?;
+        match (&args.config, &args.profile) {
+            (Some(path), Some(profile)) => {
+                writeln!(out, "{}", tr("Config: {} (profile {})", &[path, profile]))?
+            }
+            (Some(path), None) => writeln!(out, "{}", tr("Config: {}", &[path]))?,
+            _ => {}
+        }
+        writeln!(
+            out,
+            "{}",
+            tr("Jurisdiction: {}", &[&args.jurisdiction.name()])
+        )?;
+        if let Some(tz) = args.timezone {
+            writeln!(out, "{}", tr("Timezone: {}", &[&tz]))?;
+        }
+        for (template, value) in [
+            (
+                "Fallback USD/CAD FX: {}",
+                args.fallback_usd_cad_fx.to_string(),
+            ),
+            ("Trading fee mode: {}", args.fee_mode.as_str().to_string()),
+            (
+                "Maker rebate mode: {}",
+                args.rebate_mode.as_str().to_string(),
+            ),
+            (
+                "Adjustment mode: {}",
+                args.adjustment_mode.as_str().to_string(),
+            ),
+            (
+                "Donation proceeds: {}",
+                args.donation_proceeds.as_str().to_string(),
+            ),
+            (
+                "Reward income valuation: {}",
+                args.income_valuation.as_str().to_string(),
+            ),
+        ] {
+            writeln!(out, "{}", tr(template, &[&value]))?;
+        }
+        writeln!(
+            out,
+            "{}",
+            tr(
+                "Price chain: {}{}",
+                &[
+                    &opts.price_chain.names().join(" > "),
+                    &if args.offline { t(" (offline)") } else { "" },
+                ]
+            )
+        )?;
+        if !args.stablecoins_as_fiat.is_empty() {
+            let names: Vec<&str> = args.stablecoins_as_fiat.iter().map(|a| &**a).collect();
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Stablecoins valued as USD: {} (dispositions: {})",
+                    &[&names.join(", "), &args.stablecoin_dispositions.as_str()]
+                )
+            )?;
+        }
+        if !args.asset_aliases.is_empty() {
+            let aliases: Vec<String> = args
+                .asset_aliases
+                .iter()
+                .map(|(from, to)| format!("{} -> {}", from, to))
+                .collect();
+            writeln!(out, "{}", tr("Asset aliases: {}", &[&aliases.join(", ")]))?;
+        }
+        if args.pooling == Pooling::PerWallet {
+            writeln!(out, "{}", tr("Pooling: {}", &[&args.pooling.as_str()]))?;
+        }
+        if args.tie_break != TieBreak::Id {
+            writeln!(
+                out,
+                "{}",
+                tr("Same-time events: {}", &[&args.tie_break.as_str()])
+            )?;
+        }
+        if args.check_invariants {
+            writeln!(out, "{}", t("Invariant checks: on"))?;
+        }
+        if let Some(limit) = args.exemptions.fx_per_disposition {
+            writeln!(
+                out,
+                "{}",
+                tr("FX exemption: each disposition within {} CAD", &[&limit])
+            )?;
+        }
+        if let Some(limit) = args.exemptions.spends_under {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Personal-use exemption: spends of {} CAD or less",
+                    &[&limit]
+                )
+            )?;
+        }
+        let rounding = &args.rounding;
+        if rounding.full_precision {
+            writeln!(out, "{}", t("Rounding: none (full precision)"))?;
+        } else if *rounding != Rounding::default() {
+            let line = tr(
+                "Rounding: {} (proceeds {}, ACB {}, units {} decimal places)",
+                &[
+                    &rounding.strategy_name(),
+                    &rounding.proceeds_dp,
+                    &rounding.acb_dp,
+                    &rounding.units_dp,
+                ],
+            );
+            writeln!(out, "{}", line)?;
+        }
+        let jurisdiction = &args.jurisdiction;
+        if jurisdiction
+            .cost_basis_methods()
+            .contains(&opts.cost_basis.name())
+        {
+            writeln!(out, "{}", tr("Cost basis: {}", &[&opts.cost_basis.name()]))?;
+        } else {
+            let line = tr(
+                "Cost basis: {} (not accepted on a {} return)",
+                &[&opts.cost_basis.name(), &jurisdiction.name()],
+            );
+            writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+        }
+        if let Some(columns) = &args.columns {
+            for field in jurisdiction.required_columns() {
+                if !columns.iter().any(|c| c.field == *field) {
+                    let line = tr(
+                        "  WARNING: --columns leaves out {}, which a {} return needs",
+                        &[field, &jurisdiction.name()],
+                    );
+                    writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+                }
+            }
+        }
+        if let Some(days) = args.max_price_age {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Prices older than {} days: {}",
+                    &[&days, &args.stale_prices.as_str()]
+                )
+            )?;
+        }
+        if let Some(period) = args.aggregate_rewards {
+            writeln!(out, "{}", tr("Reward rows: summed {}", &[&period.as_str()]))?;
+        }
+        if let Some(year) = resumed_from {
+            writeln!(
+                out,
+                "{}",
+                tr("Resumed from checkpoint: end of {}", &[&year])
+            )?;
+        }
+
+        let mut totals_table = Table::new(&[t("Total"), "CAD"]);
+        for (label, amount, signed) in totals.rows() {
+            let style = if signed {
+                Style::signed(amount)
+            } else {
+                Style::Plain
+            };
+            totals_table.row(vec![
+                (t(label).to_string(), Style::Plain),
+                (cad(amount), style),
+            ]);
+        }
+        write!(out, "\n{}", totals_table.render(color))?;
+        let drift = &totals.row_drift;
+        if drift.amounts().iter().any(|d| !d.is_zero()) {
+            let [proceeds, acb, gain, income] = drift.amounts().map(|d| d.round_dp(4).normalize());
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Report rows minus totals (rounding): proceeds {}, ACB {}, gain {}, income {} CAD",
+                    &[&proceeds, &acb, &gain, &income]
+                )
+            )?;
+        }
+        if !drift.within_rounding() {
+            let line = tr(
+                "  WARNING: the report rows and totals differ by more than rounding explains ({} CAD)",
+                &[&drift.max_rounding_cad.normalize()],
+            );
+            writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+        }
+
+        let mut asset_table = Table::new(&[
+            t("Asset"),
+            t("Proceeds (CAD)"),
+            t("ACB disposed (CAD)"),
+            t("Gain/loss (CAD)"),
+            t("Income (CAD)"),
+        ]);
+        for (asset, t) in asset_totals(report)? {
+            asset_table.row(vec![
+                (asset.to_string(), Style::Plain),
+                (cad(t.proceeds_cad), Style::Plain),
+                (cad(t.acb_disposed_cad), Style::Plain),
+                (cad(t.gain_cad), Style::signed(t.gain_cad)),
+                (cad(t.income_cad), Style::Plain),
+            ]);
+        }
+        if !asset_table.is_empty() {
+            writeln!(out, "\n{}", heading(t("=== GAINS AND INCOME BY ASSET ===")))?;
+            write!(out, "{}", asset_table.render(color))?;
+        }
+
+        let mut holding_table = Table::new(&[
+            t("Held"),
+            t("Proceeds (CAD)"),
+            t("ACB disposed (CAD)"),
+            t("Gain/loss (CAD)"),
+        ]);
+        for (period, held) in &totals.holding_periods {
+            holding_table.row(vec![
+                (t(period.label()).to_string(), Style::Plain),
+                (cad(held.proceeds_cad), Style::Plain),
+                (cad(held.acb_disposed_cad), Style::Plain),
+                (cad(held.gain_cad), Style::signed(held.gain_cad)),
+            ]);
+        }
+        if !holding_table.is_empty() {
+            writeln!(out, "\n{}", heading(t("=== GAINS BY HOLDING PERIOD ===")))?;
+            write!(out, "{}", holding_table.render(color))?;
+        }
+
+        let activity = &totals.activity;
+        if activity.trades > 0 || activity.avg_holding_days.is_some() {
+            let gain_ref = |g: &Option<GainRef>| {
+                g.as_ref().map_or("-".to_string(), |g| {
+                    format!("{} ({} {})", cad(g.gain_cad), g.asset, g.refid)
+                })
+            };
+            let mut activity_table = Table::new(&[t("Statistic"), t("Value")]);
+            for (label, value) in [
+                ("Trades", activity.trades.to_string()),
+                ("Assets traded", activity.assets_traded.to_string()),
+                ("Volume (CAD)", cad(activity.volume_cad)),
+                (
+                    "Average holding time",
+                    activity
+                        .avg_holding_days
+                        .map_or("-".to_string(), |d| tr("{} days", &[&d.round_dp(1)])),
+                ),
+                ("Largest gain (CAD)", gain_ref(&activity.largest_gain)),
+                ("Largest loss (CAD)", gain_ref(&activity.largest_loss)),
+            ] {
+                activity_table.row(vec![
+                    (t(label).to_string(), Style::Plain),
+                    (value, Style::Plain),
+                ]);
+            }
+            writeln!(out, "\n{}", heading(t("=== TRADING ACTIVITY ===")))?;
+            write!(out, "{}", activity_table.render(color))?;
+        }
+
+        let fee_totals = &totals.fees;
+        if !fee_totals.total_cad().is_zero() || fee_totals.unpriced_count > 0 {
+            let mut fee_table = Table::new(&[t("Fees"), t("Amount (CAD)")]);
+            for (label, amount) in [
+                ("Trading", fee_totals.trading_cad),
+                ("Withdrawal and transfer", fee_totals.withdrawal_cad),
+                ("Margin and rollover", fee_totals.margin_cad),
+                ("Total", fee_totals.total_cad()),
+            ] {
+                fee_table.row(vec![
+                    (t(label).to_string(), Style::Plain),
+                    (cad(amount), Style::Plain),
+                ]);
+            }
+            writeln!(out, "\n{}", heading(t("=== FEE EXPENSES ===")))?;
+            write!(out, "{}", fee_table.render(color))?;
+            if fee_totals.unpriced_count > 0 {
+                writeln!(
+                    out,
+                    "{}",
+                    warn(
+                        tr(
+                            "Fees without a price (left out above): {}",
+                            &[&fee_totals.unpriced_count]
+                        ),
+                        fee_totals.unpriced_count
+                    )
+                )?;
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{}",
+            warn(
+                tr(
+                    "Warnings (transfer-in assumed 0 ACB): {}",
+                    &[&totals.warning_count]
+                ),
+                totals.warning_count
+            )
+        )?;
+        writeln!(
+            out,
+            "{}",
+            tr(
+                "Valuations using fallback USD/CAD FX: {}",
+                &[&fallback_fx_uses]
+            )
+        )?;
+        if let Some(days) = args.max_price_age {
+            writeln!(
+                out,
+                "{}",
+                warn(
+                    tr(
+                        "Valuations at prices older than {} days: {}",
+                        &[&days, &totals.stale_price_count]
+                    ),
+                    totals.stale_price_count
+                )
+            )?;
+        }
+        if args.round_trip_window > 0 {
+            writeln!(
+                out,
+                "{}",
+                warn(
+                    tr(
+                        "Rapid round trips (within {} minutes, price within {}%): {}",
+                        &[
+                            &args.round_trip_window,
+                            &(round_trip::PRICE_TOLERANCE * dec!(100)).normalize(),
+                            &round_trips.len()
+                        ]
+                    ),
+                    round_trips.len()
+                )
+            )?;
+        }
+        if args.match_transfers {
+            writeln!(
+                out,
+                "{}",
+                tr("Matched own-account transfers: {}", &[&matched_transfers])
+            )?;
+        }
+        if args.trades.is_some() {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Trades found in trades.csv: {} of {}",
+                    &[&joined_trades.0, &joined_trades.1]
+                )
+            )?;
+        }
+        writeln!(
+            out,
+            "{}",
+            warn(
+                tr(
+                    "Ignored ledger rows (unrecognized type): {}",
+                    &[&ignored_count]
+                ),
+                ignored_count
+            )
+        )?;
+        for s in ignored {
+            let line = tr(
+                "  WARNING: ignored {} row(s) of type={} subtype={} (e.g. refid {})",
+                &[&s.count, &s.row_type, &s.subtype, &s.sample_refids],
+            );
+            writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+        }
+        writeln!(
+            out,
+            "{}",
+            warn(
+                tr(
+                    "Ledger balance mismatches: {}",
+                    &[&balance_mismatches.len()]
+                ),
+                balance_mismatches.len()
+            )
+        )?;
+        for m in balance_mismatches {
+            let line = tr(
+                "  WARNING: {} balance at {} (refid {}) is {} in the ledger but {} from its rows",
+                &[
+                    &m.asset,
+                    &m.time,
+                    &m.refid,
+                    &m.ledger_balance,
+                    &m.computed_balance,
+                ],
+            );
+            writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+        }
+        writeln!(
+            out,
+            "{}",
+            warn(
+                tr("Data-quality issues in the inputs: {}", &[&data_warnings]),
+                data_warnings
+            )
+        )?;
+        for d in data_issues {
+            if d.is_note() {
+                writeln!(out, "{}", tr("  NOTE: {}", &[&d.describe()]))?;
+            } else {
+                let line = tr("  WARNING: {}", &[&d.describe()]);
+                writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+            }
+        }
+        if args.exclude_refids.is_some() || args.overrides.is_some() || args.repair_trades {
+            writeln!(
+                out,
+                "{}",
+                warn(
+                    tr(
+                        "Excluded, overridden or repaired ledger rows: {}",
+                        &[&edits.len()]
+                    ),
+                    edits.len()
+                )
+            )?;
+            for e in edits {
+                let line = tr("  WARNING: {}", &[&e.describe()]);
+                writeln!(out, "{}", paint(&line, Style::Warning, color))?;
+            }
+        }
+
+        if let Some(deemed_at) = args.deemed_disposition {
+            writeln!(
+                out,
+                "\n{}",
+                heading(&tr(
+                    "=== DEEMED DISPOSITION ON EMIGRATION ({}) ===",
+                    &[&deemed_at.date()]
+                ))
+            )?;
+            let mut deemed_table = Table::new(&[
+                t("Asset"),
+                t("Units"),
+                t("FMV (CAD)"),
+                t("ACB (CAD)"),
+                t("Gain/loss (CAD)"),
+            ]);
+            for r in deemed_rows
+                .iter()
+                .filter(|r| r.event_type == "deemed_disposition")
+            {
+                let gain = parse_decimal(&r.gain_cad)?;
+                deemed_table.row(vec![
+                    (r.asset.to_string(), Style::Plain),
+                    (r.units_out.clone(), Style::Plain),
+                    (r.proceeds_cad.clone(), Style::Plain),
+                    (r.acb_disposed_cad.clone(), Style::Plain),
+                    (r.gain_cad.clone(), Style::signed(gain)),
+                ]);
+            }
+            write!(out, "{}", deemed_table.render(color))?;
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Deemed proceeds (CAD): {}",
+                    &[&q2(totals.deemed_proceeds_cad)]
+                )
+            )?;
+            writeln!(
+                out,
+                "{}",
+                tr("Deemed gain/loss (CAD): {}", &[&q2(totals.deemed_gain_cad)])
+            )?;
+        }
+
+        if args.loss_carryover_from.is_some() {
+            writeln!(out, "\n{}", heading(t("=== CAPITAL LOSS CARRYOVER ===")))?;
+            let mut carryover_table = Table::new(&[
+                t("Year"),
+                t("Net gain (CAD)"),
+                t("Losses applied"),
+                t("Adjusted gain"),
+                t("Carried back"),
+                t("Carryforward balance"),
+            ]);
+            for r in carryover {
+                carryover_table.row(vec![
+                    (r.year.to_string(), Style::Plain),
+                    (cad(r.net_gain_cad), Style::signed(r.net_gain_cad)),
+                    (cad(r.loss_applied_cad), Style::Plain),
+                    (cad(r.adjusted_gain_cad), Style::Plain),
+                    (cad(r.loss_carried_back_cad), Style::Plain),
+                    (cad(r.carryforward_balance_cad), Style::Plain),
+                ]);
+            }
+            write!(out, "{}", carryover_table.render(color))?;
+        }
+
+        if let Some(province) = args.province {
+            let income = totals.reward_income_cad
+                + totals.interest_income_cad
+                + totals.credit_income_cad
+                + totals.dividend_income_cad
+                + totals.other_income_cad
+                + totals.rebate_income_cad;
+            let other_income = args.other_income.unwrap_or_default();
+            let (without, with) = tax_rates::estimate(
+                province,
+                other_income,
+                totals.taxable_capital_gain_cad,
+                income,
+            );
+            writeln!(
+                out,
+                "\n{}",
+                heading(t("=== ESTIMATED TAX PAYABLE (ESTIMATE ONLY) ==="))
+            )?;
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Province: {}, other income: {} CAD, {} rates",
+                    &[&province, &cad(other_income), &tax_rates::RATES_YEAR]
+                )
+            )?;
+            let mut tax_table = Table::new(&[
+                "",
+                t("Other income only (CAD)"),
+                t("With this report (CAD)"),
+                t("Difference (CAD)"),
+            ]);
+            for (label, amount) in [
+                (
+                    "Taxable income",
+                    (|p| p.taxable_income_cad) as fn(&tax_rates::TaxPayable) -> Decimal,
+                ),
+                ("Federal tax", |p| p.federal_cad),
+                ("Provincial tax", |p| p.provincial_cad),
+                ("Total tax", |p| p.total_cad()),
+            ] {
+                tax_table.row(vec![
+                    (t(label).to_string(), Style::Plain),
+                    (cad(amount(&without)), Style::Plain),
+                    (cad(amount(&with)), Style::Plain),
+                    (cad(amount(&with) - amount(&without)), Style::Plain),
+                ]);
+            }
+            write!(out, "{}", tax_table.render(color))?;
+            writeln!(
+                out,
+                "{}",
+                t(
+                    "A rough estimate: tax brackets and the basic personal amount only, \
+                   without other credits, deductions, surtaxes or premiums."
+                )
+            )?;
+        }
+
+        writeln!(
+            out,
+            "\n{}",
+            heading(t("=== ENDING POOLS (units + ACB) ==="))
+        )?;
+        let mut pool_rows: Vec<(&Arc<str>, Vec<Cell>)> = pools
+            .iter()
+            .filter(|(asset, _)| asset.as_ref() != "CAD")
+            .map(|(asset, p)| {
+                let cells = vec![
+                    (asset.to_string(), Style::Plain),
+                    (q8(p.units).to_string(), Style::Plain),
+                    (cad(p.acb_cad), Style::Plain),
+                    (cad(p.avg_cost_cad_per_unit()), Style::Plain),
+                ];
+                (asset, cells)
+            })
+            .collect();
+        pool_rows.sort_by(|a, b| a.0.cmp(b.0));
+        let mut pool_table = Table::new(&[
+            t("Asset"),
+            t("Units"),
+            t("ACB (CAD)"),
+            t("Avg cost (CAD/unit)"),
+        ]);
+        for (_, cells) in pool_rows {
+            pool_table.row(cells);
+        }
+        write!(out, "{}", pool_table.render(color))?;
+
+        let report_path = if args.output == STDIO_PATH {
+            "<stdout>"
+        } else {
+            args.output.as_str()
+        };
+        writeln!(out, "\n{}", tr("Wrote tax report: {}", &[&report_path]))?;
+        if args.report_filter.is_active() {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "  {} of {} rows, filtered by --from/--to/--assets",
+                    &[&shown.len(), &report.len()]
+                )
+            )?;
+        }
+        if args.json {
+            writeln!(out, "{}", tr("Wrote JSON report: {}", &[&paths.json]))?;
+        }
+        if args.formats.contains(&ReportFormat::Markdown) {
+            writeln!(
+                out,
+                "{}",
+                tr("Wrote Markdown report: {}", &[&paths.markdown])
+            )?;
+        }
+        if args.formats.contains(&ReportFormat::Tsv) {
+            writeln!(out, "{}", tr("Wrote TSV report: {}", &[&paths.tsv]))?;
+        }
+        if args.formats.contains(&ReportFormat::Parquet) {
+            writeln!(out, "{}", tr("Wrote Parquet report: {}", &[&paths.parquet]))?;
+        }
+        if let Some(split) = args.split_by {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Wrote {} report(s) split by {}:",
+                    &[&split_outputs.len(), &split.as_str()]
+                )
+            )?;
+            for (path, rows) in split_outputs {
+                writeln!(out, "{}", tr("  {} ({} rows)", &[path, &rows.len()]))?;
+            }
+        }
+        let side_outputs = [
+            ("Wrote FX audit: {}", &paths.fx_audit),
+            ("Wrote lot audit: {}", &paths.lineage),
+            ("Wrote round-trip report: {}", &paths.round_trips),
+            ("Wrote fee expense report: {}", &paths.fees),
+            ("Wrote ignored rows report: {}", &paths.ignored),
+            ("Wrote balance mismatch report: {}", &paths.balance),
+            ("Wrote data-quality report: {}", &paths.data_quality),
+            ("Wrote ending pools: {}", &paths.pools),
+            ("Wrote report metadata: {}", &paths.meta),
+        ];
+        for (template, path) in side_outputs.iter().filter(|_| side_reports) {
+            writeln!(out, "{}", tr(template, &[path]))?;
+        }
+        if args.json {
+            writeln!(
+                out,
+                "{}",
+                tr("Wrote ending pools JSON: {}", &[&paths.pools_json])
+            )?;
+        }
+        if args.pool_mutations {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Wrote pool mutations ({} changes): {}",
+                    &[&mutation_rows.len(), &paths.mutations]
+                )
+            )?;
+        }
+        if side_reports && args.deemed_disposition.is_some() {
+            writeln!(
+                out,
+                "{}",
+                tr("Wrote deemed disposition report: {}", &[&paths.deemed])
+            )?;
+        }
+        if side_reports && args.loss_carryover_from.is_some() {
+            writeln!(
+                out,
+                "{}",
+                tr("Wrote loss carryover report: {}", &[&paths.carryover])
+            )?;
+        }
+        if side_reports && args.income_valuation == IncomeValuation::Provider {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Wrote fetched daily closes ({}): {}",
+                    &[&opts.daily_closes.len(), &paths.daily_closes]
+                )
+            )?;
+        }
+        if let Some(path) = args.price_cache.as_deref().filter(|_| fetched_prices > 0) {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Wrote price cache ({} fetched prices): {}",
+                    &[&fetched_prices, &path]
+                )
+            )?;
+        }
+        if args.checksums {
+            writeln!(out, "{}", tr("Wrote checksums: {}", &[&paths.checksums]))?;
+        }
+        if side_reports && args.aggregate_fills.is_some() {
+            writeln!(
+                out,
+                "{}",
+                tr(
+                    "Wrote fill detail report ({} merged fill rows): {}",
+                    &[&shown_fills.len(), &paths.fills]
+                )
+            )?;
+        }
+        for (path, count) in export_outputs {
+            writeln!(out, "{}", tr("Wrote export ({} rows): {}", &[count, path]))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,498 @@
+use crate::credentials::{api_key, redact};
+use crate::{ReportFilter, create_output, http, positional_args, summary_writer, take_flag};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+const KRAKEN_API_URL: &str = "https://api.kraken.com";
+const LEDGERS_PATH: &str = "/0/private/Ledgers";
+const DEFAULT_OUTPUT: &str = "kraken_ledgers.csv";
+const DEFAULT_EXPORT: &str = "kraken_ledgers_export.csv";
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// Entries the Ledgers endpoint returns per request.
+const PAGE_SIZE: usize = 50;
+
+// The API's legacy X/Z-prefixed asset codes, under the names the ledger CSV
+// export uses.
+const LEGACY_ASSETS: &[(&str, &str)] = &[
+    ("XETC", "ETC"),
+    ("XETH", "ETH"),
+    ("XLTC", "LTC"),
+    ("XMLN", "MLN"),
+    ("XREP", "REP"),
+    ("XXBT", "XBT"),
+    ("XXDG", "XDG"),
+    ("XXLM", "XLM"),
+    ("XXMR", "XMR"),
+    ("XXRP", "XRP"),
+    ("XZEC", "ZEC"),
+    ("ZAUD", "AUD"),
+    ("ZCAD", "CAD"),
+    ("ZCHF", "CHF"),
+    ("ZEUR", "EUR"),
+    ("ZGBP", "GBP"),
+    ("ZJPY", "JPY"),
+    ("ZUSD", "USD"),
+];
+
+// Where a sync stands. It is saved after every page, so an interrupted sync
+// resumes where it stopped and a finished one makes the next fetch only newer
+// entries.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+struct Cursor {
+    // The newest ledger id of the last finished sync.
+    #[serde(default)]
+    synced_through: Option<String>,
+    #[serde(default)]
+    pending: Option<Pending>,
+}
+
+// A sync in progress. Pages come newest first: `newest` is where the sync
+// will finish, `oldest` the last entry fetched so far.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Pending {
+    newest: String,
+    oldest: String,
+}
+
+impl Cursor {
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(Cursor::default());
+        }
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?)
+    }
+
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // Written whole and renamed, so an interruption leaves the old cursor.
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // Records a page of entries, newest first. Returns whether the sync has
+    // more to fetch.
+    fn advance(&mut self, page: &[(String, ApiEntry)]) -> bool {
+        let (Some((first, _)), Some((last, _))) = (page.first(), page.last()) else {
+            return false;
+        };
+        let moved = match &mut self.pending {
+            Some(p) => std::mem::replace(&mut p.oldest, last.clone()) != *last,
+            None => {
+                self.pending = Some(Pending {
+                    newest: first.clone(),
+                    oldest: last.clone(),
+                });
+                true
+            }
+        };
+        moved && page.len() >= PAGE_SIZE
+    }
+
+    fn finish(&mut self) {
+        if let Some(p) = self.pending.take() {
+            self.synced_through = Some(p.newest);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiEntry {
+    refid: String,
+    time: f64,
+    #[serde(rename = "type")]
+    row_type: String,
+    #[serde(default)]
+    subtype: String,
+    #[serde(default)]
+    aclass: String,
+    asset: String,
+    amount: String,
+    fee: String,
+    #[serde(default)]
+    balance: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgersResponse {
+    error: Vec<String>,
+    #[serde(default)]
+    result: Option<LedgersResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgersResult {
+    ledger: BTreeMap<String, ApiEntry>,
+}
+
+// The ledger CSV export's columns, as `sync` writes them and `export-ledger`
+// reads them back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LedgerCsvRow {
+    txid: String,
+    refid: String,
+    time: String,
+    #[serde(rename = "type")]
+    row_type: String,
+    subtype: String,
+    aclass: String,
+    asset: String,
+    amount: String,
+    fee: String,
+    balance: String,
+}
+
+fn asset_code(api: &str) -> &str {
+    LEGACY_ASSETS
+        .iter()
+        .find(|(legacy, _)| *legacy == api)
+        .map_or(api, |(_, code)| code)
+}
+
+fn hmac_sha512(key: &[u8], msg: &[u8]) -> [u8; 64] {
+    let mut block = [0u8; 128];
+    if key.len() > block.len() {
+        block[..64].copy_from_slice(&Sha512::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha512::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(msg)
+        .finalize();
+    Sha512::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+// Kraken's `API-Sign`: HMAC-SHA512 of the path and SHA-256(nonce + body),
+// keyed with the decoded secret.
+fn sign(path: &str, nonce: u64, body: &str, secret: &[u8]) -> String {
+    let mut msg = path.as_bytes().to_vec();
+    msg.extend_from_slice(&Sha256::digest(format!("{}{}", nonce, body)));
+    BASE64.encode(hmac_sha512(secret, &msg))
+}
+
+struct Client<'a> {
+    api_url: &'a str,
+    key: &'a str,
+    secret: &'a [u8],
+    // Kraken rejects a nonce not above the last one used with the key.
+    nonce: Cell<u64>,
+}
+
+impl Client<'_> {
+    // One page of entries after `start` and up to `end` (ledger ids; the
+    // endpoint excludes `start` and includes `end`), newest first.
+    fn page(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> Result<Vec<(String, ApiEntry)>, Box<dyn Error>> {
+        let resp = http::send(|agent| {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            let nonce = now.max(self.nonce.get() + 1);
+            self.nonce.set(nonce);
+            let mut body = format!("nonce={}&type=all", nonce);
+            for (name, id) in [("start", start), ("end", end)] {
+                if let Some(id) = id {
+                    body.push_str(&format!("&{}={}", name, id));
+                }
+            }
+            let request = agent
+                .post(&format!("{}{}", self.api_url, LEDGERS_PATH))
+                .set("API-Key", self.key)
+                .set("API-Sign", &sign(LEDGERS_PATH, nonce, &body, self.secret))
+                .set("Content-Type", "application/x-www-form-urlencoded");
+            (request, Some(body))
+        })?;
+        let resp: LedgersResponse = resp.into_json()?;
+        if !resp.error.is_empty() {
+            return Err(format!("Ledgers: {}", resp.error.join(", ")).into());
+        }
+        let mut page: Vec<_> = resp
+            .result
+            .ok_or("Ledgers: missing result")?
+            .ledger
+            .into_iter()
+            .collect();
+        page.sort_by(|(a_id, a), (b_id, b)| b.time.total_cmp(&a.time).then_with(|| b_id.cmp(a_id)));
+        Ok(page)
+    }
+}
+
+// The ledger ids already in `path`, so a page fetched again after an
+// interruption is not written twice.
+fn written_ids(path: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Row {
+        txid: String,
+    }
+    if !Path::new(path).exists() {
+        return Ok(HashSet::new());
+    }
+    let mut rdr = csv::Reader::from_path(path)?;
+    rdr.deserialize::<Row>().map(|r| Ok(r?.txid)).collect()
+}
+
+// `sync [--output ledger.csv] [--cursor file] [--api-url URL]`: appends the
+// account's ledger entries from Kraken's API to a ledger CSV, with the key
+// from `KRAKEN_API_KEY`/`KRAKEN_API_SECRET` or the OS keyring.
+pub(crate) fn sync(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let output = take_flag(&mut raw, "output")?.unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    let cursor_path = take_flag(&mut raw, "cursor")?;
+    let api_url = take_flag(&mut raw, "api-url")?.map_or_else(
+        || KRAKEN_API_URL.to_string(),
+        |url| url.trim_end_matches('/').to_string(),
+    );
+    if let Some(arg) = positional_args("sync", raw)?.first() {
+        return Err(format!("sync: unexpected argument {}", arg).into());
+    }
+    if output == crate::STDIO_PATH {
+        return Err("sync: --output must be a file, which later syncs append to".into());
+    }
+    let cursor_path = cursor_path.unwrap_or_else(|| {
+        let stem = output.strip_suffix(".csv").unwrap_or(&output);
+        format!("{}_sync.json", stem)
+    });
+
+    let key = api_key("kraken", "KRAKEN_API_KEY")
+        .ok_or("sync requires KRAKEN_API_KEY or a `kraken` key in the OS keyring")?;
+    let secret = api_key("kraken-secret", "KRAKEN_API_SECRET")
+        .ok_or("sync requires KRAKEN_API_SECRET or a `kraken-secret` key in the OS keyring")?;
+    let client = Client {
+        api_url: &api_url,
+        key: &key,
+        secret: &BASE64
+            .decode(&secret)
+            .map_err(|_| "the Kraken API secret is not base64")?,
+        nonce: Cell::new(0),
+    };
+
+    let mut cursor = Cursor::load(&cursor_path)?;
+    let resumed = cursor.pending.is_some();
+    let mut written = written_ids(&output)?;
+    let file = OpenOptions::new().create(true).append(true).open(&output)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(empty)
+        .from_writer(file);
+    let (mut pages, mut rows) = (0, 0);
+    loop {
+        let start = cursor.synced_through.as_deref();
+        let end = cursor.pending.as_ref().map(|p| p.oldest.as_str());
+        let page = client
+            .page(start, end)
+            .map_err(|e| redact(&redact(&e.to_string(), &key), &secret))?;
+        pages += 1;
+        for (id, e) in &page {
+            if !written.insert(id.clone()) {
+                continue;
+            }
+            let time = DateTime::from_timestamp_millis((e.time * 1000.0).round() as i64)
+                .ok_or_else(|| format!("ledger {}: bad time {}", id, e.time))?;
+            wtr.serialize(LedgerCsvRow {
+                txid: id.clone(),
+                refid: e.refid.clone(),
+                time: time.format(TIME_FORMAT).to_string(),
+                row_type: e.row_type.clone(),
+                subtype: e.subtype.clone(),
+                aclass: e.aclass.clone(),
+                asset: asset_code(&e.asset).to_string(),
+                amount: e.amount.clone(),
+                fee: e.fee.clone(),
+                balance: e.balance.clone(),
+            })?;
+            rows += 1;
+        }
+        // Rows reach the file before the cursor moves past them.
+        wtr.flush()?;
+        let more = cursor.advance(&page);
+        if !more {
+            cursor.finish();
+        }
+        cursor.save(&cursor_path)?;
+        if !more {
+            break;
+        }
+    }
+
+    let mut out = summary_writer(&output);
+    if resumed {
+        writeln!(out, "Resumed an interrupted sync")?;
+    }
+    writeln!(
+        out,
+        "Fetched {} pages; wrote {} new ledger rows: {}",
+        pages, rows, output
+    )?;
+    writeln!(out, "Sync cursor: {}", cursor_path)?;
+    Ok(())
+}
+
+// The synced rows dated `from` through `to`, in time order, each once.
+fn export_rows(
+    rows: Vec<LedgerCsvRow>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<Vec<LedgerCsvRow>, Box<dyn Error>> {
+    let mut dated = Vec::new();
+    let mut seen = HashSet::new();
+    for row in rows {
+        let time = NaiveDateTime::parse_from_str(&row.time, TIME_FORMAT)
+            .map_err(|_| format!("ledger {}: bad time {:?}", row.txid, row.time))?;
+        let date = time.date();
+        if from.is_some_and(|d| date < d) || to.is_some_and(|d| date > d) {
+            continue;
+        }
+        if seen.insert(row.txid.clone()) {
+            dated.push((time, row));
+        }
+    }
+    dated.sort_by(|(a_time, a), (b_time, b)| a_time.cmp(b_time).then_with(|| a.txid.cmp(&b.txid)));
+    Ok(dated.into_iter().map(|(_, row)| row).collect())
+}
+
+// `export-ledger [--input ledger.csv] [--from YYYY-MM-DD] [--to YYYY-MM-DD]
+// [--output file]`: writes the synced ledger out in Kraken's CSV schema, in
+// time order, for archiving next to a report.
+pub(crate) fn export_ledger(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let input = take_flag(&mut raw, "input")?.unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    let output = take_flag(&mut raw, "output")?.unwrap_or_else(|| DEFAULT_EXPORT.to_string());
+    let mut date = |name: &str| match take_flag(&mut raw, name)? {
+        Some(value) => ReportFilter::parse_date(name, &value).map(Some),
+        None => Ok(None),
+    };
+    let (from, to) = (date("from")?, date("to")?);
+    if let Some(arg) = positional_args("export-ledger", raw)?.first() {
+        return Err(format!("export-ledger: unexpected argument {}", arg).into());
+    }
+    if let (Some(from), Some(to)) = (from, to)
+        && from > to
+    {
+        return Err(format!("--from {} is after --to {}", from, to).into());
+    }
+
+    let rows = csv::Reader::from_path(&input)
+        .map_err(|e| format!("{}: {} (run `sync` first)", input, e))?
+        .deserialize()
+        .collect::<Result<Vec<LedgerCsvRow>, _>>()
+        .map_err(|e| format!("{}: {}", input, e))?;
+    let rows = export_rows(rows, from, to)?;
+    // Every field quoted, as Kraken's own export does.
+    let mut wtr = csv::WriterBuilder::new()
+        .quote_style(csv::QuoteStyle::Always)
+        .from_writer(create_output(&output)?);
+    for row in &rows {
+        wtr.serialize(row)?;
+    }
+    if rows.is_empty() {
+        wtr.write_record([
+            "txid", "refid", "time", "type", "subtype", "aclass", "asset", "amount", "fee",
+            "balance",
+        ])?;
+    }
+    wtr.flush()?;
+
+    let mut out = summary_writer(&output);
+    writeln!(out, "Wrote {} ledger rows: {}", rows.len(), output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_signed_and_the_cursor_resumes() {
+        // Kraken's documented example.
+        let secret = BASE64
+            .decode("kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==")
+            .unwrap();
+        let body =
+            "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+        assert_eq!(
+            sign("/0/private/AddOrder", 1616492376594, body, &secret),
+            "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ=="
+        );
+
+        let entry = |id: usize| {
+            let e = ApiEntry {
+                refid: "R".to_string(),
+                time: id as f64,
+                row_type: "trade".to_string(),
+                subtype: String::new(),
+                aclass: "currency".to_string(),
+                asset: "XXBT".to_string(),
+                amount: "1".to_string(),
+                fee: "0".to_string(),
+                balance: "1".to_string(),
+            };
+            (format!("L{:03}", id), e)
+        };
+        let full: Vec<_> = (51..=100).rev().map(entry).collect();
+        let last: Vec<_> = (1..=10).rev().map(entry).collect();
+        let mut cursor = Cursor::default();
+        assert!(cursor.advance(&full));
+        assert_eq!(
+            cursor.pending,
+            Some(Pending {
+                newest: "L100".to_string(),
+                oldest: "L051".to_string()
+            })
+        );
+        // The last page is short: the sync is done, and the next one starts
+        // after the newest entry.
+        assert!(!cursor.advance(&last));
+        cursor.finish();
+        assert_eq!(cursor.synced_through.as_deref(), Some("L100"));
+        assert_eq!(cursor.pending, None);
+        assert!(!cursor.advance(&[]));
+        assert_eq!(asset_code("XXBT"), "XBT");
+        assert_eq!(asset_code("SOL"), "SOL");
+    }
+
+    #[test]
+    fn export_is_in_time_order_within_the_dates() {
+        let row = |txid: &str, time: &str| LedgerCsvRow {
+            txid: txid.to_string(),
+            refid: "R".to_string(),
+            time: time.to_string(),
+            row_type: "deposit".to_string(),
+            subtype: String::new(),
+            aclass: "currency".to_string(),
+            asset: "CAD".to_string(),
+            amount: "1".to_string(),
+            fee: "0".to_string(),
+            balance: "1".to_string(),
+        };
+        // As synced: newest first within each page, and one row twice.
+        let rows = vec![
+            row("L3", "2021-03-01 00:00:00"),
+            row("L2", "2021-01-01 00:00:00"),
+            row("L1", "2020-12-31 23:59:59"),
+            row("L4", "2022-01-01 00:00:00"),
+            row("L2", "2021-01-01 00:00:00"),
+        ];
+        let from = NaiveDate::from_ymd_opt(2021, 1, 1);
+        let to = NaiveDate::from_ymd_opt(2021, 12, 31);
+        let ids: Vec<String> = export_rows(rows, from, to)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.txid)
+            .collect();
+        assert_eq!(ids, ["L2", "L3"]);
+    }
+}
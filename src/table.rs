@@ -0,0 +1,153 @@
+use rust_decimal::Decimal;
+use std::fmt::Write;
+
+// How a cell is colored when the summary goes to a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Style {
+    Plain,
+    Heading,
+    Warning,
+    Gain,
+    Loss,
+}
+
+impl Style {
+    // Green for gains and red for losses; zero stays plain.
+    pub(crate) fn signed(x: Decimal) -> Self {
+        if x > Decimal::ZERO {
+            Style::Gain
+        } else if x < Decimal::ZERO {
+            Style::Loss
+        } else {
+            Style::Plain
+        }
+    }
+
+    fn ansi(&self) -> Option<&'static str> {
+        match self {
+            Style::Plain => None,
+            Style::Heading => Some("\x1b[1m"),
+            Style::Warning => Some("\x1b[33m"),
+            Style::Gain => Some("\x1b[32m"),
+            Style::Loss => Some("\x1b[31m"),
+        }
+    }
+}
+
+// Cell text and its style.
+pub(crate) type Cell = (String, Style);
+
+pub(crate) fn paint(text: &str, style: Style, color: bool) -> String {
+    match style.ansi().filter(|_| color) {
+        Some(code) => format!("{}{}\x1b[0m", code, text),
+        None => text.to_string(),
+    }
+}
+
+// Column-aligned table: the first column is left-aligned (labels, assets),
+// the rest right-aligned (amounts).
+#[derive(Debug)]
+pub(crate) struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub(crate) fn new(headers: &[&str]) -> Self {
+        Table {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub(crate) fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub(crate) fn render(&self, color: bool) -> String {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (i, (text, _)) in row.iter().enumerate() {
+                widths[i] = widths[i].max(text.chars().count());
+            }
+        }
+        let mut out = String::new();
+        let header: Vec<Cell> = self
+            .headers
+            .iter()
+            .map(|h| (h.clone(), Style::Heading))
+            .collect();
+        let rule: Vec<Cell> = widths
+            .iter()
+            .map(|w| ("-".repeat(*w), Style::Plain))
+            .collect();
+        for row in [&header, &rule].into_iter().chain(&self.rows) {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, (text, style))| {
+                    // Pad before painting so escape codes don't count as width.
+                    let padded = if i == 0 {
+                        format!("{:<w$}", text, w = widths[i])
+                    } else {
+                        format!("{:>w$}", text, w = widths[i])
+                    };
+                    paint(&padded, *style, color)
+                })
+                .collect();
+            let _ = writeln!(out, "{}", cells.join("  ").trim_end());
+        }
+        out
+    }
+
+    // The table in Markdown, aligned the same way as `render`. Styles are
+    // dropped.
+    pub(crate) fn markdown(&self) -> String {
+        let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+        let escape = |text: &str| text.replace('|', "\\|");
+        let mut out = line(self.headers.iter().map(|h| escape(h)).collect());
+        out.push_str(&line(
+            (0..self.headers.len())
+                .map(|i| if i == 0 { "---" } else { "---:" }.to_string())
+                .collect(),
+        ));
+        for row in &self.rows {
+            out.push_str(&line(row.iter().map(|(text, _)| escape(text)).collect()));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn columns_align_and_color_only_when_asked() {
+        let mut t = Table::new(&["Asset", "Gain (CAD)"]);
+        t.row(vec![
+            ("BTC".to_string(), Style::Plain),
+            ("1250.00".to_string(), Style::signed(dec!(1250))),
+        ]);
+        t.row(vec![
+            ("DOGE".to_string(), Style::Plain),
+            ("-3.10".to_string(), Style::signed(dec!(-3.10))),
+        ]);
+        assert_eq!(
+            t.render(false),
+            "Asset  Gain (CAD)\n-----  ----------\nBTC       1250.00\nDOGE        -3.10\n"
+        );
+        let colored = t.render(true);
+        assert!(colored.contains("\x1b[31m     -3.10\x1b[0m"));
+        assert!(colored.starts_with("\x1b[1mAsset\x1b[0m"));
+        assert_eq!(
+            t.markdown(),
+            "| Asset | Gain (CAD) |\n| --- | ---: |\n| BTC | 1250.00 |\n| DOGE | -3.10 |\n"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt;
+
+// The year the tables below are for; other tax years are estimated at them.
+pub(crate) const RATES_YEAR: i32 = 2025;
+
+// Quebec residents' abatement of basic federal tax.
+const QUEBEC_ABATEMENT: Decimal = Decimal::from_parts(165, 0, 0, false, 3);
+
+// One government's brackets: `(top of bracket, rate)` from the lowest up,
+// then the rate above the last, with rates in basis points. The basic
+// personal amount is credited at the lowest rate.
+struct Schedule {
+    brackets: &'static [(u32, u32)],
+    top_rate: u32,
+    basic_personal_amount: u32,
+}
+
+impl Schedule {
+    // Tax on `income` less the basic personal amount credit, not below zero.
+    fn tax(&self, income: Decimal) -> Decimal {
+        let rate = |bp: u32| Decimal::new(bp.into(), 4);
+        let mut tax = Decimal::ZERO;
+        let mut floor = Decimal::ZERO;
+        for &(top, bp) in self.brackets {
+            let top = Decimal::from(top);
+            tax += (income.min(top) - floor).max(Decimal::ZERO) * rate(bp);
+            floor = top;
+        }
+        tax += (income - floor).max(Decimal::ZERO) * rate(self.top_rate);
+        let lowest = self.brackets.first().map_or(self.top_rate, |b| b.1);
+        (tax - Decimal::from(self.basic_personal_amount) * rate(lowest)).max(Decimal::ZERO)
+    }
+}
+
+// The lowest federal rate is 14.5% for 2025: 15% to June, 14% from July.
+const FEDERAL: Schedule = Schedule {
+    brackets: &[
+        (57_375, 1450),
+        (114_750, 2050),
+        (177_882, 2600),
+        (253_414, 2900),
+    ],
+    top_rate: 3300,
+    basic_personal_amount: 16_129,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Province {
+    Ab,
+    Bc,
+    Mb,
+    Nb,
+    Nl,
+    Ns,
+    Nt,
+    Nu,
+    On,
+    Pe,
+    Qc,
+    Sk,
+    Yt,
+}
+
+impl Province {
+    pub(crate) fn parse(code: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(match code.trim().to_uppercase().as_str() {
+            "AB" => Province::Ab,
+            "BC" => Province::Bc,
+            "MB" => Province::Mb,
+            "NB" => Province::Nb,
+            "NL" => Province::Nl,
+            "NS" => Province::Ns,
+            "NT" => Province::Nt,
+            "NU" => Province::Nu,
+            "ON" => Province::On,
+            "PE" => Province::Pe,
+            "QC" => Province::Qc,
+            "SK" => Province::Sk,
+            "YT" => Province::Yt,
+            other => {
+                return Err(format!(
+                    "unknown --province: {} (expected a code such as ON, QC or BC)",
+                    other
+                )
+                .into());
+            }
+        })
+    }
+
+    fn schedule(self) -> Schedule {
+        let (brackets, top_rate, basic_personal_amount): (&'static [(u32, u32)], u32, u32) =
+            match self {
+                Province::Ab => (
+                    &[
+                        (60_000, 800),
+                        (151_234, 1000),
+                        (181_481, 1200),
+                        (241_974, 1300),
+                        (362_961, 1400),
+                    ],
+                    1500,
+                    22_323,
+                ),
+                Province::Bc => (
+                    &[
+                        (49_279, 506),
+                        (98_560, 770),
+                        (113_158, 1050),
+                        (137_407, 1229),
+                        (186_306, 1470),
+                        (259_829, 1680),
+                    ],
+                    2050,
+                    12_932,
+                ),
+                Province::Mb => (&[(47_000, 1080), (100_000, 1275)], 1740, 15_780),
+                Province::Nb => (
+                    &[(51_306, 940), (102_614, 1400), (190_060, 1600)],
+                    1950,
+                    13_396,
+                ),
+                Province::Nl => (
+                    &[
+                        (44_192, 870),
+                        (88_382, 1450),
+                        (157_792, 1580),
+                        (220_910, 1780),
+                        (282_214, 1980),
+                        (564_429, 2080),
+                        (1_128_858, 2130),
+                    ],
+                    2180,
+                    11_067,
+                ),
+                Province::Ns => (
+                    &[
+                        (30_507, 879),
+                        (61_015, 1495),
+                        (95_883, 1667),
+                        (154_650, 1750),
+                    ],
+                    2100,
+                    11_744,
+                ),
+                Province::Nt => (
+                    &[(51_964, 590), (103_930, 860), (168_967, 1220)],
+                    1405,
+                    17_842,
+                ),
+                Province::Nu => (
+                    &[(54_707, 400), (109_413, 700), (177_881, 900)],
+                    1150,
+                    19_274,
+                ),
+                Province::On => (
+                    &[
+                        (52_886, 505),
+                        (105_775, 915),
+                        (150_000, 1116),
+                        (220_000, 1216),
+                    ],
+                    1316,
+                    12_747,
+                ),
+                Province::Pe => (
+                    &[
+                        (33_328, 950),
+                        (64_656, 1347),
+                        (105_000, 1660),
+                        (140_000, 1762),
+                    ],
+                    1900,
+                    14_250,
+                ),
+                Province::Qc => (
+                    &[(53_255, 1400), (106_495, 1900), (129_590, 2400)],
+                    2575,
+                    18_571,
+                ),
+                Province::Sk => (&[(53_463, 1050), (152_750, 1250)], 1450, 19_491),
+                Province::Yt => (
+                    &[
+                        (57_375, 640),
+                        (114_750, 900),
+                        (177_882, 1090),
+                        (500_000, 1280),
+                    ],
+                    1500,
+                    16_129,
+                ),
+            };
+        Schedule {
+            brackets,
+            top_rate,
+            basic_personal_amount,
+        }
+    }
+}
+
+impl fmt::Display for Province {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_uppercase())
+    }
+}
+
+// Federal and provincial tax on one taxable income.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TaxPayable {
+    pub(crate) taxable_income_cad: Decimal,
+    pub(crate) federal_cad: Decimal,
+    pub(crate) provincial_cad: Decimal,
+}
+
+impl TaxPayable {
+    fn on(province: Province, taxable_income: Decimal) -> Self {
+        let mut federal = FEDERAL.tax(taxable_income);
+        if province == Province::Qc {
+            federal -= federal * QUEBEC_ABATEMENT;
+        }
+        TaxPayable {
+            taxable_income_cad: taxable_income,
+            federal_cad: federal,
+            provincial_cad: province.schedule().tax(taxable_income),
+        }
+    }
+
+    pub(crate) fn total_cad(&self) -> Decimal {
+        self.federal_cad + self.provincial_cad
+    }
+}
+
+// `--province`: tax on `--other-income` alone and with the year's taxable
+// capital gain and income added, whose difference is the tax the report
+// accounts for. A net capital loss does not reduce other income.
+pub(crate) fn estimate(
+    province: Province,
+    other_income: Decimal,
+    taxable_capital_gain: Decimal,
+    income: Decimal,
+) -> (TaxPayable, TaxPayable) {
+    let with_crypto = other_income + taxable_capital_gain.max(Decimal::ZERO) + income;
+    (
+        TaxPayable::on(province, other_income),
+        TaxPayable::on(province, with_crypto),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn crypto_is_taxed_at_the_top_of_other_income() {
+        let (without, with) = estimate(Province::On, dec!(90000), dec!(5000), dec!(1000));
+        assert_eq!(with.taxable_income_cad, dec!(96000));
+        // All 6,000 falls in the 20.5% federal and 9.15% Ontario brackets.
+        assert_eq!(with.federal_cad - without.federal_cad, dec!(1230));
+        assert_eq!(with.provincial_cad - without.provincial_cad, dec!(549));
+        // 57,375 at 14.5% and 32,625 at 20.5%, less 16,129 at 14.5%.
+        assert_eq!(without.federal_cad, dec!(12668.795));
+
+        // Losses stop at zero, and Quebec's federal tax is abated 16.5%.
+        let (without, with) = estimate(Province::Qc, dec!(90000), dec!(-5000), dec!(0));
+        assert_eq!(with, without);
+        assert_eq!(without.federal_cad, dec!(12668.795) * dec!(0.835));
+
+        assert_eq!(Province::parse(" qc ").unwrap(), Province::Qc);
+        assert_eq!(Province::Nl.to_string(), "NL");
+        assert!(Province::parse("XX").is_err());
+    }
+}
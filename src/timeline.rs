@@ -0,0 +1,118 @@
+use crate::table::{Style, Table, paint};
+use crate::{
+    Applied, CostBasisEngine, Interner, Pool, cad, explain_failure, load_inputs, parse_args_from,
+    prepare_entries, process, process_options, q8, replay, take_flag, use_color,
+};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+
+// `pools <ledger.csv> --at YYYY-MM-DD [--asset SOL] [options]`: replays the
+// history through the end of a day and prints each pool as it stood then.
+// The other options are the main command's, so the pools match its report.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let at = take_flag(&mut raw, "at")?.ok_or("pools: --at YYYY-MM-DD is required")?;
+    let date = NaiveDate::parse_from_str(at.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("--at expects YYYY-MM-DD, got {:?}", at))?;
+    let asset = take_flag(&mut raw, "asset")?.map(|a| a.trim().to_uppercase());
+    let mut args = parse_args_from(raw)?;
+    let start_of_day = date.and_time(NaiveTime::MIN);
+    // Process through the tax year holding the date; later events are cut.
+    args.tax_year = args.jurisdiction.tax_year(start_of_day);
+
+    let (mut entries, data_quality) = load_inputs(&args, &mut Interner::default())?;
+    prepare_entries(&args, &mut entries)?;
+    let opts = process_options(&args, &entries)?;
+    let start = opts.start_pools.clone();
+    let out = process(entries, &opts).map_err(|e| explain_failure(e, &data_quality))?;
+    let end = start_of_day + chrono::Duration::days(1);
+    let pools = pools_at(start, &out.mutations, end, opts.cost_basis.clone())?;
+
+    let shown: BTreeMap<&Arc<str>, &Pool> = pools
+        .iter()
+        .filter(|(a, _)| a.as_ref() != "CAD")
+        .filter(|(a, _)| asset.as_deref().is_none_or(|want| a.as_ref() == want))
+        .collect();
+    if let Some(want) = &asset
+        && shown.is_empty()
+    {
+        return Err(format!("pools: no {} pool on {}", want, date).into());
+    }
+    let color = use_color(std::io::stdout(), args.no_color);
+    println!(
+        "{}",
+        paint(
+            &format!("=== POOLS AT END OF {} (units + ACB) ===", date),
+            Style::Heading,
+            color
+        )
+    );
+    let mut table = Table::new(&["Asset", "Units", "ACB (CAD)", "Avg cost (CAD/unit)"]);
+    for (asset, p) in shown {
+        table.row(vec![
+            (asset.to_string(), Style::Plain),
+            (q8(p.units).to_string(), Style::Plain),
+            (cad(p.acb_cad), Style::Plain),
+            (cad(p.avg_cost_cad_per_unit()), Style::Plain),
+        ]);
+    }
+    print!("{}", table.render(color));
+    Ok(())
+}
+
+// The pools after every mutation logged before `end`. The log is in the order
+// applied, which is time order.
+fn pools_at(
+    start: HashMap<Arc<str>, Pool>,
+    log: &[Applied],
+    end: NaiveDateTime,
+    basis: Arc<dyn CostBasisEngine>,
+) -> Result<HashMap<Arc<str>, Pool>, Box<dyn Error>> {
+    let before_end = log
+        .iter()
+        .take_while(|a| a.mutation.time < end)
+        .map(|a| a.mutation.clone());
+    replay(start, before_end, basis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::{AverageCost, ProcessOptions, parse_time};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn pools_stand_as_of_the_end_of_the_day() {
+        let entries = vec![
+            leg("B1", "2025-01-10 00:00:00", "CAD", dec!(-100)),
+            leg("B1", "2025-01-10 00:00:00", "SOL", dec!(1)),
+            leg("B2", "2025-06-30 23:00:00", "CAD", dec!(-300)),
+            leg("B2", "2025-06-30 23:00:00", "SOL", dec!(1)),
+            leg("S1", "2025-07-01 00:00:00", "SOL", dec!(-1)),
+            leg("S1", "2025-07-01 00:00:00", "CAD", dec!(250)),
+        ];
+        let out = process(entries, &ProcessOptions::new(2025, dec!(1.4))).unwrap();
+        let at = |day: &str| {
+            let end = parse_time(day).unwrap() + chrono::Duration::days(1);
+            pools_at(HashMap::new(), &out.mutations, end, Arc::new(AverageCost)).unwrap()
+        };
+        let sol = |pools: &HashMap<Arc<str>, Pool>| {
+            let p = &pools["SOL"];
+            (p.units, p.acb_cad, p.avg_cost_cad_per_unit())
+        };
+        assert_eq!(
+            sol(&at("2025-03-31 00:00:00")),
+            (dec!(1), dec!(100), dec!(100))
+        );
+        assert_eq!(
+            sol(&at("2025-06-30 00:00:00")),
+            (dec!(2), dec!(400), dec!(200))
+        );
+        assert_eq!(
+            sol(&at("2025-07-01 00:00:00")),
+            (dec!(1), dec!(200), dec!(200))
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{LedgerEntry, is_trade_row, parse_decimal, q8};
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+// Relative difference allowed between a fill's volume and the ledger amount of
+// its base leg; the ledger rounds to the asset's display precision.
+const VOL_TOLERANCE: Decimal = dec!(0.001);
+
+// The columns used from Kraken's trades.csv; the rest are ignored.
+#[derive(Debug, Deserialize)]
+struct TradeRow {
+    txid: String,
+    #[serde(default)]
+    ordertxid: String,
+    pair: String,
+    #[serde(rename = "type")]
+    side: String,
+    cost: String,
+    #[serde(default)]
+    fee: String,
+    vol: String,
+}
+
+// One trade from trades.csv, or every fill of one order added up. `vol` is in
+// the pair's base asset; `cost` and `fee` are in its quote asset.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TradeFill {
+    pub(crate) pair: String,
+    pub(crate) ordertxid: String,
+    pub(crate) buy: bool,
+    pub(crate) vol: Decimal,
+    pub(crate) cost: Decimal,
+    pub(crate) fee: Decimal,
+}
+
+impl TradeFill {
+    // Quote units per base unit, before fees.
+    pub(crate) fn price(&self) -> Decimal {
+        self.cost / self.vol
+    }
+
+    // Pre-fee units of the (out, in) legs, or `None` when the base leg's
+    // ledger amount disagrees with the fill (e.g. a different trade).
+    pub(crate) fn leg_units(
+        &self,
+        out: &LedgerEntry,
+        inn: &LedgerEntry,
+    ) -> Option<(Decimal, Decimal)> {
+        let base_units = if self.buy { inn.amount } else { -out.amount };
+        if (base_units - self.vol).abs() > self.vol * VOL_TOLERANCE {
+            return None;
+        }
+        Some(if self.buy {
+            (self.cost, self.vol)
+        } else {
+            (self.vol, self.cost)
+        })
+    }
+
+    pub(crate) fn note(&self) -> String {
+        let mut note = format!(
+            "trades.csv: {} {} at {}, fee {} in the quote asset",
+            if self.buy { "buy" } else { "sell" },
+            self.pair,
+            q8(self.price()),
+            q8(self.fee)
+        );
+        if !self.ordertxid.is_empty() {
+            note.push_str(&format!(" (order {})", self.ordertxid));
+        }
+        note
+    }
+}
+
+pub(crate) fn load(path: &str) -> Result<BTreeMap<Arc<str>, TradeFill>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+// Fills keyed by trade txid (a ledger trade's refid) and, for exports that
+// book an order under its order id, by ordertxid with the order's fills added
+// up. Repeated identical rows (overlapping exports) are kept once.
+pub(crate) fn parse(text: &str) -> Result<BTreeMap<Arc<str>, TradeFill>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut fills: BTreeMap<Arc<str>, TradeFill> = BTreeMap::new();
+    let mut orders: BTreeMap<Arc<str>, Vec<Arc<str>>> = BTreeMap::new();
+    for (i, row) in rdr.deserialize::<TradeRow>().enumerate() {
+        let row = row?;
+        let line = i + 2;
+        let buy = match row.side.trim().to_lowercase().as_str() {
+            "buy" => true,
+            "sell" => false,
+            other => return Err(format!("line {}: unknown trade type {:?}", line, other).into()),
+        };
+        let fill = TradeFill {
+            pair: row.pair.trim().to_string(),
+            ordertxid: row.ordertxid.trim().to_string(),
+            buy,
+            vol: parse_decimal(&row.vol)?,
+            cost: parse_decimal(&row.cost)?,
+            fee: if row.fee.trim().is_empty() {
+                dec!(0)
+            } else {
+                parse_decimal(&row.fee)?
+            },
+        };
+        if fill.vol <= dec!(0) || fill.cost <= dec!(0) {
+            return Err(format!("line {}: vol and cost must be positive", line).into());
+        }
+        let txid: Arc<str> = row.txid.trim().into();
+        match fills.get(&txid) {
+            Some(seen) if *seen == fill => continue,
+            Some(_) => {
+                return Err(format!("trade {} is listed twice with different values", txid).into());
+            }
+            None => {}
+        }
+        if !fill.ordertxid.is_empty() {
+            orders
+                .entry(fill.ordertxid.as_str().into())
+                .or_default()
+                .push(txid.clone());
+        }
+        fills.insert(txid, fill);
+    }
+
+    for (order, txids) in orders {
+        if fills.contains_key(&order) {
+            continue;
+        }
+        let first = &fills[&txids[0]];
+        let mut total = TradeFill {
+            vol: dec!(0),
+            cost: dec!(0),
+            fee: dec!(0),
+            ..first.clone()
+        };
+        let mut consistent = true;
+        for txid in &txids {
+            let f = &fills[txid];
+            consistent &= f.buy == total.buy && f.pair == total.pair;
+            total.vol += f.vol;
+            total.cost += f.cost;
+            total.fee += f.fee;
+        }
+        if consistent {
+            fills.insert(order, total);
+        }
+    }
+    Ok(fills)
+}
+
+// Trade refids in the ledger, and how many of them trades.csv lists.
+pub(crate) fn joined(
+    entries: &[LedgerEntry],
+    fills: &BTreeMap<Arc<str>, TradeFill>,
+) -> (usize, usize) {
+    let refids: HashSet<&Arc<str>> = entries
+        .iter()
+        .filter(|e| is_trade_row(e))
+        .map(|e| &e.refid)
+        .collect();
+    let found = refids.iter().filter(|r| fills.contains_key(**r)).count();
+    (found, refids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::leg;
+    use crate::{ProcessOptions, process};
+
+    #[test]
+    fn fills_join_by_txid_or_summed_by_order() {
+        let text = "\"txid\",\"ordertxid\",\"pair\",\"time\",\"type\",\"ordertype\",\"price\",\"cost\",\"fee\",\"vol\",\"margin\",\"misc\",\"ledgers\"\n\
+                    \"TA\",\"O1\",\"ETHXBT\",\"2025-01-01 00:00:00\",\"sell\",\"limit\",\"0.05\",\"0.05\",\"0.0001\",\"1\",\"0\",\"\",\"L1,L2\"\n\
+                    \"TB\",\"O1\",\"ETHXBT\",\"2025-01-01 00:00:01\",\"sell\",\"limit\",\"0.06\",\"0.12\",\"0.0002\",\"2\",\"0\",\"\",\"L3,L4\"\n\
+                    \"TB\",\"O1\",\"ETHXBT\",\"2025-01-01 00:00:01\",\"sell\",\"limit\",\"0.06\",\"0.12\",\"0.0002\",\"2\",\"0\",\"\",\"L3,L4\"\n";
+        let fills = parse(text).unwrap();
+        assert_eq!(fills.len(), 3);
+        assert_eq!(fills["TA"].price(), dec!(0.05));
+        let order = &fills["O1"];
+        assert_eq!(
+            (order.vol, order.cost, order.fee),
+            (dec!(3), dec!(0.17), dec!(0.0003))
+        );
+        assert!(
+            order
+                .note()
+                .starts_with("trades.csv: sell ETHXBT at 0.05666667")
+        );
+
+        let (out, inn) = (
+            leg("TA", "2025-01-03 00:00:00", "ETH", dec!(-1)),
+            leg("TA", "2025-01-03 00:00:00", "XBT", dec!(0.05)),
+        );
+        assert_eq!(
+            fills["TA"].leg_units(&out, &inn),
+            Some((dec!(1), dec!(0.05)))
+        );
+        assert_eq!(fills["TB"].leg_units(&out, &inn), None);
+
+        // ETH last traded at 3,000 CAD and XBT at 100,000: with the fill, the
+        // XBT received costs what its quote leg is worth, not the ETH's price.
+        let entries = vec![
+            leg("R1", "2025-01-01 00:00:00", "CAD", dec!(-3000)),
+            leg("R1", "2025-01-01 00:00:00", "ETH", dec!(1)),
+            leg("R2", "2025-01-02 00:00:00", "CAD", dec!(-100000)),
+            leg("R2", "2025-01-02 00:00:00", "XBT", dec!(1)),
+            out,
+            inn,
+        ];
+        assert_eq!(joined(&entries, &fills), (1, 3));
+        let acb_added = |trade_fills| {
+            let opts = ProcessOptions {
+                trade_fills,
+                ..ProcessOptions::new(2025, dec!(1.4))
+            };
+            let out = process(entries.clone(), &opts).unwrap();
+            let row = out
+                .report
+                .into_iter()
+                .find(|r| &*r.refid == "TA" && &*r.asset == "XBT")
+                .unwrap();
+            (row.acb_added_cad, row.notes)
+        };
+        let (acb, notes) = acb_added(fills.clone());
+        assert_eq!(acb, "5000.00");
+        assert!(notes.contains("trades.csv: sell ETHXBT at 0.05"));
+        assert_eq!(acb_added(BTreeMap::new()).0, "3000");
+
+        let changed = format!("{}TB,O1,ETHXBT,,sell,,,0.13,0,2\n", text);
+        assert!(parse(&changed).is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use crate::{ReportRow, parse_decimal, read_report};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use std::error::Error;
+
+const PAGE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Rows,
+    Pools,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Asset,
+    EventType,
+    Date,
+}
+
+impl Field {
+    fn label(&self) -> &'static str {
+        match self {
+            Field::Asset => "asset",
+            Field::EventType => "type",
+            Field::Date => "date",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Filters {
+    asset: String,
+    event_type: String,
+    date: String,
+}
+
+impl Filters {
+    // Asset matches exactly (any case), event type as a substring, and date as
+    // a `time` prefix (`2025-03`) or an inclusive `from..to` range of prefixes.
+    fn matches(&self, r: &ReportRow) -> bool {
+        (self.asset.is_empty() || r.asset.eq_ignore_ascii_case(&self.asset))
+            && r.event_type.contains(&self.event_type.to_lowercase())
+            && date_matches(&self.date, &r.time)
+    }
+
+    fn field_mut(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Asset => &mut self.asset,
+            Field::EventType => &mut self.event_type,
+            Field::Date => &mut self.date,
+        }
+    }
+}
+
+fn date_matches(filter: &str, time: &str) -> bool {
+    match filter.split_once("..") {
+        Some((from, to)) => {
+            (from.is_empty() || time >= from)
+                && (to.is_empty() || time.get(..to.len()).unwrap_or(time) <= to)
+        }
+        None => time.starts_with(filter),
+    }
+}
+
+struct App {
+    rows: Vec<ReportRow>,
+    filters: Filters,
+    // Indexes into `rows` that pass the filters.
+    visible: Vec<usize>,
+    table: TableState,
+    view: View,
+    editing: Option<(Field, String)>,
+    // Assets whose pool balance changes somewhere in the report.
+    pool_assets: Vec<String>,
+    pool_asset: usize,
+    pool_table: TableState,
+    quit: bool,
+}
+
+impl App {
+    fn new(rows: Vec<ReportRow>) -> Self {
+        let mut pool_assets: Vec<String> = rows
+            .iter()
+            .filter(|r| !r.pool_units_after.is_empty())
+            .map(|r| r.asset.to_string())
+            .collect();
+        pool_assets.sort();
+        pool_assets.dedup();
+        let mut app = App {
+            rows,
+            filters: Filters::default(),
+            visible: Vec::new(),
+            table: TableState::default(),
+            view: View::Rows,
+            editing: None,
+            pool_assets,
+            pool_asset: 0,
+            pool_table: TableState::default(),
+            quit: false,
+        };
+        app.refilter();
+        app
+    }
+
+    fn refilter(&mut self) {
+        self.visible = (0..self.rows.len())
+            .filter(|&i| self.filters.matches(&self.rows[i]))
+            .collect();
+        self.table.select((!self.visible.is_empty()).then_some(0));
+    }
+
+    fn selected(&self) -> Option<&ReportRow> {
+        let i = self.table.selected()?;
+        self.visible.get(i).map(|&i| &self.rows[i])
+    }
+
+    // Pool balance after each event that touched the selected asset's pool.
+    fn pool_history(&self) -> Vec<&ReportRow> {
+        let Some(asset) = self.pool_assets.get(self.pool_asset) else {
+            return Vec::new();
+        };
+        self.rows
+            .iter()
+            .filter(|r| &*r.asset == asset && !r.pool_units_after.is_empty())
+            .collect()
+    }
+
+    fn key(&mut self, code: KeyCode) {
+        if let Some((field, mut text)) = self.editing.take() {
+            match code {
+                KeyCode::Char(c) => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Enter => {
+                    *self.filters.field_mut(field) = text;
+                    self.refilter();
+                    return;
+                }
+                KeyCode::Esc => return,
+                _ => {}
+            }
+            self.editing = Some((field, text));
+            return;
+        }
+        let edit =
+            |field: Field, filters: &mut Filters| Some((field, filters.field_mut(field).clone()));
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::Rows => View::Pools,
+                    View::Pools => View::Rows,
+                };
+                self.pool_table.select(Some(0));
+            }
+            KeyCode::Char('a') => self.editing = edit(Field::Asset, &mut self.filters),
+            KeyCode::Char('e') => self.editing = edit(Field::EventType, &mut self.filters),
+            KeyCode::Char('d') => self.editing = edit(Field::Date, &mut self.filters),
+            KeyCode::Char('c') => {
+                self.filters = Filters::default();
+                self.refilter();
+            }
+            KeyCode::Left | KeyCode::Char('h') if !self.pool_assets.is_empty() => {
+                let n = self.pool_assets.len();
+                self.pool_asset = (self.pool_asset + n - 1) % n;
+                self.pool_table.select(Some(0));
+            }
+            KeyCode::Right | KeyCode::Char('l') if !self.pool_assets.is_empty() => {
+                self.pool_asset = (self.pool_asset + 1) % self.pool_assets.len();
+                self.pool_table.select(Some(0));
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.scroll(1),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll(-1),
+            KeyCode::PageDown => self.scroll(PAGE as isize),
+            KeyCode::PageUp => self.scroll(-(PAGE as isize)),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.scroll(isize::MAX),
+            _ => {}
+        }
+    }
+
+    fn scroll(&mut self, by: isize) {
+        let (len, state) = match self.view {
+            View::Rows => (self.visible.len(), &mut self.table),
+            View::Pools => (self.pool_history().len(), &mut self.pool_table),
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        let next = current.saturating_add(by).clamp(0, len as isize - 1);
+        state.select(Some(next as usize));
+    }
+}
+
+// Browses a report written by the main command:
+// `kraken_acb tui kraken_tax_report_2025.csv`.
+pub(crate) fn run(raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut raw = raw.into_iter();
+    let path = raw.next().ok_or("tui: expected a report CSV path")?;
+    if let Some(arg) = raw.next() {
+        return Err(format!("tui: unexpected argument {}", arg).into());
+    }
+    let mut app = App::new(read_report(&path)?);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> Result<(), Box<dyn Error>> {
+    while !app.quit {
+        terminal.draw(|frame| draw(frame, app))?;
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            app.key(key.code);
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [status, main, help] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let status_line = match &app.editing {
+        Some((field, text)) => format!("{} filter> {}_", field.label(), text),
+        None => format!(
+            "{} | rows {}/{} | asset={} type={} date={}",
+            match app.view {
+                View::Rows => "REPORT ROWS",
+                View::Pools => "POOLS",
+            },
+            app.visible.len(),
+            app.rows.len(),
+            app.filters.asset,
+            app.filters.event_type,
+            app.filters.date
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(status_line).style(Style::new().add_modifier(Modifier::BOLD)),
+        status,
+    );
+    frame.render_widget(
+        Paragraph::new(
+            "q quit  ↑↓/PgUp/PgDn move  Tab rows/pools  a/e/d filter asset/type/date (2025-03 or 2025-01..2025-06)  c clear  ←→ pool asset",
+        ),
+        help,
+    );
+    match app.view {
+        View::Rows => draw_rows(frame, main, app),
+        View::Pools => draw_pools(frame, main, app),
+    }
+}
+
+fn draw_rows(frame: &mut Frame, area: Rect, app: &mut App) {
+    let [list, detail] = Layout::vertical([Constraint::Min(3), Constraint::Length(8)]).areas(area);
+    let rows = app.visible.iter().map(|&i| {
+        let r = &app.rows[i];
+        Row::new(vec![
+            r.time.clone(),
+            r.event_type.clone(),
+            r.asset.to_string(),
+            r.units_in.clone(),
+            r.units_out.clone(),
+            r.gain_cad.clone(),
+            r.income_cad.clone(),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(25),
+            Constraint::Length(30),
+            Constraint::Length(8),
+            Constraint::Length(16),
+            Constraint::Length(16),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ],
+    )
+    .header(
+        Row::new(["Time", "Event", "Asset", "In", "Out", "Gain", "Income"])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered());
+    frame.render_stateful_widget(table, list, &mut app.table);
+
+    let lines = match app.selected() {
+        Some(r) => vec![
+            Line::from(format!("refid {}  txid {}", r.refid, r.txid)),
+            Line::from(format!(
+                "proceeds {}  ACB disposed {}  gain {}  income {}",
+                r.proceeds_cad, r.acb_disposed_cad, r.gain_cad, r.income_cad
+            )),
+            Line::from(format!("ACB added {}  fee {}", r.acb_added_cad, r.fee_cad)),
+            Line::from(format!(
+                "pool after: units {}  ACB {}",
+                r.pool_units_after, r.pool_acb_cad_after
+            )),
+            Line::from(format!(
+                "price source {}  FX source {}",
+                r.price_source, r.fx_source
+            )),
+            Line::from(r.notes.clone()),
+        ],
+        None => vec![Line::from("No rows match the filters.")],
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Derivation")),
+        detail,
+    );
+}
+
+fn draw_pools(frame: &mut Frame, area: Rect, app: &mut App) {
+    let [chart, list] = Layout::vertical([Constraint::Length(8), Constraint::Min(3)]).areas(area);
+    let asset = app
+        .pool_assets
+        .get(app.pool_asset)
+        .cloned()
+        .unwrap_or_default();
+    let history = app.pool_history();
+    // Sparkline bars are whole cents of pool ACB.
+    let acb: Vec<u64> = history
+        .iter()
+        .map(|r| {
+            parse_decimal(&r.pool_acb_cad_after)
+                .ok()
+                .and_then(|x| (x * Decimal::ONE_HUNDRED).to_u64())
+                .unwrap_or(0)
+        })
+        .collect();
+    let rows: Vec<Row> = history
+        .iter()
+        .map(|r| {
+            Row::new(vec![
+                r.time.clone(),
+                r.event_type.clone(),
+                r.pool_units_after.clone(),
+                r.pool_acb_cad_after.clone(),
+            ])
+        })
+        .collect();
+    frame.render_widget(
+        Sparkline::default()
+            .data(&acb)
+            .block(Block::bordered().title(format!("{} pool ACB (CAD) over time", asset))),
+        chart,
+    );
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(25),
+            Constraint::Length(30),
+            Constraint::Length(20),
+            Constraint::Length(16),
+        ],
+    )
+    .header(
+        Row::new(["Time", "Event", "Units after", "ACB after (CAD)"])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered());
+    frame.render_stateful_widget(table, list, &mut app.pool_table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(time: &str, event_type: &str, asset: &str, pool_units_after: &str) -> ReportRow {
+        ReportRow {
+            time: time.to_string(),
+            event_type: event_type.to_string(),
+            asset: asset.into(),
+            pool_units_after: pool_units_after.to_string(),
+            ..ReportRow::default()
+        }
+    }
+
+    #[test]
+    fn filters_narrow_rows_and_pools_follow_asset() {
+        let mut app = App::new(vec![
+            row("2025-01-05T00:00:00+00:00", "trade_acquisition", "BTC", "1"),
+            row(
+                "2025-03-10T00:00:00+00:00",
+                "trade_disposition",
+                "BTC",
+                "0.5",
+            ),
+            row(
+                "2025-03-11T00:00:00+00:00",
+                "staking_reward_income",
+                "DOT",
+                "2",
+            ),
+            row(
+                "2025-07-01T00:00:00+00:00",
+                "warning_unpriced_transfer_in",
+                "ETH",
+                "",
+            ),
+        ]);
+        assert_eq!(app.visible.len(), 4);
+
+        for code in [KeyCode::Char('d')]
+            .into_iter()
+            .chain("2025-02..2025-03".chars().map(KeyCode::Char))
+            .chain([KeyCode::Enter])
+        {
+            app.key(code);
+        }
+        assert_eq!(app.visible, vec![1, 2]);
+
+        app.key(KeyCode::Char('a'));
+        app.key(KeyCode::Char('b'));
+        app.key(KeyCode::Char('t'));
+        app.key(KeyCode::Char('c'));
+        app.key(KeyCode::Enter);
+        assert_eq!(app.visible, vec![1]);
+        assert_eq!(app.selected().unwrap().event_type, "trade_disposition");
+
+        app.key(KeyCode::Char('c'));
+        assert_eq!(app.visible.len(), 4);
+        assert_eq!(app.pool_assets, vec!["BTC", "DOT"]);
+        app.key(KeyCode::Tab);
+        assert_eq!(app.pool_history().len(), 2);
+        app.key(KeyCode::Right);
+        assert_eq!(&*app.pool_history()[0].asset, "DOT");
+    }
+}
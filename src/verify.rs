@@ -0,0 +1,207 @@
+use crate::diff::{ReportDiff, diff_reports, render};
+use crate::meta::{self, InputFile, RecordedRun};
+use crate::{
+    config, is_subcommand, parse_args_from, positional_args, read_report, run_report,
+    summary_color, take_flag, take_switch,
+};
+use std::error::Error;
+use std::path::Path;
+
+// What rerunning a report found.
+#[derive(Debug)]
+struct Outcome {
+    rows: usize,
+    // `None` when the rerun wrote the report byte for byte.
+    mismatch: Option<ReportDiff>,
+    // Differences between the recorded run and this one that may explain a
+    // mismatch: a changed input, config file or version.
+    notes: Vec<String>,
+}
+
+// Reruns the run `recorded` describes on `ledger`, writing into `dir`, and
+// compares what it writes with `report`.
+fn verify(
+    report: &str,
+    ledger: &str,
+    recorded: &RecordedRun,
+    dir: &Path,
+) -> Result<Outcome, Box<dyn Error>> {
+    let mut raw = recorded.arguments.clone();
+    if is_subcommand(raw.first().map(String::as_str)) || raw.iter().any(|a| a == "--daemon") {
+        return Err("the metadata does not describe a report run".into());
+    }
+    let mut notes = Vec::new();
+    match &recorded.config {
+        // Without this, a config file created since would be picked up.
+        None => raw.push("--no-config".to_string()),
+        Some(file) => {
+            if InputFile::read(&file.path).ok().as_ref() != Some(file) {
+                notes.push(format!("{} changed since the report was run", file.path));
+            }
+        }
+    }
+    let raw = config::expand(raw)?;
+    #[cfg(feature = "cli")]
+    let raw = crate::http::configure(raw)?;
+    let mut args = parse_args_from(raw)?;
+    if !args.accounts.is_empty() || args.split.is_some() {
+        return Err("verify cannot rerun --account or --split runs".into());
+    }
+    if args.db.is_some() {
+        return Err("verify cannot rerun --db runs, whose ledger is in the database".into());
+    }
+
+    let recorded_ledger = recorded.inputs.first();
+    if recorded_ledger.map(|f| &f.sha256) != Some(&InputFile::read(ledger)?.sha256) {
+        notes.push(format!(
+            "{} is not the ledger the report was run on ({})",
+            ledger,
+            recorded_ledger.map_or("unknown", |f| &f.path)
+        ));
+    }
+    for file in recorded.inputs.iter().skip(1) {
+        if InputFile::read(&file.path).ok().as_ref() != Some(file) {
+            notes.push(format!("{} changed since the report was run", file.path));
+        }
+    }
+    if recorded.version != env!("CARGO_PKG_VERSION") {
+        notes.push(format!(
+            "the report was made by version {}, and this is {}",
+            recorded.version,
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+
+    let name = Path::new(report)
+        .file_name()
+        .ok_or("the report path names no file")?;
+    let rerun = dir.join(name);
+    args.input = ledger.to_string();
+    args.output = rerun
+        .to_str()
+        .ok_or("temporary path is not UTF-8")?
+        .to_string();
+    // The rerun must not touch the user's files.
+    args.checkpoint = None;
+    args.quiet = true;
+    run_report(&args)?;
+
+    let rows = read_report(&args.output)?;
+    let mismatch = if std::fs::read(report)? == std::fs::read(&rerun)? {
+        None
+    } else {
+        Some(diff_reports(&read_report(report)?, &rows)?)
+    };
+    Ok(Outcome {
+        rows: rows.len(),
+        mismatch,
+        notes,
+    })
+}
+
+// `verify <report.csv> --against <ledger.csv> [--meta file] [--no-color]`:
+// reruns the report from the ledger with the settings in its
+// `<report>_meta.json` and checks the result is the same file.
+pub(crate) fn run(mut raw: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let no_color = take_switch(&mut raw, "no-color");
+    let ledger = take_flag(&mut raw, "against")?;
+    let meta_path = take_flag(&mut raw, "meta")?;
+    let report = match <[String; 1]>::try_from(positional_args("verify", raw)?) {
+        Ok([report]) => report,
+        Err(rest) if rest.is_empty() => return Err("verify requires a report file".into()),
+        Err(_) => return Err("verify takes one report".into()),
+    };
+    let ledger = ledger.ok_or("verify requires --against <ledger.csv>")?;
+    let meta_path = meta_path.unwrap_or_else(|| meta::path_for(&report));
+    let recorded = meta::read(&meta_path)?;
+
+    let dir = std::env::temp_dir().join(format!("kraken_acb_verify_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let outcome = verify(&report, &ledger, &recorded, &dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    let outcome = outcome?;
+
+    println!("Verifying {} against {}", report, ledger);
+    println!("Settings: {}", meta_path);
+    for note in &outcome.notes {
+        println!("  NOTE: {}", note);
+    }
+    let Some(diff) = outcome.mismatch else {
+        println!("Reproduced exactly: {} rows", outcome.rows);
+        return Ok(());
+    };
+    print!(
+        "\n{}",
+        render(&diff, &report, "rerun", summary_color(&report, no_color))?
+    );
+    if diff.is_empty() {
+        println!("\nEvery value matches as a number, but the files differ byte for byte.");
+    }
+    Err(format!(
+        "{} is not reproducible from {} with its recorded settings",
+        report, ledger
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_report_is_reproduced_only_from_its_own_ledger() {
+        let dir =
+            std::env::temp_dir().join(format!("kraken_acb_verify_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let ledger = |sale: &str| {
+            format!(
+                "txid,refid,time,type,subtype,asset,amount,fee\n\
+                 L1,B1,2025-01-01 00:00:00,trade,tradespot,CAD,-100,0\n\
+                 L2,B1,2025-01-01 00:00:00,trade,tradespot,SOL,3,0\n\
+                 L3,S1,2025-02-01 00:00:00,trade,tradespot,SOL,-1,0\n\
+                 L4,S1,2025-02-01 00:00:00,trade,tradespot,CAD,{},0\n",
+                sale
+            )
+        };
+        std::fs::write(path("ledger.csv"), ledger("40")).unwrap();
+        std::fs::write(path("amended.csv"), ledger("45")).unwrap();
+        let mut args =
+            parse_args_from(vec![path("ledger.csv"), "2025".into(), path("report.csv")]).unwrap();
+        args.quiet = true;
+        run_report(&args).unwrap();
+        let recorded = RecordedRun {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments: vec![path("ledger.csv"), "2025".into(), path("out.csv")],
+            inputs: vec![InputFile::read(&path("ledger.csv")).unwrap()],
+            config: None,
+        };
+        let rerun_dir = dir.join("rerun");
+        std::fs::create_dir_all(&rerun_dir).unwrap();
+
+        let same = verify(
+            &path("report.csv"),
+            &path("ledger.csv"),
+            &recorded,
+            &rerun_dir,
+        );
+        let amended = verify(
+            &path("report.csv"),
+            &path("amended.csv"),
+            &recorded,
+            &rerun_dir,
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let same = same.unwrap();
+        assert!(
+            same.mismatch.is_none() && same.notes.is_empty(),
+            "{:?}",
+            same
+        );
+        assert_eq!(same.rows, 2);
+        let amended = amended.unwrap();
+        assert_eq!(amended.notes.len(), 1, "{:?}", amended.notes);
+        let diff = amended.mismatch.unwrap();
+        assert!(!diff.is_empty());
+    }
+}
@@ -0,0 +1,15 @@
+use crate::EngineOptions;
+use wasm_bindgen::prelude::*;
+
+// Browser entry point: takes the raw export bytes and the options as JSON
+// (empty for defaults) and returns the report bundle as JSON.
+#[wasm_bindgen]
+pub fn process_csv(bytes: &[u8], options: &str) -> Result<String, JsError> {
+    let options: EngineOptions = if options.trim().is_empty() {
+        EngineOptions::default()
+    } else {
+        serde_json::from_str(options)?
+    };
+    let bundle = crate::process_csv(bytes, &options).map_err(|e| JsError::new(&e.to_string()))?;
+    Ok(serde_json::to_string(&bundle)?)
+}
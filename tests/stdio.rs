@@ -0,0 +1,130 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const LEDGER: &str = "txid,refid,time,type,subtype,asset,amount,fee\n\
+                      T1,R1,2025-01-01 00:00:00,trade,tradespot,CAD,-100,0\n\
+                      T2,R1,2025-01-01 00:00:00,trade,tradespot,SOL,1,0\n\
+                      T3,R2,2025-02-01 00:00:00,trade,tradespot,SOL,-0.5,0\n\
+                      T4,R2,2025-02-01 00:00:00,trade,tradespot,CAD,80,0\n";
+
+// Runs the binary in an empty directory of its own, with `LEDGER` on stdin.
+fn run(name: &str, args: &[&str]) -> (Output, PathBuf) {
+    run_with_env(name, args, &[])
+}
+
+fn run_with_env(name: &str, args: &[&str], env: &[(&str, &str)]) -> (Output, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("kraken_acb_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kraken_acb"))
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // A run refused before reading its input closes stdin early.
+    let _ = child.stdin.take().unwrap().write_all(LEDGER.as_bytes());
+    (child.wait_with_output().unwrap(), dir)
+}
+
+#[test]
+fn reads_the_ledger_from_stdin_and_writes_the_report_to_stdout() {
+    let (out, dir) = run("stdio", &["report", "-", "2025", "-", "--offline"]);
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}", stderr);
+    let report = String::from_utf8(out.stdout).unwrap();
+    assert!(report.starts_with("time,"), "{}", report);
+    let sale = report
+        .lines()
+        .find(|l| l.contains("trade_disposition"))
+        .unwrap();
+    assert!(sale.contains(",0.5,80,50.0,30.0,"), "{}", sale);
+    // The summary goes to stderr, and no side report is left behind.
+    assert!(stderr.contains("Wrote tax report: <stdout>"));
+    assert!(!stderr.contains("Wrote FX audit"));
+    assert!(files.is_empty(), "{:?}", files);
+}
+
+#[test]
+fn stdout_runs_refuse_file_outputs_and_bad_years() {
+    let (out, dir) = run("stdio_export", &["-", "2025", "-", "--export", "koinly"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("--export needs a report file, not stdout")
+    );
+
+    let (out, dir) = run("stdio_year", &["-", "20x5", "-"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("invalid tax year: 20x5"));
+}
+
+// Reads every file a run left behind, by name, less the metadata's
+// `generated_at` line, which differs between any two runs.
+fn outputs(dir: &PathBuf) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|f| {
+            let path = f.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let mut bytes = std::fs::read(&path).unwrap();
+            if name.ends_with("_meta.json") {
+                let text = String::from_utf8(bytes).unwrap();
+                let kept: Vec<_> = text
+                    .lines()
+                    .filter(|l| !l.trim_start().starts_with("\"generated_at\""))
+                    .collect();
+                bytes = kept.join("\n").into_bytes();
+            }
+            (name, bytes)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn parallel_and_sequential_runs_write_identical_files() {
+    let args = [
+        "-",
+        "2025",
+        "out.csv",
+        "--offline",
+        "--loss-carryover-from",
+        "2022",
+        "--export",
+        "koinly",
+        "--export",
+        "schedule-3",
+        "--export",
+        "beancount",
+    ];
+    let runs: Vec<_> = ["1", "4"]
+        .iter()
+        .map(|threads| {
+            let name = format!("threads_{}", threads);
+            let (out, dir) = run_with_env(&name, &args, &[("RAYON_NUM_THREADS", threads)]);
+            let files = outputs(&dir);
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert!(
+                out.status.success(),
+                "{}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+            (out.stdout, out.stderr, files)
+        })
+        .collect();
+    assert!(
+        runs[0]
+            .2
+            .iter()
+            .any(|(name, _)| name == "out_schedule_3.csv")
+    );
+    assert!(runs[0] == runs[1]);
+}